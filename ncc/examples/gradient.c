@@ -0,0 +1,51 @@
+// Fill the window with a color gradient, polling for events
+// instead of registering callbacks. The pixels are checked by
+// test_window_headless in vm/src/vm.rs
+
+#include <uvm/syscalls.h>
+#include <uvm/graphics.h>
+#include <uvm/clock.h>
+#include <uvm/window.h>
+
+#define FRAME_WIDTH 256
+#define FRAME_HEIGHT 256
+
+// RGBA pixels: 256 * 256
+u32 frame_buffer[256][256];
+
+window_event event;
+
+int main()
+{
+    window_create(FRAME_WIDTH, FRAME_HEIGHT, "Gradient Example", 0);
+    window_event* ev = &event;
+
+    // Red increases from left to right, green from top to bottom
+    for (u32 y = 0; y < FRAME_HEIGHT; ++y)
+    {
+        for (u32 x = 0; x < FRAME_WIDTH; ++x)
+        {
+            frame_buffer[y][x] = rgb32(x, y, 128);
+        }
+    }
+
+    for (;;)
+    {
+        window_draw_frame(0, frame_buffer);
+
+        while (window_poll_event(0, ev))
+        {
+            if (ev->kind == WINDOW_EVENT_CLOSE)
+            {
+                return 0;
+            }
+
+            if (ev->kind == WINDOW_EVENT_KEYDOWN && ev->code == KEY_ESCAPE)
+            {
+                return 0;
+            }
+        }
+
+        sleep_ms(16);
+    }
+}
@@ -1,19 +1,10 @@
 #ifndef __ASSERT_H__
 #define __ASSERT_H__
 
-// assert() macro
-#ifndef NDEBUG
-#define assert(test_val)\
-if (!(test_val))\
-{\
-    asm ("assertion failed in ") -> void { syscall print_str; };\
-    asm (__FILE__) -> void { syscall print_str; };\
-    asm ("@") -> void { syscall print_str; };\
-    asm (__LINE__) -> void { syscall print_i64; };\
-    asm () -> void { syscall print_endl; };\
-    asm () -> void { panic; };\
-}
-#else
+// assert(test_val) and assert(test_val, "message") are statements
+// built into the compiler, which report the failed expression and
+// its location. Defining NDEBUG removes them.
+#ifdef NDEBUG
 #define assert(test_val) {}
 #endif
 
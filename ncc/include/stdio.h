@@ -3,12 +3,21 @@
 
 #include <assert.h>
 
+#define EOF -1
+
 int puts(char* str)
 {
     asm (str) -> void { syscall print_str; };
     return 0;
 }
 
+// Read one character of input, or EOF at the end of the input
+int getchar()
+{
+    i64 ch = asm () -> i64 { syscall read_char; };
+    return (int)ch;
+}
+
 // Internal buffer used by printf
 char* __buffer[32];
 
@@ -76,7 +85,35 @@ int printf(char* format, ...)
                 continue;
             }
 
-            // TODO: %x %X for printing hexadecimal integers
+            // Character
+            if (format[i+1] == 'c')
+            {
+                ++i;
+
+                u64 ch = asm (var_arg_idx) -> u64 { get_var_arg; };
+                __write_char((char)ch);
+                ++var_arg_idx;
+                ++ch_written;
+
+                continue;
+            }
+
+            // Hexadecimal integer
+            if (format[i+1] == 'x')
+            {
+                ++i;
+
+                // Get the integer argument and print it
+                asm (var_arg_idx) -> void {
+                    get_var_arg;
+                    syscall print_u64_hex;
+                };
+                ++var_arg_idx;
+
+                continue;
+            }
+
+            // TODO: %X for printing uppercase hexadecimal integers
 
             // TODO: %p for printing pointers
 
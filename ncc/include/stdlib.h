@@ -45,32 +45,98 @@ void srand(unsigned int seed)
 
 #define align_ptr(ptr, n_bytes) (((u64)(ptr) + ((n_bytes) - 1)) & ~((n_bytes) - 1))
 
+// Magic words at the start of allocated and free blocks,
+// used to detect invalid and double frees
+#define __MALLOC_USED__ 0x1337BAB3
+#define __MALLOC_FREE__ 0x1111_1111
+
+// Header placed before each heap block. Blocks are allocated at the
+// end of the heap, which grows as needed. Freed blocks are kept in
+// a list sorted by address, so that adjacent blocks can be merged.
+typedef struct
+{
+    // Size of the block in bytes, not counting the header
+    size_t size;
+
+    u32 magic;
+
+    // Next free block, only valid for free blocks
+    __malloc_block__* next;
+} __malloc_block__;
+
+// List of free blocks, sorted by address
+__malloc_block__* __malloc_free_list__ = NULL;
+
+// Returns NULL if there isn't enough memory left
 void* malloc(size_t size)
 {
-    u64 heap_size = asm () -> u64 { syscall vm_heap_size; };
+    // Too large to fit in the address space
+    if (size > 0x7FFF_FFFF_FFFF_FFFF)
+        return NULL;
 
-    u64 header_ptr = align_ptr(heap_size, 8);
-    u64 block_ptr = header_ptr + 8;
+    // Keep every block 8-byte aligned
+    size = align_ptr(size, 8);
+
+    // Look for the first free block which is large enough
+    __malloc_block__* prev = NULL;
+    for (__malloc_block__* block = __malloc_free_list__; block != NULL; block = block->next)
+    {
+        if (block->size >= size)
+        {
+            // Split the block if the rest can hold another block
+            if (block->size >= size + sizeof(__malloc_block__) + 8)
+            {
+                __malloc_block__* rest = (__malloc_block__*)((u8*)(block + 1) + size);
+                rest->size = block->size - size - sizeof(__malloc_block__);
+                rest->magic = __MALLOC_FREE__;
+                rest->next = block->next;
+                block->size = size;
+                block->next = rest;
+            }
+
+            if (prev == NULL)
+                __malloc_free_list__ = block->next;
+            else
+                prev->next = block->next;
+
+            block->magic = __MALLOC_USED__;
+            return (void*)(block + 1);
+        }
+
+        prev = block;
+    }
 
-    // Resize the heap
-    u64 new_heap_size = block_ptr + size;
-    asm (new_heap_size) -> void { syscall vm_resize_heap; };
+    // Grow the heap to make room for a new block
+    u64 heap_size = asm () -> u64 { syscall vm_heap_size; };
+    u64 header_ptr = align_ptr(heap_size, 8);
+    u64 new_heap_size = header_ptr + sizeof(__malloc_block__) + size;
 
-    // Write a magic word at the beginning of the block for safety checks
-    u32* magic_ptr = (u32*)header_ptr;
-    *magic_ptr = 0x1337BAB3;
+    bool resized = asm (new_heap_size) -> bool { syscall vm_resize_heap; };
+    if (!resized)
+        return NULL;
 
-    return (void*)block_ptr;
+    __malloc_block__* block = (__malloc_block__*)header_ptr;
+    block->size = size;
+    block->magic = __MALLOC_USED__;
+    return (void*)(block + 1);
 }
 
 void free(void* ptr)
 {
-    // Verify and clear the magic word
-    // This will help detect double-free errors
-    u8* header_ptr = ((u8*)ptr) - 8;
-    u32* magic_ptr = (u32*)header_ptr;
+    if (ptr == NULL)
+        return;
+
+    __malloc_block__* block = ((__malloc_block__*)ptr) - 1;
+
+    if (block->magic == __MALLOC_FREE__)
+    {
+        asm ("double free detected in free()\n") -> void {
+            syscall print_str;
+            panic;
+        };
+    }
 
-    if (*magic_ptr != 0x1337BAB3)
+    if (block->magic != __MALLOC_USED__)
     {
         asm ("magic word does not match in free()\n") -> void {
             syscall print_str;
@@ -78,8 +144,39 @@ void free(void* ptr)
         };
     }
 
-    // Corrupt the magic word to detect double-free errors
-    *magic_ptr = 0x1111_1111;
+    block->magic = __MALLOC_FREE__;
+
+    // Find the free blocks before and after this one
+    __malloc_block__* prev = NULL;
+    __malloc_block__* next = __malloc_free_list__;
+    while (next != NULL && next < block)
+    {
+        prev = next;
+        next = next->next;
+    }
+
+    // Merge with the next block if they are adjacent
+    block->next = next;
+    if (next != NULL && (u8*)(block + 1) + block->size == (u8*)next)
+    {
+        block->size = block->size + sizeof(__malloc_block__) + next->size;
+        block->next = next->next;
+    }
+
+    // Merge with the previous block if they are adjacent
+    if (prev == NULL)
+    {
+        __malloc_free_list__ = block;
+    }
+    else if ((u8*)(prev + 1) + prev->size == (u8*)block)
+    {
+        prev->size = prev->size + sizeof(__malloc_block__) + block->size;
+        prev->next = block->next;
+    }
+    else
+    {
+        prev->next = block;
+    }
 }
 
 #endif
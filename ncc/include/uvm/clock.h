@@ -0,0 +1,15 @@
+#ifndef __CLOCK_H__
+#define __CLOCK_H__
+
+// Host functions provided by the standalone VM. Embedders
+// register them with register_clock_fns.
+
+// Monotonic time in milliseconds since the VM started
+extern u64 time_ms();
+
+// Pause the program for a number of milliseconds. If the sleep is
+// interrupted, e.g. by Ctrl-C in the standalone VM, the program
+// exits with code 130.
+extern void sleep_ms(u64 ms);
+
+#endif
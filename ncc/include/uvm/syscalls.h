@@ -6,7 +6,7 @@
 #define __UVM_SYSCALLS__
 
 // void memcpy(u8* dst, const u8* src, u64 num_bytes)
-// Copy a block of memory in the heap from a source address to a destination address.
+// Copy a block of memory in the heap from a source address to a destination address. The blocks may overlap.
 #define memcpy(__dst, __src, __num_bytes) asm (__dst, __src, __num_bytes) -> void { syscall memcpy; }
 
 // void memset(u8* dst, u8 value, u64 num_bytes)
@@ -25,6 +25,14 @@
 // Resize the heap to a new size given in bytes. This is similar to the `brk()` system call on POSIX systems. Returns `true` if successful, `false` otherwise.
 #define vm_resize_heap(__num_bytes) asm (__num_bytes) -> bool { syscall vm_resize_heap; }
 
+// void rand_seed(u64 seed)
+// Seed the pseudo-random number generator of the VM. Each VM has its own generator, and the same seed always produces the same sequence of numbers, on every platform.
+#define rand_seed(__seed) asm (__seed) -> void { syscall rand_seed; }
+
+// u64 rand_u64()
+// Produce the next pseudo-random 64-bit integer, using the SplitMix64 algorithm. The generator starts with seed 0 unless rand_seed is called. This is not suitable for cryptography.
+#define rand_u64() asm () -> u64 { syscall rand_u64; }
+
 // void print_i64(i64 val)
 // Print an i64 value to standard output.
 #define print_i64(__val) asm (__val) -> void { syscall print_i64; }
@@ -33,8 +41,12 @@
 // Print an f32 value to standard output.
 #define print_f32(__val) asm (__val) -> void { syscall print_f32; }
 
+// void print_u64_hex(u64 val)
+// Print an unsigned integer in lowercase hexadecimal, without a prefix, to standard output.
+#define print_u64_hex(__val) asm (__val) -> void { syscall print_u64_hex; }
+
 // void print_str(const char* str)
-// Print a string to standard output.
+// Print a null-terminated string to standard output. The string must be terminated within 1 MiB of its start.
 #define print_str(__str) asm (__str) -> void { syscall print_str; }
 
 // void print_endl()
@@ -45,6 +57,14 @@
 // Read an i64 value from standard input.
 #define read_i64() asm () -> i64 { syscall read_i64; }
 
+// i64 read_char()
+// Read one byte from standard input. Returns -1 at the end of the input.
+#define read_char() asm () -> i64 { syscall read_char; }
+
+// i64 read_line(u8* buf, u64 buf_size)
+// Read a line from standard input into a buffer of buf_size bytes, including the newline. The line is truncated to buf_size - 1 bytes and null-terminated, and the rest of a truncated line is left for the next read. Returns the number of bytes stored, not counting the null terminator, which is 0 at the end of the input. Returns -1 without reading anything if the buffer isn't inside the heap, or if buf_size is less than 2, since the buffer can't hold a byte and the null terminator.
+#define read_line(__buf, __buf_size) asm (__buf, __buf_size) -> i64 { syscall read_line; }
+
 // u64 time_current_ms()
 // Get the UNIX time stamp in milliseconds.
 #define time_current_ms() asm () -> u64 { syscall time_current_ms; }
@@ -61,6 +81,10 @@
 // Copy a frame of pixels to be displayed into the window. The frame must have the same width and height as the window. The pixel format is 32 bits per pixel in BGRA byte order, with 8 bits for each component and the B byte at the lowest address.
 #define window_draw_frame(__window_id, __pixel_data) asm (__window_id, __pixel_data) -> void { syscall window_draw_frame; }
 
+// bool window_poll_event(u32 window_id, u8* event)
+// Take the next pending event of the window, without waiting, and store it into a 16-byte event struct made of four 32-bit fields: the event kind (one of the WINDOW_EVENT_* constants), the key code for keyboard events or the button (0 for left, 1 for middle, 2 for right) for mouse button events, and the i32 x and y mouse coordinates for mouse events. Unused fields are 0. Returns `true` if an event was stored, `false` if there are no pending events.
+#define window_poll_event(__window_id, __event) asm (__window_id, __event) -> bool { syscall window_poll_event; }
+
 // void window_on_mousemove(u32 window_id, void* callback)
 // Register a callback for mouse movement.
 #define window_on_mousemove(__window_id, __callback) asm (__window_id, __callback) -> void { syscall window_on_mousemove; }
@@ -86,9 +110,49 @@
 #define window_on_textinput(__window_id, __callback) asm (__window_id, __callback) -> void { syscall window_on_textinput; }
 
 // u32 audio_open_output(u32 sample_rate, u16 num_channels, u16 format, void* callback)
-// Open an audio output device.
+// Open an audio output device. The callback is called with the number of channels and the number of samples per channel to generate, and returns a pointer to a buffer of interleaved samples. It is called from the event loop, ahead of when the samples are played, so the program must return to the event loop for sound to be produced.
 #define audio_open_output(__sample_rate, __num_channels, __format, __callback) asm (__sample_rate, __num_channels, __format, __callback) -> u32 { syscall audio_open_output; }
 
+// i64 file_open(u8* path, u64 mode)
+// Open a file with a null-terminated path, in mode FILE_MODE_READ, FILE_MODE_WRITE (creating or truncating the file) or FILE_MODE_APPEND. Returns a file descriptor, or -1 if the file can't be opened or filesystem access isn't allowed.
+#define file_open(__path, __mode) asm (__path, __mode) -> i64 { syscall file_open; }
+
+// i64 file_read(i64 fd, u8* buf, u64 num_bytes)
+// Read up to num_bytes bytes from a file into a buffer. Returns the number of bytes read, which is less than num_bytes only at the end of the file, or -1 on error.
+#define file_read(__fd, __buf, __num_bytes) asm (__fd, __buf, __num_bytes) -> i64 { syscall file_read; }
+
+// i64 file_write(i64 fd, u8* buf, u64 num_bytes)
+// Write num_bytes bytes from a buffer to a file. Returns num_bytes, or -1 on error.
+#define file_write(__fd, __buf, __num_bytes) asm (__fd, __buf, __num_bytes) -> i64 { syscall file_write; }
+
+// i64 file_close(i64 fd)
+// Close a file. Returns 0, or -1 if the file descriptor isn't open.
+#define file_close(__fd) asm (__fd) -> i64 { syscall file_close; }
+
+// i64 tcp_connect(const char* host, u16 port)
+// Open a TCP connection to a null-terminated host name or IP address and a port. Returns a socket descriptor, or -1 if the connection fails, times out or network access isn't allowed. Operations which can wait, such as connecting, accepting, reading and writing, time out after 5 seconds.
+#define tcp_connect(__host, __port) asm (__host, __port) -> i64 { syscall tcp_connect; }
+
+// i64 tcp_listen(u16 port)
+// Listen for TCP connections on a port. Only connections from the local machine are accepted, unless the embedder chooses another address to listen on, e.g. with the --net-listen-addr option of the VM. Returns a socket descriptor, or -1 if the port can't be bound or network access isn't allowed.
+#define tcp_listen(__port) asm (__port) -> i64 { syscall tcp_listen; }
+
+// i64 tcp_accept(i64 fd)
+// Wait for a connection on a listening socket. Returns a socket descriptor for the new connection, or -1 on error or if no connection arrives within 5 seconds.
+#define tcp_accept(__fd) asm (__fd) -> i64 { syscall tcp_accept; }
+
+// i64 tcp_read(i64 fd, u8* buf, u64 num_bytes)
+// Read up to num_bytes bytes from a connection into a buffer, waiting until some are available. Returns the number of bytes read, 0 if the connection was closed by the other end, or -1 on error or if nothing arrives within 5 seconds.
+#define tcp_read(__fd, __buf, __num_bytes) asm (__fd, __buf, __num_bytes) -> i64 { syscall tcp_read; }
+
+// i64 tcp_write(i64 fd, const u8* buf, u64 num_bytes)
+// Write num_bytes bytes from a buffer to a connection. Returns num_bytes, or -1 on error or if the bytes can't be sent within 5 seconds.
+#define tcp_write(__fd, __buf, __num_bytes) asm (__fd, __buf, __num_bytes) -> i64 { syscall tcp_write; }
+
+// i64 tcp_close(i64 fd)
+// Close a socket. Returns 0, or -1 if the socket descriptor isn't open.
+#define tcp_close(__fd) asm (__fd) -> i64 { syscall tcp_close; }
+
 #define KEY_BACKSPACE 8
 #define KEY_TAB 9
 #define KEY_RETURN 10
@@ -142,6 +206,15 @@
 #define KEY_UP 16003
 #define KEY_DOWN 16004
 #define KEY_SHIFT 16005
+#define WINDOW_EVENT_CLOSE 1
+#define WINDOW_EVENT_KEYDOWN 2
+#define WINDOW_EVENT_KEYUP 3
+#define WINDOW_EVENT_MOUSEMOVE 4
+#define WINDOW_EVENT_MOUSEDOWN 5
+#define WINDOW_EVENT_MOUSEUP 6
 #define AUDIO_FORMAT_I16 0
+#define FILE_MODE_READ 0
+#define FILE_MODE_WRITE 1
+#define FILE_MODE_APPEND 2
 
 #endif
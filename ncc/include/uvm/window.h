@@ -0,0 +1,20 @@
+#ifndef __UVM_WINDOW__
+#define __UVM_WINDOW__
+
+// Event struct filled by window_poll_event. The kind is one of the
+// WINDOW_EVENT_* constants from uvm/syscalls.h, and fields which
+// don't apply to an event are 0.
+typedef struct
+{
+    u32 kind;
+
+    // Key code for keyboard events, or button for mouse button
+    // events: 0 for left, 1 for middle, 2 for right
+    u32 code;
+
+    // Mouse position for mouse events
+    i32 x;
+    i32 y;
+} window_event;
+
+#endif
@@ -34,56 +34,49 @@ pub enum Type
 
     // Reference to a typedef
     // This is used to handle cyclic types
-    Ref(Rc<Box<RefCell<Type>>>),
+    Ref(Rc<RefCell<Type>>),
 }
 
-impl Type
+/// Structural equality. References to typedefs are equal only if they
+/// point to the same definition, so that comparing cyclic types ends.
+impl PartialEq for Type
 {
-    pub fn eq(&self, other: &Type) -> bool
+    fn eq(&self, other: &Type) -> bool
     {
         use Type::*;
         match (self, other) {
             (Void, Void) => true,
-            (UInt(m), UInt(n)) if m == n => true,
-            (Int(m), Int(n)) if m == n => true,
-            (Float(m), Float(n)) if m == n => true,
-            (Pointer(ta), Pointer(tb)) => ta.eq(tb),
-
-            (Array { elem_type: elem_ta, size_expr: size_a }, Array { elem_type: elem_tb, size_expr: size_b })  => {
-                if !elem_ta.eq(elem_tb) {
-                    false
-                } else {
-                    match (size_a.as_ref(), size_b.as_ref()) {
-                        (Expr::Int(a), Expr::Int(b)) => a == b,
-                        _ => panic!()
-                    }
+            (UInt(m), UInt(n)) | (Int(m), Int(n)) | (Float(m), Float(n)) => m == n,
+            (Pointer(a), Pointer(b)) => a == b,
+
+            // Evaluated sizes are equal regardless of the literal suffix
+            (Array { elem_type: elem_a, size_expr: size_a }, Array { elem_type: elem_b, size_expr: size_b }) => {
+                elem_a == elem_b && match (&size_a.kind, &size_b.kind) {
+                    (ExprKind::Int { value: a, .. }, ExprKind::Int { value: b, .. }) => a == b,
+                    _ => size_a == size_b
                 }
             }
 
-            (Struct { fields: f_a }, Struct { fields: f_b }) => {
-                if f_a.len() != f_b.len() {
-                    return false;
-                }
-
-                for (idx, (na, ta)) in f_a.iter().enumerate() {
-                    let (nb, tb) = &f_b[idx];
-
-                    if na != nb {
-                        return false;
-                    }
+            (Fun { ret_type: ret_a, param_types: params_a, var_arg: var_a },
+             Fun { ret_type: ret_b, param_types: params_b, var_arg: var_b }) => {
+                ret_a == ret_b && params_a == params_b && var_a == var_b
+            }
 
-                    if !ta.eq(tb) {
-                        return false;
-                    }
-                }
+            (Struct { fields: a }, Struct { fields: b }) => a == b,
+            (Named(a), Named(b)) => a == b,
+            (Ref(a), Ref(b)) => Rc::ptr_eq(a, b),
 
-                true
-            }
+            // A recursive typedef referenced from inside itself is
+            // the same type as its definition used elsewhere
+            (Ref(a), t) | (t, Ref(a)) => *(**a).borrow() == *t,
 
             _ => false
         }
     }
+}
 
+impl Type
+{
     /// Produce the size of this type in bits
     /// Valid for pointer/integer/float types only
     pub fn num_bits(&self) -> usize
@@ -96,21 +89,21 @@ impl Type
         }
     }
 
-    /// Produce the size of this type in bytes
-    pub fn sizeof(&self) -> usize
+    /// Size of this type in bytes, or None for incomplete types such
+    /// as void, unresolved names and arrays of unknown size
+    pub fn size_of(&self) -> Option<usize>
     {
         use Type::*;
         match self {
-            Void => panic!(),
-            UInt(num_bits) | Int(num_bits) | Float(num_bits) => num_bits / 8,
-            Pointer(_) => 8,
+            UInt(num_bits) | Int(num_bits) | Float(num_bits) => Some(num_bits / 8),
+            Pointer(_) => Some(8),
 
             Array { elem_type, size_expr } => {
-                match size_expr.as_ref() {
-                    Expr::Int(num_elems) => {
-                        usize::try_from(*num_elems).unwrap() * elem_type.sizeof()
+                match &size_expr.kind {
+                    ExprKind::Int { value: num_elems, .. } => {
+                        usize::try_from(*num_elems).ok()?.checked_mul(elem_type.size_of()?)
                     }
-                    _ => panic!()
+                    _ => None
                 }
             }
 
@@ -118,18 +111,33 @@ impl Type
                 let mut num_bytes: usize = 0;
 
                 for (_, t) in fields {
+                    let field_size = t.size_of()?;
+
                     // Align the field
-                    let field_align = t.align_bytes();
+                    let field_align = t.align_bytes().max(1);
                     num_bytes = (num_bytes + (field_align - 1)) & !(field_align - 1);
 
                     // Add the field size
-                    num_bytes += t.sizeof();
+                    num_bytes += field_size;
                 }
 
-                num_bytes
+                Some(num_bytes)
             }
 
-            _ => panic!("sizeof {:?}", self)
+            // Typedefs can't contain themselves by value,
+            // so this doesn't recurse forever
+            Ref(t) => t.borrow().size_of(),
+
+            Void | Fun { .. } | Named(_) => None,
+        }
+    }
+
+    /// Produce the size of this type in bytes
+    pub fn sizeof(&self) -> usize
+    {
+        match self.size_of() {
+            Some(num_bytes) => num_bytes,
+            None => panic!("sizeof {:?}", self)
         }
     }
 
@@ -157,6 +165,7 @@ impl Type
 
                 None
             }
+            Type::Ref(t) => t.borrow().get_field(name),
             _ => panic!()
         }
     }
@@ -169,6 +178,7 @@ impl Type
             UInt(num_bits) | Int(num_bits) | Float(num_bits) => num_bits / 8,
             Pointer(_) => 8,
             Array { elem_type, .. } => elem_type.align_bytes(),
+            Ref(t) => t.borrow().align_bytes(),
 
             Struct { fields } => {
                 let mut max_align = 0;
@@ -191,6 +201,46 @@ impl Type
         }
     }
 
+    /// Check if this is a fixed-width integer type
+    pub fn is_integer(&self) -> bool
+    {
+        matches!(self, Type::UInt(_) | Type::Int(_))
+    }
+
+    /// Check if this is an integer or floating-point type
+    pub fn is_numeric(&self) -> bool
+    {
+        self.is_integer() || matches!(self, Type::Float(_))
+    }
+
+    pub fn is_pointer(&self) -> bool
+    {
+        matches!(self, Type::Pointer(_))
+    }
+
+    pub fn is_void(&self) -> bool
+    {
+        matches!(self, Type::Void)
+    }
+
+    /// Get the type pointed to, if this is a pointer type
+    pub fn pointee(&self) -> Option<&Type>
+    {
+        match self {
+            Type::Pointer(t) => Some(t),
+            _ => None
+        }
+    }
+
+    /// Get the underlying type after stripping all levels of pointers
+    pub fn base_type(&self) -> &Type
+    {
+        match self {
+            Type::Pointer(t) => t.base_type(),
+            _ => self
+        }
+    }
+
     pub fn is_signed(&self) -> bool
     {
         use Type::*;
@@ -211,24 +261,69 @@ impl fmt::Display for Type {
             Void => write!(f, "void"),
             UInt(n) => write!(f, "u{}", n),
             Int(n) => write!(f, "i{}", n),
+            Float(32) => write!(f, "float"),
             Float(n) => write!(f, "f{}", n),
             Pointer(t) => write!(f, "{}*", t.as_ref()),
-            Array { elem_type, size_expr } => write!(f, "{}[]", elem_type.as_ref()),
-            Struct { .. } => write!(f, "struct"),
-            _ => todo!()
+            Named(name) => write!(f, "{}", name),
+
+            // Cycles through typedefs can only go through structs,
+            // so the fields of referenced structs aren't written
+            Ref(t) => match &*t.borrow() {
+                Struct { .. } => write!(f, "struct {{ ... }}"),
+                t => write!(f, "{}", t),
+            },
+
+            // The sizes of nested arrays are written outermost first,
+            // as in the declaration u8 a[2][3]
+            Array { .. } => {
+                let mut t = self;
+                let mut sizes = String::new();
+
+                while let Array { elem_type, size_expr } = t {
+                    match size_expr.kind {
+                        ExprKind::Int { value, .. } => sizes += &format!("[{}]", value),
+                        _ => sizes += "[]",
+                    }
+                    t = elem_type;
+                }
+
+                write!(f, "{}{}", t, sizes)
+            }
+
+            Fun { ret_type, param_types, var_arg } => {
+                write!(f, "{}(", ret_type)?;
+                for (idx, t) in param_types.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", t)?;
+                }
+                if *var_arg {
+                    write!(f, ", ...")?;
+                }
+                write!(f, ")")
+            }
+
+            Struct { fields } => {
+                write!(f, "struct {{")?;
+                for (name, t) in fields {
+                    write!(f, " {} {};", t, name)?;
+                }
+                write!(f, " }}")
+            }
         }
     }
 }
 
 /// Variable/function Declaration
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Decl
 {
     Global { name: String, t: Type },
     Arg { idx: usize, t: Type },
     Local { idx: usize, t: Type },
-    Fun { name: String, t: Type },
-    TypeDef { name: String, t: Rc<Box<RefCell<Type>>> },
+    Fun { name: String, t: Type, is_extern: bool },
+    TypeDef { name: String, t: Rc<RefCell<Type>> },
 }
 
 impl Decl
@@ -239,7 +334,7 @@ impl Decl
             Decl::Global { name, t } => t.clone(),
             Decl::Arg { idx, t } => t.clone(),
             Decl::Local { idx, t } => t.clone(),
-            Decl::Fun { name, t } => t.clone(),
+            Decl::Fun { name, t, .. } => t.clone(),
             Decl::TypeDef { name, t } => t.borrow().clone(),
         }
     }
@@ -296,11 +391,303 @@ pub enum BinOp
     Comma,
 }
 
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use UnOp::*;
+        let op_str = match self {
+            Minus => "-",
+            Not => "!",
+            BitNot => "~",
+            Deref => "*",
+            AddressOf => "&",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BinOp::*;
+        let op_str = match self {
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "^",
+            LShift => "<<",
+            RShift => ">>",
+            Add => "+",
+            Sub => "-",
+            Mul => "*",
+            Div => "/",
+            Mod => "%",
+            Eq => "==",
+            Ne => "!=",
+            Lt => "<",
+            Le => "<=",
+            Gt => ">",
+            Ge => ">=",
+            And => "&&",
+            Or => "||",
+            Assign => "=",
+            Comma => ",",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
+/// Type suffix of an integer literal, e.g. 42u64 or 42ULL
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IntSuffix
+{
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl IntSuffix
+{
+    /// Parse a suffix, either a sized type name like u32 or a
+    /// C-style suffix like UL. C-style suffixes are case-insensitive
+    /// and map to the sizes of unsigned int, long and unsigned long.
+    pub fn from_str(suffix: &str) -> Option<IntSuffix>
+    {
+        use IntSuffix::*;
+        match suffix {
+            "u8" => Some(U8),
+            "u16" => Some(U16),
+            "u32" => Some(U32),
+            "u64" => Some(U64),
+            "i8" => Some(I8),
+            "i16" => Some(I16),
+            "i32" => Some(I32),
+            "i64" => Some(I64),
+            _ => match suffix.to_ascii_lowercase().as_str() {
+                "u" => Some(U32),
+                "l" | "ll" => Some(I64),
+                "ul" | "lu" | "ull" | "llu" => Some(U64),
+                _ => None
+            }
+        }
+    }
+
+    /// Type of literals with this suffix
+    pub fn get_type(&self) -> Type
+    {
+        use IntSuffix::*;
+        match self {
+            U8 => Type::UInt(8),
+            U16 => Type::UInt(16),
+            U32 => Type::UInt(32),
+            U64 => Type::UInt(64),
+            I8 => Type::Int(8),
+            I16 => Type::Int(16),
+            I32 => Type::Int(32),
+            I64 => Type::Int(64),
+        }
+    }
+}
+
+impl fmt::Display for IntSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_type())
+    }
+}
+
+/// Location of an AST node in the source code
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Span
+{
+    /// Name of the source file
+    pub src_name: Rc<String>,
+
+    /// Line and column where the node begins
+    pub line_no: u32,
+    pub col_no: u32,
+
+    /// Byte offsets of the start and end of the node,
+    /// the end being exclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span
+{
+    /// Span from the start of this one to the end of another
+    pub fn to(&self, other: &Span) -> Span
+    {
+        Span { end: other.end, ..self.clone() }
+    }
+}
+
+impl fmt::Display for Span
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.src_name, self.line_no, self.col_no)
+    }
+}
+
 /// Expression
 #[derive(Clone, Debug)]
-pub enum Expr
+pub struct Expr
+{
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// Expressions compare structurally, ignoring where they came from
+impl PartialEq for Expr
+{
+    fn eq(&self, other: &Expr) -> bool
+    {
+        self.kind == other.kind
+    }
+}
+
+impl Expr
+{
+    pub fn new(kind: ExprKind, span: Span) -> Self
+    {
+        Expr { kind, span }
+    }
+
+    /// Check if this expression designates a storage location that can
+    /// be assigned to, i.e. a variable, dereference or struct field
+    pub fn is_lvalue(&self) -> bool
+    {
+        match &self.kind {
+            ExprKind::Ident(_) => true,
+            ExprKind::Ref(Decl::Arg { t, .. }) |
+            ExprKind::Ref(Decl::Local { t, .. }) |
+            ExprKind::Ref(Decl::Global { t, .. }) => !matches!(t, Type::Array { .. }),
+            ExprKind::Unary { op: UnOp::Deref, .. } => true,
+            ExprKind::Arrow { .. } => true,
+            _ => false
+        }
+    }
+}
+
+/// Write a comma-separated list of expressions
+fn write_expr_list(f: &mut fmt::Formatter, exprs: &[Expr]) -> fmt::Result
+{
+    for (idx, expr) in exprs.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", expr)?;
+    }
+    Ok(())
+}
+
+/// Reconstruct the expression as source text. Operations are fully
+/// parenthesized so that the grouping is explicit, and the output
+/// parses back into the same expression.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            // Negative literals are parenthesized so that
+            // a preceding minus doesn't turn into --
+            ExprKind::Int { value, suffix } => {
+                if *value < 0 {
+                    write!(f, "(")?;
+                }
+                write!(f, "{}", value)?;
+                if let Some(suffix) = suffix {
+                    write!(f, "{}", suffix)?;
+                }
+                if *value < 0 {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+
+            ExprKind::Float32(val) => {
+                // There must be a decimal point for this to parse as a float
+                let mut val_str = format!("{}", val.abs());
+                if !val_str.contains('.') {
+                    val_str += ".0";
+                }
+
+                if *val < 0.0 {
+                    write!(f, "(-{}f)", val_str)
+                } else {
+                    write!(f, "{}f", val_str)
+                }
+            }
+
+            ExprKind::String(str_val) => {
+                write!(f, "\"")?;
+                for ch in str_val.chars() {
+                    match ch {
+                        '\\' => write!(f, "\\\\")?,
+                        '\"' => write!(f, "\\\"")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\0' => write!(f, "\\0")?,
+                        ch if (ch as u32) < 0x20 || ch as u32 == 0x7F => write!(f, "\\x{:02X}", ch as u32)?,
+                        ch => write!(f, "{}", ch)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+
+            ExprKind::Array(exprs) => {
+                write!(f, "{{")?;
+                write_expr_list(f, exprs)?;
+                write!(f, "}}")
+            }
+
+            ExprKind::Ident(name) => write!(f, "{}", name),
+
+            ExprKind::Ref(decl) => match decl {
+                Decl::Global { name, .. } | Decl::Fun { name, .. } | Decl::TypeDef { name, .. } => {
+                    write!(f, "{}", name)
+                }
+                Decl::Arg { idx, .. } => write!(f, "arg_{}", idx),
+                Decl::Local { idx, .. } => write!(f, "local_{}", idx),
+            },
+
+            ExprKind::Cast { new_type, child } => write!(f, "(({}){})", new_type, child),
+            ExprKind::SizeofExpr { child } => write!(f, "sizeof({})", child),
+            ExprKind::SizeofType { t } => write!(f, "sizeof({})", t),
+            ExprKind::Arrow { base, field } => write!(f, "{}->{}", base, field),
+            ExprKind::Unary { op, child } => write!(f, "({}{})", op, child),
+
+            ExprKind::Binary { op: BinOp::Comma, lhs, rhs } => write!(f, "({}, {})", lhs, rhs),
+            ExprKind::Binary { op, lhs, rhs } => write!(f, "({} {} {})", lhs, op, rhs),
+
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                write!(f, "({} ? {} : {})", test_expr, then_expr, else_expr)
+            }
+
+            ExprKind::Call { callee, args } => {
+                write!(f, "{}(", callee)?;
+                write_expr_list(f, args)?;
+                write!(f, ")")
+            }
+
+            ExprKind::Asm { text, args, out_type } => {
+                write!(f, "asm (")?;
+                write_expr_list(f, args)?;
+                write!(f, ") -> {} {{ {} }}", out_type, text)
+            }
+        }
+    }
+}
+
+/// Kind of expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind
 {
-    Int(i128),
+    Int {
+        value: i128,
+        suffix: Option<IntSuffix>,
+    },
+
     String(String),
     Float32(f32),
 
@@ -364,7 +751,32 @@ pub enum Expr
 
 /// Statement
 #[derive(Clone, Debug)]
-pub enum Stmt
+pub struct Stmt
+{
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+/// Statements compare structurally, ignoring where they came from
+impl PartialEq for Stmt
+{
+    fn eq(&self, other: &Stmt) -> bool
+    {
+        self.kind == other.kind
+    }
+}
+
+impl Stmt
+{
+    pub fn new(kind: StmtKind, span: Span) -> Self
+    {
+        Stmt { kind, span }
+    }
+}
+
+/// Kind of statement
+#[derive(Clone, Debug)]
+pub enum StmtKind
 {
     Expr(Expr),
 
@@ -376,6 +788,16 @@ pub enum Stmt
 
     Block(Vec<Stmt>),
 
+    /// Panic with a message if the test expression is false
+    Assert {
+        test_expr: Expr,
+        msg: Option<String>,
+
+        /// Text of the test expression, captured before symbol
+        /// resolution, for the failure message
+        expr_text: String,
+    },
+
     If {
         test_expr: Expr,
         then_stmt: Box<Stmt>,
@@ -404,6 +826,275 @@ pub enum Stmt
         var_type: Type,
         var_name: String,
         init_expr: Option<Expr>,
+
+        /// Location of the type annotation
+        type_span: Span,
+    }
+}
+
+/// Statements compare structurally, ignoring the type annotation
+/// location of variable declarations
+impl PartialEq for StmtKind
+{
+    fn eq(&self, other: &StmtKind) -> bool
+    {
+        use StmtKind::*;
+        match (self, other) {
+            (Expr(a), Expr(b)) => a == b,
+            (ReturnExpr(a), ReturnExpr(b)) => a == b,
+            (ReturnVoid, ReturnVoid) => true,
+            (Break, Break) => true,
+            (Continue, Continue) => true,
+            (Block(a), Block(b)) => a == b,
+
+            (
+                Assert { test_expr: ta, msg: ma, expr_text: xa },
+                Assert { test_expr: tb, msg: mb, expr_text: xb },
+            ) => ta == tb && ma == mb && xa == xb,
+
+            (
+                If { test_expr: ta, then_stmt: sa, else_stmt: ea },
+                If { test_expr: tb, then_stmt: sb, else_stmt: eb },
+            ) => ta == tb && sa == sb && ea == eb,
+
+            (
+                While { test_expr: ta, body_stmt: ba },
+                While { test_expr: tb, body_stmt: bb },
+            ) => ta == tb && ba == bb,
+
+            (
+                DoWhile { body_stmt: ba, test_expr: ta },
+                DoWhile { body_stmt: bb, test_expr: tb },
+            ) => ba == bb && ta == tb,
+
+            (
+                For { init_stmt: ia, test_expr: ta, incr_expr: xa, body_stmt: ba },
+                For { init_stmt: ib, test_expr: tb, incr_expr: xb, body_stmt: bb },
+            ) => ia == ib && ta == tb && xa == xb && ba == bb,
+
+            (
+                VarDecl { var_type: ta, var_name: na, init_expr: ea, .. },
+                VarDecl { var_type: tb, var_name: nb, init_expr: eb, .. },
+            ) => ta == tb && na == nb && ea == eb,
+
+            _ => false,
+        }
+    }
+}
+
+/// Write a declaration with C syntax, where array sizes follow
+/// the name, as in u8 a[2][3], and function pointers wrap it,
+/// as in u64 (*f)(u64)
+fn write_decl(f: &mut fmt::Formatter, t: &Type, name: &str) -> fmt::Result
+{
+    match t {
+        Type::Array { .. } => {
+            let mut elem_type = t;
+            while let Type::Array { elem_type: t, .. } = elem_type {
+                elem_type = t;
+            }
+
+            let type_str = t.to_string();
+            let sizes = &type_str[elem_type.to_string().len()..];
+            write_decl(f, elem_type, &format!("{}{}", name, sizes))
+        }
+
+        Type::Pointer(fun_type) if matches!(**fun_type, Type::Fun { .. }) => {
+            let Type::Fun { ret_type, param_types, var_arg } = &**fun_type else { unreachable!() };
+            write!(f, "{} (*{})(", ret_type, name)?;
+            for (idx, t) in param_types.iter().enumerate() {
+                if idx > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", t)?;
+            }
+            if *var_arg {
+                write!(f, ", ...")?;
+            }
+            write!(f, ")")
+        }
+
+        // Fields are declarations themselves
+        Type::Struct { fields } => {
+            write!(f, "struct {{")?;
+            for (field_name, t) in fields {
+                write!(f, " ")?;
+                write_decl(f, t, field_name)?;
+                write!(f, ";")?;
+            }
+            write!(f, " }} {}", name)
+        }
+
+        _ => write!(f, "{} {}", t, name)
+    }
+}
+
+/// Write a statement indented by the given depth, with
+/// nested statements indented one level further
+fn write_stmt(f: &mut fmt::Formatter, stmt: &Stmt, depth: usize) -> fmt::Result
+{
+    let indent = "    ".repeat(depth);
+
+    // Write a sub-statement on the same line if it's a block,
+    // or on its own indented line otherwise
+    let write_body = |f: &mut fmt::Formatter, body: &Stmt| -> fmt::Result {
+        if let StmtKind::Block(_) = body.kind {
+            write!(f, " ")?;
+            write_stmt(f, body, depth)
+        } else {
+            write!(f, "\n{}    ", indent)?;
+            write_stmt(f, body, depth + 1)
+        }
+    };
+
+    match &stmt.kind {
+        StmtKind::Expr(expr) => write!(f, "{};", expr),
+        StmtKind::ReturnExpr(expr) => write!(f, "return {};", expr),
+        StmtKind::ReturnVoid => write!(f, "return;"),
+        StmtKind::Break => write!(f, "break;"),
+        StmtKind::Continue => write!(f, "continue;"),
+
+        StmtKind::Assert { test_expr, msg: None, .. } => write!(f, "assert({});", test_expr),
+        StmtKind::Assert { test_expr, msg: Some(msg), .. } => {
+            write!(f, "assert({}, \"{}\");", test_expr, msg.escape_default())
+        }
+
+        StmtKind::Block(stmts) => {
+            writeln!(f, "{{")?;
+            for stmt in stmts {
+                write!(f, "{}    ", indent)?;
+                write_stmt(f, stmt, depth + 1)?;
+                writeln!(f)?;
+            }
+            write!(f, "{}}}", indent)
+        }
+
+        StmtKind::If { test_expr, then_stmt, else_stmt } => {
+            write!(f, "if ({})", test_expr)?;
+            write_body(f, then_stmt)?;
+
+            if let Some(else_stmt) = else_stmt {
+                if let StmtKind::Block(_) = then_stmt.kind {
+                    write!(f, " else")?;
+                } else {
+                    write!(f, "\n{}else", indent)?;
+                }
+
+                // Keep else if chains at the same depth
+                if let StmtKind::If { .. } = else_stmt.kind {
+                    write!(f, " ")?;
+                    write_stmt(f, else_stmt, depth)?;
+                } else {
+                    write_body(f, else_stmt)?;
+                }
+            }
+
+            Ok(())
+        }
+
+        StmtKind::While { test_expr, body_stmt } => {
+            write!(f, "while ({})", test_expr)?;
+            write_body(f, body_stmt)
+        }
+
+        StmtKind::DoWhile { body_stmt, test_expr } => {
+            write!(f, "do")?;
+            write_body(f, body_stmt)?;
+            if let StmtKind::Block(_) = body_stmt.kind {
+                write!(f, " while ({});", test_expr)
+            } else {
+                write!(f, "\n{}while ({});", indent, test_expr)
+            }
+        }
+
+        StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+            write!(f, "for (")?;
+            match init_stmt {
+                Some(init_stmt) => write_stmt(f, init_stmt, depth)?,
+                None => write!(f, ";")?,
+            }
+            write!(f, " {}; {})", test_expr, incr_expr)?;
+            write_body(f, body_stmt)
+        }
+
+        StmtKind::VarDecl { var_type, var_name, init_expr, .. } => {
+            write_decl(f, var_type, var_name)?;
+            if let Some(init_expr) = init_expr {
+                write!(f, " = {}", init_expr)?;
+            }
+            write!(f, ";")
+        }
+    }
+}
+
+/// Reconstruct the statement as source text, indented by four
+/// spaces per nesting level
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_stmt(f, self, 0)
+    }
+}
+
+impl Stmt
+{
+    /// Check if this statement contains a return statement
+    pub fn contains_return(&self) -> bool
+    {
+        match &self.kind {
+            StmtKind::ReturnExpr(_) | StmtKind::ReturnVoid => true,
+            StmtKind::Block(stmts) => stmts.iter().any(|s| s.contains_return()),
+            StmtKind::If { then_stmt, else_stmt, .. } => {
+                then_stmt.contains_return() ||
+                else_stmt.as_ref().map_or(false, |s| s.contains_return())
+            }
+            StmtKind::While { body_stmt, .. } => body_stmt.contains_return(),
+            StmtKind::DoWhile { body_stmt, .. } => body_stmt.contains_return(),
+            StmtKind::For { body_stmt, .. } => body_stmt.contains_return(),
+            _ => false
+        }
+    }
+
+    /// Check if executing this statement always ends in a return
+    pub fn always_returns(&self) -> bool
+    {
+        match &self.kind {
+            StmtKind::ReturnExpr(_) | StmtKind::ReturnVoid => true,
+            StmtKind::Block(stmts) => stmts.iter().any(|s| s.always_returns()),
+            StmtKind::If { then_stmt, else_stmt: Some(else_stmt), .. } => {
+                then_stmt.always_returns() && else_stmt.always_returns()
+            }
+
+            // Continuing skips the rest of the body and evaluates
+            // the test, which may leave the loop
+            StmtKind::DoWhile { body_stmt, .. } => {
+                body_stmt.always_returns() && !body_stmt.contains_loop_exit(true)
+            }
+
+            // Infinite loops can only be left by returning or breaking
+            StmtKind::While { test_expr, body_stmt } |
+            StmtKind::For { test_expr, body_stmt, .. } => {
+                matches!(test_expr.kind, ExprKind::Int { value, .. } if value != 0) &&
+                !body_stmt.contains_loop_exit(false)
+            }
+
+            _ => false
+        }
+    }
+
+    /// Check if this statement contains a break out of the enclosing
+    /// loop, or also a continue if with_continue is set
+    fn contains_loop_exit(&self, with_continue: bool) -> bool
+    {
+        match &self.kind {
+            StmtKind::Break => true,
+            StmtKind::Continue => with_continue,
+            StmtKind::Block(stmts) => stmts.iter().any(|s| s.contains_loop_exit(with_continue)),
+            StmtKind::If { then_stmt, else_stmt, .. } => {
+                then_stmt.contains_loop_exit(with_continue) ||
+                else_stmt.as_ref().is_some_and(|s| s.contains_loop_exit(with_continue))
+            }
+            _ => false
+        }
     }
 }
 
@@ -417,9 +1108,15 @@ pub struct Function
     // Return type
     pub ret_type: Type,
 
+    /// Location of the return type annotation
+    pub ret_type_span: Span,
+
     /// Parameter list
     pub params: Vec<(Type, String)>,
 
+    /// Locations of the parameter type annotations
+    pub param_type_spans: Vec<Span>,
+
     /// Variadic function, variable argument count
     pub var_arg: bool,
 
@@ -429,8 +1126,27 @@ pub struct Function
     /// Body of the function
     pub body: Stmt,
 
-    /// Number of local variables
+    /// Number of local variable slots in the stack frame,
+    /// computed during symbol resolution
     pub num_locals: usize,
+
+    /// Location of the function declaration
+    pub span: Span,
+}
+
+/// Functions compare structurally, ignoring where they came from
+impl PartialEq for Function
+{
+    fn eq(&self, other: &Function) -> bool
+    {
+        self.name == other.name &&
+        self.ret_type == other.ret_type &&
+        self.params == other.params &&
+        self.var_arg == other.var_arg &&
+        self.inline == other.inline &&
+        self.body == other.body &&
+        self.num_locals == other.num_locals
+    }
 }
 
 impl Function
@@ -446,6 +1162,88 @@ impl Function
     }
 }
 
+/// Reconstruct the function definition as source text
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.inline {
+            write!(f, "inline ")?;
+        }
+        write!(f, "{} {}(", self.ret_type, self.name)?;
+        write_params(f, &self.params, self.var_arg)?;
+        write!(f, ") {}", self.body)
+    }
+}
+
+/// Write a parameter list, without the parentheses
+fn write_params(f: &mut fmt::Formatter, params: &[(Type, String)], var_arg: bool) -> fmt::Result
+{
+    for (idx, (t, name)) in params.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ", ")?;
+        }
+        write_decl(f, t, name)?;
+    }
+    if var_arg {
+        write!(f, ", ...")?;
+    }
+    Ok(())
+}
+
+/// Function prototype, a declaration without a body
+#[derive(Clone, Debug)]
+pub struct FunProto
+{
+    pub name: String,
+    pub ret_type: Type,
+    pub ret_type_span: Span,
+    pub params: Vec<(Type, String)>,
+    pub param_type_spans: Vec<Span>,
+    pub var_arg: bool,
+
+    /// Host function provided by the VM, called with call_host
+    pub is_extern: bool,
+
+    /// Location of the declaration
+    pub span: Span,
+}
+
+/// Prototypes compare structurally, ignoring where they came from
+impl PartialEq for FunProto
+{
+    fn eq(&self, other: &FunProto) -> bool
+    {
+        self.name == other.name &&
+        self.ret_type == other.ret_type &&
+        self.params == other.params &&
+        self.var_arg == other.var_arg &&
+        self.is_extern == other.is_extern
+    }
+}
+
+impl FunProto
+{
+    /// Get a type representing the function signature
+    pub fn get_type(&self) -> Type
+    {
+        Type::Fun {
+            ret_type: Box::new(self.ret_type.clone()),
+            param_types: self.params.iter().map(|p| p.0.clone()).collect(),
+            var_arg: self.var_arg,
+        }
+    }
+}
+
+impl fmt::Display for FunProto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_extern {
+            write!(f, "extern ")?;
+        }
+        write!(f, "{} {}(", self.ret_type, self.name)?;
+        write_params(f, &self.params, self.var_arg)?;
+        write!(f, ");")
+    }
+}
+
 /// Global variable declaration
 #[derive(Clone, Debug)]
 pub struct Global
@@ -456,17 +1254,356 @@ pub struct Global
     // Return type
     pub var_type: Type,
 
+    /// Location of the type annotation
+    pub type_span: Span,
+
     // Initialization expression
     pub init_expr: Option<Expr>,
+
+    /// Location of the variable declaration
+    pub span: Span,
+}
+
+/// Type definition
+#[derive(Clone, Debug)]
+pub struct Typedef
+{
+    pub name: String,
+    pub t: Rc<RefCell<Type>>,
+
+    /// Location of the typedef declaration
+    pub span: Span,
+
+    /// Location of the type annotation
+    pub type_span: Span,
+}
+
+/// Globals and typedefs compare structurally, ignoring where they came from
+impl PartialEq for Global
+{
+    fn eq(&self, other: &Global) -> bool
+    {
+        self.name == other.name &&
+        self.var_type == other.var_type &&
+        self.init_expr == other.init_expr
+    }
+}
+
+impl PartialEq for Typedef
+{
+    fn eq(&self, other: &Typedef) -> bool
+    {
+        self.name == other.name && self.t == other.t
+    }
 }
 
 /// Top-level unit (e.g. source file)
 #[derive(Default, Clone, Debug)]
 pub struct Unit
 {
-    pub typedefs: Vec<(String, Rc<Box<RefCell<Type>>>)>,
+    pub typedefs: Vec<Typedef>,
 
     pub global_vars: Vec<Global>,
 
     pub fun_decls: Vec<Function>,
+
+    /// Function prototypes, checked against the definitions
+    pub fun_protos: Vec<FunProto>,
+
+    /// Location of the whole unit
+    pub span: Span,
+}
+
+/// Units compare structurally, ignoring where they came from
+impl PartialEq for Unit
+{
+    fn eq(&self, other: &Unit) -> bool
+    {
+        self.typedefs == other.typedefs &&
+        self.global_vars == other.global_vars &&
+        self.fun_decls == other.fun_decls &&
+        self.fun_protos == other.fun_protos
+    }
+}
+
+impl Unit
+{
+    /// Find a function definition by name
+    pub fn find_function(&self, name: &str) -> Option<&Function>
+    {
+        self.fun_decls.iter().find(|f| f.name == name)
+    }
+
+    pub fn find_function_mut(&mut self, name: &str) -> Option<&mut Function>
+    {
+        self.fun_decls.iter_mut().find(|f| f.name == name)
+    }
+
+    /// Find a global variable by name
+    pub fn find_global(&self, name: &str) -> Option<&Global>
+    {
+        self.global_vars.iter().find(|g| g.name == name)
+    }
+}
+
+impl fmt::Display for Global {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_decl(f, &self.var_type, &self.name)?;
+        if let Some(init_expr) = &self.init_expr {
+            write!(f, " = {}", init_expr)?;
+        }
+        write!(f, ";")
+    }
+}
+
+/// Reconstruct the unit as source text. Type definitions come first
+/// so that their names parse as types, followed by the global
+/// variables, the prototypes and the function definitions.
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for typedef in &self.typedefs {
+            write!(f, "typedef ")?;
+            write_decl(f, &typedef.t.borrow(), &typedef.name)?;
+            writeln!(f, ";")?;
+        }
+
+        for global in &self.global_vars {
+            writeln!(f, "{}", global)?;
+        }
+
+        for proto in &self.fun_protos {
+            writeln!(f, "{}", proto)?;
+        }
+
+        for fun in &self.fun_decls {
+            writeln!(f, "\n{}", fun)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn array(elem_type: Type, size: i128) -> Type
+    {
+        Type::Array {
+            elem_type: Box::new(elem_type),
+            size_expr: Box::new(Expr::new(ExprKind::Int { value: size, suffix: None }, Span::default())),
+        }
+    }
+
+    #[test]
+    fn display_type()
+    {
+        use Type::*;
+
+        assert_eq!(format!("{}", Void), "void");
+        assert_eq!(format!("{}", UInt(64)), "u64");
+        assert_eq!(format!("{}", Int(8)), "i8");
+        assert_eq!(format!("{}", Float(32)), "float");
+        assert_eq!(format!("{}", Pointer(Box::new(UInt(8)))), "u8*");
+        assert_eq!(format!("{}", Pointer(Box::new(Pointer(Box::new(Void))))), "void**");
+        assert_eq!(format!("{}", Named("size_t".to_string())), "size_t");
+
+        // Arrays, including arrays of arrays and pointers
+        assert_eq!(format!("{}", array(UInt(8), 16)), "u8[16]");
+        assert_eq!(format!("{}", array(array(Int(32), 3), 2)), "i32[2][3]");
+        assert_eq!(format!("{}", array(Pointer(Box::new(UInt(8))), 4)), "u8*[4]");
+
+        // Functions and function pointers
+        let fun = Fun {
+            ret_type: Box::new(UInt(64)),
+            param_types: vec![UInt(64), Pointer(Box::new(UInt(8)))],
+            var_arg: false,
+        };
+        assert_eq!(format!("{}", fun), "u64(u64, u8*)");
+        assert_eq!(format!("{}", Pointer(Box::new(fun))), "u64(u64, u8*)*");
+        assert_eq!(format!("{}", Fun { ret_type: Box::new(Void), param_types: vec![], var_arg: false }), "void()");
+        assert_eq!(
+            format!("{}", Fun { ret_type: Box::new(Int(32)), param_types: vec![Pointer(Box::new(UInt(8)))], var_arg: true }),
+            "i32(u8*, ...)"
+        );
+
+        let s = Struct { fields: vec![("x".to_string(), UInt(64)), ("name".to_string(), array(UInt(8), 8))] };
+        assert_eq!(format!("{}", s), "struct { u64 x; u8[8] name; }");
+
+        // Self-referential struct through a typedef
+        let node = Rc::new(RefCell::new(Void));
+        *node.borrow_mut() = Struct { fields: vec![("next".to_string(), Pointer(Box::new(Ref(node.clone()))))] };
+        assert_eq!(format!("{}", node.borrow()), "struct { struct { ... }* next; }");
+        assert_eq!(format!("{}", Ref(Rc::new(RefCell::new(UInt(32))))), "u32");
+    }
+
+    #[test]
+    fn predicates()
+    {
+        use Type::*;
+
+        let fun = Fun { ret_type: Box::new(Void), param_types: vec![], var_arg: false };
+        let node = Ref(Rc::new(RefCell::new(Struct { fields: vec![] })));
+
+        // (type, integer, numeric, pointer, void)
+        let cases = [
+            (Void, false, false, false, true),
+            (UInt(8), true, true, false, false),
+            (UInt(64), true, true, false, false),
+            (Int(16), true, true, false, false),
+            (Int(32), true, true, false, false),
+            (Float(32), false, true, false, false),
+            (Pointer(Box::new(Void)), false, false, true, false),
+            (Pointer(Box::new(UInt(64))), false, false, true, false),
+            (array(UInt(8), 4), false, false, false, false),
+            (fun, false, false, false, false),
+            (Struct { fields: vec![] }, false, false, false, false),
+            (Named("size_t".to_string()), false, false, false, false),
+            (node, false, false, false, false),
+        ];
+
+        for (t, integer, numeric, pointer, void) in cases {
+            assert_eq!(t.is_integer(), integer, "{}", t);
+            assert_eq!(t.is_numeric(), numeric, "{}", t);
+            assert_eq!(t.is_pointer(), pointer, "{}", t);
+            assert_eq!(t.is_void(), void, "{}", t);
+        }
+    }
+
+    #[test]
+    fn is_lvalue()
+    {
+        let expr = |kind| Expr::new(kind, Span::default());
+        let int = |value| expr(ExprKind::Int { value, suffix: None });
+        let ident = |name: &str| expr(ExprKind::Ident(name.to_string()));
+
+        assert!(ident("x").is_lvalue());
+        assert!(expr(ExprKind::Unary { op: UnOp::Deref, child: Box::new(ident("p")) }).is_lvalue());
+        assert!(expr(ExprKind::Arrow { base: Box::new(ident("p")), field: "f".to_string() }).is_lvalue());
+        assert!(expr(ExprKind::Ref(Decl::Local { idx: 0, t: Type::UInt(64) })).is_lvalue());
+
+        assert!(!int(3).is_lvalue());
+        assert!(!expr(ExprKind::String("s".to_string())).is_lvalue());
+        assert!(!expr(ExprKind::Binary { op: BinOp::Add, lhs: Box::new(ident("x")), rhs: Box::new(int(1)) }).is_lvalue());
+        assert!(!expr(ExprKind::Call { callee: Box::new(ident("f")), args: vec![] }).is_lvalue());
+        assert!(!expr(ExprKind::Unary { op: UnOp::Minus, child: Box::new(ident("x")) }).is_lvalue());
+
+        // Arrays and functions can't be assigned to
+        assert!(!expr(ExprKind::Ref(Decl::Global { name: "a".to_string(), t: array(Type::UInt(8), 4) })).is_lvalue());
+        assert!(!expr(ExprKind::Ref(Decl::Fun { name: "f".to_string(), t: Type::Void, is_extern: false })).is_lvalue());
+    }
+
+    #[test]
+    fn find_decls()
+    {
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+
+        let mut input = Input::new("u64 g = 1; u8* p; void f() {} u64 main() { return 0; }", "src");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+
+        assert_eq!(unit.find_function("main").unwrap().name, "main");
+        assert_eq!(unit.find_function("f").unwrap().ret_type, Type::Void);
+        assert!(unit.find_function("g").is_none());
+        assert!(unit.find_function("foo").is_none());
+
+        assert_eq!(unit.find_global("p").unwrap().var_type, Type::Pointer(Box::new(Type::UInt(8))));
+        assert!(unit.find_global("g").unwrap().init_expr.is_some());
+        assert!(unit.find_global("main").is_none());
+
+        unit.find_function_mut("f").unwrap().inline = true;
+        assert!(unit.find_function("f").unwrap().inline);
+        assert!(unit.find_function_mut("bar").is_none());
+    }
+
+    #[test]
+    fn pointee()
+    {
+        use Type::*;
+
+        let ptr = |t| Pointer(Box::new(t));
+
+        assert_eq!(ptr(UInt(8)).pointee(), Some(&UInt(8)));
+        assert_eq!(ptr(ptr(Void)).pointee(), Some(&ptr(Void)));
+        assert_eq!(UInt(64).pointee(), None);
+        assert_eq!(array(UInt(8), 4).pointee(), None);
+
+        assert_eq!(ptr(ptr(Int(32))).base_type(), &Int(32));
+        assert_eq!(ptr(Void).base_type(), &Void);
+        assert_eq!(Float(32).base_type(), &Float(32));
+
+        // Arrays are not pointers
+        let arr_ptr = ptr(array(UInt(8), 4));
+        assert_eq!(arr_ptr.base_type(), &array(UInt(8), 4));
+    }
+
+    #[test]
+    fn size_of()
+    {
+        use Type::*;
+
+        assert_eq!(UInt(8).size_of(), Some(1));
+        assert_eq!(UInt(16).size_of(), Some(2));
+        assert_eq!(Int(32).size_of(), Some(4));
+        assert_eq!(UInt(64).size_of(), Some(8));
+        assert_eq!(Float(32).size_of(), Some(4));
+        assert_eq!(Pointer(Box::new(Void)).size_of(), Some(8));
+
+        // Arrays multiply the element size
+        assert_eq!(array(UInt(32), 10).size_of(), Some(40));
+        assert_eq!(array(array(UInt(8), 3), 2).size_of(), Some(6));
+        assert_eq!(array(UInt(64), 0).size_of(), Some(0));
+        assert_eq!(array(UInt(64), -1).size_of(), None);
+
+        // Fields are aligned to their size
+        let s = Struct { fields: vec![("a".to_string(), UInt(8)), ("b".to_string(), UInt(64)), ("c".to_string(), UInt(16))] };
+        assert_eq!(s.size_of(), Some(18));
+        assert_eq!(Struct { fields: vec![] }.size_of(), Some(0));
+
+        // Incomplete types
+        assert_eq!(Void.size_of(), None);
+        assert_eq!(Named("foo".to_string()).size_of(), None);
+        assert_eq!(Fun { ret_type: Box::new(Void), param_types: vec![], var_arg: false }.size_of(), None);
+        assert_eq!(array(Void, 4).size_of(), None);
+        assert_eq!(Struct { fields: vec![("x".to_string(), Named("foo".to_string()))] }.size_of(), None);
+        let unknown_size = Array {
+            elem_type: Box::new(UInt(8)),
+            size_expr: Box::new(Expr::new(ExprKind::Ident("N".to_string()), Span::default())),
+        };
+        assert_eq!(unknown_size.size_of(), None);
+
+        // Self-referential struct through a typedef
+        let node = Rc::new(RefCell::new(Void));
+        *node.borrow_mut() = Struct { fields: vec![("val".to_string(), UInt(32)), ("next".to_string(), Pointer(Box::new(Ref(node.clone()))))] };
+        assert_eq!(Ref(node.clone()).size_of(), Some(16));
+    }
+
+    #[test]
+    fn structural_eq()
+    {
+        use Type::*;
+        use crate::parser::parse_str;
+
+        let unit = parse_str("u64 foo() { return 1 + 2; }").unwrap();
+        assert_eq!(unit.fun_decls[0].name, "foo");
+
+        // Locations are not compared
+        let other = parse_str("u64 foo()\n{\n    return (1 + 2);\n}").unwrap();
+        assert_eq!(unit.fun_decls[0], other.fun_decls[0]);
+        assert_ne!(unit.fun_decls[0].body.span, other.fun_decls[0].body.span);
+        assert_ne!(unit.fun_decls[0].body, parse_str("u64 foo() { return 1 - 2; }").unwrap().fun_decls[0].body);
+        assert_ne!(unit, parse_str("u64 foo() { return 1 + 2; } u64 g;").unwrap());
+
+        assert_eq!(array(UInt(8), 4), array(UInt(8), 4));
+        assert_ne!(array(UInt(8), 4), array(UInt(8), 5));
+        assert_ne!(Pointer(Box::new(UInt(8))), Pointer(Box::new(Int(8))));
+
+        // Cyclic types compare references by identity
+        let node = Rc::new(RefCell::new(Void));
+        *node.borrow_mut() = Struct { fields: vec![("next".to_string(), Pointer(Box::new(Ref(node.clone()))))] };
+        let other = Rc::new(RefCell::new(node.borrow().clone()));
+        assert_eq!(*node.borrow(), *other.borrow());
+        assert_ne!(Ref(node.clone()), Ref(other.clone()));
+    }
 }
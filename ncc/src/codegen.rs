@@ -8,6 +8,9 @@ use Type::*;
 struct SymGen
 {
     next_id: usize,
+
+    // Emit the source positions of the code
+    debug_info: bool,
 }
 
 impl SymGen
@@ -18,6 +21,19 @@ impl SymGen
         self.next_id += 1;
         name
     }
+
+    /// Emit the source position of the code that follows
+    fn gen_loc(&self, span: &Span, out: &mut String)
+    {
+        if self.debug_info {
+            out.push_str(&format!(
+                ".loc \"{}\", {}, {};\n",
+                span.src_name.escape_default(),
+                span.line_no,
+                span.col_no
+            ));
+        }
+    }
 }
 
 // FIXME: ideally, all error checking should be done before we get to the
@@ -38,8 +54,8 @@ fn gen_array_init(array_type: &Type, init_expr: &Expr, out: &mut String) -> Resu
         _ => panic!()
     };
 
-    let elem_exprs = match init_expr {
-        Expr::Array(elem_exprs) => elem_exprs,
+    let elem_exprs = match &init_expr.kind {
+        ExprKind::Array(elem_exprs) => elem_exprs,
         _ => return ParseError::msg_only("invalid initializer for global array variable")
     };
 
@@ -47,8 +63,8 @@ fn gen_array_init(array_type: &Type, init_expr: &Expr, out: &mut String) -> Resu
         // Array of signed integers
         Type::Int(n) => {
             for expr in elem_exprs {
-                match expr {
-                    Expr::Int(v) => out.push_str(&format!(".i{} {};\n", n, v)),
+                match &expr.kind {
+                    ExprKind::Int { value: v, .. } => out.push_str(&format!(".i{} {};\n", n, v)),
                     _ => panic!()
                 }
             }
@@ -57,8 +73,8 @@ fn gen_array_init(array_type: &Type, init_expr: &Expr, out: &mut String) -> Resu
         // Array of unsigned integers
         Type::UInt(n) => {
             for expr in elem_exprs {
-                match expr {
-                    Expr::Int(v) => out.push_str(&format!(".u{} {};\n", n, v)),
+                match &expr.kind {
+                    ExprKind::Int { value: v, .. } => out.push_str(&format!(".u{} {};\n", n, v)),
                     _ => panic!()
                 }
             }
@@ -67,8 +83,8 @@ fn gen_array_init(array_type: &Type, init_expr: &Expr, out: &mut String) -> Resu
         // Array of floats
         Type::Float(32) => {
             for expr in elem_exprs {
-                match expr {
-                    Expr::Float32(v) => out.push_str(&format!(".f32 {};\n", v)),
+                match &expr.kind {
+                    ExprKind::Float32(v) => out.push_str(&format!(".f32 {};\n", v)),
                     _ => panic!()
                 }
             }
@@ -91,7 +107,14 @@ impl Unit
 {
     pub fn gen_code(&self) -> Result<String, ParseError>
     {
-        let mut sym = SymGen::default();
+        self.gen_code_with_debug(false)
+    }
+
+    /// Generate code, optionally with the source positions of
+    /// the code so that runtime errors can be located
+    pub fn gen_code_with_debug(&self, debug_info: bool) -> Result<String, ParseError>
+    {
+        let mut sym = SymGen { debug_info, ..SymGen::default() };
         let mut out: String = "".to_string();
 
         out.push_str("#\n");
@@ -119,36 +142,36 @@ impl Unit
             // Write a label
             out.push_str(&format!("{}:\n", global.name));
 
-            match (&global.var_type, &global.init_expr) {
+            match (&global.var_type, global.init_expr.as_ref().map(|e| &e.kind)) {
                 (_, None) => {
                     out.push_str(&format!(".zero {};\n", global.var_type.sizeof()));
                 }
 
-                (Type::UInt(n), Some(Expr::Int(v))) => {
+                (Type::UInt(n), Some(ExprKind::Int { value: v, .. })) => {
                     out.push_str(&format!(".u{} {};\n", n, v))
                 }
 
-                (Type::Int(n), Some(Expr::Int(v))) => {
+                (Type::Int(n), Some(ExprKind::Int { value: v, .. })) => {
                     out.push_str(&format!(".i{} {};\n", n, v))
                 }
 
-                (Type::Float(32), Some(Expr::Float32(v))) => {
+                (Type::Float(32), Some(ExprKind::Float32(v))) => {
                     out.push_str(&format!(".f32 {};\n", v))
                 }
 
-                (Type::Pointer(_), Some(Expr::Int(v))) => {
+                (Type::Pointer(_), Some(ExprKind::Int { value: v, .. })) => {
                     out.push_str(&format!(".u64 {};\n", v))
                 }
 
                 // Pointer to a global array
-                (Type::Pointer(_), Some(Expr::Ref(Decl::Global { name, t: Array { .. } } ))) => {
+                (Type::Pointer(_), Some(ExprKind::Ref(Decl::Global { name, t: Array { .. } } ))) => {
                     out.push_str(&format!(".addr64 {};\n", name))
                 }
 
                 // Global string constant
-                (Type::Array { elem_type, size_expr }, Some(Expr::String(s))) => {
-                    match (elem_type.as_ref(), size_expr.as_ref()) {
-                        (Type::UInt(8), Expr::Int(n)) => {
+                (Type::Array { elem_type, size_expr }, Some(ExprKind::String(s))) => {
+                    match (elem_type.as_ref(), &size_expr.kind) {
+                        (Type::UInt(8), ExprKind::Int { value: n, .. }) => {
                             assert!(*n as usize == s.bytes().len() + 1);
                             out.push_str(&format!(".stringz \"{}\";\n", s.escape_default()))
                         }
@@ -156,8 +179,8 @@ impl Unit
                     }
                 }
 
-                (Type::Array {..}, Some(init_expr)) => {
-                    gen_array_init(&global.var_type, &init_expr, &mut out)?;
+                (Type::Array {..}, Some(_)) => {
+                    gen_array_init(&global.var_type, global.init_expr.as_ref().unwrap(), &mut out)?;
                 }
 
                 _ => todo!("{:?} {:?}", global.var_type, global.init_expr)
@@ -172,14 +195,31 @@ impl Unit
         out.push_str("\n");
 
         // If there is a main function
-        let main_fn: Vec<&Function> = self.fun_decls.iter().filter(|f| f.name == "main").collect();
-        if let [main_fn] = main_fn[..] {
-            //
-            // TODO: support calling main with argc, argv as well
-            //
+        if let Some(main_fun) = self.find_function("main") {
+            let num_params = main_fun.params.len();
+            if num_params > 2 {
+                return ParseError::msg_only("main can only take argc and argv as parameters");
+            }
+
+            // The VM passes argc and argv to the entry point. When it
+            // is called without arguments, main gets zeros instead.
+            if num_params > 0 {
+                out.push_str("# pass argc and argv to main, if given\n");
+                out.push_str("get_argc;\n");
+                out.push_str("jz __main_no_args__;\n");
+                for idx in 0..num_params {
+                    out.push_str(&format!("get_arg {};\n", idx));
+                }
+                out.push_str("jmp __main_call__;\n");
+                out.push_str("__main_no_args__:\n");
+                for _ in 0..num_params {
+                    out.push_str("push 0;\n");
+                }
+                out.push_str("__main_call__:\n");
+            }
 
             out.push_str("# call the main function and then exit\n");
-            out.push_str("call main, 0;\n");
+            out.push_str(&format!("call main, {};\n", num_params));
             out.push_str("push __EVENT_LOOP_ENABLED__;\n");
             out.push_str("load_u8;\n");
             out.push_str("jnz __ret_to_event_loop__;\n");
@@ -209,15 +249,15 @@ impl Function
 {
     fn needs_final_return(&self) -> bool
     {
-        if let Stmt::Block(stmts) = &self.body {
+        if let StmtKind::Block(stmts) = &self.body.kind {
             if stmts.len() > 0 {
                 let last_stmt = &stmts[stmts.len() - 1];
 
-                if let Stmt::ReturnVoid = last_stmt {
+                if let StmtKind::ReturnVoid = last_stmt.kind {
                     return false;
                 }
 
-                if let Stmt::ReturnExpr(_) = last_stmt {
+                if let StmtKind::ReturnExpr(_) = last_stmt.kind {
                     return false;
                 }
             }
@@ -242,6 +282,7 @@ impl Function
 
         // Emit label for function
         out.push_str(&format!("{}:\n", self.name));
+        sym.gen_loc(&self.span, out);
 
         // Allocate stack slots for the local variables
         for i in 0..self.num_locals {
@@ -272,19 +313,23 @@ impl Stmt
         out: &mut String
     ) -> Result<(), ParseError>
     {
-        match self {
-            Stmt::Expr(expr) => {
+        if !matches!(self.kind, StmtKind::Block(_)) {
+            sym.gen_loc(&self.span, out);
+        }
+
+        match &self.kind {
+            StmtKind::Expr(expr) => {
 
-                match expr {
+                match &expr.kind {
                     // For assignment expressions as statements,
                     // avoid generating output that we would then need to pop
-                    Expr::Binary { op: BinOp::Assign, lhs, rhs } => {
+                    ExprKind::Binary { op: BinOp::Assign, lhs, rhs } => {
                         gen_assign(lhs, rhs, sym, out, false)?;
                     }
 
                     // For asm expressions with void output type, don't pop
                     // the output because no output is produced
-                    Expr::Asm { out_type: Type::Void, .. } => {
+                    ExprKind::Asm { out_type: Type::Void, .. } => {
                         expr.gen_code(sym, out)?;
                     }
 
@@ -295,14 +340,14 @@ impl Stmt
                 }
             }
 
-            Stmt::Break => {
+            StmtKind::Break => {
                 match break_label {
                     Some(label) => out.push_str(&format!("jmp {};\n", label)),
                     None => return ParseError::msg_only("break outside of loop context")
                 }
             }
 
-            Stmt::Continue => {
+            StmtKind::Continue => {
                 match cont_label {
                     Some(label) => out.push_str(&format!("jmp {};\n", label)),
                     None => return ParseError::msg_only("continue outside of loop context")
@@ -310,17 +355,33 @@ impl Stmt
             }
 
             // Return void
-            Stmt::ReturnVoid => {
+            StmtKind::ReturnVoid => {
                 out.push_str("push 0;\n");
                 out.push_str("ret;\n");
             }
 
-            Stmt::ReturnExpr(expr) => {
-                if let Expr::Asm { out_type: Type::Void, .. } = expr.as_ref() {
+            StmtKind::ReturnExpr(expr) => {
+                if let ExprKind::Asm { out_type: Type::Void, .. } = &expr.kind {
                     expr.gen_code(sym, out)?;
                     out.push_str("push 0;\n");
                     out.push_str("ret;\n");
                 }
+                else if let ExprKind::Call { callee, args } = &expr.kind {
+                    match &callee.kind {
+                        // The callee returns directly to our caller,
+                        // so that tail recursion runs in constant space
+                        ExprKind::Ref(Decl::Fun { name, is_extern: false, .. }) => {
+                            for arg in args {
+                                arg.gen_code(sym, out)?;
+                            }
+                            out.push_str(&format!("tail_call {}, {};\n", name, args.len()));
+                        }
+                        _ => {
+                            expr.gen_code(sym, out)?;
+                            out.push_str("ret;\n");
+                        }
+                    }
+                }
                 else
                 {
                     expr.gen_code(sym, out)?;
@@ -328,7 +389,7 @@ impl Stmt
                 }
             }
 
-            Stmt::If { test_expr, then_stmt, else_stmt } => {
+            StmtKind::If { test_expr, then_stmt, else_stmt } => {
                 test_expr.gen_code(sym, out)?;
 
                 let false_label = sym.gen_sym("if_false");
@@ -353,11 +414,12 @@ impl Stmt
                 }
             }
 
-            Stmt::While { test_expr, body_stmt } => {
+            StmtKind::While { test_expr, body_stmt } => {
                 let loop_label = sym.gen_sym("while_loop");
                 let break_label = sym.gen_sym("while_break");
 
                 out.push_str(&format!("{}:\n", loop_label));
+                sym.gen_loc(&test_expr.span, out);
                 test_expr.gen_code(sym, out)?;
                 out.push_str(&format!("jz {};\n", break_label));
 
@@ -372,7 +434,7 @@ impl Stmt
                 out.push_str(&format!("{}:\n", break_label));
             }
 
-            Stmt::DoWhile { test_expr, body_stmt } => {
+            StmtKind::DoWhile { test_expr, body_stmt } => {
                 let loop_label = sym.gen_sym("dowhile_loop");
                 let cont_label = sym.gen_sym("dowhile_cont");
                 let break_label = sym.gen_sym("dowhile_break");
@@ -386,6 +448,7 @@ impl Stmt
                 )?;
 
                 out.push_str(&format!("{}:\n", cont_label));
+                sym.gen_loc(&test_expr.span, out);
                 test_expr.gen_code(sym, out)?;
                 out.push_str(&format!("jz {};\n", break_label));
                 out.push_str(&format!("jmp {};\n", loop_label));
@@ -393,7 +456,7 @@ impl Stmt
                 out.push_str(&format!("{}:\n", break_label));
             }
 
-            Stmt::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+            StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
                 if init_stmt.is_some() {
                     init_stmt.as_ref().unwrap().gen_code(break_label, cont_label, sym, out)?;
                 }
@@ -403,6 +466,7 @@ impl Stmt
                 let break_label = sym.gen_sym("for_break");
 
                 out.push_str(&format!("{}:\n", loop_label));
+                sym.gen_loc(&test_expr.span, out);
                 test_expr.gen_code(sym, out)?;
                 out.push_str(&format!("jz {};\n", break_label));
 
@@ -414,6 +478,7 @@ impl Stmt
                 )?;
 
                 out.push_str(&format!("{}:\n", cont_label));
+                sym.gen_loc(&incr_expr.span, out);
                 incr_expr.gen_code(sym, out)?;
                 out.push_str("pop;\n");
                 out.push_str(&format!("jmp {};\n", loop_label));
@@ -421,12 +486,43 @@ impl Stmt
                 out.push_str(&format!("{}:\n", break_label));
             }
 
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 for stmt in stmts {
                     stmt.gen_code(break_label, cont_label, sym, out)?;
                 }
             }
 
+            StmtKind::Assert { test_expr, msg, expr_text } => {
+                let ok_label = sym.gen_sym("assert_ok");
+                let msg_label = sym.gen_sym("assert_msg");
+
+                // A passing assert only costs the test and a branch
+                test_expr.gen_code(sym, out)?;
+                out.push_str(&format!("jnz {};\n", ok_label));
+
+                let mut fail_msg = format!(
+                    "{}:{}: assertion failed: {}",
+                    self.span.src_name,
+                    self.span.line_no,
+                    expr_text
+                );
+                if let Some(msg) = msg {
+                    fail_msg += &format!(": {}", msg);
+                }
+                fail_msg += "\n";
+
+                // The message goes in the data section
+                out.push_str(".data;\n");
+                out.push_str(&format!("{}:\n", msg_label));
+                out.push_str(&format!(".stringz \"{}\";\n", fail_msg.escape_default()));
+                out.push_str(".code;\n");
+
+                out.push_str(&format!("push {};\n", msg_label));
+                out.push_str("syscall print_str;\n");
+                out.push_str("panic;\n");
+                out.push_str(&format!("{}:\n", ok_label));
+            }
+
             _ => todo!()
         }
 
@@ -438,16 +534,16 @@ impl Expr
 {
     fn gen_code(&self, sym: &mut SymGen, out: &mut String) -> Result<(), ParseError>
     {
-        match self {
-            Expr::Int(v) => {
+        match &self.kind {
+            ExprKind::Int { value: v, .. } => {
                 out.push_str(&format!("push {};\n", v));
             }
 
-            Expr::Float32(v) => {
+            ExprKind::Float32(v) => {
                 out.push_str(&format!("push_f32 {};\n", v));
             }
 
-            Expr::Ref(decl) => {
+            ExprKind::Ref(decl) => {
                 match decl {
                     Decl::Arg { idx, .. } => {
                         out.push_str(&format!("get_arg {};\n", idx));
@@ -476,7 +572,12 @@ impl Expr
                             _ => todo!()
                         }
                     }
-                    Decl::Fun { name, t } => {
+                    Decl::Fun { name, is_extern: true, .. } => {
+                        return ParseError::msg_only(
+                            &format!("can't take the address of extern function \"{}\"", name)
+                        );
+                    }
+                    Decl::Fun { name, .. } => {
                         out.push_str(&format!("push {};\n", name));
                     }
 
@@ -484,7 +585,7 @@ impl Expr
                 }
             }
 
-            Expr::Cast { new_type, child } => {
+            ExprKind::Cast { new_type, child } => {
                 use Type::*;
 
                 let src_type = child.eval_type()?;
@@ -524,16 +625,16 @@ impl Expr
                 }
             }
 
-            Expr::SizeofExpr { child } => {
+            ExprKind::SizeofExpr { child } => {
                 let t = child.eval_type()?;
                 out.push_str(&format!("push {};\n", t.sizeof()));
             }
 
-            Expr::SizeofType { t } => {
+            ExprKind::SizeofType { t } => {
                 out.push_str(&format!("push {};\n", t.sizeof()));
             }
 
-            Expr::Arrow { base, field } => {
+            ExprKind::Arrow { base, field } => {
                 base.gen_code(sym, out)?;
                 let base_type = base.eval_type()?;
 
@@ -545,6 +646,11 @@ impl Expr
                         out.push_str(&format!("push {};\n", offset));
                         out.push_str("add_u64;");
                         out.push_str(&format!("load_u{};\n", num_bits));
+
+                        // Signed fields are extended like signed globals
+                        if let Int(32) = self.eval_type()? {
+                            out.push_str("sx_i32_i64;\n");
+                        }
                     }
                     else
                     {
@@ -559,7 +665,21 @@ impl Expr
                 }
             }
 
-            Expr::Unary { op, child } => {
+            ExprKind::Unary { op: UnOp::AddressOf, child } if !matches!(child.eval_type()?, Struct { .. }) => {
+                match &child.kind {
+                    // The address of a global is its label
+                    ExprKind::Ref(Decl::Global { name, .. }) => {
+                        out.push_str(&format!("push {};\n", name));
+                    }
+
+                    // Functions already evaluate to their address
+                    ExprKind::Ref(Decl::Fun { .. }) => child.gen_code(sym, out)?,
+
+                    _ => return ParseError::msg_only("can only take the address of global variables and structs")
+                }
+            }
+
+            ExprKind::Unary { op, child } => {
                 child.gen_code(sym, out)?;
 
                 match op {
@@ -568,10 +688,8 @@ impl Expr
 
                         // If this is a pointer to an array, this is a no-op
                         // because a pointer to an array is the array itself
-                        if let Pointer(t) = child_type {
-                            if let Array { .. } = t.as_ref() {
-                                return Ok(())
-                            }
+                        if let Some(Array { .. }) = child_type.pointee() {
+                            return Ok(())
                         }
 
                         let ptr_type = child.eval_type()?;
@@ -638,12 +756,12 @@ impl Expr
                 }
             },
 
-            Expr::Binary { op, lhs, rhs } => {
+            ExprKind::Binary { op, lhs, rhs } => {
                 let out_type = self.eval_type()?;
                 gen_bin_op(op, lhs, rhs, &out_type, sym, out)?;
             }
 
-            Expr::Ternary { test_expr, then_expr, else_expr } => {
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
                 let false_label = sym.gen_sym("and_false");
                 let done_label = sym.gen_sym("and_done");
 
@@ -661,23 +779,27 @@ impl Expr
                 out.push_str(&format!("{}:\n", done_label));
             }
 
-            Expr::Call { callee, args } => {
+            ExprKind::Call { callee, args } => {
                 //callee.gen_code(out)?;
 
-                match callee.as_ref() {
-                    Expr::Ref(Decl::Fun { name, .. }) =>
+                match &callee.kind {
+                    ExprKind::Ref(Decl::Fun { name, is_extern, .. }) =>
                     {
                         for arg in args {
                             arg.gen_code(sym, out)?;
                         }
 
-                        out.push_str(&format!("call {}, {};\n", name, args.len()));
+                        if *is_extern {
+                            out.push_str(&format!("call_host {}, {};\n", name, args.len()));
+                        } else {
+                            out.push_str(&format!("call {}, {};\n", name, args.len()));
+                        }
                     }
                     _ => todo!()
                 }
             }
 
-            Expr::Asm { text, args, out_type } => {
+            ExprKind::Asm { text, args, out_type } => {
                 for arg in args {
                     arg.gen_code(sym, out)?;
                 }
@@ -751,6 +873,34 @@ fn emit_cmp_op(lhs_type: &Type, rhs_type: &Type, signed_op: &str, unsigned_op: &
     }
 }
 
+/// Size of the elements a pointer steps over. The type checker
+/// rejects arithmetic on pointers to void and functions.
+fn ptr_elem_size(elem_type: &Type) -> Result<usize, ParseError>
+{
+    match elem_type.size_of() {
+        Some(elem_size) => Ok(elem_size),
+        None => ParseError::msg_only(&format!("pointer arithmetic on unsized type {}", elem_type)),
+    }
+}
+
+/// Scale the index on top of the stack by the element size
+/// so that it can be added to or subtracted from a pointer
+fn emit_ptr_offset(elem_type: &Type, idx_type: &Type, out: &mut String) -> Result<(), ParseError>
+{
+    let elem_size = ptr_elem_size(elem_type)?;
+
+    // Small signed indices need to be sign-extended
+    if let Type::Int(n) = idx_type {
+        if *n <= 32 {
+            out.push_str(&format!("sx_i{}_i64;\n", n));
+        }
+    }
+
+    out.push_str(&format!("push {};\n", elem_size));
+    out.push_str("mul_u64;\n");
+    Ok(())
+}
+
 fn gen_bin_op(
     op: &BinOp,
     lhs: &Expr,
@@ -855,38 +1005,19 @@ fn gen_bin_op(
             emit_arith_op(out_type, "rshift_i", "rshift_u", "", out);
         }
 
-        // For now we're ignoring the type
         Add => {
             match (lhs_type, rhs_type) {
-                // Small signed indices need to be sign-extended
-                (Pointer(b), Int(n)) if n <= 32 => {
-                    out.push_str(&format!("sx_i{}_i64;\n", n));
-                    let elem_sizeof = b.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
+                (Pointer(elem_type), idx_type @ (Int(_) | UInt(_))) |
+                (Array { elem_type, .. }, idx_type @ (Int(_) | UInt(_))) => {
+                    emit_ptr_offset(&elem_type, &idx_type, out)?;
                     out.push_str("add_u64;\n");
                 }
 
-                (Pointer(b), UInt(n)) | (Pointer(b), Int(n)) => {
-                    let elem_sizeof = b.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
-                    out.push_str("add_u64;\n");
-                }
-
-                // Small signed indices need to be sign-extended
-                (Array{ elem_type , ..}, Int(n)) if n <= 32 => {
-                    out.push_str(&format!("sx_i{}_i64;\n", n));
-                    let elem_sizeof = elem_type.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
-                    out.push_str("add_u64;\n");
-                }
-
-                (Array{ elem_type , ..}, UInt(n)) | (Array{ elem_type , ..}, Int(n)) => {
-                    let elem_sizeof = elem_type.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
+                // The index is below the pointer on the stack
+                (idx_type @ (Int(_) | UInt(_)), Pointer(elem_type)) |
+                (idx_type @ (Int(_) | UInt(_)), Array { elem_type, .. }) => {
+                    out.push_str("swap;\n");
+                    emit_ptr_offset(&elem_type, &idx_type, out)?;
                     out.push_str("add_u64;\n");
                 }
 
@@ -898,20 +1029,17 @@ fn gen_bin_op(
 
         Sub => {
             match (lhs_type, rhs_type) {
-                // Small signed indices need to be sign-extended
-                (Pointer(b), Int(n)) if n <= 32 => {
-                    out.push_str(&format!("sx_i{}_i64;\n", n));
-                    let elem_sizeof = b.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
+                (Pointer(elem_type), idx_type @ (Int(_) | UInt(_))) |
+                (Array { elem_type, .. }, idx_type @ (Int(_) | UInt(_))) => {
+                    emit_ptr_offset(&elem_type, &idx_type, out)?;
                     out.push_str("sub_u64;\n");
                 }
 
-                (Pointer(b), UInt(n)) | (Pointer(b), Int(n)) => {
-                    let elem_sizeof = b.sizeof();
-                    out.push_str(&format!("push {};\n", elem_sizeof));
-                    out.push_str("mul_u64;\n");
+                // Pointer difference, divided by the element size
+                (Pointer(elem_type), Pointer(_)) => {
                     out.push_str("sub_u64;\n");
+                    out.push_str(&format!("push {};\n", ptr_elem_size(&elem_type)?));
+                    out.push_str("div_i64;\n");
                 }
 
                 _ => {
@@ -974,8 +1102,8 @@ fn gen_assign(
     //dbg!(lhs);
     //dbg!(rhs);
 
-    match lhs {
-        Expr::Arrow { base, field } => {
+    match &lhs.kind {
+        ExprKind::Arrow { base, field } => {
             let base_type = base.eval_type()?;
 
             if let Pointer(s) = base_type {
@@ -1020,7 +1148,7 @@ fn gen_assign(
             }
         }
 
-        Expr::Unary { op, child } => {
+        ExprKind::Unary { op, child } => {
             match op {
                 UnOp::Deref => {
                     let ptr_type = child.eval_type()?;
@@ -1052,7 +1180,7 @@ fn gen_assign(
             }
         },
 
-        Expr::Ref(decl) => {
+        ExprKind::Ref(decl) => {
             match decl {
                 Decl::Arg { idx, .. } => {
                     rhs.gen_code(sym, out)?;
@@ -1115,7 +1243,7 @@ mod tests
 
         dbg!(src);
         let mut input = Input::new(&src, "src");
-        let mut unit = parse_unit(&mut input).unwrap();
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
         unit.resolve_syms().unwrap();
         unit.check_types().unwrap();
         dbg!(&unit.fun_decls[0]);
@@ -1134,7 +1262,7 @@ mod tests
         //println!("{}", output);
 
         let mut input = Input::new(&output, file_name);
-        let mut unit = parse_unit(&mut input).unwrap();
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
         unit.resolve_syms().unwrap();
         unit.check_types().unwrap();
         unit.gen_code().unwrap();
@@ -1157,6 +1285,10 @@ mod tests
         gen_ok("void main() { int a = 1; }");
         gen_ok("void main() { int c; c = 1; }");
 
+        // One stack slot per local, with sibling blocks sharing slots
+        let out = gen_ok("void main() { u64 a = 5; { u64 b = 6; } { u64 c = 7; } }");
+        assert!(out.contains("main:\npush 0;\npush 0;\npush 5;"));
+
         // Infix expressions
         gen_ok("u64 foo(u64 a, u64 b) { return a + b * 2; }");
         gen_ok("u64 foo() { return 1 + 2, 3; }");
@@ -1170,6 +1302,17 @@ mod tests
         gen_ok("void foo() {}").contains("ret;");
     }
 
+    #[test]
+    fn associativity()
+    {
+        // (8 - 3) - 2, evaluated left to right
+        let out = gen_ok("u64 main() { return 8 - 3 - 2; }");
+        assert!(out.contains("push 8;\npush 3;\nsub_u32;\npush 2;\nsub_u32;\n"));
+
+        let out = gen_ok("u64 main() { return 16 / 4 / 2; }");
+        assert!(out.contains("push 16;\npush 4;\ndiv_i32;\npush 2;\ndiv_i32;\n"));
+    }
+
     #[test]
     fn globals()
     {
@@ -1187,6 +1330,23 @@ mod tests
         gen_ok("float g = +3.5f; void foo() {}");
         gen_ok("bool levar = true; bool foo() { return levar; }");
         gen_ok("int g = 5; int f() { return g; }");
+
+        // Globals are read and written at their label
+        let out = gen_ok("u64 g = 5; void foo() { g = g + 1; }");
+        assert!(out.contains("push g;\npush g;\nload_u64;\npush 1;\nadd_u64;\nstore_u64;\n"));
+
+        // The address of a global is its label
+        let out = gen_ok("u32 g = 5; u32* foo() { return &g; }");
+        assert!(out.contains("push g;\nret;\n"));
+
+        // Locals live on the stack, so they have no address
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+        let mut input = Input::new("u64* foo() { u64 x = 1; return &x; }", "src");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+        unit.resolve_syms().unwrap();
+        unit.check_types().unwrap();
+        assert!(unit.gen_code().is_err());
     }
 
     #[test]
@@ -1195,6 +1355,73 @@ mod tests
         gen_ok("void foo() {} void bar() {}");
         gen_ok("void foo() {} void bar() { return foo(); } ");
         gen_ok("void print_i64(i64 v) {} void bar(u64 v) { print_i64(v); }");
+
+        // Arguments are pushed left to right, and read by index in the callee
+        let out = gen_ok("u64 sub(u64 a, u64 b) { return a - b; } u64 foo() { u64 r = sub(5, 3); return r; }");
+        assert!(out.contains("get_arg 0;\nget_arg 1;\nsub_u64;\nret;\n"));
+        assert!(out.contains("push 5;\npush 3;\ncall sub, 2;\n"));
+
+        // The return value is left on the stack of the caller
+        let out = gen_ok("u64 fact(u64 n) { if (n < 2) return 1; return n * fact(n - 1); }");
+        assert!(out.contains("get_arg 0;\nget_arg 0;\npush 1;\nsub_u64;\ncall fact, 1;\nmul_u64;\nret;\n"));
+    }
+
+    #[test]
+    fn tail_calls()
+    {
+        // Calls whose value is returned reuse the frame of the caller
+        let out = gen_ok("u64 count(u64 n) { if (n == 0) return 0; return count(n - 1); }");
+        assert!(out.contains("get_arg 0;\npush 1;\nsub_u64;\ntail_call count, 1;\n"));
+        assert!(!out.contains("\ncall count"));
+
+        let out = gen_ok("u64 b(u64 x, u64 y) { return x; } u64 a(u64 n) { u64 t = n; return b(t, 2); }");
+        assert!(out.contains("get_local 0;\npush 2;\ntail_call b, 2;\n"));
+
+        // Calls in other positions return through the caller
+        let out = gen_ok("u64 f(u64 n) { return f(n) + 1; }");
+        assert!(out.contains("call f, 1;\npush 1;\nadd_u64;\nret;\n"));
+        let out = gen_ok("void f(u64 n) { f(n); return; }");
+        assert!(out.contains("call f, 1;\n"));
+
+        // Host functions have no frame to reuse
+        let out = gen_ok("extern u64 host(u64 n); u64 f() { return host(1); }");
+        assert!(out.contains("push 1;\ncall_host host, 1;\nret;\n"));
+    }
+
+    #[test]
+    fn extern_fns()
+    {
+        let out = gen_ok("extern u64 host(u8* p, u64 n); u64 main() { return host(null, 3); }");
+        assert!(out.contains("push 0;\npush 3;\ncall_host host, 2;\n"));
+
+        // Host functions have no address
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+        let mut input = Input::new("extern void host(); void main() { void* p = host; }", "src");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+        unit.resolve_syms().unwrap();
+        unit.check_types().unwrap();
+        assert!(unit.gen_code().is_err());
+    }
+
+    #[test]
+    fn main_args()
+    {
+        let out = gen_ok("void main() {}");
+        assert!(out.contains("call main, 0;\n"));
+        assert!(!out.contains("get_argc;"));
+
+        let out = gen_ok("u64 main(u64 argc, char** argv) { return argc; }");
+        assert!(out.contains("get_arg 0;\nget_arg 1;\n"));
+        assert!(out.contains("call main, 2;\n"));
+
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+        let mut input = Input::new("void main(u64 a, u64 b, u64 c) {}", "src");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+        unit.resolve_syms().unwrap();
+        unit.check_types().unwrap();
+        assert!(unit.gen_code().is_err());
     }
 
     #[test]
@@ -1237,6 +1464,65 @@ mod tests
         gen_ok("size_t strlen(char* p) { size_t l = 0; while (*(p + l) != 0) l = l + 1; return l; }");
     }
 
+    #[test]
+    fn debug_info()
+    {
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+
+        let src = "u64 f(u64 n)\n{\n    u64 s = 0;\n    for (u64 i = 0; i < n; i = i + 1)\n        s = s + i;\n    return s;\n}\n";
+        let mut input = Input::new(src, "foo.c");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+        unit.resolve_syms().unwrap();
+        unit.check_types().unwrap();
+
+        let out = unit.gen_code_with_debug(true).unwrap();
+        for loc in ["1, 1", "3, 5", "4, 5", "4, 21", "4, 28", "5, 9", "6, 5"] {
+            assert!(out.contains(&format!(".loc \"foo.c\", {};\n", loc)), "{}", loc);
+        }
+
+        assert!(!unit.gen_code().unwrap().contains(".loc"));
+    }
+
+    #[test]
+    fn assert_stmt()
+    {
+        // A passing assert only costs the test and a branch
+        let out = gen_ok("void main(u64 x) { assert(x < 3, \"x too big\"); }");
+        assert!(out.contains("lt_u64;\njnz _assert_ok_"));
+        assert!(out.contains(".stringz \"src:1: assertion failed: x < 3: x too big\\n\";"));
+        assert!(out.contains("syscall print_str;\npanic;\n"));
+
+        let out = gen_ok("void main(u64 x) { assert x; }");
+        assert!(out.contains(".stringz \"src:1: assertion failed: x\\n\";"));
+    }
+
+    #[test]
+    fn ptr_arith()
+    {
+        // Offsets are scaled by the pointee size
+        let out = gen_ok("u8* foo(u8* p, u64 n) { return p + n; }");
+        assert!(out.contains("push 1;\nmul_u64;\nadd_u64;\n"));
+        let out = gen_ok("u64* foo(u64* p, u64 n) { return p + n; }");
+        assert!(out.contains("push 8;\nmul_u64;\nadd_u64;\n"));
+        let out = gen_ok("u64* foo(u64* p, u64 n) { return p - n; }");
+        assert!(out.contains("push 8;\nmul_u64;\nsub_u64;\n"));
+
+        // The integer may come first
+        let out = gen_ok("u64* foo(u64* p, u64 n) { return n + p; }");
+        assert!(out.contains("swap;\npush 8;\nmul_u64;\nadd_u64;\n"));
+        let out = gen_ok("u32* foo(u32* p, int n) { return n + p; }");
+        assert!(out.contains("swap;\nsx_i32_i64;\npush 4;\nmul_u64;\nadd_u64;\n"));
+
+        // Arrays decay to pointers
+        let out = gen_ok("u64 A[4]; u64* foo() { return A - 1; }");
+        assert!(out.contains("push 8;\nmul_u64;\nsub_u64;\n"));
+
+        // Pointer difference counts elements
+        let out = gen_ok("i64 foo(u64* a, u64* b) { return a - b; }");
+        assert!(out.contains("sub_u64;\npush 8;\ndiv_i64;\n"));
+    }
+
     #[test]
     fn strings()
     {
@@ -1259,6 +1545,33 @@ mod tests
         gen_ok("void foo(u64 a) { if (a) {} else {} }");
         gen_ok("void foo(u64 a, u64 b) { if (a || b) {} }");
         gen_ok("void foo(u64 a, u64 b) { if (a && b) {} }");
+
+        // Without an else branch, a false test skips the then branch
+        let out = gen_ok("u64 foo(u64 a) { if (a) return 1; return 2; }");
+        assert!(out.contains("get_arg 0;\njz _if_false_0;\npush 1;\nret;\n_if_false_0:\npush 2;\nret;\n"));
+
+        // The then branch jumps over the else branch
+        let out = gen_ok("u64 foo(u64 a) { if (a) return 1; else return 2; }");
+        assert!(out.contains("jz _if_false_0;\npush 1;\nret;\njmp _if_join_1;\n_if_false_0:\npush 2;\nret;\n_if_join_1:\n"));
+
+        gen_ok("u64 foo(u64 a) { if (a == 1) return 1; else if (a == 2) return 2; else return 3; }");
+    }
+
+    #[test]
+    fn while_loop()
+    {
+        gen_ok("void foo(u64 a) { while (a) {} }");
+        gen_ok("void foo(u64 a) { while (a) { break; } }");
+        gen_ok("void foo(u64 a) { while (a) { continue; } }");
+
+        // The test comes first, and the body jumps back to it
+        let out = gen_ok("void foo(u64 a) { while (a) { a = a - 1; } }");
+        assert!(out.contains("_while_loop_0:\nget_arg 0;\njz _while_break_1;\n"));
+        assert!(out.contains("jmp _while_loop_0;\n_while_break_1:\n"));
+
+        // break and continue refer to the innermost loop
+        let out = gen_ok("void foo(u64 a) { while (a) { while (a) { break; } continue; } }");
+        assert!(out.contains("jz _while_break_3;\njmp _while_break_3;\njmp _while_loop_2;\n_while_break_3:\njmp _while_loop_0;\n"));
     }
 
     #[test]
@@ -1271,6 +1584,11 @@ mod tests
         gen_ok("void foo(size_t n) { for (size_t i = 0; i < n; i = i + 1) { break; } }");
         gen_ok("void foo(size_t n) { for (size_t i = 0; i < n; i = i + 1) { continue; } }");
         gen_ok("void foo(int n) { for (int i = 0; i < n; ++i) {} }");
+
+        // continue runs the increment before testing again
+        let out = gen_ok("void foo(u64 n) { for (u64 i = 0; i < n; i = i + 1) { continue; } }");
+        assert!(out.contains("jz _for_break_2;\njmp _for_cont_1;\n_for_cont_1:\nget_local 0;\npush 1;\nadd_u64;\n"));
+        assert!(out.contains("pop;\njmp _for_loop_0;\n_for_break_2:\n"));
     }
 
     #[test]
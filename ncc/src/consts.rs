@@ -0,0 +1,657 @@
+use crate::ast::*;
+use crate::parsing::{ParseError, ErrorKind};
+
+/// Error for an expression that can't be evaluated at compile time
+fn const_error<T>(expr: &Expr, msg: &str) -> Result<T, ParseError>
+{
+    Err(ParseError::at_span(&expr.span, ErrorKind::ConstEval, msg))
+}
+
+/// Convert an integer value to the range of an integer or pointer type,
+/// truncating it as a cast to that type would
+pub fn wrap_to_type(value: i128, t: &Type) -> i128
+{
+    let num_bits = match t {
+        Type::UInt(n) | Type::Int(n) => *n as u32,
+        Type::Pointer(_) => 64,
+        _ => return value,
+    };
+
+    if num_bits >= 128 {
+        return value;
+    }
+
+    let shift = 128 - num_bits;
+
+    match t {
+        // Sign-extend from the top bit of the type
+        Type::Int(_) => (value << shift) >> shift,
+        _ => ((value as u128) << shift >> shift) as i128,
+    }
+}
+
+impl Expr
+{
+    /// Evaluate an integer constant expression at compile time.
+    /// Errors are ParseErrors of kind ErrorKind::ConstEval, located at
+    /// the offending subexpression, like the other compile errors.
+    pub fn eval_const(&self) -> Result<i128, ParseError>
+    {
+        match &self.kind {
+            ExprKind::Int { value, .. } => Ok(*value),
+
+            ExprKind::Cast { new_type, child } => {
+                let value = child.eval_const()?;
+
+                match new_type {
+                    Type::UInt(_) | Type::Int(_) | Type::Pointer(_) => Ok(wrap_to_type(value, new_type)),
+                    _ => const_error(self, "not an integer constant expression")
+                }
+            }
+
+            ExprKind::SizeofType { t } => {
+                match t.size_of() {
+                    Some(num_bytes) => Ok(num_bytes as i128),
+                    None => const_error(self, "not a constant expression")
+                }
+            }
+
+            ExprKind::SizeofExpr { child } => {
+                match &child.kind {
+                    ExprKind::Ref(decl) => {
+                        match decl.get_type().size_of() {
+                            Some(num_bytes) => Ok(num_bytes as i128),
+                            None => const_error(self, "not a constant expression")
+                        }
+                    }
+                    _ => const_error(self, "not a constant expression")
+                }
+            }
+
+            ExprKind::Unary { op, child } => {
+                let value = child.eval_const()?;
+
+                match op {
+                    UnOp::Minus => match value.checked_neg() {
+                        Some(v) if fits_in_64(v) => Ok(v),
+                        _ => const_error(self, "integer overflow in constant expression")
+                    },
+                    UnOp::Not => Ok((value == 0) as i128),
+                    UnOp::BitNot => Ok(!value),
+                    UnOp::Deref | UnOp::AddressOf => const_error(self, "not a constant expression"),
+                }
+            }
+
+            ExprKind::Binary { op, lhs, rhs } => {
+                use BinOp::*;
+
+                let lhs_val = lhs.eval_const()?;
+
+                // The right side of a short-circuiting operator
+                // doesn't need to be valid if it isn't evaluated
+                match op {
+                    And if lhs_val == 0 => return Ok(0),
+                    Or if lhs_val != 0 => return Ok(1),
+                    Assign | Comma => return const_error(self, "not a constant expression"),
+                    _ => {}
+                }
+
+                let rhs_val = rhs.eval_const()?;
+
+                if let Div | Mod = op {
+                    if rhs_val == 0 {
+                        return const_error(rhs, "division by zero in constant expression");
+                    }
+                }
+
+                if let LShift | RShift = op {
+                    if !(0..128).contains(&rhs_val) {
+                        return const_error(rhs, "shift amount out of range in constant expression");
+                    }
+                }
+
+                let result = match op {
+                    Add => lhs_val.checked_add(rhs_val),
+                    Sub => lhs_val.checked_sub(rhs_val),
+                    Mul => lhs_val.checked_mul(rhs_val),
+                    Div => lhs_val.checked_div(rhs_val),
+                    Mod => lhs_val.checked_rem(rhs_val),
+                    LShift => {
+                        // Shifting back must give the original value
+                        let value = lhs_val << rhs_val;
+                        if value >> rhs_val == lhs_val { Some(value) } else { None }
+                    }
+                    RShift => Some(lhs_val >> rhs_val),
+                    BitAnd => Some(lhs_val & rhs_val),
+                    BitOr => Some(lhs_val | rhs_val),
+                    BitXor => Some(lhs_val ^ rhs_val),
+                    Eq => Some((lhs_val == rhs_val) as i128),
+                    Ne => Some((lhs_val != rhs_val) as i128),
+                    Lt => Some((lhs_val < rhs_val) as i128),
+                    Le => Some((lhs_val <= rhs_val) as i128),
+                    Gt => Some((lhs_val > rhs_val) as i128),
+                    Ge => Some((lhs_val >= rhs_val) as i128),
+                    And | Or => Some((rhs_val != 0) as i128),
+                    Assign | Comma => unreachable!(),
+                };
+
+                match result {
+                    Some(value) if fits_in_64(value) => Ok(value),
+                    _ => const_error(self, "integer overflow in constant expression")
+                }
+            }
+
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                if test_expr.eval_const()? != 0 {
+                    then_expr.eval_const()
+                }
+                else
+                {
+                    else_expr.eval_const()
+                }
+            }
+
+            ExprKind::Float32(_) => const_error(self, "not an integer constant expression"),
+
+            _ => const_error(self, "not a constant expression"),
+        }
+    }
+}
+
+impl Unit
+{
+    /// Check that global variable initializers are constant,
+    /// and replace integer initializers by their value
+    pub fn fold_global_inits(&mut self) -> Result<(), ParseError>
+    {
+        for global in &mut self.global_vars {
+            let init_expr = match &mut global.init_expr {
+                Some(init_expr) => init_expr,
+                None => continue,
+            };
+
+            match (&global.var_type, &mut init_expr.kind) {
+                // Pointers to global arrays and string constants
+                (Type::Pointer(_), ExprKind::Ref(Decl::Global { .. })) => {}
+
+                (Type::UInt(_) | Type::Int(_) | Type::Pointer(_), _) => {
+                    fold_init(init_expr, &global.var_type)?;
+                }
+
+                (Type::Array { elem_type, .. }, ExprKind::Array(elem_exprs)) if elem_type.is_integer() => {
+                    for elem_expr in elem_exprs {
+                        fold_init(elem_expr, elem_type)?;
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace an initializer expression by its constant value
+fn fold_init(init_expr: &mut Expr, var_type: &Type) -> Result<(), ParseError>
+{
+    let value = wrap_to_type(init_expr.eval_const()?, var_type);
+    init_expr.kind = ExprKind::Int { value, suffix: None };
+    Ok(())
+}
+
+/// Signedness and width of an integer type
+fn int_kind(t: &Type) -> Option<(bool, usize)>
+{
+    match t {
+        Type::Int(n) => Some((true, *n)),
+        Type::UInt(n) => Some((false, *n)),
+        _ => None
+    }
+}
+
+/// Check if a value is representable in a 64-bit integer,
+/// either signed or unsigned
+fn fits_in_64(value: i128) -> bool
+{
+    fits_in(value, true, 64) || fits_in(value, false, 64)
+}
+
+/// Check if a value is representable in an integer type
+fn fits_in(value: i128, signed: bool, num_bits: usize) -> bool
+{
+    if num_bits >= 128 {
+        return true;
+    }
+
+    if signed {
+        let max = (1i128 << (num_bits - 1)) - 1;
+        value >= -max - 1 && value <= max
+    }
+    else
+    {
+        value >= 0 && value < (1i128 << num_bits)
+    }
+}
+
+/// Get the value of an integer literal, if the value fits in its type
+fn literal_value(expr: &Expr) -> Option<(i128, bool)>
+{
+    match &expr.kind {
+        ExprKind::Int { value, .. } => {
+            let (signed, num_bits) = int_kind(&expr.eval_type().ok()?)?;
+            if fits_in(*value, signed, num_bits) { Some((*value, signed)) } else { None }
+        }
+        _ => None
+    }
+}
+
+/// Make an integer literal of a given type
+fn make_literal(value: i128, t: &Type) -> Option<ExprKind>
+{
+    use IntSuffix::*;
+
+    let (signed, num_bits) = int_kind(t)?;
+
+    if !fits_in(value, signed, num_bits) {
+        return None;
+    }
+
+    // Unsuffixed literals are int if the value fits, long otherwise
+    let default_bits = if fits_in(value, true, 32) { 32 } else { 64 };
+    if signed && num_bits == default_bits {
+        return Some(ExprKind::Int { value, suffix: None });
+    }
+
+    [U8, U16, U32, U64, I8, I16, I32, I64]
+        .into_iter()
+        .find(|suffix| int_kind(&suffix.get_type()) == Some((signed, num_bits)))
+        .map(|suffix| ExprKind::Int { value, suffix: Some(suffix) })
+}
+
+impl Unit
+{
+    /// Collapse operations on integer literals in function bodies
+    /// into single literals
+    pub fn fold_constants(&mut self) -> Result<(), ParseError>
+    {
+        for fun in &mut self.fun_decls {
+            fun.body.fold_constants()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Stmt
+{
+    fn fold_constants(&mut self) -> Result<(), ParseError>
+    {
+        match &mut self.kind {
+            StmtKind::Expr(expr) => expr.fold_constants()?,
+            StmtKind::ReturnExpr(expr) => expr.fold_constants()?,
+            StmtKind::ReturnVoid | StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Assert { test_expr, .. } => test_expr.fold_constants()?,
+
+            StmtKind::Block(stmts) => {
+                for stmt in stmts {
+                    stmt.fold_constants()?;
+                }
+            }
+
+            StmtKind::If { test_expr, then_stmt, else_stmt } => {
+                test_expr.fold_constants()?;
+                then_stmt.fold_constants()?;
+
+                if let Some(else_stmt) = else_stmt {
+                    else_stmt.fold_constants()?;
+                }
+            }
+
+            StmtKind::While { test_expr, body_stmt } |
+            StmtKind::DoWhile { test_expr, body_stmt } => {
+                test_expr.fold_constants()?;
+                body_stmt.fold_constants()?;
+            }
+
+            StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+                if let Some(init_stmt) = init_stmt {
+                    init_stmt.fold_constants()?;
+                }
+
+                test_expr.fold_constants()?;
+                incr_expr.fold_constants()?;
+                body_stmt.fold_constants()?;
+            }
+
+            StmtKind::VarDecl { init_expr, .. } => {
+                if let Some(init_expr) = init_expr {
+                    init_expr.fold_constants()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Expr
+{
+    /// Replace unary and binary operations whose operands are integer
+    /// literals by the resulting literal, bottom-up. Operations are only
+    /// folded when the result is the same as what would be computed at
+    /// run time, so overflowing operations are kept. Division by a zero
+    /// constant is reported as an error.
+    pub fn fold_constants(&mut self) -> Result<(), ParseError>
+    {
+        match &mut self.kind {
+            ExprKind::Int { .. } | ExprKind::Float32(_) | ExprKind::String(_) => {}
+            ExprKind::Ident(_) | ExprKind::Ref(_) => {}
+
+            // The operand of sizeof isn't evaluated
+            ExprKind::SizeofExpr { .. } | ExprKind::SizeofType { .. } => {}
+
+            ExprKind::Array(exprs) => {
+                for expr in exprs {
+                    expr.fold_constants()?;
+                }
+            }
+
+            ExprKind::Cast { child, .. } => child.fold_constants()?,
+            ExprKind::Arrow { base, .. } => base.fold_constants()?,
+
+            ExprKind::Unary { op, child } => {
+                child.fold_constants()?;
+
+                if let UnOp::Deref | UnOp::AddressOf = op {
+                    return Ok(());
+                }
+
+                if literal_value(child).is_none() {
+                    return Ok(());
+                }
+
+                self.fold_literal();
+            }
+
+            ExprKind::Binary { op, lhs, rhs } => {
+                lhs.fold_constants()?;
+                rhs.fold_constants()?;
+
+                if let BinOp::Assign | BinOp::Comma = op {
+                    return Ok(());
+                }
+
+                // This would always trap at run time
+                if let BinOp::Div | BinOp::Mod = op {
+                    if matches!(rhs.kind, ExprKind::Int { value: 0, .. }) {
+                        return const_error(rhs, "division by zero");
+                    }
+                }
+
+                let is_shift = matches!(op, BinOp::LShift | BinOp::RShift);
+
+                let shift_amount = match (literal_value(lhs), literal_value(rhs)) {
+                    // Mixing signed and unsigned operands is only folded
+                    // when the values are the same either way
+                    (Some((l, l_signed)), Some((r, r_signed))) => {
+                        if l_signed != r_signed && (l < 0 || r < 0) {
+                            return Ok(());
+                        }
+                        r
+                    }
+                    _ => return Ok(())
+                };
+
+                // The VM masks the shift amount to the width of the
+                // operation, so larger amounts give a different result
+                if is_shift {
+                    match self.eval_type() {
+                        Ok(t) if shift_amount >= 0 && shift_amount < t.num_bits() as i128 => {}
+                        _ => return Ok(())
+                    }
+                }
+
+                self.fold_literal();
+            }
+
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                test_expr.fold_constants()?;
+                then_expr.fold_constants()?;
+                else_expr.fold_constants()?;
+            }
+
+            ExprKind::Call { callee, args } => {
+                callee.fold_constants()?;
+                for arg in args {
+                    arg.fold_constants()?;
+                }
+            }
+
+            ExprKind::Asm { args, .. } => {
+                for arg in args {
+                    arg.fold_constants()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace an operation on literals by its value, if it
+    /// is representable in the type of the operation
+    fn fold_literal(&mut self)
+    {
+        let (value, t) = match (self.eval_const(), self.eval_type()) {
+            (Ok(value), Ok(t)) => (value, t),
+            _ => return
+        };
+
+        if let Some(kind) = make_literal(value, &t) {
+            self.kind = kind;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Evaluate the initializer of a global variable
+    fn eval(src: &str) -> Result<i128, ParseError>
+    {
+        let unit = crate::parser::parse_str(&format!("u64 g = {};", src)).unwrap();
+        unit.global_vars[0].init_expr.as_ref().unwrap().eval_const()
+    }
+
+    fn eval_err(src: &str) -> String
+    {
+        let error = eval(src).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConstEval);
+        format!("{}:{}: {}", error.line(), error.column(), error.message())
+    }
+
+    #[test]
+    fn arithmetic()
+    {
+        assert_eq!(eval("42").unwrap(), 42);
+        assert_eq!(eval("2 * 8").unwrap(), 16);
+        assert_eq!(eval("(1 + 2) * (3 + 4)").unwrap(), 21);
+        assert_eq!(eval("8 - 3 - 2").unwrap(), 3);
+        assert_eq!(eval("7 / 2").unwrap(), 3);
+        assert_eq!(eval("-7 / 2").unwrap(), -3);
+        assert_eq!(eval("-7 % 3").unwrap(), -1);
+        assert_eq!(eval("1 << 40 | 0xFF & 0x0F ^ 1").unwrap(), (1 << 40) | 14);
+        assert_eq!(eval("0x100 >> 4").unwrap(), 16);
+        assert_eq!(eval("3 < 4 && 4 <= 4 || 0").unwrap(), 1);
+        assert_eq!(eval("3 == 4").unwrap(), 0);
+        assert_eq!(eval("1? 10:20").unwrap(), 10);
+        assert_eq!(eval("!5").unwrap(), 0);
+        assert_eq!(eval("~0").unwrap(), -1);
+
+        // Nested unary minus
+        assert_eq!(eval("-5").unwrap(), -5);
+        assert_eq!(eval("- -5").unwrap(), 5);
+        assert_eq!(eval("-(-(-(3)))").unwrap(), -3);
+        assert_eq!(eval("-(2 - 5)").unwrap(), 3);
+
+        // Casts and sizeof
+        assert_eq!(eval("(u8)300").unwrap(), 44);
+        assert_eq!(eval("(i8)255").unwrap(), -1);
+        assert_eq!(eval("(u32)-1").unwrap(), 0xFFFF_FFFF);
+        assert_eq!(eval("sizeof(u64) * 4").unwrap(), 32);
+        assert_eq!(eval("sizeof(u8*)").unwrap(), 8);
+
+        // Short-circuiting skips the right side
+        assert_eq!(eval("0 && 1 / 0").unwrap(), 0);
+        assert_eq!(eval("1 || 1 / 0").unwrap(), 1);
+    }
+
+    #[test]
+    fn errors()
+    {
+        // Overflow during folding
+        assert_eq!(
+            eval_err("0xFFFFFFFFFFFFFFFF * 0xFFFFFFFFFFFFFFFF * 2"),
+            "1:9: integer overflow in constant expression"
+        );
+        assert_eq!(
+            eval_err("0xFFFFFFFFFFFFFFFF * 0x10"),
+            "1:9: integer overflow in constant expression"
+        );
+        assert_eq!(eval_err("0xFFFFFFFFFFFFFFFF + 1"), "1:9: integer overflow in constant expression");
+        assert_eq!(eval_err("0 - 0xFFFFFFFFFFFFFFFF"), "1:9: integer overflow in constant expression");
+        assert!(eval("1 << 64").is_err());
+        assert_eq!(eval("0xFFFFFFFFFFFFFFFF").unwrap(), 0xFFFFFFFFFFFFFFFF);
+        assert_eq!(eval("-0x7FFFFFFFFFFFFFFF - 1").unwrap(), i64::MIN as i128);
+        assert!(eval("1 << 127").is_err());
+        assert!(eval("1 << 200").is_err());
+        assert!(eval("1 >> -1").is_err());
+
+        // Division by zero, pointing at the divisor
+        assert_eq!(eval_err("1 + 10 / (2 - 2)"), "1:18: division by zero in constant expression");
+        assert_eq!(eval_err("10 % 0"), "1:14: division by zero in constant expression");
+
+        // Non-constant subexpressions
+        assert_eq!(eval_err("1 + x"), "1:13: not a constant expression");
+        assert_eq!(eval_err("2 * foo(1)"), "1:13: not a constant expression");
+        assert_eq!(eval_err("1 + \"str\""), "1:13: not a constant expression");
+        assert_eq!(eval_err("(1, 2)"), "1:9: not a constant expression");
+        assert_eq!(eval_err("1.5f"), "1:9: not an integer constant expression");
+    }
+
+    #[test]
+    fn global_inits()
+    {
+        fn global_init(src: &str) -> Result<Vec<String>, ParseError>
+        {
+            let mut unit = crate::parser::parse_str(src)?;
+            unit.resolve_syms()?;
+            unit.fold_global_inits()?;
+
+            Ok(unit.global_vars.iter().map(|g| match &g.init_expr.as_ref().unwrap().kind {
+                ExprKind::Int { value, .. } => value.to_string(),
+                ExprKind::Array(exprs) => format!("{:?}", exprs.iter().map(|e| e.eval_const().unwrap()).collect::<Vec<_>>()),
+                ExprKind::Ref(_) => "ref".to_string(),
+                kind => format!("{:?}", kind),
+            }).collect())
+        }
+
+        assert_eq!(global_init("u64 g = 2 * 8;").unwrap(), vec!["16"]);
+        assert_eq!(global_init("u8 g = 255 + 1;").unwrap(), vec!["0"]);
+        assert_eq!(global_init("u64 g = -1;").unwrap(), vec!["18446744073709551615"]);
+        assert_eq!(global_init("i32 g = -(4 * 4);").unwrap(), vec!["-16"]);
+        assert_eq!(global_init("u8 a[3] = { 1 + 1, 2 * 2, 1 << 3 };").unwrap(), vec!["[2, 4, 8]"]);
+        assert_eq!(global_init("char* s = \"foo\";").unwrap(), vec!["String(\"foo\")", "ref"]);
+
+        let error = global_init("u64 f() { return 1; } u64 g = f();").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConstEval);
+        assert_eq!(error.to_string(), "src:1:31: error: not a constant expression");
+        assert!(global_init("u64 a = 1; u64 b = a + 1;").is_err());
+    }
+
+    #[test]
+    fn fold_constants()
+    {
+        /// Fold the expression returned by a function
+        fn fold(ret_type: &str, src: &str) -> Expr
+        {
+            let src = format!("{} f(u64 x) {{ return {}; }}", ret_type, src);
+            let mut unit = crate::parser::parse_str(&src).unwrap();
+            unit.resolve_syms().unwrap();
+            unit.check_types().unwrap();
+            unit.fold_constants().unwrap();
+
+            let mut body = unit.fun_decls[0].body.clone();
+
+            // Folding twice gives the same result
+            body.fold_constants().unwrap();
+            assert_eq!(format!("{:?}", body), format!("{:?}", unit.fun_decls[0].body));
+
+            match body.kind {
+                StmtKind::Block(mut stmts) => match stmts.remove(0).kind {
+                    StmtKind::ReturnExpr(expr) => *expr,
+                    _ => panic!()
+                },
+                _ => panic!()
+            }
+        }
+
+        fn is_int(expr: &Expr, val: i128) -> bool
+        {
+            matches!(expr.kind, ExprKind::Int { value, .. } if value == val)
+        }
+
+        assert!(is_int(&fold("i64", "1 + 2 * 3"), 7));
+        assert!(is_int(&fold("u8", "!0"), 1));
+        assert!(is_int(&fold("i64", "-(2 - 5)"), 3));
+        assert!(is_int(&fold("u8", "1 < 2 && 3 != 3"), 0));
+        assert!(is_int(&fold("u64", "1 << 10"), 1024));
+        assert!(is_int(&fold("u8", "200u8 + 50u8"), 250));
+        assert!(matches!(fold("u64", "4u * 4u").kind, ExprKind::Int { value: 16, suffix: Some(IntSuffix::U32) }));
+
+        // Nested three levels deep
+        assert!(is_int(&fold("i64", "((1 + 2) * (3 - (8 / 4))) - ~0"), 4));
+
+        // Only the constant part of an expression is folded
+        match fold("u64", "x + (2 * 3)").kind {
+            ExprKind::Binary { op: BinOp::Add, lhs, rhs } => {
+                assert!(matches!(lhs.kind, ExprKind::Ref(_)));
+                assert!(is_int(&rhs, 6));
+            }
+            _ => panic!()
+        }
+
+        // Division by a zero constant is an error, pointing at the divisor
+        fn fold_err(src: &str) -> String
+        {
+            let src = format!("i64 f(i64 x) {{ return {}; }}", src);
+            let mut unit = crate::parser::parse_str(&src).unwrap();
+            unit.resolve_syms().unwrap();
+            unit.check_types().unwrap();
+            let error = unit.fold_constants().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ConstEval);
+            format!("{}:{}: {}", error.line(), error.column(), error.message())
+        }
+        assert_eq!(fold_err("1 + 10 / (4 - 4)"), "1:32: division by zero");
+        assert_eq!(fold_err("x % 0"), "1:27: division by zero");
+        assert!(matches!(fold("i64", "x / (4 - 3)").kind, ExprKind::Binary { .. }));
+
+        // Results that would wrap around at run time aren't folded
+        assert!(matches!(fold("i64", "2147483647 + 1").kind, ExprKind::Binary { .. }));
+        assert!(matches!(fold("u8", "200u8 + 100u8").kind, ExprKind::Binary { .. }));
+        assert!(matches!(fold("u64", "0u - 1u").kind, ExprKind::Binary { .. }));
+
+        // Shift amounts are masked to the width of the operation at run time
+        assert!(matches!(fold("i32", "256 >> 40").kind, ExprKind::Binary { .. }));
+        assert!(matches!(fold("u64", "1u64 << 64").kind, ExprKind::Binary { .. }));
+        assert!(matches!(fold("u64", "1 << -1").kind, ExprKind::Binary { .. }));
+        assert!(is_int(&fold("u64", "1u64 << 63"), 1 << 63));
+        assert!(is_int(&fold("i32", "256 >> 8"), 1));
+
+        // Mixed signedness compares as unsigned
+        assert!(matches!(fold("u8", "-1 < 1u").kind, ExprKind::Binary { .. }));
+        assert!(is_int(&fold("u8", "1 < 2u"), 1));
+    }
+}
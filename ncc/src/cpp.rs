@@ -39,7 +39,7 @@ impl Input
         loop
         {
             if self.eof() {
-                return self.parse_error("end of input inside string");
+                return self.parse_error(ErrorKind::UnterminatedString, "end of input inside string");
             }
 
             let ch = self.eat_ch();
@@ -88,7 +88,7 @@ fn parse_def(input: &mut Input) -> Result<Def, ParseError>
             }
 
             if input.eof() {
-                return input.parse_error("eof inside #define macro parameters");
+                return input.parse_error(ErrorKind::UnexpectedEof, "eof inside #define macro parameters");
             }
 
             param_vec.push(input.parse_ident()?);
@@ -151,7 +151,7 @@ fn parse_def(input: &mut Input) -> Result<Def, ParseError>
                     '\n' => break,
                     '\r' => {},
                     ' ' => {},
-                    _ => return input.parse_error("expected newline")
+                    _ => return input.parse_error(ErrorKind::Preprocessor, "expected newline")
                 }
             }
         }
@@ -230,7 +230,7 @@ fn process_branches(
             )?;
 
             if end_keyword != "endif" {
-                return input.parse_error("expected #endif");
+                return input.parse_error(ErrorKind::Preprocessor, "expected #endif");
             }
         }
 
@@ -254,7 +254,7 @@ fn process_branches(
             )?;
 
             if end_keyword != "endif" {
-                return input.parse_error("expected #endif");
+                return input.parse_error(ErrorKind::Preprocessor, "expected #endif");
             }
 
             output += &sub_output;
@@ -272,7 +272,7 @@ fn read_macro_arg(input: &mut Input, depth: usize) -> Result<String, ParseError>
     loop
     {
         if input.eof() {
-            return input.parse_error("end of input inside macro argument");
+            return input.parse_error(ErrorKind::UnexpectedEof, "end of input inside macro argument");
         }
 
         let ch = input.peek_ch();
@@ -333,7 +333,7 @@ fn expand_macro(
         loop
         {
             if input.eof() {
-                return input.parse_error("unexpected end of input");
+                return input.parse_error(ErrorKind::UnexpectedEof, "unexpected end of input");
             }
 
             if input.match_token(")")? {
@@ -351,7 +351,7 @@ fn expand_macro(
 
         // If the argument count doesn't match
         if args.len() != params.len() {
-            return input.parse_error(&format!(
+            return input.parse_error(ErrorKind::Preprocessor, &format!(
                 "macro {} expected {} arguments",
                 def.name,
                 params.len()
@@ -425,7 +425,7 @@ fn expand_macro(
     )?;
 
     if end_keyword != "" {
-        return input.parse_error(&format!("unexpected #{}", end_keyword));
+        return input.parse_error(ErrorKind::Preprocessor, &format!("unexpected #{}", end_keyword));
     }
 
     return Ok(sub_input);
@@ -443,7 +443,7 @@ pub fn process_input(input: &mut Input) -> Result<String, ParseError>
     )?;
 
     if end_keyword != "" {
-        return input.parse_error(&format!("unexpected #{}", end_keyword));
+        return input.parse_error(ErrorKind::Preprocessor, &format!("unexpected #{}", end_keyword));
     }
 
     Ok(output)
@@ -502,7 +502,7 @@ fn process_input_rec(
                     // Compute the include path based on the
                     // current file's directory
                     let rel_include_path = input.parse_str('"')?;
-                    let mut src_path = Path::new(&input.src_name).parent().unwrap();
+                    let mut src_path = Path::new(input.src_name.as_str()).parent().unwrap();
                     src_path.join(rel_include_path).display().to_string()
                 };
 
@@ -514,7 +514,7 @@ fn process_input_rec(
                 )?;
 
                 if end_keyword != "" {
-                    return include_input.parse_error(&format!("unexpected #{}", end_keyword));
+                    return include_input.parse_error(ErrorKind::Preprocessor, &format!("unexpected #{}", end_keyword));
                 }
 
                 output += &include_output;
@@ -541,7 +541,7 @@ fn process_input_rec(
             }
 
             if gen_output {
-                return input.parse_error(&format!(
+                return input.parse_error(ErrorKind::Preprocessor, &format!(
                     "unknown preprocessor directive {}", directive
                 ));
             }
@@ -633,7 +633,7 @@ mod tests
         let output = process_input(&mut input)?;
 
         let mut input = Input::new(&output, file_name);
-        let mut unit = parse_unit(&mut input)?;
+        let (mut unit, _) = parse_unit(&mut input)?;
         //unit.resolve_syms().unwrap();
         //unit.check_types().unwrap();
         //unit.gen_code().unwrap();
@@ -645,7 +645,7 @@ mod tests
     {
         match compile(file_name) {
             Ok(_) => panic!(),
-            Err(error) => error.line_no as usize
+            Err(error) => error.line() as usize
         }
     }
 
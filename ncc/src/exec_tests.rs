@@ -2,10 +2,10 @@
 
 use std::fs;
 use std::io::{self, Write};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::collections::HashSet;
 
-fn compile_and_run(file_path: &str, run_example: bool)
+fn compile_and_run(file_path: &str, run_example: bool, optimize: bool)
 {
     if run_example {
         io::stdout().write(format!("compiling and running: {}\n", file_path).as_bytes()).unwrap();
@@ -18,6 +18,8 @@ fn compile_and_run(file_path: &str, run_example: bool)
     let mut command = Command::new("cargo");
     command.current_dir(".");
     command.arg("run");
+    command.arg("--");
+    if optimize { command.arg("-O"); }
     command.arg(file_path);
     println!("{:?}", command);
     let output = command.output().unwrap();
@@ -53,14 +55,251 @@ fn exec_tests()
         if file_name.ends_with(".c") {
             let run_example = run_examples.get(file_name).is_some();
             let file_path = file_path.display().to_string();
-            compile_and_run(&file_path, run_example);
+            compile_and_run(&file_path, run_example, false);
         }
     }
 
     for file in fs::read_dir("./tests").unwrap() {
         let file_path = file.unwrap().path().display().to_string();
         if file_path.ends_with(".c") {
-            compile_and_run(&file_path, true);
+            compile_and_run(&file_path, true, false);
+
+            // The optimized program must behave the same
+            compile_and_run(&file_path, true, true);
         }
     }
 }
+
+/// Compile and run a program, feeding it the given input, and
+/// return the standard output of the VM
+fn compile_and_run_with_input(file_path: &str, input: &str) -> String
+{
+    compile_and_run_with(file_path, input, &[])
+}
+
+/// Same as compile_and_run_with_input, passing extra options to the VM
+fn compile_and_run_with(file_path: &str, input: &str, vm_args: &[&str]) -> String
+{
+    let file_stem = std::path::Path::new(file_path).file_stem().unwrap().to_str().unwrap();
+    let out_file = std::env::temp_dir().join(format!("ncc_input_{}.asm", file_stem)).display().to_string();
+
+    let mut command = Command::new("cargo");
+    command.current_dir(".");
+    command.args(["run", "--", "-o", &out_file, file_path]);
+    println!("{:?}", command);
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "compilation failed");
+
+    let mut command = Command::new("cargo");
+    command.current_dir("../vm");
+    command.args(["run", "--"]);
+    command.args(vm_args);
+    command.arg(&out_file);
+    command.stdin(Stdio::piped());
+    command.stdout(Stdio::piped());
+    println!("{:?}", command);
+    let mut child = command.spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "execution failed");
+
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn exec_tests_input()
+{
+    let stdout = compile_and_run_with_input("tests/input/echo.c", "hello\nthis is a long line\n");
+    assert_eq!(stdout, "first: 104\nello\nthis is a long line\nreads: 4\n");
+
+    let stdout = compile_and_run_with_input("tests/input/echo.c", "");
+    assert_eq!(stdout, "no input\n");
+}
+
+#[test]
+fn exec_tests_files()
+{
+    let root = std::env::temp_dir().join(format!("ncc_files_{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+
+    let root_arg = root.display().to_string();
+    let stdout = compile_and_run_with("tests/files/roundtrip.c", "", &["--fs-root", &root_arg]);
+    assert_eq!(stdout, "written: 10\nread: 11 hello file!\nclosed: 0 -1\nparent: -1\nabsolute: -1\nmissing: -1\n");
+    assert_eq!(fs::read_to_string(root.join("data.txt")).unwrap(), "hello file!");
+
+    // Without filesystem access, nothing can be opened
+    let stdout = compile_and_run_with("tests/files/roundtrip.c", "", &["--no-fs"]);
+    assert_eq!(stdout, "open for writing failed\n");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn exec_tests_heap_exhaust()
+{
+    // With 1MiB of memory, the heap fills up after about a thousand blocks
+    compile_and_run_with("tests/heap/exhaust.c", "", &["--mem-size", "1048576"]);
+}
+
+/// Compile and run a program, returning the exit status of the VM
+fn compile_and_get_status(file_path: &str) -> i32
+{
+    let file_stem = std::path::Path::new(file_path).file_stem().unwrap().to_str().unwrap();
+    let out_file = std::env::temp_dir().join(format!("ncc_status_{}.asm", file_stem)).display().to_string();
+
+    let mut command = Command::new("cargo");
+    command.current_dir(".");
+    command.args(["run", "--", "-o", &out_file, file_path]);
+    println!("{:?}", command);
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "compilation failed");
+
+    let mut command = Command::new("cargo");
+    command.current_dir("../vm");
+    command.args(["run", "--", &out_file]);
+    println!("{:?}", command);
+    let output = command.output().unwrap();
+    output.status.code().unwrap()
+}
+
+#[test]
+fn exec_tests_exit_status()
+{
+    // cargo run exits with the status of the program it runs
+    assert_eq!(compile_and_get_status("tests/exit/ret_val.c"), 7);
+    assert_eq!(compile_and_get_status("tests/exit/void_main.c"), 0);
+    assert_eq!(compile_and_get_status("tests/exit/deep_exit.c"), 3);
+}
+
+/// Compile and run a program which is expected to panic,
+/// returning the standard and error output of the VM
+fn compile_and_panic(file_path: &str, debug_info: bool) -> (String, String)
+{
+    compile_and_panic_with(file_path, debug_info, &[])
+}
+
+/// Same as compile_and_panic, passing extra options to the VM
+fn compile_and_panic_with(file_path: &str, debug_info: bool, vm_args: &[&str]) -> (String, String)
+{
+    // Tests run in parallel, so each gets its own output file
+    let file_stem = std::path::Path::new(file_path).file_stem().unwrap().to_str().unwrap();
+    let out_name = format!("ncc_panic_{}_{}.asm", file_stem, debug_info);
+    let out_file = std::env::temp_dir().join(out_name).display().to_string();
+
+    let mut command = Command::new("cargo");
+    command.current_dir(".");
+    command.arg("run");
+    command.arg("--");
+    if debug_info { command.arg("-g"); }
+    command.args(["-o", &out_file, file_path]);
+    println!("{:?}", command);
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "compilation failed");
+
+    let mut command = Command::new("cargo");
+    command.current_dir("../vm");
+    command.args(["run", "--"]);
+    command.args(vm_args);
+    command.arg(&out_file);
+    println!("{:?}", command);
+    let output = command.output().unwrap();
+    assert!(!output.status.success(), "execution should have failed");
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    (stdout, stderr)
+}
+
+#[test]
+fn exec_tests_panic_location()
+{
+    // Panics report the source position with debug info
+    let (_, stderr) = compile_and_panic("tests/panics/div_zero.c", true);
+    assert!(stderr.contains("panicked at tests/panics/div_zero.c:6:5\n"), "{}", stderr);
+
+    // Without debug info the program fails the same way
+    let (_, stderr) = compile_and_panic("tests/panics/div_zero.c", false);
+    assert!(!stderr.contains("div_zero.c"), "{}", stderr);
+}
+
+#[test]
+fn exec_tests_panic_call_stack()
+{
+    // Frames are printed from the innermost outward
+    let (_, stderr) = compile_and_panic("tests/panics/call_stack.c", true);
+    let frames = [
+        "#0 b at tests/panics/call_stack.c:8:5",
+        "#1 a at tests/panics/call_stack.c:13:5",
+        "#2 main at tests/panics/call_stack.c:18:5",
+    ];
+    let positions: Vec<usize> = frames.iter().map(|frame| {
+        match stderr.find(frame) {
+            Some(pos) => pos,
+            None => panic!("missing frame \"{}\" in:\n{}", frame, stderr),
+        }
+    }).collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", stderr);
+
+    // Function names are known without debug info
+    let (_, stderr) = compile_and_panic("tests/panics/call_stack.c", false);
+    assert!(stderr.contains("#0 b at address"), "{}", stderr);
+    assert!(stderr.contains("#2 main at address"), "{}", stderr);
+}
+
+#[test]
+fn exec_tests_panic_assert()
+{
+    // Failed asserts print the expression, its location and the message
+    let (stdout, stderr) = compile_and_panic("tests/panics/assert_msg.c", false);
+    assert_eq!(stdout, "tests/panics/assert_msg.c:10: assertion failed: (n % 2) == 0: n must be even\n");
+    assert!(stderr.contains("#0 check at address"), "{}", stderr);
+
+    let (stdout, _) = compile_and_panic("tests/panics/call_stack.c", false);
+    assert_eq!(stdout, "tests/panics/call_stack.c:8: assertion failed: n < 3\n");
+}
+
+#[test]
+fn exec_tests_panic_double_free()
+{
+    let (stdout, _) = compile_and_panic("tests/panics/double_free.c", false);
+    assert_eq!(stdout, "double free detected in free()\n");
+}
+
+#[test]
+fn exec_tests_panic_null_deref()
+{
+    let (_, stderr) = compile_and_panic_with("tests/panics/null_deref.c", true, &["--null-check"]);
+    // Reported as a trap, with the access which failed
+    assert!(stderr.contains("null pointer dereference: load_u32 of 4 bytes at address 0, pc"), "{}", stderr);
+    assert!(stderr.contains("panicked at tests/panics/null_deref.c:6:5\n"), "{}", stderr);
+}
+
+#[test]
+fn exec_tests_panic_trap()
+{
+    let (_, stderr) = compile_and_panic("tests/panics/mod_zero.c", true);
+    assert!(stderr.contains("division by zero: mod_i32"), "{}", stderr);
+    assert!(stderr.contains("panicked at tests/panics/mod_zero.c:6:5\n"), "{}", stderr);
+
+    // Traps are reported by the VM rather than by a Rust panic
+    assert!(!stderr.contains("thread 'main' panicked"), "{}", stderr);
+    assert_eq!(compile_and_get_status("tests/panics/mod_zero.c"), 101);
+
+    // Signed division overflow wraps around unless checked
+    assert_eq!(compile_and_get_status("tests/panics/overflow.c"), 0);
+    let (_, stderr) = compile_and_panic_with("tests/panics/overflow.c", true, &["--overflow-check"]);
+    assert!(stderr.contains("integer overflow: div_i32"), "{}", stderr);
+    assert!(stderr.contains("panicked at tests/panics/overflow.c:6:5\n"), "{}", stderr);
+}
+
+#[test]
+fn exec_tests_panic_stack_overflow()
+{
+    let (_, stderr) = compile_and_panic("tests/panics/stack_overflow.c", true);
+    assert!(stderr.contains("stack overflow: call depth exceeds 1000 frames"), "{}", stderr);
+    assert!(stderr.contains("frames omitted"), "{}", stderr);
+
+    // A higher limit only changes where the overflow happens
+    let (_, stderr) = compile_and_panic_with("tests/panics/stack_overflow.c", true, &["--max-call-depth", "5000"]);
+    assert!(stderr.contains("stack overflow: call depth exceeds 5000 frames"), "{}", stderr);
+}
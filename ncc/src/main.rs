@@ -10,7 +10,9 @@ mod parser;
 mod ast;
 mod symbols;
 mod types;
+mod consts;
 mod codegen;
+mod peephole;
 mod exec_tests;
 
 use std::env;
@@ -28,6 +30,15 @@ struct Options
     // Print the preprocessor output
     print_cpp_out: bool,
 
+    // Report warnings as errors
+    warnings_as_errors: bool,
+
+    // Run the peephole optimizer on the generated code
+    optimize: bool,
+
+    // Emit source positions so runtime errors can be located
+    debug_info: bool,
+
     // Output file
     out_file: String,
 
@@ -38,6 +49,9 @@ fn parse_args(args: Vec<String>) -> Options
 {
     let mut opts = Options {
         print_cpp_out: false,
+        warnings_as_errors: false,
+        optimize: false,
+        debug_info: false,
         out_file: "out.asm".to_string(),
         rest: Vec::default(),
     };
@@ -64,6 +78,18 @@ fn parse_args(args: Vec<String>) -> Options
                 opts.print_cpp_out = true;
             }
 
+            "-Werror" => {
+                opts.warnings_as_errors = true;
+            }
+
+            "-O" => {
+                opts.optimize = true;
+            }
+
+            "-g" => {
+                opts.debug_info = true;
+            }
+
             "-o" => {
                 opts.out_file = args[idx].clone();
                 idx += 1;
@@ -76,6 +102,16 @@ fn parse_args(args: Vec<String>) -> Options
     opts
 }
 
+/// Report all the errors but the last one, which is returned
+fn report_errors(mut errors: Vec<ParseError>) -> ParseError
+{
+    let last_error = errors.pop().unwrap();
+    for error in errors {
+        println!("{}", error);
+    }
+    last_error
+}
+
 fn compile_file(file_name: &str, opts: &Options) -> Result<(), ParseError>
 {
     let mut input = Input::from_file(file_name)?;
@@ -87,17 +123,33 @@ fn compile_file(file_name: &str, opts: &Options) -> Result<(), ParseError>
     }
 
     let mut input = Input::new(&output, file_name);
-    let mut unit = parse_unit(&mut input)?;
+    input.set_warnings_as_errors(opts.warnings_as_errors);
+    let (mut unit, warnings) = parse_unit_all_errors(&mut input).map_err(report_errors)?;
+
+    for warning in &warnings {
+        println!("{}", warning);
+    }
+
+    unit.resolve_syms_all_errors().map_err(report_errors)?;
+    unit.check_types_all_errors().map_err(report_errors)?;
+
+    unit.fold_global_inits()?;
+    unit.fold_constants()?;
 
-    unit.resolve_syms()?;
-    unit.check_types()?;
-    let out = unit.gen_code()?;
+    let mut out = unit.gen_code_with_debug(opts.debug_info)?;
+
+    if opts.optimize {
+        out = peephole::optimize(&out);
+    }
 
     std::fs::write(&opts.out_file, out).unwrap();
 
     Ok(())
 }
 
+/// Stack size of the compiler thread, in bytes
+const COMPILER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
 fn main()
 {
     let opts = parse_args(env::args().collect());
@@ -107,17 +159,17 @@ fn main()
         panic!("Must specify exactly one input source file to compile.");
     }
 
-    let file_name = &opts.rest[0];
-    let result = compile_file(file_name, &opts);
+    // Parsing and the passes after it are recursive, so compile on a
+    // thread with a stack large enough for the maximum nesting depth
+    let result = std::thread::Builder::new()
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(move || compile_file(&opts.rest[0], &opts))
+        .unwrap()
+        .join()
+        .unwrap();
 
     if let Err(error) = result {
-        if error.line_no != 0 {
-            println!("Error {}@{}:{}: {}", error.src_name, error.line_no, error.col_no, error.msg);
-        } else
-        {
-            println!("Error: {}", error.msg);
-        }
-
+        println!("{}", error);
         std::process::exit(-1);
     }
 }
@@ -7,57 +7,131 @@ use std::cmp::max;
 use crate::parsing::*;
 use crate::ast::*;
 
-/// Parse an atomic expression
-fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
+/// Keywords that can't be used as variable, parameter or function names
+const RESERVED_KEYWORDS: [&str; 36] = [
+    "asm", "assert", "break", "continue", "do", "else", "extern", "for", "if", "inline",
+    "return", "sizeof", "struct", "typedef", "while",
+    "NULL", "null", "true", "false",
+    "unsigned", "void", "float",
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64",
+    "size_t", "char", "bool", "short", "int", "long",
+];
+
+/// Parse the optional type suffix of an integer literal, e.g. 42u64,
+/// and produce the literal expression. The literal is negated if it
+/// is the operand of a unary minus.
+fn parse_int_suffix(input: &mut Input, value: u64, negated: bool, start_pos: SrcPos, span: Span) -> Result<Expr, ParseError>
 {
-    input.eat_ws()?;
-    let ch = input.peek_ch();
+    let signed_value = if negated { -(value as i128) } else { value as i128 };
+
+    if !is_ident_ch(input.peek_ch()) {
+        return Ok(Expr::new(ExprKind::Int { value: signed_value, suffix: None }, span));
+    }
+
+    let suffix_pos = input.get_pos();
+    let suffix_str = input.collect(|input| {
+        while is_ident_ch(input.peek_ch()) {
+            input.eat_ch();
+        }
+        Ok(())
+    })?;
+
+    let suffix = match IntSuffix::from_str(&suffix_str) {
+        Some(suffix) => suffix,
+        None => return input.parse_error_at(
+            suffix_pos,
+            ErrorKind::InvalidLiteral,
+            &format!("invalid suffix \"{}\" on integer literal", suffix_str)
+        )
+    };
+
+    // Negated signed literals may be one past the maximum value,
+    // so that the minimum value can be written, e.g. -128i8
+    let suffix_type = suffix.get_type();
+    let num_bits = suffix_type.num_bits();
+    let max_val: u128 = match suffix_type {
+        Type::Int(_) if negated => 1 << (num_bits - 1),
+        Type::Int(_) => (1 << (num_bits - 1)) - 1,
+        _ => (1 << num_bits) - 1,
+    };
+
+    if value as u128 > max_val {
+        return input.parse_error_at(
+            start_pos,
+            ErrorKind::InvalidLiteral,
+            &format!("integer literal {} does not fit in {}", signed_value, suffix_type)
+        );
+    }
+
+    Ok(Expr::new(ExprKind::Int { value: signed_value, suffix: Some(suffix) }, span))
+}
+
+/// Parse a numeric literal, negating it if it
+/// is the operand of a unary minus
+fn parse_number(input: &mut Input, negated: bool) -> Result<Expr, ParseError>
+{
+    let start_pos = input.get_pos();
+    let span = get_span(input);
 
     // Hexadecimal integer literal
     if input.match_token("0x")? {
-        let val = input.parse_int(16)?;
-        return Ok(Expr::Int(val));
+        let val = input.parse_uint(16)?;
+        return parse_int_suffix(input, val, negated, start_pos, span);
     }
 
     // Binary integer literal
     if input.match_token("0b")? {
-        let val = input.parse_int(2)?;
-        return Ok(Expr::Int(val));
+        let val = input.parse_uint(2)?;
+        return parse_int_suffix(input, val, negated, start_pos, span);
     }
 
-    // Decimal numeric value
-    if ch.is_digit(10) {
-        let num_str = input.read_numeric();
-        //println!("{}", num_str);
+    let num_str = input.read_numeric();
+    //println!("{}", num_str);
 
-        // If we can parse this value as an integer
-        if let Ok(int_val) = num_str.parse::<i128>() {
-            return Ok(Expr::Int(int_val));
-        }
+    // Integer literals must fit in 64 bits
+    if num_str.chars().all(|ch| ch.is_ascii_digit()) {
+        return match num_str.parse::<u64>() {
+            Ok(int_val) => parse_int_suffix(input, int_val, negated, start_pos, span),
+            Err(_) => input.parse_error_at(start_pos, ErrorKind::InvalidLiteral, "integer literal too large"),
+        };
+    }
 
-        // Parse this value as a floating-point number
-        let float_val: f32 = num_str.parse().unwrap();
+    // Parse this value as a floating-point number
+    let float_val: f32 = num_str.parse().unwrap();
 
-        if !input.match_char('f') {
-            return input.parse_error(&concat!("
-                only floats are supported for now, ",
-                "e.g. 3.5f (float), not 3.5 (double)"
-            ));
-        }
+    if !input.match_char('f') {
+        return input.parse_error(ErrorKind::InvalidLiteral, &concat!("
+            only floats are supported for now, ",
+            "e.g. 3.5f (float), not 3.5 (double)"
+        ));
+    }
+
+    let float_val = if negated { -float_val } else { float_val };
+    Ok(Expr::new(ExprKind::Float32(float_val), span))
+}
+
+/// Parse an atomic expression
+fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
+{
+    input.eat_ws()?;
+    let ch = input.peek_ch();
+    let span = get_span(input);
 
-        return Ok(Expr::Float32(float_val));
+    // Numeric literal
+    if ch.is_ascii_digit() {
+        return parse_number(input, false);
     }
 
     if input.match_keyword("NULL")? || input.match_keyword("null")? {
-        return Ok(Expr::Int(0));
+        return Ok(Expr::new(ExprKind::Int { value: 0, suffix: None }, span));
     }
 
     if input.match_keyword("true")? {
-        return Ok(Expr::Int(1));
+        return Ok(Expr::new(ExprKind::Int { value: 1, suffix: None }, span));
     }
 
     if input.match_keyword("false")? {
-        return Ok(Expr::Int(0));
+        return Ok(Expr::new(ExprKind::Int { value: 0, suffix: None }, span));
     }
 
     // String literal
@@ -67,12 +141,12 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
         {
             str_val += &input.parse_str('"')?;
             input.eat_ws()?;
-            if input.peek_ch() != '\"' {
+            if input.peek_ch() != '"' {
                 break;
             }
         }
 
-        return Ok(Expr::String(str_val));
+        return Ok(Expr::new(ExprKind::String(str_val), span));
     }
 
     // Character literal
@@ -81,14 +155,15 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
         let chars: Vec<char> = char_str.chars().collect();
 
         if chars.len() != 1 {
-            return input.parse_error("invalid character constant");
+            return input.parse_error(ErrorKind::InvalidLiteral, "invalid character constant");
         }
 
-        return Ok(Expr::Int(chars[0] as i128));
+        return Ok(Expr::new(ExprKind::Int { value: chars[0] as i128, suffix: None }, span));
     }
 
     // Parenthesized expression or type casting expression
     if ch == '(' {
+        let open_pos = input.get_pos();
         input.eat_ch();
 
         // Try to parse this as a type casting expression
@@ -100,10 +175,10 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
             // Expression being casted
             let child_expr = parse_prefix(input)?;
 
-            Ok(Expr::Cast {
+            Ok(Expr::new(ExprKind::Cast {
                 new_type,
                 child: Box::new(child_expr)
-            })
+            }, span.clone()))
         });
 
         // If the parsing as a type casting expression was successful
@@ -112,16 +187,19 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
         }
 
         // Try parsing this as an expression
-        let expr = parse_expr(input)?;
-        input.expect_token(")")?;
+        // The span of the expression includes the parentheses
+        let mut expr = parse_expr(input)?;
+        input.expect_closing(")", open_pos, "(")?;
+        expr.span = span;
         return Ok(expr);
     }
 
     // Array literal
     if ch == '{' {
+        let open_pos = input.get_pos();
         input.eat_ch();
-        let elem_exprs = parse_expr_list(input, "}")?;
-        return Ok(Expr::Array(elem_exprs));
+        let elem_exprs = parse_expr_list(input, open_pos, "{", "}")?;
+        return Ok(Expr::new(ExprKind::Array(elem_exprs), span));
     }
 
     // Sizeof expression
@@ -132,7 +210,7 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
         let sizeof_expr = input.with_backtracking(|input| {
             let t = parse_type(input)?;
             input.expect_token(")")?;
-            Ok(Expr::SizeofType { t })
+            Ok(Expr::new(ExprKind::SizeofType { t }, span.clone()))
         });
 
         if sizeof_expr.is_ok() {
@@ -142,34 +220,35 @@ fn parse_atom(input: &mut Input) -> Result<Expr, ParseError>
         // Try parsing this as sizeof(expr)
         let expr = parse_expr(input)?;
         input.expect_token(")")?;
-        return Ok(Expr::SizeofExpr {
+        return Ok(Expr::new(ExprKind::SizeofExpr {
             child: Box::new(expr)
-        });
+        }, span));
     }
 
     // Inline assembly expression
     if input.match_token("asm")? {
-        return parse_asm_expr(input);
+        return parse_asm_expr(input, span);
     }
 
     // Identifier (variable reference)
     if is_ident_start(ch) {
         let ident = input.parse_ident()?;
-        return Ok(Expr::Ident(ident));
+        return Ok(Expr::new(ExprKind::Ident(ident), span));
     }
 
-    input.parse_error("unknown atomic expression")
+    input.parse_error(ErrorKind::ExpectedExpression, "unknown atomic expression")
 }
 
 /// Parse a function call expression
-fn parse_call_expr(input: &mut Input, callee: Expr) -> Result<Expr, ParseError>
+fn parse_call_expr(input: &mut Input, callee: Expr, open_pos: SrcPos) -> Result<Expr, ParseError>
 {
-    let arg_exprs = parse_expr_list(input, ")")?;
+    let arg_exprs = parse_expr_list(input, open_pos, "(", ")")?;
+    let span = callee.span.clone();
 
-    Ok(Expr::Call {
+    Ok(Expr::new(ExprKind::Call {
         callee: Box::new(callee),
         args: arg_exprs
-    })
+    }, span))
 }
 
 /// Parse a postfix expression
@@ -179,9 +258,13 @@ fn parse_postfix(input: &mut Input) -> Result<Expr, ParseError>
 
     loop
     {
+        end_span(input, &mut base_expr.span);
+
         // If this is a function call
+        input.eat_ws()?;
+        let open_pos = input.get_pos();
         if input.match_token("(")? {
-            base_expr = parse_call_expr(input, base_expr)?;
+            base_expr = parse_call_expr(input, base_expr, open_pos)?;
             continue;
         }
 
@@ -191,14 +274,16 @@ fn parse_postfix(input: &mut Input) -> Result<Expr, ParseError>
             input.expect_token("]")?;
 
             // Transform into dereferencing and pointer addition
-            base_expr = Expr::Unary {
+            let mut span = base_expr.span.clone();
+            end_span(input, &mut span);
+            base_expr = Expr::new(ExprKind::Unary {
                 op: UnOp::Deref,
-                child: Box::new(Expr::Binary {
+                child: Box::new(Expr::new(ExprKind::Binary {
                     op: BinOp::Add,
                     lhs: Box::new(base_expr),
                     rhs: Box::new(index_expr),
-                })
-            };
+                }, span.clone()))
+            }, span);
 
             continue;
         }
@@ -206,10 +291,13 @@ fn parse_postfix(input: &mut Input) -> Result<Expr, ParseError>
         // Arrow operator (a->b)
         if input.match_token("->")? {
             let field_name = input.parse_ident()?;
-            base_expr = Expr::Arrow {
+            let span = base_expr.span.clone();
+            base_expr = Expr::new(ExprKind::Arrow {
                 base: Box::new(base_expr),
                 field: field_name
-            };
+            }, span);
+
+            continue;
         }
 
         // Postfix increment expression
@@ -242,19 +330,29 @@ fn parse_postfix(input: &mut Input) -> Result<Expr, ParseError>
 /// Note: this function should only call parse_postfix directly
 /// to respect the priority of operations in C
 fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
+{
+    // Nested expressions, e.g. parentheses and unary
+    // operators, all recurse through here
+    let mut expr = input.nested(parse_prefix_inner)?;
+    end_span(input, &mut expr.span);
+    Ok(expr)
+}
+
+fn parse_prefix_inner(input: &mut Input) -> Result<Expr, ParseError>
 {
     input.eat_ws()?;
     let ch = input.peek_ch();
+    let span = get_span(input);
 
     // Unary logical not expression
     if ch == '!' {
         input.eat_ch();
         let sub_expr = parse_prefix(input)?;
 
-        return Ok(Expr::Unary{
+        return Ok(Expr::new(ExprKind::Unary {
             op: UnOp::Not,
             child: Box::new(sub_expr)
-        });
+        }, span));
     }
 
     // Pre-increment expression
@@ -263,15 +361,15 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
 
         // Transform into i = i + 1
         return Ok(
-            Expr::Binary{
+            Expr::new(ExprKind::Binary {
                 op: BinOp::Assign,
                 lhs: Box::new(sub_expr.clone()),
-                rhs: Box::new(Expr::Binary{
+                rhs: Box::new(Expr::new(ExprKind::Binary {
                     op: BinOp::Add,
                     lhs: Box::new(sub_expr.clone()),
-                    rhs: Box::new(Expr::Int(1))
-                })
-            }
+                    rhs: Box::new(Expr::new(ExprKind::Int { value: 1, suffix: None }, span.clone()))
+                }, span.clone()))
+            }, span)
         );
     }
 
@@ -281,31 +379,41 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
 
         // Transform into i = i - 1
         return Ok(
-            Expr::Binary{
+            Expr::new(ExprKind::Binary {
                 op: BinOp::Assign,
                 lhs: Box::new(sub_expr.clone()),
-                rhs: Box::new(Expr::Binary{
+                rhs: Box::new(Expr::new(ExprKind::Binary {
                     op: BinOp::Sub,
                     lhs: Box::new(sub_expr.clone()),
-                    rhs: Box::new(Expr::Int(1))
-                })
-            }
+                    rhs: Box::new(Expr::new(ExprKind::Int { value: 1, suffix: None }, span.clone()))
+                }, span.clone()))
+            }, span)
         );
     }
 
     // Unary minus expression
     if ch == '-' {
         input.eat_ch();
+
+        // Negated literals are parsed together with the minus,
+        // since they can hold the minimum value of their type
+        input.eat_ws()?;
+        if input.peek_ch().is_ascii_digit() {
+            let mut expr = parse_number(input, true)?;
+            expr.span = span;
+            return Ok(expr);
+        }
+
         let sub_expr = parse_prefix(input)?;
 
         // If this is an integer or floating-point value, negate it
-        let expr = match sub_expr {
-            Expr::Int(int_val) => Expr::Int(-int_val),
-            Expr::Float32(f_val) => Expr::Float32(-f_val),
-            _ => Expr::Unary{
+        let expr = match sub_expr.kind {
+            ExprKind::Int { value, suffix } => Expr::new(ExprKind::Int { value: -value, suffix }, span),
+            ExprKind::Float32(f_val) => Expr::new(ExprKind::Float32(-f_val), span),
+            _ => Expr::new(ExprKind::Unary {
                 op: UnOp::Minus,
                 child: Box::new(sub_expr)
-            }
+            }, span)
         };
 
         return Ok(expr)
@@ -317,10 +425,10 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
         let sub_expr = parse_prefix(input)?;
 
         // If this is an integer or floating-point value, negate it
-        let expr = match sub_expr {
-            Expr::Int(int_val) => sub_expr,
-            Expr::Float32(f_val) => sub_expr,
-            _ => return input.parse_error("plus operator applied to non-constant value")
+        let expr = match sub_expr.kind {
+            ExprKind::Int { value: int_val, .. } => sub_expr,
+            ExprKind::Float32(f_val) => sub_expr,
+            _ => return input.parse_error(ErrorKind::Other, "plus operator applied to non-constant value")
         };
 
         return Ok(expr)
@@ -331,10 +439,10 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
         input.eat_ch();
         let sub_expr = parse_prefix(input)?;
 
-        return Ok(Expr::Unary{
+        return Ok(Expr::new(ExprKind::Unary {
             op: UnOp::BitNot,
             child: Box::new(sub_expr)
-        });
+        }, span));
     }
 
     // Pointer dereference
@@ -342,10 +450,10 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
         input.eat_ch();
         let sub_expr = parse_prefix(input)?;
 
-        return Ok(Expr::Unary{
+        return Ok(Expr::new(ExprKind::Unary {
             op: UnOp::Deref,
             child: Box::new(sub_expr)
-        });
+        }, span));
     }
 
     // Address of operator
@@ -353,18 +461,23 @@ fn parse_prefix(input: &mut Input) -> Result<Expr, ParseError>
         input.eat_ch();
         let sub_expr = parse_prefix(input)?;
 
-        return Ok(Expr::Unary{
+        return Ok(Expr::new(ExprKind::Unary {
             op: UnOp::AddressOf,
             child: Box::new(sub_expr)
-        });
+        }, span));
     }
 
     // Try to parse this as a postfix expression
     parse_postfix(input)
 }
 
-/// Parse a list of argument expressions
-fn parse_expr_list(input: &mut Input, end_token: &str) -> Result<Vec<Expr>, ParseError>
+/// Parse a list of argument expressions, after the opening token
+fn parse_expr_list(
+    input: &mut Input,
+    open_pos: SrcPos,
+    open_token: &str,
+    end_token: &str
+) -> Result<Vec<Expr>, ParseError>
 {
     let mut arg_exprs = Vec::default();
 
@@ -372,7 +485,12 @@ fn parse_expr_list(input: &mut Input, end_token: &str) -> Result<Vec<Expr>, Pars
         input.eat_ws()?;
 
         if input.eof() {
-            return input.parse_error("unexpected end of input in call expression");
+            return input.unclosed_error(
+                open_pos,
+                open_token,
+                ErrorKind::UnexpectedEof,
+                "unexpected end of input in expression list"
+            );
         }
 
         if input.match_token(end_token)? {
@@ -388,17 +506,19 @@ fn parse_expr_list(input: &mut Input, end_token: &str) -> Result<Vec<Expr>, Pars
 
         // If this isn't the last argument, there
         // has to be a comma separator
-        input.expect_token(",")?;
+        input.expect_closing(",", open_pos, open_token)?;
     }
 
     Ok(arg_exprs)
 }
 
 /// Parse an inline assembly expression
-fn parse_asm_expr(input: &mut Input) -> Result<Expr, ParseError>
+fn parse_asm_expr(input: &mut Input, span: Span) -> Result<Expr, ParseError>
 {
+    input.eat_ws()?;
+    let open_pos = input.get_pos();
     input.expect_token("(")?;
-    let arg_exprs = parse_expr_list(input, ")")?;
+    let arg_exprs = parse_expr_list(input, open_pos, "(", ")")?;
     input.expect_token("->")?;
     let out_type = parse_type(input)?;
     input.expect_token("{")?;
@@ -407,7 +527,7 @@ fn parse_asm_expr(input: &mut Input) -> Result<Expr, ParseError>
 
     loop {
         if input.eof() {
-            return input.parse_error("unexpected end of input in asm expression");
+            return input.parse_error(ErrorKind::UnexpectedEof, "unexpected end of input in asm expression");
         }
 
         let ch = input.peek_ch();
@@ -440,11 +560,11 @@ fn parse_asm_expr(input: &mut Input) -> Result<Expr, ParseError>
     // Trim leading and trailing whitespace
     let text = text.trim().to_string();
 
-    Ok(Expr::Asm {
+    Ok(Expr::new(ExprKind::Asm {
         text,
         args: arg_exprs,
         out_type
-    })
+    }, span))
 }
 
 struct OpInfo
@@ -543,11 +663,12 @@ fn parse_infix_expr(input: &mut Input, no_comma: bool) -> Result<Expr, ParseErro
                 let lhs = expr_stack.pop().unwrap();
                 let top_op = op_stack.pop().unwrap();
 
-                expr_stack.push(Expr::Binary {
+                let span = lhs.span.to(&rhs.span);
+                expr_stack.push(Expr::new(ExprKind::Binary {
                     op: top_op.op,
                     lhs: Box::new(lhs),
                     rhs: Box::new(rhs)
-                });
+                }, span));
             }
             else {
                 break;
@@ -572,11 +693,12 @@ fn parse_infix_expr(input: &mut Input, no_comma: bool) -> Result<Expr, ParseErro
             input.expect_token(":")?;
             let else_expr = parse_expr(input)?;
 
-            expr_stack.push(Expr::Ternary {
+            let span = test_expr.span.to(&else_expr.span);
+            expr_stack.push(Expr::new(ExprKind::Ternary {
                 test_expr: Box::new(test_expr),
                 then_expr: Box::new(then_expr),
                 else_expr: Box::new(else_expr),
-            });
+            }, span));
 
             break;
         }
@@ -598,11 +720,12 @@ fn parse_infix_expr(input: &mut Input, no_comma: bool) -> Result<Expr, ParseErro
 
             let lhs = expr_stack.pop().unwrap();
 
-            expr_stack.push(Expr::Binary {
+            let span = lhs.span.to(&rhs.span);
+            expr_stack.push(Expr::new(ExprKind::Binary {
                 op: new_op.op,
                 lhs: Box::new(lhs),
                 rhs: Box::new(rhs)
-            });
+            }, span));
 
             break;
         }
@@ -624,11 +747,12 @@ fn parse_infix_expr(input: &mut Input, no_comma: bool) -> Result<Expr, ParseErro
         let lhs = expr_stack.pop().unwrap();
         let top_op = op_stack.pop().unwrap();
 
-        expr_stack.push(Expr::Binary {
+        let span = lhs.span.to(&rhs.span);
+        expr_stack.push(Expr::new(ExprKind::Binary {
             op: top_op.op,
             lhs: Box::new(lhs),
             rhs: Box::new(rhs)
-        });
+        }, span));
     }
 
     assert!(expr_stack.len() == 1);
@@ -638,14 +762,29 @@ fn parse_infix_expr(input: &mut Input, no_comma: bool) -> Result<Expr, ParseErro
 /// Parse a block statement
 fn parse_block_stmt(input: &mut Input) -> Result<Stmt, ParseError>
 {
+    input.eat_ws()?;
+    let span = get_span(input);
+    let open_pos = input.get_pos();
     input.expect_token("{")?;
 
     let mut stmts = Vec::default();
 
+    // Set once a return statement is seen, so that the statement
+    // following it is reported, once per block
+    let mut after_return = false;
+    let mut reported_unreachable = false;
+
     loop
     {
+        input.eat_ws()?;
+
         if input.eof() {
-            return input.parse_error("unexpected end of input in block statement");
+            return input.unclosed_error(
+                open_pos,
+                "{",
+                ErrorKind::UnexpectedEof,
+                "unexpected end of input in block statement"
+            );
         }
 
         if input.match_token("}")? {
@@ -657,16 +796,86 @@ fn parse_block_stmt(input: &mut Input) -> Result<Stmt, ParseError>
             continue;
         }
 
-        stmts.push(parse_stmt(input)?);
+        let stmt_start = input.get_pos();
+
+        match parse_stmt(input) {
+            Ok(stmt) => {
+                if after_return && !reported_unreachable {
+                    input.warn(stmt_start, "statement after return is unreachable");
+                    reported_unreachable = true;
+                }
+
+                if let StmtKind::ReturnExpr(_) | StmtKind::ReturnVoid = stmt.kind {
+                    after_return = true;
+                }
+
+                stmts.push(stmt);
+            }
+            Err(error) => {
+                input.recover_from(error)?;
+                skip_to_stmt_end(input, stmt_start);
+            }
+        }
+    }
+
+    let mut span = span;
+    end_span(input, &mut span);
+    return Ok(Stmt::new(StmtKind::Block(stmts), span));
+}
+
+/// Skip forward after an error in a statement, up to and including
+/// the next semicolon or matching closing brace. A closing brace for
+/// the enclosing block is left in the input, but at least one character
+/// is always consumed past the start of the statement.
+fn skip_to_stmt_end(input: &mut Input, stmt_start: SrcPos)
+{
+    if input.get_pos() == stmt_start {
+        input.eat_ch();
     }
 
-    return Ok(Stmt::Block(stmts));
+    let mut depth = 0;
+
+    while !input.eof() {
+        match input.peek_ch() {
+            ';' if depth == 0 => {
+                input.eat_ch();
+                break;
+            }
+            '{' => {
+                depth += 1;
+            }
+            '}' if depth == 0 => {
+                break;
+            }
+            '}' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    input.eat_ch();
+                    break;
+                }
+            }
+            '"' | '\'' => {
+                // Skip string and character literals, which may contain ; or }
+                let end_ch = input.eat_ch();
+
+                while !input.eof() && input.peek_ch() != end_ch && input.peek_ch() != '\n' {
+                    if input.eat_ch() == '\\' {
+                        input.eat_ch();
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        input.eat_ch();
+    }
 }
 
 /// Try to parse a variable declaration
-fn parse_decl(input: &mut Input) -> Result<(Type, String, Option<Expr>), ParseError>
+fn parse_decl(input: &mut Input) -> Result<(Type, Span, String, Option<Expr>), ParseError>
 {
-    let var_type = parse_type(input)?;
+    let (var_type, type_span) = parse_type_span(input)?;
     let var_name = input.parse_ident()?;
 
     // For now, no support for local array variables
@@ -679,67 +888,139 @@ fn parse_decl(input: &mut Input) -> Result<(Type, String, Option<Expr>), ParseEr
         None
     };
 
-    Ok((var_type, var_name, init_expr))
+    Ok((var_type, type_span, var_name, init_expr))
+}
+
+/// Parse an assert statement, either assert expr; or
+/// assert(expr, "message"); with an optional message
+fn parse_assert(input: &mut Input, span: Span) -> Result<Stmt, ParseError>
+{
+    let mut msg = None;
+
+    let test_expr = if input.match_token("(")? {
+        // The comma separates the message
+        let test_expr = parse_infix_expr(input, true)?;
+
+        if input.match_token(",")? {
+            input.eat_ws()?;
+            if input.peek_ch() != '"' {
+                return input.parse_error(ErrorKind::UnexpectedToken, "expected message string in assert");
+            }
+
+            let mut msg_str = "".to_string();
+            while input.peek_ch() == '"' {
+                msg_str += &input.parse_str('"')?;
+                input.eat_ws()?;
+            }
+            msg = Some(msg_str);
+        }
+
+        input.expect_token(")")?;
+        test_expr
+    }
+    else
+    {
+        parse_expr(input)?
+    };
+
+    input.expect_token(";")?;
+
+    // Binary operations are displayed in parentheses
+    let mut expr_text = test_expr.to_string();
+    if let ExprKind::Binary { .. } = test_expr.kind {
+        expr_text = expr_text[1..expr_text.len() - 1].to_string();
+    }
+
+    Ok(Stmt::new(StmtKind::Assert { test_expr, msg, expr_text }, span))
 }
 
 /// Parse a statement
 fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
+{
+    // Block statements and statement bodies recurse through here
+    let mut stmt = input.nested(parse_stmt_inner)?;
+    end_span(input, &mut stmt.span);
+    Ok(stmt)
+}
+
+fn parse_stmt_inner(input: &mut Input) -> Result<Stmt, ParseError>
 {
     input.eat_ws()?;
+    let span = get_span(input);
 
     if input.match_keyword("return")? {
         if input.match_token(";")? {
-            return Ok(Stmt::ReturnVoid);
+            return Ok(Stmt::new(StmtKind::ReturnVoid, span));
         }
         else
         {
             let expr = parse_expr(input)?;
             input.expect_token(";")?;
             return Ok(
-                Stmt::ReturnExpr(Box::new(expr))
+                Stmt::new(StmtKind::ReturnExpr(Box::new(expr)), span)
             );
         }
     }
 
     if input.match_keyword("break")? {
         input.expect_token(";")?;
-        return Ok(Stmt::Break);
+        return Ok(Stmt::new(StmtKind::Break, span));
     }
 
     if input.match_keyword("continue")? {
         input.expect_token(";")?;
-        return Ok(Stmt::Continue);
+        return Ok(Stmt::new(StmtKind::Continue, span));
+    }
+
+    if input.match_keyword("assert")? {
+        return parse_assert(input, span);
     }
 
     // If-else statement
     if input.match_keyword("if")? {
-        // Parse the test expression
-        input.expect_token("(")?;
-        let test_expr = parse_expr(input)?;
-        input.expect_token(")")?;
+        // Else-if chains are flat in the source, so they are parsed
+        // in a loop and don't count toward the nesting depth
+        let mut branches = Vec::new();
+        let mut if_span = span;
+        let mut else_stmt = None;
 
-        // Parse the then statement
-        let then_stmt = parse_stmt(input)?;
+        loop
+        {
+            // Parse the test expression
+            input.expect_token("(")?;
+            let test_expr = parse_expr(input)?;
+            input.expect_token(")")?;
 
-        // If there is an else statement
-        if input.match_keyword("else")? {
-            // Parse the else statement
-            let else_stmt = parse_stmt(input)?;
+            // Parse the then statement
+            let then_stmt = parse_stmt(input)?;
+            branches.push((if_span, test_expr, then_stmt));
 
-            return Ok(Stmt::If {
-                test_expr,
-                then_stmt: Box::new(then_stmt),
-                else_stmt: Some(Box::new(else_stmt)),
-            });
+            if !input.match_keyword("else")? {
+                break;
+            }
+
+            input.eat_ws()?;
+            if_span = get_span(input);
+
+            if !input.match_keyword("if")? {
+                // Parse the else statement
+                else_stmt = Some(Box::new(parse_stmt(input)?));
+                break;
+            }
         }
-        else
-        {
-            return Ok(Stmt::If {
+
+        // Nest the branches from the last one, each else-if
+        // being the else statement of the branch before it
+        for (mut span, test_expr, then_stmt) in branches.into_iter().rev() {
+            end_span(input, &mut span);
+            else_stmt = Some(Box::new(Stmt::new(StmtKind::If {
                 test_expr,
                 then_stmt: Box::new(then_stmt),
-                else_stmt: None
-            });
+                else_stmt: else_stmt.take(),
+            }, span)));
         }
+
+        return Ok(*else_stmt.unwrap());
     }
 
     // While loop
@@ -752,10 +1033,10 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
         // Parse the loop body
         let body_stmt = parse_stmt(input)?;
 
-        return Ok(Stmt::While {
+        return Ok(Stmt::new(StmtKind::While {
             test_expr,
             body_stmt: Box::new(body_stmt),
-        });
+        }, span));
     }
 
     // Do-while loop
@@ -771,10 +1052,10 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
         input.expect_token(")")?;
         input.expect_token(";")?;
 
-        return Ok(Stmt::DoWhile {
+        return Ok(Stmt::new(StmtKind::DoWhile {
             test_expr,
             body_stmt: Box::new(body_stmt),
-        });
+        }, span));
     }
 
     // For loop
@@ -790,7 +1071,7 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
         };
 
         let test_expr = if input.match_token(";")? {
-            Expr::Int(1)
+            Expr::new(ExprKind::Int { value: 1, suffix: None }, span.clone())
         }
         else
         {
@@ -800,7 +1081,7 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
         };
 
         let incr_expr = if input.match_token(")")? {
-            Expr::Int(1)
+            Expr::new(ExprKind::Int { value: 1, suffix: None }, span.clone())
         }
         else
         {
@@ -812,12 +1093,12 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
         // Parse the loop body
         let body_stmt = parse_stmt(input)?;
 
-        return Ok(Stmt::For {
+        return Ok(Stmt::new(StmtKind::For {
             init_stmt,
             test_expr,
             incr_expr,
             body_stmt: Box::new(body_stmt),
-        });
+        }, span));
     }
 
     // Block statement
@@ -826,27 +1107,39 @@ fn parse_stmt(input: &mut Input) -> Result<Stmt, ParseError>
     }
 
     // Try to parse this as a variable declaration
-    let var_decl = input.with_backtracking(|input| parse_decl(input));
-    if let Ok((var_type, var_name, init_expr)) = var_decl {
+    let var_decl = match input.with_backtracking(|input| parse_decl(input)) {
+        // Parsing as an expression would fail the same way, and
+        // report a misleading error about the declaration instead
+        Err(error) if error.kind() == ErrorKind::NestingTooDeep => return Err(error),
+        var_decl => var_decl,
+    };
+
+    if let Ok((var_type, type_span, var_name, init_expr)) = var_decl {
         input.expect_token(";")?;
 
-        return Ok(Stmt::VarDecl {
+        return Ok(Stmt::new(StmtKind::VarDecl {
             var_type,
             var_name,
             init_expr,
-        });
+            type_span,
+        }, span));
     }
 
     // Try to parse this as an expression statement
     let expr = parse_expr(input)?;
     input.expect_token(";")?;
-    Ok(Stmt::Expr(expr))
+    Ok(Stmt::new(StmtKind::Expr(expr), span))
 }
 
 /// Parse an atomic type expression
 fn parse_type_atom(input: &mut Input) -> Result<Type, ParseError>
 {
     input.eat_ws()?;
+
+    if input.peek_ident() == "" {
+        return input.parse_error(ErrorKind::ExpectedType, "expected type name");
+    }
+
     let keyword = input.parse_ident()?;
 
     match keyword.as_str() {
@@ -929,6 +1222,16 @@ fn parse_type(input: &mut Input) -> Result<Type, ParseError>
     Ok(cur_type)
 }
 
+/// Parse a type name, along with the location of the type annotation
+fn parse_type_span(input: &mut Input) -> Result<(Type, Span), ParseError>
+{
+    input.eat_ws()?;
+    let mut span = get_span(input);
+    let t = parse_type(input)?;
+    end_span(input, &mut span);
+    Ok((t, span))
+}
+
 /// Parse an array type
 fn parse_array_type(input: &mut Input, base_type: Type) -> Result<Type, ParseError>
 {
@@ -962,17 +1265,18 @@ fn parse_struct(input: &mut Input) -> Result<Type, ParseError>
         input.eat_ws()?;
 
         if input.eof() {
-            return input.parse_error("unexpected end of input inside struct");
+            return input.parse_error(ErrorKind::UnexpectedEof, "unexpected end of input inside struct");
         }
 
         if input.match_token("}")? {
             break;
         }
 
-        // Parse one field name and its type
+        // Parse one field name and its type,
+        // with any array size following the name
         let field_type = parse_type(input)?;
-        let field_type = parse_array_type(input, field_type)?;
         let field_name = input.parse_ident()?;
+        let field_type = parse_array_type(input, field_type)?;
         fields.push((field_name, field_type));
         input.expect_token(";")?;
     }
@@ -982,10 +1286,18 @@ fn parse_struct(input: &mut Input) -> Result<Type, ParseError>
     })
 }
 
-/// Parse a function declaration
-fn parse_function(input: &mut Input, name: String, ret_type: Type, inline: bool) -> Result<Function, ParseError>
+/// Parse a function pointer declarator, e.g. (*fn_ptr)(u64, u8*)
+/// The opening parenthesis must already have been consumed.
+/// Returns the function pointer type and the declared name
+fn parse_fun_ptr_decl(input: &mut Input, ret_type: Type) -> Result<(Type, String), ParseError>
 {
-    let mut params = Vec::default();
+    input.expect_token("*")?;
+    input.eat_ws()?;
+    let name = input.parse_ident()?;
+    input.expect_token(")")?;
+    input.expect_token("(")?;
+
+    let mut param_types = Vec::default();
     let mut var_arg = false;
 
     loop
@@ -993,7 +1305,7 @@ fn parse_function(input: &mut Input, name: String, ret_type: Type, inline: bool)
         input.eat_ws()?;
 
         if input.eof() {
-            return input.parse_error("unexpected end of input inside function parameter list");
+            return input.parse_error(ErrorKind::UnexpectedEof, "unexpected end of input inside function pointer parameter list");
         }
 
         if input.match_token(")")? {
@@ -1007,132 +1319,606 @@ fn parse_function(input: &mut Input, name: String, ret_type: Type, inline: bool)
             break;
         }
 
-        // Parse one parameter and its type
+        // Parameter names are optional in function pointer types
         let param_type = parse_type(input)?;
-        let param_name = input.parse_ident()?;
-        let param_type = parse_array_type(input, param_type)?;
-        params.push((param_type, param_name));
+        input.eat_ws()?;
+        if is_ident_start(input.peek_ch()) {
+            input.parse_ident()?;
+        }
+        param_types.push(param_type);
 
         if input.match_token(")")? {
             break;
         }
 
-        // If this isn't the last argument, there
-        // has to be a comma separator
         input.expect_token(",")?;
     }
 
-    // Parse the function body (must be a block statement)
-    let body = parse_block_stmt(input)?;
-
-    Ok(Function
-    {
-        name,
-        ret_type,
-        params,
+    let fun_type = Type::Fun {
+        ret_type: Box::new(ret_type),
+        param_types,
         var_arg,
-        inline,
-        body,
-        num_locals: 0,
-    })
+    };
+
+    Ok((Type::Pointer(Box::new(fun_type)), name))
 }
 
-/// Parse a single unit of source code (e.g. one source file)
-pub fn parse_unit(input: &mut Input) -> Result<Unit, ParseError>
+/// Parameters, the locations of their types, the locations of their
+/// names and whether the function is variadic
+type ParamList = (Vec<(Type, String)>, Vec<Span>, Vec<Span>, bool);
+
+/// Parse a function parameter list, after the opening parenthesis
+fn parse_params(input: &mut Input) -> Result<ParamList, ParseError>
 {
-    let mut unit = Unit::default();
+    let mut params = Vec::default();
+    let mut type_spans = Vec::default();
+    let mut param_spans = Vec::default();
+    let mut var_arg = false;
+
+    // A parameter list of just (void) means no parameters
+    let void_params = input.with_backtracking(|input| {
+        if !input.match_keyword("void")? {
+            return input.parse_error(ErrorKind::UnexpectedToken, "expected void");
+        }
+        input.expect_token(")")
+    }).is_ok();
 
     loop
     {
         input.eat_ws()?;
 
-        // If this is the end of the input
-        if input.eof() {
+        if void_params {
             break;
         }
 
-        // If this is a type definition
-        if input.match_token("typedef")? {
-            let t = parse_type(input)?;
-            let name = input.parse_ident()?;
-            let t = parse_array_type(input, t)?;
-            input.expect_token(";")?;
-            unit.typedefs.push((name, Rc::new(Box::new(RefCell::new(t)))));
-            continue;
+        if input.eof() {
+            return input.parse_error(ErrorKind::UnexpectedEof, "unexpected end of input inside function parameter list");
         }
 
-        // If this is an inline function attribute
-        let inline = input.match_token("inline")?;
+        if input.match_token(")")? {
+            break;
+        }
 
-        // Parse the global declaration type and name
-        let decl_type = parse_type(input)?;
-        input.eat_ws()?;
-        let name = input.parse_ident()?;
+        // If this is a variable argument count function
+        if input.match_token("...")? {
+            if params.len() == 0 {
+                return input.parse_error(ErrorKind::InvalidDeclaration, "variadic function must have at least one named parameter");
+            }
 
-        // If this is the beginning of a function declaration
-        if input.match_token("(")? {
-            let fun = parse_function(input, name, decl_type, inline)?;
-            unit.fun_decls.push(fun);
-            continue;
-        }
+            if !input.match_token(")")? {
+                return input.parse_error(ErrorKind::InvalidDeclaration, "\"...\" must be the last parameter");
+            }
 
-        // If we parsed a function attribute
-        if inline {
-            return input.parse_error("expected function declaration");
+            var_arg = true;
+            break;
         }
 
-        let decl_type = parse_array_type(input, decl_type)?;
+        // Parse one parameter and its type
+        let (param_type, type_span) = parse_type_span(input)?;
+        input.eat_ws()?;
+        let name_pos = input.get_pos();
+        let name_span = get_span(input);
+        let param_name = input.parse_ident_non_keyword(&RESERVED_KEYWORDS)?;
+        let param_type = parse_array_type(input, param_type)?;
 
-        // Global variable initialization
-        let init_expr = if input.match_token("=")? {
-            Some(parse_expr(input)?)
+        if params.iter().any(|(_, name)| *name == param_name) {
+            return input.parse_error_at(
+                name_pos,
+                ErrorKind::DuplicateDeclaration,
+                &format!("duplicate parameter name \"{}\"", param_name)
+            );
         }
-        else
-        {
-            None
-        };
 
-        // This must be a global variable declaration
-        input.expect_token(";")?;
+        params.push((param_type, param_name));
+        type_spans.push(type_span);
+        param_spans.push(name_span);
 
-        unit.global_vars.push(Global {
-            name,
-            var_type: decl_type,
-            init_expr
-        });
+        if input.match_token(")")? {
+            break;
+        }
+
+        // If this isn't the last argument, there
+        // has to be a comma separator
+        input.expect_token(",")?;
     }
 
-    Ok(unit)
+    Ok((params, type_spans, param_spans, var_arg))
 }
 
-pub fn parse_str(src: &str) -> Result<Unit, ParseError>
+/// Parse a function declaration, after its parameter list
+fn parse_function(
+    input: &mut Input,
+    proto: FunProto,
+    param_spans: Vec<Span>,
+    inline: bool,
+) -> Result<Function, ParseError>
 {
-    let mut input = Input::new(&src, "src");
-    parse_unit(&mut input)
+    // Parse the function body (must be a block statement)
+    let body = parse_block_stmt(input)?;
+    let span = Span { end: body.span.end, ..proto.span };
+
+    let fun = Function
+    {
+        name: proto.name,
+        ret_type: proto.ret_type,
+        ret_type_span: proto.ret_type_span,
+        params: proto.params,
+        param_type_spans: proto.param_type_spans,
+        var_arg: proto.var_arg,
+        inline,
+        body,
+        num_locals: 0,
+        span,
+    };
+
+    warn_unused_vars(input, &fun, param_spans);
+
+    Ok(fun)
 }
 
-pub fn parse_file(file_name: &str) -> Result<Unit, ParseError>
+/// Parameter or local variable declaration,
+/// tracked to find variables that are never used
+struct VarUse
 {
-    let mut input = Input::from_file(file_name)?;
-    parse_unit(&mut input)
+    name: String,
+    is_param: bool,
+    span: Span,
+    used: bool,
 }
 
-#[cfg(test)]
-mod tests
+/// Variable declarations visible at some point in a function body
+#[derive(Default)]
+struct UseScopes
 {
-    use super::*;
+    vars: Vec<VarUse>,
 
-    fn parse_ok(src: &str)
-    {
-        dbg!(src);
-        let mut input = Input::new(&src, "src");
-        parse_unit(&mut input).unwrap();
-    }
+    /// Indices of the variables declared in each scope, innermost last
+    scopes: Vec<Vec<usize>>,
+}
 
-    fn parse_fails(src: &str)
+impl UseScopes
+{
+    fn declare(&mut self, name: &str, is_param: bool, span: Span)
     {
-        let mut input = Input::new(&src, "src");
-        assert!(parse_unit(&mut input).is_err());
+        self.scopes.last_mut().unwrap().push(self.vars.len());
+        self.vars.push(VarUse { name: name.to_string(), is_param, span, used: false });
+    }
+
+    /// Mark the innermost variable with a given name as used
+    fn mark_used(&mut self, name: &str)
+    {
+        for scope in self.scopes.iter().rev() {
+            for &idx in scope.iter().rev() {
+                if self.vars[idx].name == name {
+                    self.vars[idx].used = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt)
+    {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => self.visit_expr(expr),
+            StmtKind::ReturnExpr(expr) => self.visit_expr(expr),
+            StmtKind::ReturnVoid | StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::Assert { test_expr, .. } => self.visit_expr(test_expr),
+
+            StmtKind::Block(stmts) => {
+                self.scopes.push(Vec::default());
+                for stmt in stmts {
+                    self.visit_stmt(stmt);
+                }
+                self.scopes.pop();
+            }
+
+            StmtKind::If { test_expr, then_stmt, else_stmt } => {
+                self.visit_expr(test_expr);
+                self.visit_stmt(then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.visit_stmt(else_stmt);
+                }
+            }
+
+            StmtKind::While { test_expr, body_stmt } |
+            StmtKind::DoWhile { body_stmt, test_expr } => {
+                self.visit_expr(test_expr);
+                self.visit_stmt(body_stmt);
+            }
+
+            StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+                self.scopes.push(Vec::default());
+                if let Some(init_stmt) = init_stmt {
+                    self.visit_stmt(init_stmt);
+                }
+                self.visit_expr(test_expr);
+                self.visit_expr(incr_expr);
+                self.visit_stmt(body_stmt);
+                self.scopes.pop();
+            }
+
+            // The variable is in scope in its own initializer,
+            // as it is during symbol resolution
+            StmtKind::VarDecl { var_name, init_expr, .. } => {
+                self.declare(var_name, false, stmt.span.clone());
+                if let Some(init_expr) = init_expr {
+                    self.visit_expr(init_expr);
+                }
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr)
+    {
+        match &expr.kind {
+            ExprKind::Int { .. } | ExprKind::Float32(_) | ExprKind::String(_) | ExprKind::Ref(_) => {}
+
+            ExprKind::Ident(name) => self.mark_used(name),
+
+            // sizeof(x) on a variable is parsed as a named type
+            ExprKind::SizeofType { t: Type::Named(name) } => self.mark_used(name),
+            ExprKind::SizeofType { .. } => {}
+
+            ExprKind::Cast { child, .. } |
+            ExprKind::SizeofExpr { child } |
+            ExprKind::Unary { child, .. } => self.visit_expr(child),
+
+            ExprKind::Arrow { base, .. } => self.visit_expr(base),
+
+            ExprKind::Binary { lhs, rhs, .. } => {
+                self.visit_expr(lhs);
+                self.visit_expr(rhs);
+            }
+
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                self.visit_expr(test_expr);
+                self.visit_expr(then_expr);
+                self.visit_expr(else_expr);
+            }
+
+            ExprKind::Call { callee, args } => {
+                self.visit_expr(callee);
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+            }
+
+            ExprKind::Array(exprs) | ExprKind::Asm { args: exprs, .. } => {
+                for expr in exprs {
+                    self.visit_expr(expr);
+                }
+            }
+        }
+    }
+}
+
+/// Warn about parameters and local variables of a function that are
+/// never referenced. Names starting with an underscore are exempt.
+fn warn_unused_vars(input: &mut Input, fun: &Function, param_spans: Vec<Span>)
+{
+    let mut uses = UseScopes::default();
+    uses.scopes.push(Vec::default());
+
+    for ((_, param_name), span) in fun.params.iter().zip(param_spans) {
+        uses.declare(param_name, true, span);
+    }
+
+    uses.visit_stmt(&fun.body);
+
+    for var in uses.vars {
+        if var.used || var.name.starts_with("_") {
+            continue;
+        }
+
+        let msg = if var.is_param {
+            format!("unused parameter \"{}\"", var.name)
+        } else {
+            format!("unused variable \"{}\"", var.name)
+        };
+
+        input.add_warning(Diagnostic::at_span(&var.span, &msg));
+    }
+}
+
+/// Parse one top-level declaration and add it to the unit
+fn parse_toplevel(input: &mut Input, unit: &mut Unit) -> Result<(), ParseError>
+{
+    // If this is a type definition
+    input.eat_ws()?;
+    let span = get_span(input);
+
+    if input.match_token("typedef")? {
+        let (t, type_span) = parse_type_span(input)?;
+
+        // Function pointer typedef, e.g. typedef u64 (*fn_ptr)(u64);
+        let (t, name) = if input.match_token("(")? {
+            parse_fun_ptr_decl(input, t)?
+        }
+        else
+        {
+            let name = input.parse_ident()?;
+            (parse_array_type(input, t)?, name)
+        };
+
+        input.expect_token(";")?;
+
+        let mut span = span;
+        end_span(input, &mut span);
+        unit.typedefs.push(Typedef {
+            name,
+            t: Rc::new(RefCell::new(t)),
+            span,
+            type_span,
+        });
+        return Ok(());
+    }
+
+    // If this is a host function provided by the VM
+    let is_extern = input.match_keyword("extern")?;
+
+    // If this is an inline function attribute
+    let inline = input.match_token("inline")?;
+
+    // Parse the global declaration type and name
+    let (decl_type, type_span) = parse_type_span(input)?;
+    input.eat_ws()?;
+    let name_pos = input.get_pos();
+    let name = input.parse_ident_non_keyword(&RESERVED_KEYWORDS)?;
+
+    // If this is the beginning of a function declaration
+    if input.match_token("(")? {
+        let (params, param_type_spans, param_spans, var_arg) = parse_params(input)?;
+        let proto = FunProto {
+            name,
+            ret_type: decl_type,
+            ret_type_span: type_span,
+            params,
+            param_type_spans,
+            var_arg,
+            is_extern,
+            span,
+        };
+
+        // Function prototype without a body
+        if input.match_token(";")? {
+            let mut proto = proto;
+            end_span(input, &mut proto.span);
+            unit.fun_protos.push(proto);
+            return Ok(());
+        }
+
+        if is_extern {
+            return input.parse_error(ErrorKind::InvalidDeclaration, "extern functions can't have a body");
+        }
+
+        let fun = parse_function(input, proto, param_spans, inline)?;
+
+        if !fun.ret_type.is_void() {
+            if !fun.body.contains_return() {
+                input.warn(
+                    name_pos,
+                    &format!("function \"{}\" has a non-void return type but no return statement", fun.name)
+                );
+            }
+            else if !fun.body.always_returns() {
+                input.warn(
+                    name_pos,
+                    &format!("function \"{}\" may not return a value on all paths", fun.name)
+                );
+            }
+        }
+
+        unit.fun_decls.push(fun);
+        return Ok(());
+    }
+
+    // If we parsed a function attribute
+    if inline || is_extern {
+        return input.parse_error(ErrorKind::InvalidDeclaration, "expected function declaration");
+    }
+
+    let decl_type = parse_array_type(input, decl_type)?;
+
+    // Global variable initialization
+    let init_expr = if input.match_token("=")? {
+        Some(parse_expr(input)?)
+    }
+    else
+    {
+        None
+    };
+
+    // This must be a global variable declaration
+    input.expect_token(";")?;
+
+    let mut span = span;
+    end_span(input, &mut span);
+    unit.global_vars.push(Global {
+        name,
+        var_type: decl_type,
+        type_span,
+        init_expr,
+        span,
+    });
+
+    Ok(())
+}
+
+/// Get the source location of the current input position
+fn get_span(input: &Input) -> Span
+{
+    Span {
+        src_name: input.src_name.clone(),
+        line_no: input.line_no,
+        col_no: input.col_no,
+        start: input.byte_offset,
+        end: input.byte_offset,
+    }
+}
+
+/// Set the end of a span to the end of the input consumed so far
+fn end_span(input: &Input, span: &mut Span)
+{
+    span.end = input.end_offset();
+}
+
+/// Parse a single unit of source code (e.g. one source file)
+/// Returns the unit along with any warnings produced
+pub fn parse_unit(input: &mut Input) -> Result<(Unit, Vec<Diagnostic>), ParseError>
+{
+    let mut unit = Unit { span: get_span(input), ..Default::default() };
+
+    loop
+    {
+        input.eat_ws()?;
+
+        // If this is the end of the input
+        if input.eof() {
+            break;
+        }
+
+        parse_toplevel(input, &mut unit)?;
+    }
+
+    end_span(input, &mut unit.span);
+
+    match input.take_warnings() {
+        Ok(warnings) => Ok((unit, warnings)),
+        Err(mut errors) => Err(errors.remove(0)),
+    }
+}
+
+/// Keywords that can begin a top-level declaration
+const DECL_KEYWORDS: [&str; 21] = [
+    "typedef", "extern", "inline", "struct", "unsigned", "void", "float",
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64",
+    "size_t", "char", "bool", "short", "int", "long",
+];
+
+/// Skip forward after a parse error until what looks like the start of
+/// a new top-level declaration, that is, a type keyword or typedef name
+/// at the beginning of a line. This always consumes at least one character.
+fn skip_to_next_decl(input: &mut Input, unit: &Unit) -> Result<(), ParseError>
+{
+    input.eat_ch();
+
+    loop
+    {
+        if input.eof() {
+            break;
+        }
+
+        if input.col_no == 1 {
+            // Also handles linenum directives from the preprocessor
+            input.eat_ws()?;
+
+            if input.col_no == 1 {
+                let ident = input.peek_ident();
+                let is_keyword = DECL_KEYWORDS.contains(&ident.as_str());
+                let is_typedef = unit.typedefs.iter().any(|typedef| typedef.name == ident);
+
+                if is_keyword || is_typedef {
+                    break;
+                }
+            }
+        }
+
+        input.eat_ch();
+    }
+
+    Ok(())
+}
+
+/// Parse a unit of source code, recovering after errors in top-level
+/// declarations so that all of the errors can be reported at once
+pub fn parse_unit_all_errors(input: &mut Input) -> Result<(Unit, Vec<Diagnostic>), Vec<ParseError>>
+{
+    let mut unit = Unit { span: get_span(input), ..Default::default() };
+    let mut errors = Vec::default();
+
+    // Errors inside statements are recovered from in parse_block_stmt
+    input.enable_recovery();
+
+    loop
+    {
+        if let Err(error) = input.eat_ws() {
+            errors.push(error);
+            break;
+        }
+
+        // If this is the end of the input
+        if input.eof() {
+            break;
+        }
+
+        let result = parse_toplevel(input, &mut unit);
+        errors.append(&mut input.take_recovered_errors());
+
+        if let Err(error) = result {
+            let too_deep = error.kind() == ErrorKind::NestingTooDeep;
+            errors.push(error);
+
+            // The input can't be resynchronized inside nesting
+            // that is too deep, so stop at the first such error
+            if too_deep {
+                break;
+            }
+
+            if let Err(error) = skip_to_next_decl(input, &unit) {
+                errors.push(error);
+                break;
+            }
+        }
+    }
+
+    end_span(input, &mut unit.span);
+
+    let warnings = match input.take_warnings() {
+        Ok(warnings) => warnings,
+        Err(mut warning_errors) => {
+            errors.append(&mut warning_errors);
+            Vec::default()
+        }
+    };
+
+    if errors.len() > 0 {
+        return Err(errors);
+    }
+
+    Ok((unit, warnings))
+}
+
+pub fn parse_str(src: &str) -> Result<Unit, ParseError>
+{
+    let mut input = Input::new(&src, "src");
+    let (unit, _warnings) = parse_unit(&mut input)?;
+    Ok(unit)
+}
+
+pub fn parse_file(file_name: &str) -> Result<Unit, ParseError>
+{
+    let mut input = Input::from_file(file_name)?;
+    let (unit, _warnings) = parse_unit(&mut input)?;
+    Ok(unit)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse_ok(src: &str)
+    {
+        dbg!(src);
+        let mut input = Input::new(&src, "src");
+        parse_unit(&mut input).unwrap();
+    }
+
+    fn parse_fails(src: &str)
+    {
+        let mut input = Input::new(&src, "src");
+        assert!(parse_unit(&mut input).is_err());
     }
 
     fn parse_file(file_name: &str)
@@ -1141,6 +1927,32 @@ mod tests
         super::parse_file(file_name).unwrap();
     }
 
+    /// Render the shape of an expression tree as an s-expression
+    fn expr_shape(expr: &Expr) -> String
+    {
+        match &expr.kind {
+            ExprKind::Int { value: v, .. } => v.to_string(),
+            ExprKind::Ident(name) => name.clone(),
+            ExprKind::Unary { op, child } => format!("({:?} {})", op, expr_shape(child)),
+            ExprKind::Binary { op, lhs, rhs } => format!("({:?} {} {})", op, expr_shape(lhs), expr_shape(rhs)),
+            ExprKind::Call { callee, args } => {
+                let args: Vec<String> = args.iter().map(expr_shape).collect();
+                format!("(call {} [{}])", expr_shape(callee), args.join(" "))
+            }
+            ExprKind::Arrow { base, field } => format!("(-> {} {})", expr_shape(base), field),
+            _ => format!("{:?}", expr.kind),
+        }
+    }
+
+    fn expr_ok(src: &str, shape: &str)
+    {
+        dbg!(src);
+        let mut input = Input::new(&src, "src");
+        let expr = parse_expr(&mut input).unwrap();
+        assert!(input.eof());
+        assert_eq!(expr_shape(&expr), shape);
+    }
+
     #[test]
     fn simple_unit()
     {
@@ -1156,12 +1968,740 @@ mod tests
         parse_fails("/* Hi\nthere");
     }
 
+    #[test]
+    fn all_errors()
+    {
+        fn parse_errors(src: &str) -> Vec<(u32, u32)>
+        {
+            let mut input = Input::new(&src, "src");
+            match parse_unit_all_errors(&mut input) {
+                Ok(_) => Vec::default(),
+                Err(errors) => errors.iter().map(|e| (e.line(), e.column())).collect()
+            }
+        }
+
+        assert_eq!(parse_errors(""), vec![]);
+        assert_eq!(parse_errors("u64 x;\nvoid main() { x = 1; }"), vec![]);
+
+        // Three independent errors
+        let src = concat!(
+            "u64 x = ;\n",
+            "void foo() {\n",
+            "    return 1 +;\n",
+            "}\n",
+            "u64 y;\n",
+            "int bar( { }\n",
+            "u64 z;\n",
+        );
+        assert_eq!(parse_errors(src), vec![(1, 9), (3, 15), (6, 10)]);
+
+        // Typedef names are recognized as the start of declarations
+        let src = "typedef u64 word;\nu64 a = ;\nword b = ;\n";
+        assert_eq!(parse_errors(src), vec![(2, 9), (3, 10)]);
+
+        // Errors on the last line must not loop forever
+        assert_eq!(parse_errors("u64 x = ;"), vec![(1, 9)]);
+        assert_eq!(parse_errors("}}}}"), vec![(1, 1)]);
+
+        // Unterminated comment
+        assert_eq!(parse_errors("u64 x = ;\n/* foo").len(), 2);
+
+        // Three errors in statements of the same function
+        let src = concat!(
+            "void foo(u64 a) {\n",
+            "    a = 1 +;\n",
+            "    if (a) { a = ; }\n",
+            "    puts(\"};\" ;\n",
+            "    return;\n",
+            "}\n",
+            "u64 y = 1;\n",
+        );
+        assert_eq!(parse_errors(src), vec![(2, 12), (3, 18), (4, 15)]);
+
+        // Recovery stops at the closing brace of the enclosing block
+        assert_eq!(parse_errors("void foo() { x = 1 }\nu64 y;"), vec![(1, 20)]);
+        assert_eq!(parse_errors("void foo() { ) }"), vec![(1, 14)]);
+        assert_eq!(parse_errors("void foo() { { ) } ) }"), vec![(1, 16), (1, 20)]);
+        assert_eq!(parse_errors("void foo() { ) ").len(), 2);
+
+        // Semicolons and braces inside string literals are skipped over
+        assert_eq!(parse_errors("void foo() { ) \"};\"; }\nu64 y = ;"), vec![(1, 14), (2, 9)]);
+    }
+
+    #[test]
+    fn spans()
+    {
+        fn pos(span: &Span) -> (u32, u32)
+        {
+            (span.line_no, span.col_no)
+        }
+
+        let src = concat!(
+            "// comment\n",
+            "/* multi-line\n",
+            "   comment */ u64 g = 1;\n",
+            "u64 foo(u64 a)\n",
+            "{\n",
+            "    if (a) {\n",
+            "        return a + foo(a - 1);\n",
+            "    }\n",
+            "    return 0;\n",
+            "}\n",
+        );
+        let unit = super::parse_str(src).unwrap();
+
+        let global = &unit.global_vars[0];
+        assert_eq!(pos(&global.span), (3, 15));
+        assert_eq!(*global.span.src_name, "src");
+        assert_eq!(pos(&global.init_expr.as_ref().unwrap().span), (3, 23));
+
+        let fun = &unit.fun_decls[0];
+        assert_eq!(pos(&fun.span), (4, 1));
+        assert_eq!(pos(&fun.body.span), (5, 1));
+
+        let stmts = match &fun.body.kind {
+            StmtKind::Block(stmts) => stmts,
+            _ => panic!()
+        };
+        assert_eq!(pos(&stmts[0].span), (6, 5));
+        assert_eq!(pos(&stmts[1].span), (9, 5));
+
+        let then_stmt = match &stmts[0].kind {
+            StmtKind::If { then_stmt, .. } => then_stmt,
+            _ => panic!()
+        };
+        let ret_stmt = match &then_stmt.kind {
+            StmtKind::Block(stmts) => &stmts[0],
+            _ => panic!()
+        };
+        assert_eq!(pos(&ret_stmt.span), (7, 9));
+
+        let (lhs, rhs) = match &ret_stmt.kind {
+            StmtKind::ReturnExpr(expr) => match &expr.kind {
+                ExprKind::Binary { lhs, rhs, .. } => (lhs, rhs),
+                _ => panic!()
+            },
+            _ => panic!()
+        };
+        assert_eq!(pos(&lhs.span), (7, 16));
+        assert_eq!(pos(&rhs.span), (7, 20));
+
+        let arg = match &rhs.kind {
+            ExprKind::Call { args, .. } => &args[0],
+            _ => panic!()
+        };
+        assert_eq!(pos(&arg.span), (7, 24));
+    }
+
+    #[test]
+    fn unclosed_delimiters()
+    {
+        fn opener(src: &str) -> (u32, u32)
+        {
+            let error = super::parse_str(src).unwrap_err();
+            assert_eq!(error.notes.len(), 1);
+            (error.notes[0].line_no, error.notes[0].col_no)
+        }
+
+        // Unclosed function body
+        assert_eq!(opener("u64 a;\nvoid f()\n{\n    a = 1;\n"), (3, 1));
+        assert_eq!(opener("void f() {\n    if (1) {\n        return;\n}\n"), (1, 10));
+
+        // Unclosed call
+        assert_eq!(opener("void f() {\n    g(1,\n      2"), (2, 6));
+        assert_eq!(opener("void f() { g(1 2); }"), (1, 13));
+
+        // Unclosed parenthesized expression and array literal
+        assert_eq!(opener("u64 x = (1 + 2;"), (1, 9));
+        assert_eq!(opener("u64 x = {1, 2;"), (1, 9));
+
+        // Unterminated string
+        assert_eq!(opener("char* s = \"foo;\nu64 x;"), (1, 11));
+
+        let error = super::parse_str("void f() {\n    return;\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            concat!(
+                "src:3:1: error: unexpected end of input in block statement\n",
+                "\n",
+                "^\n",
+                "src:1:10: note: to match this \"{\""
+            )
+        );
+    }
+
+    #[test]
+    fn nesting_depth()
+    {
+        // Deep but reasonable nesting is fine
+        parse_ok(&format!("u64 x = {}1{};", "(".repeat(40), ")".repeat(40)));
+        parse_ok(&format!("void f() {}{}", "{".repeat(40), "}".repeat(40)));
+
+        // Nesting close to the limit fits in the compiler's stack
+        fn nesting_ok(src: &str)
+        {
+            let src = src.to_string();
+            std::thread::Builder::new()
+                .stack_size(crate::COMPILER_STACK_SIZE)
+                .spawn(move || { super::parse_str(&src).unwrap(); })
+                .unwrap()
+                .join()
+                .unwrap();
+        }
+
+        nesting_ok(&format!("u64 x = {}1{};", "(".repeat(250), ")".repeat(250)));
+        nesting_ok(&format!("void f() {{ u64 x = {}1{}; }}", "(".repeat(250), ")".repeat(250)));
+        nesting_ok(&format!("void f() {}{}", "{".repeat(250), "}".repeat(250)));
+
+        fn nesting_fails(src: &str)
+        {
+            // Parse on a thread with a large stack so that a missing
+            // depth check overflows the stack rather than passing
+            let src = src.to_string();
+            let error = std::thread::Builder::new()
+                .stack_size(256 * 1024 * 1024)
+                .spawn(move || super::parse_str(&src).unwrap_err())
+                .unwrap()
+                .join()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::NestingTooDeep);
+        }
+
+        nesting_fails(&format!("u64 x = {};", "(".repeat(100_000)));
+        nesting_fails(&format!("u64 x = {}1;", "-".repeat(100_000)));
+        nesting_fails(&format!("u64 x = {};", "{".repeat(100_000)));
+        nesting_fails(&format!("void f() {}", "{".repeat(100_000)));
+        nesting_fails(&format!("void f() {{ {} }}", "if (1) ".repeat(100_000)));
+        nesting_fails(&format!("void f() {{ u64 x = {}; }}", "(".repeat(100_000)));
+
+        // Recovery stops at the first error, since the rest of
+        // the input can't be resynchronized
+        let src = format!("void f() {{ {} }} void g() {{ }}", "{".repeat(300));
+        let errors = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(move || parse_unit_all_errors(&mut Input::new(&src, "src")).unwrap_err())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::NestingTooDeep);
+
+        // Else-if chains don't nest
+        let chain: String = (0..150).map(|i| format!("if (x == {}) return {}; else ", i, i)).collect();
+        parse_ok(&format!("u64 f(u64 x) {{ {}return 0; }}", chain));
+
+        // The limit is configurable
+        let src = format!("u64 x = {}1{};", "(".repeat(10), ")".repeat(10));
+        let mut input = Input::new(&src, "src");
+        input.set_max_depth(5);
+        assert_eq!(parse_unit(&mut input).unwrap_err().kind(), ErrorKind::NestingTooDeep);
+    }
+
+    #[test]
+    fn error_positions()
+    {
+        fn error_pos(src: &str) -> (u32, u32)
+        {
+            let error = super::parse_str(src).unwrap_err();
+            (error.line(), error.column())
+        }
+
+        // CRLF line endings count as a single newline
+        assert_eq!(error_pos("u64 a;\r\nu64 b;\r\nu64 c = ;\r\n"), (3, 9));
+        assert_eq!(error_pos("u64 a;\r\n// comment\r\n\r\nu64 b = 1\r\n"), (5, 1));
+        assert_eq!(error_pos("u64 a = 1\r\n"), (2, 1));
+
+        // Tabs count as one column
+        assert_eq!(error_pos("void f()\n{\n\t\treturn 1 +;\n}"), (3, 13));
+        assert_eq!(error_pos("u64\ta\t=\t;"), (1, 9));
+
+        // Columns count characters, not bytes
+        assert_eq!(error_pos("/* 🎉 party */ u64 a = ;"), (1, 23));
+        assert_eq!(error_pos("// héllo wörld 🦀\nu64 a = ;"), (2, 9));
+        assert_eq!(error_pos("char* s = \"🦀🦀\"; u64 a = ;"), (1, 25));
+    }
+
+    #[test]
+    fn error_kinds()
+    {
+        fn error_kind(src: &str) -> ErrorKind
+        {
+            super::parse_str(src).unwrap_err().kind()
+        }
+
+        assert_eq!(error_kind("u64 x = 1"), ErrorKind::UnexpectedToken);
+        assert_eq!(error_kind("void f() {"), ErrorKind::UnexpectedEof);
+        assert_eq!(error_kind("char* s = \"foo"), ErrorKind::UnterminatedString);
+        assert_eq!(error_kind("/* foo"), ErrorKind::UnterminatedComment);
+        assert_eq!(error_kind("char c = '\\q';"), ErrorKind::InvalidLiteral);
+        assert_eq!(error_kind("u64 1;"), ErrorKind::ExpectedIdentifier);
+        assert_eq!(error_kind("void f(u64 a, 3) {}"), ErrorKind::ExpectedType);
+        assert_eq!(error_kind("u64 x = ;"), ErrorKind::ExpectedExpression);
+        assert_eq!(error_kind("void f(u64 a, u64 a) {}"), ErrorKind::DuplicateDeclaration);
+        assert_eq!(error_kind("void f(...) {}"), ErrorKind::InvalidDeclaration);
+        assert_eq!(super::parse_file("does_not_exist.c").unwrap_err().kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn warnings()
+    {
+        fn parse_warnings(src: &str) -> Vec<String>
+        {
+            let mut input = Input::new(src, "src");
+            let (_unit, warnings) = parse_unit(&mut input).unwrap();
+            warnings.iter().map(|w| w.to_string()).collect()
+        }
+
+        assert_eq!(parse_warnings("void foo() {}"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo() { return 0; }"), Vec::<String>::new());
+        assert_eq!(
+            parse_warnings("u64 a;\nu64 foo() {}"),
+            vec!["src:2:5: warning: function \"foo\" has a non-void return type but no return statement"]
+        );
+
+        // Returning on some paths only
+        assert_eq!(
+            parse_warnings("u64 foo(u64 x) { if (x) return 1; }"),
+            vec!["src:1:5: warning: function \"foo\" may not return a value on all paths"]
+        );
+        assert_eq!(parse_warnings("u64 foo(u64 a) { if (a) { return 0; } return 1; }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { while (a) { return 0; } }").len(), 1);
+        assert_eq!(parse_warnings("u64 foo(u64 a) { while (1) { if (a) break; return 0; } }").len(), 1);
+        assert_eq!(parse_warnings("u64 foo(u64 a, u64 c) { do { if (c) break; return 1; } while (a); }").len(), 1);
+        assert_eq!(parse_warnings("u64 foo(u64 a, u64 c) { do { if (c) continue; return 1; } while (a); }").len(), 1);
+
+        // Returning on every path
+        assert_eq!(parse_warnings("u64 foo(u64 a) { if (a) return 1; else return 2; }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { if (a) return 1; return 2; }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo() { { return 1; } }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { do { return 1; } while (a); }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { for (;;) { if (a) return 1; } }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { while (1) { if (a) continue; return 1; } }"), Vec::<String>::new());
+        assert_eq!(
+            parse_warnings("u64 foo(u64 a) { while (1) { for (;;) { break; } if (a) return 1; } }"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            parse_warnings("u64 foo(u64 a) { if (a) { return 1; } else if (a > 1) { return 2; } else { return 3; } }"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn unreachable_code()
+    {
+        fn parse_warnings(src: &str) -> Vec<String>
+        {
+            let mut input = Input::new(src, "src");
+            let (_unit, warnings) = parse_unit(&mut input).unwrap();
+            warnings.iter().map(|w| w.to_string()).collect()
+        }
+
+        assert_eq!(parse_warnings("void foo() { return; }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo(u64 a) { if (a) return 1; return 0; }"), Vec::<String>::new());
+        assert_eq!(
+            parse_warnings("u64 foo(u64 a) {\n    return a;\n    a = 1;\n}"),
+            vec!["src:3:5: warning: statement after return is unreachable"]
+        );
+
+        // Reported once per block, and in nested blocks
+        assert_eq!(
+            parse_warnings("void foo() { return; foo(); return; foo(); }"),
+            vec!["src:1:22: warning: statement after return is unreachable"]
+        );
+        assert_eq!(
+            parse_warnings("void foo(u64 a) { while (a) { return; ; break; } }"),
+            vec!["src:1:41: warning: statement after return is unreachable"]
+        );
+
+        // Compilation still succeeds with warnings
+        let src = "u64 foo(u64 a) { return a; a = 1; }";
+        let mut input = Input::new(src, "src");
+        let (mut unit, warnings) = parse_unit(&mut input).unwrap();
+        assert_eq!(warnings.len(), 1);
+        unit.resolve_syms().unwrap();
+        unit.check_types().unwrap();
+        unit.gen_code().unwrap();
+
+        // Unless warnings are reported as errors
+        let mut input = Input::new(src, "src");
+        input.set_warnings_as_errors(true);
+        let error = parse_unit(&mut input).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Warning);
+        assert_eq!(error.to_string(), "src:1:28: error: statement after return is unreachable");
+
+        let src = "u64 foo() {}\nvoid bar() { return; bar(); }\nu64 x = ;";
+        let mut input = Input::new(src, "src");
+        input.set_warnings_as_errors(true);
+        let errors = parse_unit_all_errors(&mut input).unwrap_err();
+        let kinds: Vec<ErrorKind> = errors.iter().map(|e| e.kind()).collect();
+        assert_eq!(kinds, vec![ErrorKind::ExpectedExpression, ErrorKind::Warning, ErrorKind::Warning]);
+        assert_eq!((errors[2].line(), errors[2].column()), (2, 22));
+    }
+
+    #[test]
+    fn span_offsets()
+    {
+        fn text<'a>(src: &'a str, span: &Span) -> &'a str
+        {
+            &src[span.start..span.end]
+        }
+
+        // Expressions
+        let src = "u64 g = 1 + foo(2, 3) * x[4] -  ( y - 1 ) ;";
+        let unit = super::parse_str(src).unwrap();
+        let global = &unit.global_vars[0];
+        assert_eq!(text(src, &global.span), src);
+
+        let expr = global.init_expr.as_ref().unwrap();
+        assert_eq!(text(src, &expr.span), "1 + foo(2, 3) * x[4] -  ( y - 1 )");
+        match &expr.kind {
+            ExprKind::Binary { lhs, rhs, .. } => {
+                assert_eq!(text(src, &lhs.span), "1 + foo(2, 3) * x[4]");
+                assert_eq!(text(src, &rhs.span), "( y - 1 )");
+
+                match &lhs.kind {
+                    ExprKind::Binary { rhs, .. } => {
+                        assert_eq!(text(src, &rhs.span), "foo(2, 3) * x[4]");
+                        match &rhs.kind {
+                            ExprKind::Binary { lhs, rhs, .. } => {
+                                assert_eq!(text(src, &lhs.span), "foo(2, 3)");
+                                assert_eq!(text(src, &rhs.span), "x[4]");
+                            }
+                            _ => panic!()
+                        }
+                    }
+                    _ => panic!()
+                }
+            }
+            _ => panic!()
+        }
+
+        let src = "u64 g = -x ? (u8)y : a->b;";
+        let unit = super::parse_str(src).unwrap();
+        let expr = unit.global_vars[0].init_expr.as_ref().unwrap();
+        assert_eq!(text(src, &expr.span), "-x ? (u8)y : a->b");
+        match &expr.kind {
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                assert_eq!(text(src, &test_expr.span), "-x");
+                assert_eq!(text(src, &then_expr.span), "(u8)y");
+                assert_eq!(text(src, &else_expr.span), "a->b");
+            }
+            _ => panic!()
+        }
+
+        // Statements and functions, with multi-byte characters
+        let src = "// é\nu64 f(u64 a)\n{\n    if (a) { return a - 1; }\n    return \"ü\"[0];\n}\n";
+        let unit = super::parse_str(src).unwrap();
+        let fun = &unit.fun_decls[0];
+        assert_eq!(text(src, &fun.span), &src[6..src.len() - 1]);
+        assert_eq!(text(src, &fun.body.span), "{\n    if (a) { return a - 1; }\n    return \"ü\"[0];\n}");
+
+        match &fun.body.kind {
+            StmtKind::Block(stmts) => {
+                assert_eq!(text(src, &stmts[0].span), "if (a) { return a - 1; }");
+                assert_eq!(text(src, &stmts[1].span), "return \"ü\"[0];");
+            }
+            _ => panic!()
+        }
+
+        // Errors report the byte offset of the span
+        let src = "// é\nvoid f() { g = 1; }";
+        let mut unit = super::parse_str(src).unwrap();
+        let error = unit.resolve_syms().unwrap_err();
+        assert_eq!(error.byte_offset(), src.find("g =").unwrap());
+    }
+
+    #[test]
+    fn type_spans()
+    {
+        fn text<'a>(src: &'a str, span: &Span) -> &'a str
+        {
+            &src[span.start..span.end]
+        }
+
+        let src = concat!(
+            "\n",
+            "typedef struct { u64 x; } * point_ptr;\n",
+            "typedef u8 bytes[4];\n",
+            "unsigned  long g = 1;\n",
+            "u8* f(point_ptr p, i32 n);\n",
+            "u8 * f(point_ptr p, i32 n)\n",
+            "{\n",
+            "    const_t* x = 0;\n",
+            "    return 0;\n",
+            "}\n",
+            "\n",
+        );
+        let unit = super::parse_str(src).unwrap();
+
+        assert_eq!(unit.span.start, 0);
+        assert_eq!(unit.span.end, src.trim_end().len());
+
+        assert_eq!(text(src, &unit.typedefs[0].span), "typedef struct { u64 x; } * point_ptr;");
+        assert_eq!(text(src, &unit.typedefs[0].type_span), "struct { u64 x; } *");
+        assert_eq!(text(src, &unit.typedefs[1].type_span), "u8");
+
+        let global = &unit.global_vars[0];
+        assert_eq!(text(src, &global.type_span), "unsigned  long");
+
+        let proto = &unit.fun_protos[0];
+        assert_eq!(text(src, &proto.ret_type_span), "u8*");
+        assert_eq!(proto.param_type_spans.len(), 2);
+        assert_eq!(text(src, &proto.param_type_spans[0]), "point_ptr");
+        assert_eq!(text(src, &proto.param_type_spans[1]), "i32");
+
+        let fun = &unit.fun_decls[0];
+        assert_eq!(fun.ret_type_span.start, src.find("u8 * f").unwrap());
+        assert_eq!(text(src, &fun.ret_type_span), "u8 *");
+        assert_eq!(fun.param_type_spans[0].start, src.rfind("point_ptr p").unwrap());
+
+        match &fun.body.kind {
+            StmtKind::Block(stmts) => match &stmts[0].kind {
+                StmtKind::VarDecl { type_span, .. } => {
+                    assert_eq!(text(src, type_span), "const_t*");
+                    assert_eq!(type_span.line_no, 8);
+                    assert_eq!(type_span.col_no, 5);
+                }
+                _ => panic!()
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn display_expr()
+    {
+        fn parse(src: &str) -> Expr
+        {
+            let mut input = Input::new(src, "src");
+            let expr = parse_expr(&mut input).unwrap();
+            input.eat_ws().unwrap();
+            assert!(input.eof(), "{} not fully parsed", src);
+            expr
+        }
+
+        // The displayed expression parses back into the same
+        // expression, which displays the same way
+        fn round_trip(src: &str) -> String
+        {
+            let out = parse(src).to_string();
+            assert_eq!(parse(&out).to_string(), out, "{}", src);
+            out
+        }
+
+        assert_eq!(round_trip("1 + 2 * 3"), "(1 + (2 * 3))");
+        assert_eq!(round_trip("(1 + 2) * 3"), "((1 + 2) * 3)");
+        assert_eq!(round_trip("8 - 3 - 2"), "((8 - 3) - 2)");
+        assert_eq!(round_trip("a = b = c"), "(a = (b = c))");
+        assert_eq!(round_trip("a, b, c"), "((a, b), c)");
+        assert_eq!(round_trip("a ? b : c ? d : e"), "(a ? b : (c ? d : e))");
+        assert_eq!(round_trip("foo(1, x + 1, bar())"), "foo(1, (x + 1), bar())");
+        assert_eq!(round_trip("-x - -5"), "((-x) - (-5))");
+        assert_eq!(round_trip("- -5"), "5");
+        assert_eq!(round_trip("!~*&x"), "(!(~(*(&x))))");
+        assert_eq!(round_trip("a[i + 1]"), "(*(a + (i + 1)))");
+        assert_eq!(round_trip("p->next->val"), "p->next->val");
+        assert_eq!(round_trip("(u8*)p + sizeof(u64) + sizeof(x)"), "((((u8*)p) + sizeof(u64)) + sizeof(x))");
+        assert_eq!(round_trip("200u8 + 5UL + -3i8"), "((200u8 + 5u64) + (-3i8))");
+        assert_eq!(round_trip("1.5f * 2.0f - -0.25f"), "((1.5f * 2.0f) - (-0.25f))");
+        assert_eq!(round_trip(r#""say \"hi\"\n\x01""#), r#""say \"hi\"\n\x01""#);
+        assert_eq!(round_trip("'a' + '\\n'"), "(97 + 10)");
+        assert_eq!(round_trip("{1, 2, {3}}"), "{1, 2, {3}}");
+        assert_eq!(round_trip("asm (a, b) -> u64 { add_u64; }"), "asm (a, b) -> u64 { add_u64; }");
+
+        for src in [
+            "a && b || !c",
+            "x << 2 >> 1 & 0xFF | y ^ z",
+            "a == b != c < d <= e > f >= g",
+            "x = y % 3 / 2",
+            "++x",
+            "--p",
+            "f(g(h(1)), (a, b))",
+            "-(-(-1))",
+            "0xFFFFFFFFFFFFFFFF",
+            "(i32)-1 + (u8)(x * 2)",
+        ] {
+            round_trip(src);
+        }
+    }
+
+    #[test]
+    fn display_stmt()
+    {
+        fn body(src: &str) -> String
+        {
+            let unit = parse_str(src).unwrap();
+            unit.fun_decls[0].body.to_string()
+        }
+
+        // The displayed function body parses back into the same body
+        fn round_trip(src: &str) -> String
+        {
+            let out = body(&format!("void foo(u64 a, u8* p) {}", src));
+            assert_eq!(body(&format!("void foo(u64 a, u8* p) {}", out)), out, "{}", src);
+            out
+        }
+
+        assert_eq!(round_trip("{}"), "{\n}");
+        assert_eq!(round_trip("{ return; }"), "{\n    return;\n}");
+        assert_eq!(round_trip("{ a = a + 1; }"), "{\n    (a = (a + 1));\n}");
+        assert_eq!(
+            round_trip("{ u64 x = 1; u8* q; { x; } return x; }"),
+            "{\n    u64 x = 1;\n    u8* q;\n    {\n        x;\n    }\n    return x;\n}"
+        );
+        assert_eq!(
+            round_trip("{ if (a) return; else if (p) a = 1; else { a = 2; } }"),
+            "{\n    if (a)\n        return;\n    else if (p)\n        (a = 1);\n    else {\n        (a = 2);\n    }\n}"
+        );
+        assert_eq!(
+            round_trip("{ while (a) { if (p) break; continue; } }"),
+            "{\n    while (a) {\n        if (p)\n            break;\n        continue;\n    }\n}"
+        );
+        assert_eq!(
+            round_trip("{ for (u64 i = 0; i < a; ++i) p[i] = 0; for (;;) {} }"),
+            "{\n    for (u64 i = 0; (i < a); (i = (i + 1)))\n        ((*(p + i)) = 0);\n    for (; 1; 1) {\n    }\n}"
+        );
+        assert_eq!(
+            round_trip("{ do { a = a - 1; } while (a); do a; while (p); }"),
+            "{\n    do {\n        (a = (a - 1));\n    } while (a);\n    do\n        a;\n    while (p);\n}"
+        );
+    }
+
+    #[test]
+    fn display_unit()
+    {
+        // The displayed unit parses back into the same unit,
+        // which still type checks
+        fn round_trip(src: &str, src_name: &str) -> String
+        {
+            let mut input = Input::new(src, src_name);
+            let (unit, _) = parse_unit(&mut input).unwrap();
+            let out = unit.to_string();
+
+            let mut input = Input::new(&out, src_name);
+            let (mut unit2, _) = parse_unit(&mut input).unwrap();
+            assert_eq!(unit2.to_string(), out, "{}", src_name);
+            assert!(unit2 == unit, "{}", src_name);
+            unit2.resolve_syms().unwrap();
+            unit2.check_types().unwrap();
+            out
+        }
+
+        assert_eq!(
+            round_trip("u64 g = 1 + 2; u8 buf[4][2]; inline u64 foo(u64 a, ...) { return a * g; }", "src"),
+            "u64 g = (1 + 2);\nu8 buf[4][2];\n\ninline u64 foo(u64 a, ...) {\n    return (a * g);\n}\n"
+        );
+        assert_eq!(
+            round_trip("typedef struct { u8 name[8]; } S; typedef u64 (*cb_t)(u64, ...); void bar(S* s);", "src"),
+            "typedef struct { u8 name[8]; } S;\ntypedef u64 (*cb_t)(u64, ...);\nvoid bar(S* s);\n"
+        );
+        assert_eq!(round_trip("extern u64 host(u8* p, u64 n);", "src"), "extern u64 host(u8* p, u64 n);\n");
+
+        // Every test program and example, after preprocessing
+        for dir in ["./tests", "./examples"] {
+            for file in std::fs::read_dir(dir).unwrap() {
+                let file_path = file.unwrap().path().display().to_string();
+                if file_path.ends_with(".c") {
+                    let mut input = Input::from_file(&file_path).unwrap();
+                    let src = crate::cpp::process_input(&mut input).unwrap();
+                    round_trip(&src, &file_path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unused_vars()
+    {
+        fn parse_warnings(src: &str) -> Vec<String>
+        {
+            let mut input = Input::new(src, "src");
+            let (_unit, warnings) = parse_unit(&mut input).unwrap();
+            warnings.iter().map(|w| w.to_string()).collect()
+        }
+
+        assert_eq!(
+            parse_warnings("u64 foo(u64 a, u64 b) { return a; }"),
+            vec!["src:1:20: warning: unused parameter \"b\""]
+        );
+        assert_eq!(parse_warnings("u64 foo(u64 a, u64 _unused) { return a; }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("void foo(u64 a) { a; }"), Vec::<String>::new());
+
+        // Locals
+        assert_eq!(
+            parse_warnings("void foo() {\n    u64 x = 1;\n    u64 _y = 2;\n}"),
+            vec!["src:2:5: warning: unused variable \"x\""]
+        );
+        assert_eq!(parse_warnings("void foo(u64 a) { u64 x = a; if (a) { { x = 2; } } }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("void foo() { for (u64 i = 0; i < 10; i = i + 1) {} }"), Vec::<String>::new());
+        assert_eq!(parse_warnings("u64 foo() { u64 n = 0; return sizeof(n); }"), Vec::<String>::new());
+
+        // A use of a shadowing local doesn't count for the parameter
+        assert_eq!(
+            parse_warnings("u64 foo(u64 a) { { u64 a = 1; return a; } }"),
+            vec!["src:1:13: warning: unused parameter \"a\""]
+        );
+    }
+
+    #[test]
+    fn error_display()
+    {
+        let err = super::parse_str("u64x;").unwrap_err();
+        assert_eq!((err.line(), err.column()), (1, 5));
+        assert_eq!(err.message(), "expected identifier");
+        assert_eq!(format!("{}", err), "src:1:5: error: expected identifier\nu64x;\n    ^");
+
+        // Only the line containing the error is shown
+        let err = super::parse_str("u64 a;\nvoid f() { return 1 +; }\nu64 b;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "src:2:22: error: unknown atomic expression\nvoid f() { return 1 +; }\n                     ^"
+        );
+
+        // Error at the end of the input
+        let err = super::parse_str("void f() {").unwrap_err();
+        assert_eq!(err.src_line.as_deref(), Some("void f() {"));
+
+        // Tabs are preserved in the caret padding so the caret doesn't drift
+        let err = super::parse_str("void f()\n{\n\tu64 x = 1\n\t\tx = 2;\n}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "src:4:3: error: expected token \";\"\n\t\tx = 2;\n\t\t^"
+        );
+
+        // Missing semicolon after a global
+        let err = super::parse_str("u64 a = 1\nu64 b = 2;").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "src:2:1: error: expected token \";\"\nu64 b = 2;\n^"
+        );
+
+        let err = ParseError::msg_only::<()>("no location").unwrap_err();
+        assert_eq!(err.to_string(), "error: no location");
+
+        // ParseError can be used as a boxed std::error::Error
+        fn boxed() -> Result<(), Box<dyn std::error::Error>> {
+            super::parse_str("u64 foo(")?;
+            Ok(())
+        }
+        assert!(boxed().is_err());
+    }
+
     #[test]
     fn typedefs()
     {
         parse_ok("typedef int foo;");
         parse_ok("typedef struct {} foo;");
         parse_ok("typedef struct { float x; float y; float z; } vec;");
+        parse_ok("typedef u64 size_t;");
+        parse_ok("typedef u8* string_t;");
+        parse_ok("typedef u64 (*fn_ptr)(u64);");
+        parse_ok("typedef void (*cb_t)(u64 x, u8* p, ...);");
+        parse_ok("typedef u64 word_t; word_t w; word_t foo(word_t a) { word_t b = a; return b; }");
+
+        parse_fails("typedef u64 (fn_ptr)(u64);");
+        parse_fails("typedef u64 (*fn_ptr)(u64;");
     }
 
     #[test]
@@ -1187,20 +2727,119 @@ mod tests
         parse_ok("char** foo() { return NULL; }");
         parse_ok("u64 foo( u64 a , u64 b ) { return 77; }");
 
+        // Prototypes
+        parse_ok("u64 foo();");
+        parse_ok("u64 foo(u64 a, char* b);");
+        parse_ok("u64 foo(u64 a, ...);");
+
+        // Host functions
+        parse_ok("extern void draw_line(u32 x0, u32 y0, u32 x1, u32 y1);");
+        parse_ok("extern u64 foo(u8* p, ...);");
+        parse_fails("extern u64 foo() { return 0; }");
+        parse_fails("extern u64 g;");
+        parse_fails("u64 extern() { return 0; }");
+
         // Should fail to parse
-        parse_fails("u64 foo();");
+        parse_fails("u64 foo()");
         parse_fails("u64 foo() return 0;");
         parse_fails("void* f foo();");
         parse_fails("voidfoo() {}");
         parse_fails("void foo(u64 a, u64 b) { a = a b; }");
     }
 
+    #[test]
+    fn void_params()
+    {
+        parse_ok("void main(void) {}");
+        parse_ok("u64 f( void ) { return 0; }");
+        parse_ok("void f(void* p) {}");
+        parse_ok("void f(void** p, u64 n) {}");
+
+        let unit = super::parse_str("u64 f( void ) { return 0; }").unwrap();
+        assert_eq!(unit.fun_decls[0].params.len(), 0);
+
+        parse_fails("void foo(void, u64 x) {}");
+        parse_fails("void foo(u64 x, void) {}");
+    }
+
+    #[test]
+    fn missing_file()
+    {
+        let error = super::parse_file("tests/does_not_exist.c").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Io);
+        assert_eq!(error.line(), 0);
+        assert!(error.message().starts_with("could not read input file \"tests/does_not_exist.c\": "));
+        assert!(error.to_string().starts_with("error: could not read input file"));
+    }
+
+    #[test]
+    fn underscore_idents()
+    {
+        for ident in ["_x", "__attribute__", "_1foo", "_"] {
+            let mut input = Input::new(ident, "src");
+            assert_eq!(input.parse_ident().unwrap(), ident);
+            assert!(input.eof());
+        }
+
+        parse_ok("u64 _x;");
+        parse_ok("u64 __attribute__;");
+        parse_ok("u64 _1foo;");
+        parse_ok("u64 _;");
+        parse_ok("u64 _foo(u64 _) { return _; }");
+        parse_ok("typedef u64 _word; _word _w;");
+    }
+
+    #[test]
+    fn reserved_names()
+    {
+        parse_fails("u64 if;");
+        parse_fails("u64 while = 1;");
+        parse_fails("u64 return() { return 0; }");
+        parse_fails("void foo(u64 for) {}");
+        parse_fails("void foo(u64 a, char* sizeof) {}");
+        parse_fails("u64 int;");
+
+        // Keywords as a prefix of a name are fine
+        parse_ok("u64 iffy;");
+        parse_ok("u64 return_value() { return 0; }");
+        parse_ok("void foo(u64 for_each) {}");
+
+        let error = super::parse_str("u64 a;\nvoid foo(u64 a, u64 do) {}").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ExpectedIdentifier);
+        assert_eq!(error.message(), "identifier expected, found keyword \"do\"");
+        assert_eq!((error.line(), error.column()), (2, 21));
+    }
+
+    #[test]
+    fn dup_params()
+    {
+        parse_ok("u64 foo(u64 a, u64 aa, u64 a_) { return a; }");
+        parse_ok("u64 foo(u64 foo) { return foo; }");
+
+        parse_fails("u64 foo(u64 a, u64 a) { return a; }");
+        parse_fails("u64 foo(u64 a, u8* b, u64 c, u8 a) { return a; }");
+
+        // The error should point at the second occurrence
+        let mut input = Input::new("u64 foo(u64 a,\n    u64 a) {}", "src");
+        let err = parse_unit(&mut input).unwrap_err();
+        assert!(err.message().contains("\"a\""));
+        assert_eq!((err.line(), err.column()), (2, 9));
+    }
+
     #[test]
     fn var_arg()
     {
-        parse_ok("void foo(...) {}");
+        parse_ok("void foo(int x, ...) {}");
         parse_ok("void foo(int x, int y, ...) {}");
-        parse_fails("void foo(..., int x);");
+        parse_ok("void printf(char* fmt, ...) {}");
+
+        let unit = super::parse_str("void printf(char* fmt, ...) {}").unwrap();
+        assert!(unit.fun_decls[0].var_arg);
+        assert_eq!(unit.fun_decls[0].params.len(), 1);
+
+        parse_fails("void foo(...) {}");
+        parse_fails("void foo(..., int x) {}");
+        parse_fails("void foo(int x, ..., ...) {}");
     }
 
     #[test]
@@ -1222,6 +2861,8 @@ mod tests
         parse_ok("unsigned int v = 1;");
         parse_ok("unsigned long v = 1;");
         parse_ok("unsigned n = 1;");
+        parse_ok("float f = 1.5f;");
+        parse_ok("u64 f32 = 1; u64 main() { return f32; }");
 
         parse_ok("char* str = \"FOO\n\";");
 
@@ -1248,6 +2889,73 @@ mod tests
         parse_ok("int f = 4.5_6e8_f;");
 
         parse_fails("int f = 4..5f;");
+
+        // Integer literals must fit in 64 bits
+        parse_ok("u64 g = 18446744073709551615;");
+        parse_ok("u64 g = 0xFFFF_FFFF_FFFF_FFFF;");
+        parse_ok("u64 g = 0b1111111111111111111111111111111111111111111111111111111111111111;");
+        parse_fails("u64 g = 0x1_0000_0000_0000_0000;");
+        parse_fails("u64 g = 0b11111111111111111111111111111111111111111111111111111111111111111;");
+        parse_fails(&format!("u64 g = {};", "9".repeat(100)));
+
+        let error = super::parse_str("u64 g = 18446744073709551616;").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidLiteral);
+        assert_eq!(error.message(), "integer literal too large");
+        assert_eq!((error.line(), error.column()), (1, 9));
+    }
+
+    #[test]
+    fn int_suffixes()
+    {
+        fn parse_lit(src: &str) -> Result<(i128, Option<IntSuffix>), ParseError>
+        {
+            let unit = super::parse_str(&format!("u64 g = {};", src))?;
+            match unit.global_vars[0].init_expr.as_ref().unwrap().kind {
+                ExprKind::Int { value, suffix } => Ok((value, suffix)),
+                _ => panic!()
+            }
+        }
+
+        assert_eq!(parse_lit("42").unwrap(), (42, None));
+        assert_eq!(parse_lit("42u64").unwrap(), (42, Some(IntSuffix::U64)));
+        assert_eq!(parse_lit("255u8").unwrap(), (255, Some(IntSuffix::U8)));
+        assert_eq!(parse_lit("1000u32").unwrap(), (1000, Some(IntSuffix::U32)));
+        assert_eq!(parse_lit("1_000i16").unwrap(), (1000, Some(IntSuffix::I16)));
+        assert_eq!(parse_lit("0xFFFFFFFFu32").unwrap(), (0xFFFFFFFF, Some(IntSuffix::U32)));
+        assert_eq!(parse_lit("0b101u8").unwrap(), (5, Some(IntSuffix::U8)));
+        assert_eq!(parse_lit("-128i8").unwrap(), (-128, Some(IntSuffix::I8)));
+
+        // C-style suffixes
+        assert_eq!(parse_lit("42ULL").unwrap(), (42, Some(IntSuffix::U64)));
+        assert_eq!(parse_lit("42ul").unwrap(), (42, Some(IntSuffix::U64)));
+        assert_eq!(parse_lit("42U").unwrap(), (42, Some(IntSuffix::U32)));
+        assert_eq!(parse_lit("42L").unwrap(), (42, Some(IntSuffix::I64)));
+
+        let error = parse_lit("42xyz").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidLiteral);
+        assert_eq!(error.message(), "invalid suffix \"xyz\" on integer literal");
+        assert_eq!((error.line(), error.column()), (1, 11));
+        assert!(parse_lit("42U64").is_err());
+        assert!(parse_lit("42f").is_err());
+
+        // The value must fit in the suffix type
+        let error = parse_lit("256u8").unwrap_err();
+        assert_eq!(error.message(), "integer literal 256 does not fit in u8");
+        assert_eq!((error.line(), error.column()), (1, 9));
+        assert!(parse_lit("0x1_0000_0000u32").is_err());
+        assert!(parse_lit("129i8").is_err());
+        assert!(parse_lit("-129i8").is_err());
+
+        // Only negated signed literals can hold the minimum value
+        let error = parse_lit("128i8").unwrap_err();
+        assert_eq!(error.message(), "integer literal 128 does not fit in i8");
+        assert!(parse_lit("32768i16").is_err());
+        assert!(parse_lit("2147483648i32").is_err());
+        assert!(parse_lit("0x8000_0000_0000_0000i64").is_err());
+        assert_eq!(parse_lit("-32768i16").unwrap(), (-32768, Some(IntSuffix::I16)));
+        assert_eq!(parse_lit("- 2147483648i32").unwrap(), (-2147483648, Some(IntSuffix::I32)));
+        assert_eq!(parse_lit("-0x8000_0000_0000_0000i64").unwrap(), (i64::MIN as i128, Some(IntSuffix::I64)));
+        assert!(parse_lit("18446744073709551615u64").is_ok());
     }
 
     #[test]
@@ -1255,6 +2963,39 @@ mod tests
     {
         parse_ok("u8 array[3] = {};");
         parse_ok("u8 array[3] = { 0, 1, 2 };");
+
+        fn global_type(src: &str) -> Type
+        {
+            super::parse_str(src).unwrap().global_vars[0].var_type.clone()
+        }
+
+        fn array_shape(t: &Type) -> String
+        {
+            match t {
+                Type::Array { elem_type, size_expr } => match size_expr.kind {
+                    ExprKind::Int { value, .. } => format!("[{}]{}", value, array_shape(elem_type)),
+                    _ => panic!()
+                },
+                _ => t.to_string()
+            }
+        }
+
+        // The declared type is the element type, the
+        // first size is the outermost array dimension
+        assert_eq!(array_shape(&global_type("u8 a[3];")), "[3]u8");
+        assert_eq!(array_shape(&global_type("char* a[4];")), "[4]u8*");
+        assert_eq!(array_shape(&global_type("u32 fb[800][600];")), "[800][600]u32");
+
+        // Array fields in structs
+        let unit = super::parse_str("typedef struct { u8 len; u8 buf[4][2]; } buf_t;").unwrap();
+        match &*unit.typedefs[0].t.borrow() {
+            Type::Struct { fields } => {
+                assert_eq!(fields[1].0, "buf");
+                assert_eq!(array_shape(&fields[1].1), "[4][2]u8");
+            }
+            _ => panic!()
+        }
+        parse_fails("typedef struct { u8[4] buf; } buf_t;");
     }
 
     #[test]
@@ -1291,6 +3032,36 @@ mod tests
         parse_fails("u64 foo() { return 1 + 2 +; }");
     }
 
+    #[test]
+    fn associativity()
+    {
+        // Equal-precedence operators are left-associative
+        expr_ok("a - b - c", "(Sub (Sub a b) c)");
+        expr_ok("a - b + c", "(Add (Sub a b) c)");
+        expr_ok("a / b / c", "(Div (Div a b) c)");
+        expr_ok("a / b * c % d", "(Mod (Mul (Div a b) c) d)");
+        expr_ok("10 - 3 - 2", "(Sub (Sub 10 3) 2)");
+        expr_ok("a << b >> c", "(RShift (LShift a b) c)");
+        expr_ok("a - b * c - d", "(Sub (Sub a (Mul b c)) d)");
+
+        // Assignment is right-associative
+        expr_ok("a = b = c", "(Assign a (Assign b c))");
+        expr_ok("a = b - c - d", "(Assign a (Sub (Sub b c) d))");
+    }
+
+    #[test]
+    fn prefix_postfix()
+    {
+        // Postfix operators bind tighter than prefix operators
+        expr_ok("!foo()", "(Not (call foo []))");
+        expr_ok("-bar(1) + 2", "(Add (Minus (call bar [1])) 2)");
+        expr_ok("-p->y", "(Minus (-> p y))");
+        expr_ok("!p->x->y", "(Not (-> (-> p x) y))");
+        expr_ok("*p->f(1)", "(Deref (call (-> p f) [1]))");
+        expr_ok("-a[1] * b", "(Mul (Minus (Deref (Add a 1))) b)");
+        expr_ok("~f(x)(y)", "(BitNot (call (call f [x]) [y]))");
+    }
+
     #[test]
     fn cast_exprs()
     {
@@ -1369,4 +3140,31 @@ mod tests
         parse_ok("void main() { if (1) { foo(); } }");
         parse_ok("void main() { if (1) { foo(); } else { bar(); } }");
     }
+
+    #[test]
+    fn assert_stmt()
+    {
+        parse_ok("void main() { assert 1; }");
+        parse_ok("void main() { assert(1); }");
+        parse_ok("void main(u64 x) { assert(x < 3, \"x too big\"); }");
+        parse_ok("void main(u64 x) { assert(x, \"foo\" \"bar\"); }");
+        parse_fails("void main() { assert(1) }");
+        parse_fails("void main() { assert(1, 2); }");
+        parse_fails("void main() { assert; }");
+        parse_fails("void assert() {}");
+
+        // The expression is kept as text for the failure message
+        let mut input = Input::new("void main(u64 x) { assert(x + 1 < 3, \"a\" \"b\"); }", "src");
+        let (unit, _) = parse_unit(&mut input).unwrap();
+        match &unit.fun_decls[0].body.kind {
+            StmtKind::Block(stmts) => match &stmts[0].kind {
+                StmtKind::Assert { msg, expr_text, .. } => {
+                    assert_eq!(expr_text, "(x + 1) < 3");
+                    assert_eq!(msg.as_deref(), Some("ab"));
+                }
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+    }
 }
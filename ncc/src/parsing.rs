@@ -1,42 +1,199 @@
 use std::fs;
 use std::fmt;
+use std::io;
+use std::rc::Rc;
+use crate::ast::Span;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct SrcPos
 {
+    idx: usize,
+    byte_offset: usize,
     line_no: u32,
     col_no: u32,
 }
 
+/// Saved input state that parsing can be reset to
+#[derive(Debug, Clone)]
+pub struct InputMark
+{
+    pos: usize,
+    byte_offset: usize,
+    line_no: u32,
+    col_no: u32,
+    src_name: Rc<String>,
+    num_warnings: usize,
+}
+
+/// Category of a parse error, so that tools can
+/// distinguish kinds of errors without matching on messages
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind
+{
+    /// The input didn't match an expected token
+    UnexpectedToken,
+
+    /// The input ended in the middle of a construct
+    UnexpectedEof,
+
+    /// A string or character literal is missing its closing quote
+    UnterminatedString,
+
+    /// A multi-line comment is missing its closing */
+    UnterminatedComment,
+
+    /// Expressions or statements are nested beyond the limit
+    NestingTooDeep,
+
+    /// Malformed numeric or character literal, or escape sequence
+    InvalidLiteral,
+
+    /// Expected an identifier
+    ExpectedIdentifier,
+
+    /// Expected a type name
+    ExpectedType,
+
+    /// Expected an expression
+    ExpectedExpression,
+
+    /// A name was declared twice in the same scope
+    DuplicateDeclaration,
+
+    /// Reference to a name that isn't declared
+    UndeclaredIdentifier,
+
+    /// Operand or value with the wrong type
+    TypeError,
+
+    /// Call with the wrong number of arguments
+    ArgumentCount,
+
+    /// Constant expression that isn't constant, overflows or divides by zero
+    ConstEval,
+
+    /// A declaration that is syntactically well-formed but not allowed
+    InvalidDeclaration,
+
+    /// Malformed preprocessor directive or macro use
+    Preprocessor,
+
+    /// The source file could not be read
+    Io,
+
+    /// A warning reported as an error, as with -Werror
+    Warning,
+
+    /// Errors not covered by a more specific kind
+    Other,
+}
+
 #[derive(Debug, Clone)]
 pub struct ParseError
 {
+    pub kind: ErrorKind,
     pub msg: String,
     pub src_name: String,
     pub line_no: u32,
     pub col_no: u32,
+
+    // Byte offset of the error in the source text
+    pub byte_offset: usize,
+
+    // Text of the source line where the error occurred, if known
+    pub src_line: Option<String>,
+
+    // Notes pointing at related locations, e.g. an unclosed delimiter
+    pub notes: Vec<Diagnostic>,
 }
 
 impl ParseError
 {
-    pub fn new(input: &Input, msg: &str) -> Self
+    pub fn new(input: &Input, kind: ErrorKind, msg: &str) -> Self
+    {
+        ParseError::with_pos(input, input.get_pos(), kind, msg)
+    }
+
+    /// Parse error at a previously recorded source position
+    pub fn with_pos(input: &Input, pos: SrcPos, kind: ErrorKind, msg: &str) -> Self
+    {
+        ParseError {
+            kind,
+            msg: msg.to_string(),
+            src_name: input.src_name.to_string(),
+            line_no: pos.line_no,
+            col_no: pos.col_no,
+            byte_offset: pos.byte_offset,
+            src_line: Some(input.line_text(pos.idx)),
+            notes: Vec::default(),
+        }
+    }
+
+    /// Error at the location of an AST node
+    pub fn at_span(span: &Span, kind: ErrorKind, msg: &str) -> Self
     {
         ParseError {
+            kind,
             msg: msg.to_string(),
-            src_name: input.src_name.clone(),
-            line_no: input.line_no,
-            col_no: input.col_no
+            src_name: span.src_name.to_string(),
+            line_no: span.line_no,
+            col_no: span.col_no,
+            byte_offset: span.start,
+            src_line: None,
+            notes: Vec::default(),
         }
     }
 
+    /// Attach a note pointing at a related location
+    pub fn with_note(mut self, note: Diagnostic) -> Self
+    {
+        self.notes.push(note);
+        self
+    }
+
+    /// Category of the error
+    pub fn kind(&self) -> ErrorKind
+    {
+        self.kind
+    }
+
+    /// Line number of the error, 0 if the location is unknown
+    pub fn line(&self) -> u32
+    {
+        self.line_no
+    }
+
+    /// Column number of the error, 0 if the location is unknown
+    pub fn column(&self) -> u32
+    {
+        self.col_no
+    }
+
+    /// Byte offset of the error in the source text,
+    /// only meaningful if the location is known
+    pub fn byte_offset(&self) -> usize
+    {
+        self.byte_offset
+    }
+
+    /// Error message without location information
+    pub fn message(&self) -> &str
+    {
+        &self.msg
+    }
+
     /// Parse error with just an error message, no location
     pub fn msg_only<T>(msg: &str) -> Result<T, ParseError>
     {
         Err(ParseError {
+            kind: ErrorKind::Other,
             msg: msg.to_string(),
             src_name: String::new(),
             line_no: 0,
             col_no: 0,
+            byte_offset: 0,
+            src_line: None,
+            notes: Vec::default(),
         })
     }
 }
@@ -44,7 +201,149 @@ impl ParseError
 impl fmt::Display for ParseError
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "parse error")
+        // Errors produced without a location only have a message
+        if self.line_no == 0 {
+            return write!(f, "error: {}", self.msg);
+        }
+
+        // Same format as GCC and clang so editors can jump to the location
+        write!(f, "{}:{}:{}: error: {}", self.src_name, self.line_no, self.col_no, self.msg)?;
+
+        // Show the source line with a caret under the error column.
+        // Tabs are copied into the padding so the caret lines up
+        // with the source line whatever the tab width is.
+        if let Some(src_line) = &self.src_line {
+            let caret_pad: String = src_line
+                .chars()
+                .take((self.col_no as usize).saturating_sub(1))
+                .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                .collect();
+            write!(f, "\n{}\n{}^", src_line, caret_pad)?;
+        }
+
+        for note in &self.notes {
+            write!(f, "\n{}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Severity level of a diagnostic that doesn't stop compilation.
+/// Errors are reported with ParseError.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity
+{
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Diagnostic for code that is legal but suspicious,
+/// or a note attached to another diagnostic
+#[derive(Debug, Clone)]
+pub struct Diagnostic
+{
+    pub severity: Severity,
+    pub msg: String,
+    pub src_name: String,
+    pub line_no: u32,
+    pub col_no: u32,
+    pub byte_offset: usize,
+}
+
+impl Diagnostic
+{
+    /// Warning at a previously recorded source position
+    pub fn new(input: &Input, pos: SrcPos, msg: &str) -> Self
+    {
+        Diagnostic {
+            severity: Severity::Warning,
+            msg: msg.to_string(),
+            src_name: input.src_name.to_string(),
+            line_no: pos.line_no,
+            col_no: pos.col_no,
+            byte_offset: pos.byte_offset,
+        }
+    }
+
+    /// Warning at the location of an AST node
+    pub fn at_span(span: &Span, msg: &str) -> Self
+    {
+        Diagnostic {
+            severity: Severity::Warning,
+            msg: msg.to_string(),
+            src_name: span.src_name.to_string(),
+            line_no: span.line_no,
+            col_no: span.col_no,
+            byte_offset: span.start,
+        }
+    }
+
+    /// Note at a previously recorded source position
+    pub fn note(input: &Input, pos: SrcPos, msg: &str) -> Self
+    {
+        Diagnostic {
+            severity: Severity::Note,
+            ..Diagnostic::new(input, pos, msg)
+        }
+    }
+
+    /// Note at the location of an AST node
+    pub fn note_at_span(span: &Span, msg: &str) -> Self
+    {
+        Diagnostic {
+            severity: Severity::Note,
+            ..Diagnostic::at_span(span, msg)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line_no == 0 {
+            return write!(f, "{}: {}", self.severity, self.msg);
+        }
+
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.src_name,
+            self.line_no,
+            self.col_no,
+            self.severity,
+            self.msg
+        )
+    }
+}
+
+impl From<Diagnostic> for ParseError
+{
+    /// Report a warning as an error
+    fn from(warning: Diagnostic) -> Self
+    {
+        ParseError {
+            kind: ErrorKind::Warning,
+            msg: warning.msg,
+            src_name: warning.src_name,
+            line_no: warning.line_no,
+            col_no: warning.col_no,
+            byte_offset: warning.byte_offset,
+            src_line: None,
+            notes: Vec::default(),
+        }
     }
 }
 
@@ -69,44 +368,228 @@ pub struct Input
     // Current index in the input string
     idx: usize,
 
+    // Current byte offset in the UTF-8 encoded source text
+    pub byte_offset: usize,
+
     // Input source name
-    pub src_name: String,
+    pub src_name: Rc<String>,
 
     // Current line number
     pub line_no: u32,
 
     // Current column number
     pub col_no: u32,
+
+    // Errors recorded while recovering, None if recovery is disabled
+    recovered_errors: Option<Vec<ParseError>>,
+
+    // Warnings reported so far
+    warnings: Vec<Diagnostic>,
+
+    // Report warnings as errors, as with -Werror
+    warnings_as_errors: bool,
+
+    // Current nesting depth of expressions and statements
+    depth: usize,
+
+    // Maximum nesting depth before parsing fails
+    max_depth: usize,
 }
 
+/// Default maximum nesting depth of expressions and statements.
+/// Parsing is recursive, so this keeps deeply nested input from
+/// overflowing the native stack.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
 impl Input
 {
     pub fn from_file(file_name: &str) -> Result<Self, ParseError>
     {
-        let data = match fs::read_to_string(file_name) {
+        let file = fs::File::open(file_name);
+
+        match file.and_then(|file| Input::new_from_reader(file, file_name)) {
+            Ok(input) => Ok(input),
+            Err(error) => Err(ParseError {
+                kind: ErrorKind::Io,
+                msg: format!("could not read input file \"{}\": {}", file_name, error),
+                src_name: String::new(),
+                line_no: 0,
+                col_no: 0,
+                byte_offset: 0,
+                src_line: None,
+                notes: Vec::default(),
+            })
+        }
+    }
+
+    /// Read all of the source from a reader, which must be valid UTF-8
+    pub fn new_from_reader<R: io::Read>(mut reader: R, src_name: &str) -> Result<Self, io::Error>
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let data = match String::from_utf8(bytes) {
             Ok(data) => data,
-            Err(_) => {
-                return Err(ParseError {
-                    msg: format!("could not read input file \"{}\"", file_name),
-                    src_name: String::new(),
-                    line_no: 0,
-                    col_no: 0,
-                })
-            }
+            Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error))
         };
 
-        Ok(Input::new(&data, file_name))
+        Ok(Input::new(&data, src_name))
     }
 
     pub fn new(input_str: &str, src_name: &str) -> Self
     {
         Input {
             input: input_str.chars().collect(),
-            src_name: src_name.to_string(),
+            src_name: Rc::new(src_name.to_string()),
             idx: 0,
+            byte_offset: 0,
             line_no: 1,
-            col_no: 1
+            col_no: 1,
+            recovered_errors: None,
+            warnings: Vec::default(),
+            warnings_as_errors: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Set the maximum nesting depth of expressions and statements
+    pub fn set_max_depth(&mut self, max_depth: usize)
+    {
+        self.max_depth = max_depth;
+    }
+
+    /// Run a parsing function one nesting level deeper,
+    /// failing if the maximum nesting depth is exceeded
+    pub fn nested<T, F>(&mut self, parse_fn: F) -> Result<T, ParseError>
+    where F : FnOnce(&mut Input) -> Result<T, ParseError>
+    {
+        if self.depth >= self.max_depth {
+            return self.parse_error(
+                ErrorKind::NestingTooDeep,
+                &format!("nesting too deep, the limit is {} levels", self.max_depth)
+            );
+        }
+
+        self.depth += 1;
+        let ret = parse_fn(self);
+        self.depth -= 1;
+
+        ret
+    }
+
+    /// Enable error recovery, so that the parser can record
+    /// errors and continue instead of stopping at the first one
+    pub fn enable_recovery(&mut self)
+    {
+        self.recovered_errors = Some(Vec::default());
+    }
+
+    /// Record an error if recovery is enabled,
+    /// otherwise return it so that parsing stops
+    pub fn recover_from(&mut self, error: ParseError) -> Result<(), ParseError>
+    {
+        // Skipping to the end of the statement doesn't get out
+        // of nesting that is too deep, so this isn't recovered from
+        if error.kind() == ErrorKind::NestingTooDeep {
+            return Err(error);
+        }
+
+        match self.recovered_errors.as_mut() {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error)
+        }
+    }
+
+    /// Take the errors recorded so far during recovery
+    pub fn take_recovered_errors(&mut self) -> Vec<ParseError>
+    {
+        match self.recovered_errors.as_mut() {
+            Some(errors) => std::mem::take(errors),
+            None => Vec::default()
+        }
+    }
+
+    /// Report warnings as errors, as with -Werror
+    pub fn set_warnings_as_errors(&mut self, enabled: bool)
+    {
+        self.warnings_as_errors = enabled;
+    }
+
+    /// Report a warning at a previously recorded source position
+    pub fn warn(&mut self, pos: SrcPos, msg: &str)
+    {
+        let warning = Diagnostic::new(self, pos, msg);
+        self.add_warning(warning);
+    }
+
+    /// Report a warning created elsewhere, e.g. from an AST node span
+    pub fn add_warning(&mut self, warning: Diagnostic)
+    {
+        self.warnings.push(warning);
+    }
+
+    /// Take the warnings reported so far. If warnings are
+    /// reported as errors, they are returned as errors instead.
+    pub fn take_warnings(&mut self) -> Result<Vec<Diagnostic>, Vec<ParseError>>
+    {
+        let warnings = std::mem::take(&mut self.warnings);
+
+        if self.warnings_as_errors && warnings.len() > 0 {
+            return Err(warnings.into_iter().map(ParseError::from).collect());
+        }
+
+        Ok(warnings)
+    }
+
+    /// Get the current source position
+    pub fn get_pos(&self) -> SrcPos
+    {
+        SrcPos {
+            idx: self.idx,
+            byte_offset: self.byte_offset,
+            line_no: self.line_no,
+            col_no: self.col_no,
+        }
+    }
+
+    /// Get the byte offset just past the last non-whitespace
+    /// character consumed, for the end of AST node spans
+    pub fn end_offset(&self) -> usize
+    {
+        // Eating past the end of the input counts one byte per char
+        let mut idx = self.idx.min(self.input.len());
+        let mut byte_offset = self.byte_offset - (self.idx - idx);
+
+        while idx > 0 && self.input[idx - 1].is_ascii_whitespace() {
+            idx -= 1;
+            byte_offset -= self.input[idx].len_utf8();
+        }
+
+        byte_offset
+    }
+
+    /// Get the text of the source line containing a given index,
+    /// without the line terminator
+    pub fn line_text(&self, idx: usize) -> String
+    {
+        let idx = idx.min(self.input.len());
+
+        let mut start = idx;
+        while start > 0 && self.input[start - 1] != '\n' {
+            start -= 1;
+        }
+
+        let mut end = idx;
+        while end < self.input.len() && self.input[end] != '\n' {
+            end += 1;
         }
+
+        let line: String = self.input[start..end].iter().collect();
+        line.trim_end_matches('\r').to_string()
     }
 
     /// Test if the end of the input has been reached
@@ -127,18 +610,26 @@ impl Input
     }
 
     /// Consume a character from the input
+    ///
+    /// Columns count Unicode scalar values, so a tab or an emoji
+    /// advances the column by one. A \r\n pair is a single newline.
     pub fn eat_ch(&mut self) -> char
     {
         let ch = self.peek_ch();
 
         // Move to the next char
         self.idx += 1;
+        self.byte_offset += ch.len_utf8();
 
         if ch == '\n'
         {
             self.line_no += 1;
             self.col_no = 1;
         }
+        else if ch == '\r' && self.peek_ch() == '\n'
+        {
+            // The column doesn't move, the \n that follows ends the line
+        }
         else
         {
             self.col_no += 1;
@@ -158,20 +649,19 @@ impl Input
         return false;
     }
 
+    /// Peek at the next n characters without consuming them.
+    /// Produces None if fewer than n characters remain.
+    pub fn peek_n(&self, n: usize) -> Option<&[char]>
+    {
+        self.input.get(self.idx..self.idx + n)
+    }
+
     /// Match characters in the input, no preceding whitespace allowed
     pub fn match_chars(&mut self, chars: &[char]) -> bool
     {
-        let end_pos = self.idx + chars.len();
-
-        if end_pos > self.input.len() {
-            return false;
-        }
-
         // Compare the characters to match
-        for i in 0..chars.len() {
-            if chars[i] != self.input[self.idx + i] {
-                return false;
-            }
+        if self.peek_n(chars.len()) != Some(chars) {
+            return false;
         }
 
         // Consumed the matched characters
@@ -202,7 +692,7 @@ impl Input
         loop
         {
             if self.eof() {
-                return self.parse_error(&format!("unexpected end of input inside multi-line comment"));
+                return self.parse_error(ErrorKind::UnterminatedComment, "unexpected end of input inside multi-line comment");
             }
             else if self.match_chars(&['/', '*']) {
                 depth += 1;
@@ -241,18 +731,19 @@ impl Input
                 let linenum = self.parse_int(10)?;
 
                 if !self.match_char(' ') {
-                    return self.parse_error("expected space in linenum directive");
+                    return self.parse_error(ErrorKind::Preprocessor, "expected space in linenum directive");
                 }
 
                 let file_name = self.parse_str('"')?;
 
+                self.match_char('\r');
                 if !self.match_char('\n') {
-                    return self.parse_error("expected newline after linenum directive");
+                    return self.parse_error(ErrorKind::Preprocessor, "expected newline after linenum directive");
                 }
 
                 // Update the source position
                 self.line_no = linenum.try_into().unwrap();
-                self.src_name = file_name;
+                self.src_name = Rc::new(file_name);
             }
 
             // Single-line comment
@@ -313,13 +804,25 @@ impl Input
             return Ok(false);
         }
 
+        // Nor if we are in the middle of an identifier,
+        // e.g. positioned after the "x" in "xreturn"
+        if self.idx > 0 && is_ident_ch(self.input[self.idx - 1]) {
+            return Ok(false);
+        }
+
         return Ok(self.match_chars(&chars));
     }
 
     /// Shortcut for yielding a parse error wrapped in a result type
-    pub fn parse_error<T>(&self, msg: &str) -> Result<T, ParseError>
+    pub fn parse_error<T>(&self, kind: ErrorKind, msg: &str) -> Result<T, ParseError>
+    {
+        Err(ParseError::new(self, kind, msg))
+    }
+
+    /// Yield a parse error pointing at a previously recorded position
+    pub fn parse_error_at<T>(&self, pos: SrcPos, kind: ErrorKind, msg: &str) -> Result<T, ParseError>
     {
-        Err(ParseError::new(self, msg))
+        Err(ParseError::with_pos(self, pos, kind, msg))
     }
 
     /// Produce an error if the input doesn't match a given token
@@ -329,16 +832,53 @@ impl Input
             return Ok(())
         }
 
-        self.parse_error(&format!("expected token \"{}\"", token))
+        self.parse_error(ErrorKind::UnexpectedToken, &format!("expected token \"{}\"", token))
+    }
+
+    /// Yield a parse error with a note pointing at an unclosed opening delimiter
+    pub fn unclosed_error<T>(&self, open_pos: SrcPos, open_token: &str, kind: ErrorKind, msg: &str) -> Result<T, ParseError>
+    {
+        let note = Diagnostic::note(self, open_pos, &format!("to match this \"{}\"", open_token));
+        Err(ParseError::new(self, kind, msg).with_note(note))
+    }
+
+    /// Produce an error if the input doesn't match the closing token
+    /// for a delimiter opened at a given position
+    pub fn expect_closing(&mut self, token: &str, open_pos: SrcPos, open_token: &str) -> Result<(), ParseError>
+    {
+        if self.match_token(token)? {
+            return Ok(())
+        }
+
+        self.unclosed_error(
+            open_pos,
+            open_token,
+            ErrorKind::UnexpectedToken,
+            &format!("expected token \"{}\"", token)
+        )
     }
 
     /// Parse a decimal integer value
     pub fn parse_int(&mut self, radix: u32) -> Result<i128, ParseError>
     {
+        self.parse_int_bounded(radix, i128::MAX)
+    }
+
+    /// Parse an unsigned integer that must fit in 64 bits
+    pub fn parse_uint(&mut self, radix: u32) -> Result<u64, ParseError>
+    {
+        let int_val = self.parse_int_bounded(radix, u64::MAX as i128)?;
+        Ok(int_val as u64)
+    }
+
+    /// Parse an integer, failing if its value is above max_val
+    pub fn parse_int_bounded(&mut self, radix: u32, max_val: i128) -> Result<i128, ParseError>
+    {
+        let start_pos = self.get_pos();
         let mut int_val: i128 = 0;
 
         if self.eof() || self.peek_ch().to_digit(radix).is_none() {
-            return self.parse_error("expected digit");
+            return self.parse_error(ErrorKind::InvalidLiteral, "expected digit");
         }
 
         loop
@@ -361,7 +901,14 @@ impl Input
                 break
             }
 
-            int_val = (radix as i128) * int_val + digit.unwrap() as i128;
+            int_val = match int_val
+                .checked_mul(radix as i128)
+                .and_then(|v| v.checked_add(digit.unwrap() as i128))
+            {
+                Some(v) if v <= max_val => v,
+                _ => return self.parse_error_at(start_pos, ErrorKind::InvalidLiteral, "integer literal too large")
+            };
+
             self.eat_ch();
         }
 
@@ -427,16 +974,23 @@ impl Input
     pub fn parse_str(&mut self, end_ch: char) -> Result<String, ParseError>
     {
         // Eat the opening character
-        self.eat_ch();
+        let open_pos = self.get_pos();
+        let open_ch = self.eat_ch();
 
         let mut out = String::new();
 
         loop
         {
             if self.eof() {
-                return self.parse_error("unexpected end of input while parsing string literal");
+                return self.unclosed_error(
+                    open_pos,
+                    &open_ch.to_string(),
+                    ErrorKind::UnterminatedString,
+                    "unexpected end of input while parsing string literal"
+                );
             }
 
+            let esc_pos = self.get_pos();
             let ch = self.eat_ch();
 
             if ch == end_ch {
@@ -444,6 +998,10 @@ impl Input
             }
 
             if ch == '\\' {
+                if self.eof() {
+                    continue;
+                }
+
                 match self.eat_ch() {
                     '\\' => out.push('\\'),
                     '\'' => out.push('\''),
@@ -463,11 +1021,15 @@ impl Input
                                 let byte_val = ((d0 << 4) + d1) as u8;
                                 out.push(byte_val as char);
                             }
-                            _ => return self.parse_error("invalid hexadecimal escape sequence")
+                            _ => return self.parse_error_at(esc_pos, ErrorKind::InvalidLiteral, "invalid hexadecimal escape sequence")
                         }
                     }
 
-                    _ => return self.parse_error("unknown escape sequence")
+                    esc_ch => return self.parse_error_at(
+                        esc_pos,
+                        ErrorKind::InvalidLiteral,
+                        &format!("unknown escape sequence \"\\{}\"", esc_ch)
+                    )
                 }
 
                 continue;
@@ -485,7 +1047,7 @@ impl Input
         let mut ident = String::new();
 
         if self.eof() || !is_ident_start(self.peek_ch()) {
-            return self.parse_error("expected identifier");
+            return self.parse_error(ErrorKind::ExpectedIdentifier, "expected identifier");
         }
 
         loop
@@ -507,23 +1069,76 @@ impl Input
         return Ok(ident);
     }
 
+    /// Parse an identifier, rejecting any of the given reserved keywords
+    pub fn parse_ident_non_keyword(&mut self, reserved: &[&str]) -> Result<String, ParseError>
+    {
+        let pos = self.get_pos();
+        let ident = self.parse_ident()?;
+
+        if reserved.contains(&ident.as_str()) {
+            return self.parse_error_at(
+                pos,
+                ErrorKind::ExpectedIdentifier,
+                &format!("identifier expected, found keyword \"{}\"", ident)
+            );
+        }
+
+        Ok(ident)
+    }
+
+    /// Peek at the identifier starting at the current position without
+    /// consuming it. Produces an empty string if there is no identifier.
+    pub fn peek_ident(&self) -> String
+    {
+        if self.eof() || !is_ident_start(self.peek_ch()) {
+            return String::new();
+        }
+
+        self.input[self.idx..]
+            .iter()
+            .take_while(|ch| is_ident_ch(**ch))
+            .collect()
+    }
+
+    /// Save the current input state so that it can be restored later
+    pub fn mark(&self) -> InputMark
+    {
+        InputMark {
+            pos: self.idx,
+            byte_offset: self.byte_offset,
+            line_no: self.line_no,
+            col_no: self.col_no,
+            src_name: self.src_name.clone(),
+            num_warnings: self.warnings.len(),
+        }
+    }
+
+    /// Restore an input state saved with mark()
+    pub fn reset(&mut self, mark: InputMark)
+    {
+        self.idx = mark.pos;
+        self.byte_offset = mark.byte_offset;
+        self.line_no = mark.line_no;
+        self.col_no = mark.col_no;
+        self.src_name = mark.src_name;
+
+        // Drop warnings about input that will be parsed again
+        self.warnings.truncate(mark.num_warnings);
+    }
+
     /// Try to parse something using a parsing function,
     /// and backtrack if the parsing fails
     pub fn with_backtracking<T, F>(&mut self, parse_fn: F) -> Result<T, ParseError>
     where F : FnOnce(&mut Input) -> Result<T, ParseError>
     {
-        let pos = self.idx;
-        let line_no = self.line_no;
-        let col_no = self.col_no;
+        let mark = self.mark();
 
         // Try to parse using the parsing function provided
         let ret = parse_fn(self);
 
         if ret.is_err() {
             // Backtrack
-            self.idx = pos;
-            self.line_no = line_no;
-            self.col_no = col_no;
+            self.reset(mark);
         }
 
         ret
@@ -553,3 +1168,250 @@ impl Input
         }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn from_reader()
+    {
+        let mut input = Input::new_from_reader("u64 x;".as_bytes(), "reader").unwrap();
+        assert_eq!(*input.src_name, "reader");
+        assert_eq!(input.parse_ident().unwrap(), "u64");
+
+        let input = Input::new_from_reader(io::Cursor::new("/* é */".to_string()), "cursor").unwrap();
+        assert_eq!(input.input.len(), 7);
+
+        // Invalid UTF-8
+        let error = Input::new_from_reader(&[b'a', 0xff, 0xfe][..], "bad").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Invalid UTF-8 in a file is reported as an input error
+        let path = std::env::temp_dir().join("ncc_invalid_utf8.c");
+        fs::write(&path, [b'a', 0xff, 0xfe]).unwrap();
+        let error = Input::from_file(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Io);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mark_reset()
+    {
+        fn state(input: &Input) -> (usize, u32, u32, String)
+        {
+            (input.idx, input.line_no, input.col_no, input.src_name.to_string())
+        }
+
+        let mut input = Input::new("foo\n  bar(", "src");
+        input.parse_ident().unwrap();
+        let mark = input.mark();
+        let before = state(&input);
+
+        // Failed match after consuming whitespace and a newline
+        assert!(!input.match_token("baz").unwrap());
+        assert_ne!(state(&input), before);
+        input.reset(mark.clone());
+        assert_eq!(state(&input), before);
+
+        // Partially successful parse
+        assert!(input.parse_ident().is_err());
+        input.eat_ws().unwrap();
+        assert_eq!(input.parse_ident().unwrap(), "bar");
+        assert!(input.expect_token(")").is_err());
+        input.reset(mark.clone());
+        assert_eq!(state(&input), before);
+        assert_eq!(input.peek_ch(), '\n');
+
+        // Linenum directives change the source name
+        let mut input = Input::new("x\n# 10 \"foo.c\"\ny", "src");
+        input.eat_ch();
+        let mark = input.mark();
+        let before = state(&input);
+        input.eat_ws().unwrap();
+        assert_eq!(state(&input), (15, 10, 1, "foo.c".to_string()));
+        input.reset(mark);
+        assert_eq!(state(&input), before);
+    }
+
+    #[test]
+    fn peek_n()
+    {
+        let mut input = Input::new("a->b", "src");
+        assert_eq!(input.peek_n(0), Some(&[][..]));
+        assert_eq!(input.peek_n(1), Some(&['a'][..]));
+        input.eat_ch();
+
+        // Lookahead doesn't consume anything
+        assert_eq!(input.peek_n(2), Some(&['-', '>'][..]));
+        assert_eq!(input.peek_n(2), Some(&['-', '>'][..]));
+        assert_eq!(input.peek_ch(), '-');
+
+        // Exactly n characters remaining
+        assert_eq!(input.peek_n(3), Some(&['-', '>', 'b'][..]));
+        assert_eq!(input.peek_n(4), None);
+
+        // At the end of the input
+        input.eat_ch();
+        input.eat_ch();
+        input.eat_ch();
+        assert!(input.eof());
+        assert_eq!(input.peek_n(0), Some(&[][..]));
+        assert_eq!(input.peek_n(1), None);
+    }
+
+    #[test]
+    fn int_overflow()
+    {
+        fn parse_uint(src: &str, radix: u32) -> Result<u64, ParseError>
+        {
+            Input::new(src, "src").parse_uint(radix)
+        }
+
+        assert_eq!(parse_uint("18446744073709551615", 10).unwrap(), u64::MAX);
+        assert_eq!(parse_uint("18_446_744_073_709_551_615", 10).unwrap(), u64::MAX);
+        assert_eq!(parse_uint("ffffffffffffffff", 16).unwrap(), u64::MAX);
+
+        let error = parse_uint("18446744073709551616", 10).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidLiteral);
+        assert_eq!(error.message(), "integer literal too large");
+        assert_eq!((error.line(), error.column()), (1, 1));
+        assert!(parse_uint("10000000000000000", 16).is_err());
+        assert!(parse_uint(&"1".repeat(65), 2).is_err());
+
+        // Values that don't even fit in an i128
+        let long_digits = "9".repeat(100);
+        assert!(parse_uint(&long_digits, 10).is_err());
+        let error = Input::new(&long_digits, "src").parse_int(10).unwrap_err();
+        assert_eq!(error.message(), "integer literal too large");
+        assert_eq!(Input::new(&i128::MAX.to_string(), "src").parse_int(10).unwrap(), i128::MAX);
+
+        // Configurable maximum
+        assert_eq!(Input::new("255", "src").parse_int_bounded(10, 255).unwrap(), 255);
+        assert!(Input::new("256", "src").parse_int_bounded(10, 255).is_err());
+    }
+
+    #[test]
+    fn match_keyword()
+    {
+        let mut input = Input::new("return x", "src");
+        assert!(input.match_keyword("return").unwrap());
+        assert!(input.match_keyword("x").unwrap());
+
+        // Keyword followed by identifier characters
+        let mut input = Input::new("returnvalue", "src");
+        assert!(!input.match_keyword("return").unwrap());
+        assert_eq!(input.get_pos().idx, 0);
+
+        // Positioned in the middle of a word
+        let mut input = Input::new("xreturn;", "src");
+        input.eat_ch();
+        assert!(!input.match_keyword("return").unwrap());
+        assert_eq!(input.peek_ch(), 'r');
+        let mut input = Input::new("u8if (", "src");
+        input.eat_ch();
+        input.eat_ch();
+        assert!(!input.match_keyword("if").unwrap());
+
+        // Non-identifier characters before the keyword
+        let mut input = Input::new("}else{", "src");
+        input.eat_ch();
+        assert!(input.match_keyword("else").unwrap());
+        assert_eq!(input.peek_ch(), '{');
+    }
+
+    #[test]
+    fn byte_offset()
+    {
+        let mut input = Input::new("\"é€😀\" x", "src");
+        assert_eq!(input.byte_offset, 0);
+
+        // Multi-byte characters advance the byte offset by their
+        // UTF-8 length, but the column by one
+        input.eat_ch();
+        input.eat_ch();
+        assert_eq!((input.byte_offset, input.col_no), (3, 3));
+        input.eat_ch();
+        assert_eq!((input.byte_offset, input.col_no), (6, 4));
+        let mark = input.mark();
+        input.eat_ch();
+        assert_eq!((input.byte_offset, input.col_no), (10, 5));
+
+        input.reset(mark);
+        assert_eq!(input.byte_offset, 6);
+
+        // The byte offset matches the offset into the source string
+        let src = "/* ü */ u64 x = \"ö\";\r\nbad";
+        let mut input = Input::new(src, "src");
+        input.eat_ws().unwrap();
+        assert_eq!(input.byte_offset, src.find("u64").unwrap());
+        input.parse_ident().unwrap();
+        input.eat_ws().unwrap();
+        input.parse_ident().unwrap();
+        input.expect_token("=").unwrap();
+        input.eat_ws().unwrap();
+        input.parse_str('"').unwrap();
+        input.expect_token(";").unwrap();
+        let error = input.expect_token("(").unwrap_err();
+        assert_eq!(error.byte_offset(), src.find("bad").unwrap());
+        assert_eq!((error.line(), error.column()), (2, 1));
+    }
+
+    #[test]
+    fn string_escapes()
+    {
+        fn parse(src: &str) -> Result<String, ParseError>
+        {
+            let mut input = Input::new(src, "src");
+            let end_ch = input.peek_ch();
+            input.parse_str(end_ch)
+        }
+
+        assert_eq!(parse(r#""hello world""#).unwrap(), "hello world");
+        assert_eq!(parse(r#""hello \"world\"""#).unwrap(), "hello \"world\"");
+        assert_eq!(parse(r#"'it\'s fine'"#).unwrap(), "it's fine");
+        assert_eq!(parse(r#""it's fine""#).unwrap(), "it's fine");
+        assert_eq!(parse(r#"'"'"#).unwrap(), "\"");
+        assert_eq!(parse(r#""a\\b\t\n\0\x41""#).unwrap(), "a\\b\t\n\0A");
+
+        // Errors point at the start of the escape sequence
+        let error = parse(r#""ab\qc""#).unwrap_err();
+        assert_eq!(error.message(), "unknown escape sequence \"\\q\"");
+        assert_eq!(error.column(), 4);
+        let error = parse(r#""\x4g""#).unwrap_err();
+        assert_eq!(error.message(), "invalid hexadecimal escape sequence");
+        assert_eq!(error.column(), 2);
+
+        // An escaped quote doesn't close the string
+        let error = parse(r#""abc\""#).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnterminatedString);
+        let error = parse(r#""abc\"#).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn crlf_comments()
+    {
+        fn pos_after_ws(src: &str) -> (u32, u32)
+        {
+            let mut input = Input::new(src, "src");
+            input.eat_ws().unwrap();
+            assert_eq!(input.peek_ch(), 'x');
+            (input.line_no, input.col_no)
+        }
+
+        assert_eq!(pos_after_ws("// comment\r\nx"), (2, 1));
+        assert_eq!(pos_after_ws("// a\r\n// b\r\n  x"), (3, 3));
+        assert_eq!(pos_after_ws("/* a\r\n b */\r\n\tx"), (3, 2));
+        assert_eq!(pos_after_ws("\r\n\r\n// c\r\n\r\nx"), (5, 1));
+
+        // The same positions as with \n line endings
+        assert_eq!(pos_after_ws("// a\n// b\n  x"), (3, 3));
+
+        // Linenum directives followed by CRLF
+        let mut input = Input::new("# 10 \"foo.c\"\r\n  x", "src");
+        input.eat_ws().unwrap();
+        assert_eq!((input.line_no, input.col_no, input.src_name.as_str()), (10, 3, "foo.c"));
+    }
+}
@@ -0,0 +1,305 @@
+// Peephole optimizations over the generated assembly text.
+//
+// Branches refer to symbolic labels which are resolved by the
+// assembler, so removing instructions never requires fixing up
+// branch offsets. The rewrites applied are:
+//
+// - push 0 followed by add/sub/or/xor/shift on 64-bit values is removed
+// - push 1 followed by mul/div on 64-bit values is removed
+// - two constant pushes followed by add/sub/mul/and/or/xor on 64-bit
+//   values are folded into a single push
+// - a jmp to a label which immediately follows it is removed
+// - instructions after a jmp, ret, tail_call, exit or panic are removed up to
+//   the next label, since they can never be reached
+
+/// Operations for which pushing 0 as the rhs has no effect
+const ZERO_IDENTITY_OPS: [&str; 7] = [
+    "add_u64", "sub_u64", "or_u64", "xor_u64", "lshift_u64", "rshift_u64", "rshift_i64",
+];
+
+/// Operations for which pushing 1 as the rhs has no effect
+const ONE_IDENTITY_OPS: [&str; 3] = ["mul_u64", "div_u64", "div_i64"];
+
+/// Instructions after which execution never falls through
+const NO_FALLTHROUGH_OPS: [&str; 5] = ["jmp", "ret", "tail_call", "exit", "panic"];
+
+/// Kind of line in the assembly output
+#[derive(Debug, PartialEq)]
+enum Line<'a>
+{
+    /// Single instruction, split into the opcode and its operands
+    Insn(&'a str, &'a str),
+
+    Label(&'a str),
+
+    /// Blank lines, comments and source positions
+    Blank,
+
+    /// Anything else, such as directives or multiple instructions
+    /// on one line from inline assembly, which is left untouched
+    Other,
+}
+
+fn classify(line: &str) -> Line<'_>
+{
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') || line.starts_with(".loc ") {
+        return Line::Blank;
+    }
+
+    if let Some(name) = line.strip_suffix(':') {
+        if !name.contains(|c: char| c.is_whitespace() || c == ';') {
+            return Line::Label(name);
+        }
+        return Line::Other;
+    }
+
+    match line.strip_suffix(';') {
+        Some(insn) if !insn.contains(';') && !insn.contains(':') && !insn.starts_with('.') => {
+            match insn.split_once(' ') {
+                Some((op, args)) => Line::Insn(op, args.trim()),
+                None => Line::Insn(insn, ""),
+            }
+        }
+        _ => Line::Other
+    }
+}
+
+/// Get the value pushed by a push instruction with an integer operand
+fn push_const(line: &Line) -> Option<u64>
+{
+    match line {
+        Line::Insn("push", arg) => {
+            let val: i128 = arg.parse().ok()?;
+            if val < i64::MIN as i128 || val > u64::MAX as i128 {
+                return None;
+            }
+            Some(val as u64)
+        }
+        _ => None
+    }
+}
+
+/// Fold a 64-bit operation on constant operands
+fn fold_op(op: &str, lhs: u64, rhs: u64) -> Option<u64>
+{
+    match op {
+        "add_u64" => Some(lhs.wrapping_add(rhs)),
+        "sub_u64" => Some(lhs.wrapping_sub(rhs)),
+        "mul_u64" => Some(lhs.wrapping_mul(rhs)),
+        "and_u64" => Some(lhs & rhs),
+        "or_u64" => Some(lhs | rhs),
+        "xor_u64" => Some(lhs ^ rhs),
+        _ => None
+    }
+}
+
+/// Apply one rewrite at the given line index, if any matches
+fn rewrite(lines: &mut Vec<String>, idx: usize) -> bool
+{
+    let cur = classify(&lines[idx]);
+    let next = lines.get(idx + 1).map(|l| classify(l));
+
+    // Identity operations on a pushed constant
+    if let (Some(val), Some(Line::Insn(op, ""))) = (push_const(&cur), &next) {
+        if (val == 0 && ZERO_IDENTITY_OPS.contains(op)) || (val == 1 && ONE_IDENTITY_OPS.contains(op)) {
+            lines.drain(idx..idx + 2);
+            return true;
+        }
+    }
+
+    // Operations on two pushed constants
+    if let (Some(lhs), Some(rhs)) = (push_const(&cur), next.as_ref().and_then(push_const)) {
+        if let Some(Line::Insn(op, "")) = lines.get(idx + 2).map(|l| classify(l)) {
+            if let Some(val) = fold_op(op, lhs, rhs) {
+                // Write negative values as such for readability
+                let val_str = if (val as i64) < 0 { (val as i64).to_string() } else { val.to_string() };
+                lines.splice(idx..idx + 3, [format!("push {};", val_str)]);
+                return true;
+            }
+        }
+    }
+
+    // Jump to a label directly below
+    if let Line::Insn("jmp", target) = cur {
+        for line in &lines[idx + 1..] {
+            match classify(line) {
+                Line::Label(name) if name == target => {
+                    lines.remove(idx);
+                    return true;
+                }
+                Line::Label(_) | Line::Blank => continue,
+                _ => break,
+            }
+        }
+    }
+
+    // Unreachable instructions
+    if let Line::Insn(op, _) = cur {
+        if NO_FALLTHROUGH_OPS.contains(&op) {
+            let mut changed = false;
+            let mut next_idx = idx + 1;
+
+            while next_idx < lines.len() {
+                match classify(&lines[next_idx]) {
+                    Line::Insn(..) => {
+                        lines.remove(next_idx);
+                        changed = true;
+                    }
+                    Line::Blank => next_idx += 1,
+                    _ => break,
+                }
+            }
+
+            return changed;
+        }
+    }
+
+    false
+}
+
+/// Optimize the code section of the generated assembly
+pub fn optimize(asm: &str) -> String
+{
+    let mut lines: Vec<String> = asm.lines().map(|l| l.to_string()).collect();
+
+    // Only the code section contains instructions
+    let code_start = match lines.iter().position(|l| l.trim() == ".code;") {
+        Some(idx) => idx + 1,
+        None => return asm.to_string(),
+    };
+
+    // Rewrites can expose new opportunities, so iterate
+    // until no more changes are made
+    loop {
+        let mut changed = false;
+        let mut idx = code_start;
+
+        while idx < lines.len() {
+            if rewrite(&mut lines, idx) {
+                changed = true;
+            } else {
+                idx += 1;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn opt(code: &str) -> String
+    {
+        let asm = format!(".data;\n.u64 0;\n.code;\n{}", code);
+        let out = optimize(&asm);
+        out.strip_prefix(".data;\n.u64 0;\n.code;\n").unwrap().to_string()
+    }
+
+    #[test]
+    fn identities()
+    {
+        assert_eq!(opt("get_arg 0;\npush 0;\nadd_u64;\nret;\n"), "get_arg 0;\nret;\n");
+        assert_eq!(opt("get_arg 0;\npush 1;\nmul_u64;\nret;\n"), "get_arg 0;\nret;\n");
+        assert_eq!(opt("get_arg 0;\npush 0;\nlshift_u64;\nret;\n"), "get_arg 0;\nret;\n");
+
+        // 32-bit and floating-point operations are left alone
+        assert_eq!(opt("get_arg 0;\npush 0;\nadd_u32;\nret;\n"), "get_arg 0;\npush 0;\nadd_u32;\nret;\n");
+        assert_eq!(opt("get_arg 0;\npush 1;\nmul_f32;\nret;\n"), "get_arg 0;\npush 1;\nmul_f32;\nret;\n");
+
+        // Not an identity
+        assert_eq!(opt("get_arg 0;\npush 0;\nmul_u64;\nret;\n"), "get_arg 0;\npush 0;\nmul_u64;\nret;\n");
+    }
+
+    #[test]
+    fn const_folding()
+    {
+        assert_eq!(opt("push 3;\npush 8;\nmul_u64;\nret;\n"), "push 24;\nret;\n");
+        assert_eq!(opt("push 3;\npush 8;\nsub_u64;\nret;\n"), "push -5;\nret;\n");
+        assert_eq!(opt("push -1;\npush 255;\nand_u64;\nret;\n"), "push 255;\nret;\n");
+        assert_eq!(opt("push 18446744073709551615;\npush 1;\nadd_u64;\nret;\n"), "push 0;\nret;\n");
+
+        // Folding exposes more folding and identities
+        assert_eq!(opt("get_arg 0;\npush 2;\npush 3;\nmul_u64;\npush 6;\nsub_u64;\nadd_u64;\nret;\n"), "get_arg 0;\nret;\n");
+
+        // Labels and non-constant operands are not folded
+        assert_eq!(opt("push foo;\npush 8;\nadd_u64;\nret;\n"), "push foo;\npush 8;\nadd_u64;\nret;\n");
+        assert_eq!(opt("push 3;\npush 8;\ndiv_u64;\nret;\n"), "push 3;\npush 8;\ndiv_u64;\nret;\n");
+    }
+
+    #[test]
+    fn jumps()
+    {
+        // Jump to the next instruction
+        assert_eq!(opt("jmp L0;\nL0:\nret;\n"), "L0:\nret;\n");
+        assert_eq!(opt("jmp L1;\nL0:\n\nL1:\nret;\n"), "L0:\n\nL1:\nret;\n");
+        assert_eq!(opt("jmp L1;\nL0:\nret;\nL1:\nret;\n"), "jmp L1;\nL0:\nret;\nL1:\nret;\n");
+
+        // Unreachable code up to the next label
+        assert_eq!(opt("jmp L1;\npush 1;\npop;\nL0:\nret;\nL1:\nret;\n"), "jmp L1;\nL0:\nret;\nL1:\nret;\n");
+        assert_eq!(opt("ret;\n# comment\npush 0;\nret;\nfoo:\npush 1;\nret;\n"), "ret;\n# comment\nfoo:\npush 1;\nret;\n");
+        assert_eq!(opt("panic;\npush 0;\nexit;\n"), "panic;\n");
+
+        // Removing unreachable code can make a jump target the next label
+        assert_eq!(opt("jmp L0;\npush 1;\nL0:\nret;\n"), "L0:\nret;\n");
+
+        // Conditional jumps fall through
+        assert_eq!(opt("jz L0;\npush 1;\nL0:\nret;\n"), "jz L0;\npush 1;\nL0:\nret;\n");
+
+        // Source positions don't stop the removal of unreachable code
+        assert_eq!(opt("ret;\n.loc \"a.c\", 2, 5;\npush 0;\nret;\nL0:\nret;\n"), "ret;\n.loc \"a.c\", 2, 5;\nL0:\nret;\n");
+
+        // Inline assembly with several instructions per line is a barrier
+        assert_eq!(opt("ret;\nfoo: push 1; ret;\n"), "ret;\nfoo: push 1; ret;\n");
+    }
+
+    #[test]
+    fn data_untouched()
+    {
+        let asm = ".data;\nG:\n.u64 0;\n.u64 1;\n.code;\npush 0;\nexit;\n";
+        assert_eq!(optimize(asm), asm);
+    }
+
+    #[test]
+    fn compile_files()
+    {
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+        use crate::cpp::process_input;
+
+        // Optimizing every test program and example never grows the
+        // output, and optimizing twice changes nothing more
+        for dir in ["./tests", "./examples"] {
+            for file in std::fs::read_dir(dir).unwrap() {
+                let file_name = file.unwrap().path().display().to_string();
+                if !file_name.ends_with(".c") {
+                    continue;
+                }
+
+                let mut input = Input::from_file(&file_name).unwrap();
+                let output = process_input(&mut input).unwrap();
+                let mut input = Input::new(&output, &file_name);
+                let (mut unit, _) = parse_unit(&mut input).unwrap();
+                unit.resolve_syms().unwrap();
+                unit.check_types().unwrap();
+                unit.fold_global_inits().unwrap();
+                unit.fold_constants().unwrap();
+                let asm = unit.gen_code().unwrap();
+
+                let opt_asm = optimize(&asm);
+                assert!(opt_asm.lines().count() <= asm.lines().count(), "{}", file_name);
+                assert_eq!(optimize(&opt_asm), opt_asm, "{}", file_name);
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use crate::ast::*;
-use crate::parsing::{ParseError};
+use crate::parsing::{ParseError, ErrorKind, Diagnostic};
 
 #[derive(Default)]
 struct Scope
@@ -45,11 +45,18 @@ impl Env
     }
 
     /// Define a new local variable in the topmost scope
-    fn define_local(&mut self, name: &str, var_type: Type)
+    fn define_local(&mut self, name: &str, var_type: Type, span: &Span) -> Result<(), ParseError>
     {
         let num_scopes = self.scopes.len();
         let top_scope = &mut self.scopes[num_scopes - 1];
-        assert!(top_scope.decls.get(name).is_none());
+
+        if top_scope.decls.contains_key(name) {
+            return Err(ParseError::at_span(
+                span,
+                ErrorKind::DuplicateDeclaration,
+                &format!("redeclaration of \"{}\" in the same scope", name)
+            ));
+        }
 
         let decl = Decl::Local {
             idx: top_scope.next_idx,
@@ -62,6 +69,8 @@ impl Env
         }
 
         top_scope.decls.insert(name.to_string(), decl);
+
+        Ok(())
     }
 
     /// Define a new entity in the topmost scope
@@ -97,7 +106,7 @@ impl Env
             // FIXME: should be const char type once we support const
             t: Type::Array {
                 elem_type: Box::new(Type::UInt(8)),
-                size_expr: Box::new(Expr::Int(str_num_bytes as i128))
+                size_expr: Box::new(Expr::new(ExprKind::Int { value: str_num_bytes as i128, suffix: None }, Span::default()))
             }
         };
 
@@ -120,6 +129,90 @@ impl Env
 
         return None;
     }
+
+    /// Find the declared variable or function name closest to an
+    /// unknown identifier, for use in "did you mean" suggestions.
+    /// Inner scopes are searched first, so that on equal distance a
+    /// local variable is preferred over a global with a similar name.
+    fn suggest(&self, name: &str) -> Option<String>
+    {
+        // Only suggest names that are a small number of edits away
+        let max_dist = name.chars().count() / 3;
+        if max_dist == 0 {
+            return None;
+        }
+
+        let mut best: Option<(usize, &str)> = None;
+
+        for scope in self.scopes.iter().rev() {
+            let mut names: Vec<&String> = scope.decls.iter()
+                .filter(|(_, decl)| !matches!(decl, Decl::TypeDef { .. }))
+                .map(|(n, _)| n)
+                .collect();
+            names.sort();
+
+            for candidate in names {
+                let dist = edit_distance(name, candidate);
+
+                if dist > max_dist {
+                    continue;
+                }
+
+                match best {
+                    Some((best_dist, _)) if best_dist <= dist => {}
+                    _ => best = Some((dist, candidate.as_str())),
+                }
+            }
+        }
+
+        best.map(|(_, n)| n.to_string())
+    }
+}
+
+/// Edit distance between two strings, counted in characters.
+/// Insertions, deletions, substitutions and swaps of two adjacent
+/// characters each count as one edit (optimal string alignment).
+fn edit_distance(a: &str, b: &str) -> usize
+{
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // d[i][j] is the distance between the first i chars of a
+    // and the first j chars of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, dist) in d[0].iter_mut().enumerate() {
+        *dist = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let subst_cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j - 1] + subst_cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/// Error for a name that isn't declared, suggesting
+/// a similar name that is when there is one
+fn undeclared_error(env: &Env, what: &str, name: &str, span: &Span) -> ParseError
+{
+    let mut msg = format!("{} \"{}\"", what, name);
+
+    if let Some(suggestion) = env.suggest(name) {
+        msg += &format!(", did you mean \"{}\"?", suggestion);
+    }
+
+    ParseError::at_span(span, ErrorKind::UndeclaredIdentifier, &msg)
 }
 
 /// Resolve typedefs inside the AST
@@ -136,7 +229,7 @@ fn resolve_types(t: &mut Type, env: &Env, inside_def: Option<&str>) -> Result<()
 
             if let Some(Decl::TypeDef { name, t: dt }) = env.lookup(name) {
                 // Since we're not inside this typedef, we just clone the type
-                *t = (**dt).borrow().clone();
+                *t = (*dt).borrow().clone();
             }
             else
             {
@@ -195,41 +288,75 @@ fn resolve_types(t: &mut Type, env: &Env, inside_def: Option<&str>) -> Result<()
 
 impl Unit
 {
+    /// Resolve the symbols in the unit, stopping at the first error
     pub fn resolve_syms(&mut self) -> Result<(), ParseError>
     {
+        self.resolve_syms_all_errors().map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Resolve the symbols in the unit, reporting every unresolved
+    /// or conflicting name in all functions and scopes
+    pub fn resolve_syms_all_errors(&mut self) -> Result<(), Vec<ParseError>>
+    {
+        let mut errors = Vec::default();
         let mut env = Env::default();
         env.push_scope();
 
         // Add definitions for each typedef
-        for (name, t) in &self.typedefs {
-            env.define(&name, Decl::TypeDef {
-                name: name.clone(),
-                t: t.clone(),
+        for typedef in &self.typedefs {
+            env.define(&typedef.name, Decl::TypeDef {
+                name: typedef.name.clone(),
+                t: typedef.t.clone(),
             });
         }
 
         // Resolve typedefs inside of typedefs
-        for (name, t) in &mut self.typedefs {
-            resolve_types(&mut t.borrow_mut(), &mut env, Some(name))?;
+        for typedef in &mut self.typedefs {
+            errors.extend(resolve_types(&mut typedef.t.borrow_mut(), &env, Some(&typedef.name)).err());
         }
 
-        // Add definitions for all global variables
+        // Resolve the types of all top-level declarations
         for global in &mut self.global_vars {
-            resolve_types(&mut global.var_type, &env, None)?;
+            errors.extend(resolve_types(&mut global.var_type, &env, None).err());
+        }
 
-            env.define(&global.name, Decl::Global {
-                name: global.name.clone(),
-                t: global.var_type.clone(),
-            });
+        for fun in &mut self.fun_decls {
+            errors.extend(resolve_types(&mut fun.ret_type, &env, None).err());
+
+            for (t, _) in &mut fun.params {
+                errors.extend(resolve_types(t, &env, None).err());
+            }
+        }
+
+        for proto in &mut self.fun_protos {
+            errors.extend(resolve_types(&mut proto.ret_type, &env, None).err());
+
+            for (t, _) in &mut proto.params {
+                errors.extend(resolve_types(t, &env, None).err());
+            }
+        }
+
+        self.check_redeclarations(&env, &mut errors);
+
+        // Add definitions for all global variables. Only the first
+        // declaration of a name is defined, conflicting redeclarations
+        // have been reported above.
+        for global in &mut self.global_vars {
+            if env.lookup(&global.name).is_none() {
+                env.define(&global.name, Decl::Global {
+                    name: global.name.clone(),
+                    t: global.var_type.clone(),
+                });
+            }
 
             // If this is a global pointer to a string constant
             match (&global.var_type, &global.init_expr) {
-                (Type::Pointer(_), Some(Expr::String(str_const))) => {
+                (Type::Pointer(_), Some(Expr { kind: ExprKind::String(str_const), span })) => {
                     // Get a global symbol for the string constant
                     let decl = env.get_string(&str_const);
 
                     // Replace the init expr by a reference to the string constant
-                    global.init_expr = Some(Expr::Ref(decl));
+                    global.init_expr = Some(Expr::new(ExprKind::Ref(decl), span.clone()));
                 }
                 _ => {}
             }
@@ -237,21 +364,34 @@ impl Unit
 
         // Add definitions for all functions
         for fun in &mut self.fun_decls {
-            resolve_types(&mut fun.ret_type, &env, None)?;
-
-            for (t, name) in &mut fun.params {
-                resolve_types(t, &env, None)?;
+            if env.lookup(&fun.name).is_none() {
+                env.define(&fun.name, Decl::Fun {
+                    name: fun.name.clone(),
+                    t: fun.get_type(),
+                    is_extern: false,
+                });
             }
+        }
 
-            env.define(&fun.name, Decl::Fun {
-                name: fun.name.clone(),
-                t: fun.get_type()
-            });
+        // Host functions have no definition in the unit, and
+        // may be declared more than once
+        for proto in &self.fun_protos {
+            if proto.is_extern && env.lookup(&proto.name).is_none() {
+                env.define(&proto.name, Decl::Fun {
+                    name: proto.name.clone(),
+                    t: proto.get_type(),
+                    is_extern: true,
+                });
+            }
         }
 
         // Resolve symbols in all functions
         for fun in &mut self.fun_decls {
-            fun.resolve_syms(&mut env)?;
+            fun.resolve_syms(&mut env, &mut errors);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
         }
 
         // Create new globals for each string constant
@@ -260,7 +400,9 @@ impl Unit
                 self.global_vars.push(Global {
                     name: name.clone(),
                     var_type: t.clone(),
-                    init_expr: Some(Expr::String(str_const.clone()))
+                    type_span: Span::default(),
+                    init_expr: Some(Expr::new(ExprKind::String(str_const.clone()), Span::default())),
+                    span: Span::default(),
                 });
             }
         }
@@ -273,9 +415,103 @@ impl Unit
     }
 }
 
+/// Top-level declaration, for detecting conflicting redeclarations
+enum TopDecl<'a>
+{
+    Global(&'a Global),
+    Fun(&'a Function),
+    Proto(&'a FunProto),
+}
+
+impl Unit
+{
+    /// Check that top-level names are declared only once, except for
+    /// function prototypes matching other declarations of the function
+    fn check_redeclarations(&self, env: &Env, errors: &mut Vec<ParseError>)
+    {
+        let mut decls: Vec<(&str, &Span, TopDecl)> = Vec::default();
+        decls.extend(self.global_vars.iter().map(|g| (g.name.as_str(), &g.span, TopDecl::Global(g))));
+        decls.extend(self.fun_decls.iter().map(|f| (f.name.as_str(), &f.span, TopDecl::Fun(f))));
+        decls.extend(self.fun_protos.iter().map(|p| (p.name.as_str(), &p.span, TopDecl::Proto(p))));
+
+        // Visit the declarations in source order, so that errors
+        // point at the later declaration
+        decls.sort_by_key(|(_, span, _)| span.start);
+
+        let mut seen: HashMap<&str, (&Span, &TopDecl)> = HashMap::default();
+
+        for (name, span, decl) in &decls {
+            if let Some(Decl::TypeDef { .. }) = env.lookup(name) {
+                errors.push(ParseError::at_span(
+                    span,
+                    ErrorKind::DuplicateDeclaration,
+                    &format!("\"{}\" redeclared as a different kind of symbol", name)
+                ));
+                continue;
+            }
+
+            let (prev_span, prev_decl) = match seen.get(name) {
+                Some(prev) => *prev,
+                None => {
+                    seen.insert(name, (span, decl));
+                    continue;
+                }
+            };
+
+            let fun_type = |decl: &TopDecl| match decl {
+                TopDecl::Fun(fun) => Some(fun.get_type()),
+                TopDecl::Proto(proto) => Some(proto.get_type()),
+                TopDecl::Global(_) => None,
+            };
+
+            // Host functions can't also be declared or defined in the unit
+            let is_extern = |decl: &TopDecl| matches!(decl, TopDecl::Proto(proto) if proto.is_extern);
+
+            let msg = match (prev_decl, decl) {
+                (TopDecl::Global(prev), TopDecl::Global(global)) => {
+                    if prev.var_type == global.var_type {
+                        format!("redefinition of global variable \"{}\"", name)
+                    } else {
+                        format!("conflicting types for \"{}\"", name)
+                    }
+                }
+
+                (TopDecl::Global(_), _) | (_, TopDecl::Global(_)) => {
+                    format!("\"{}\" redeclared as a different kind of symbol", name)
+                }
+
+                (prev, decl) => {
+                    if fun_type(prev) != fun_type(decl) {
+                        format!("conflicting types for \"{}\"", name)
+                    }
+                    else if is_extern(prev) != is_extern(decl) {
+                        format!("conflicting extern declaration of \"{}\"", name)
+                    }
+                    else if let (TopDecl::Fun(_), TopDecl::Fun(_)) = (prev, decl) {
+                        format!("redefinition of function \"{}\"", name)
+                    }
+                    else
+                    {
+                        // A prototype matching the definition
+                        if let TopDecl::Fun(_) = decl {
+                            seen.insert(name, (span, decl));
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            errors.push(
+                ParseError::at_span(span, ErrorKind::DuplicateDeclaration, &msg)
+                    .with_note(Diagnostic::note_at_span(prev_span, "previous declaration is here"))
+            );
+        }
+    }
+}
+
 impl Function
 {
-    fn resolve_syms(&mut self, env: &mut Env) -> Result<(), ParseError>
+    fn resolve_syms(&mut self, env: &mut Env, errors: &mut Vec<ParseError>)
     {
         // Reset the local variable slot count
         env.num_locals = 0;
@@ -288,224 +524,238 @@ impl Function
             env.define(param_name, decl);
         }
 
-        self.body.resolve_syms(env)?;
+        self.body.resolve_syms(env, errors);
 
         env.pop_scope();
 
         // Set the local variable slot count for the function
         self.num_locals = env.num_locals;
-
-        Ok(())
     }
 }
 
 impl Stmt
 {
-    fn resolve_syms(&mut self, env: &mut Env) -> Result<(), ParseError>
+    /// Resolve the symbols in a statement and the statements nested in it.
+    /// An error in one statement doesn't stop the others from being resolved.
+    fn resolve_syms(&mut self, env: &mut Env, errors: &mut Vec<ParseError>)
     {
-        match self {
-            Stmt::Expr(expr) => {
-                expr.resolve_syms(env)?;
+        match &mut self.kind {
+            StmtKind::Expr(expr) => {
+                expr.resolve_syms(env, errors);
             }
 
-            Stmt::Break | Stmt::Continue => {}
+            StmtKind::Break | StmtKind::Continue => {}
 
-            Stmt::ReturnVoid => {}
+            StmtKind::ReturnVoid => {}
 
-            Stmt::ReturnExpr(expr) => {
-                expr.resolve_syms(env)?;
+            StmtKind::Assert { test_expr, .. } => {
+                test_expr.resolve_syms(env, errors);
             }
 
-            Stmt::If { test_expr, then_stmt, else_stmt } => {
-                test_expr.resolve_syms(env)?;
-                then_stmt.resolve_syms(env)?;
+            StmtKind::ReturnExpr(expr) => {
+                expr.resolve_syms(env, errors);
+            }
+
+            StmtKind::If { test_expr, then_stmt, else_stmt } => {
+                test_expr.resolve_syms(env, errors);
+                then_stmt.resolve_syms(env, errors);
 
                 if else_stmt.is_some() {
-                    else_stmt.as_mut().unwrap().resolve_syms(env)?;
+                    else_stmt.as_mut().unwrap().resolve_syms(env, errors);
                 }
             }
 
-            Stmt::While { test_expr, body_stmt } => {
-                test_expr.resolve_syms(env)?;
-                body_stmt.resolve_syms(env)?;
+            StmtKind::While { test_expr, body_stmt } => {
+                test_expr.resolve_syms(env, errors);
+                body_stmt.resolve_syms(env, errors);
             }
 
-            Stmt::DoWhile { test_expr, body_stmt } => {
-                test_expr.resolve_syms(env)?;
-                body_stmt.resolve_syms(env)?;
+            StmtKind::DoWhile { test_expr, body_stmt } => {
+                test_expr.resolve_syms(env, errors);
+                body_stmt.resolve_syms(env, errors);
             }
 
-            Stmt::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+            StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
                 env.push_scope();
 
                 if init_stmt.is_some() {
-                    init_stmt.as_mut().unwrap().resolve_syms(env)?;
+                    init_stmt.as_mut().unwrap().resolve_syms(env, errors);
                 }
 
-                test_expr.resolve_syms(env)?;
-                incr_expr.resolve_syms(env)?;
+                test_expr.resolve_syms(env, errors);
+                incr_expr.resolve_syms(env, errors);
 
-                body_stmt.resolve_syms(env)?;
+                body_stmt.resolve_syms(env, errors);
 
                 env.pop_scope();
             }
 
             // Local variable declaration
-            Stmt::VarDecl { var_type, var_name, init_expr } => {
-                resolve_types(var_type, env, None)?;
+            StmtKind::VarDecl { var_type, var_name, init_expr, .. } => {
+                errors.extend(resolve_types(var_type, env, None).err());
 
-                env.define_local(var_name, var_type.clone());
+                // A redeclared name keeps its first declaration
+                errors.extend(env.define_local(var_name, var_type.clone(), &self.span).err());
 
                 let decl = env.lookup(var_name).unwrap();
-                let ref_expr = Expr::Ref(decl);
+                let ref_expr = Expr::new(ExprKind::Ref(decl), self.span.clone());
 
                 // If there is an initiaization expression
                 if let Some(init_expr) = init_expr {
-                    init_expr.resolve_syms(env)?;
+                    init_expr.resolve_syms(env, errors);
 
-                    let assign_expr = Expr::Binary {
+                    let assign_expr = ExprKind::Binary {
                         op: BinOp::Assign,
                         lhs: Box::new(ref_expr),
                         rhs: Box::new(init_expr.clone()),
                     };
 
-                    *self = Stmt::Expr(assign_expr);
+                    self.kind = StmtKind::Expr(Expr::new(assign_expr, self.span.clone()));
                 }
                 else
                 {
-                    *self = Stmt::Expr(Expr::Int(0));
+                    self.kind = StmtKind::Expr(Expr::new(ExprKind::Int { value: 0, suffix: None }, self.span.clone()));
                 }
             }
 
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 env.push_scope();
 
                 for stmt in stmts {
-                    stmt.resolve_syms(env)?;
+                    stmt.resolve_syms(env, errors);
                 }
 
                 env.pop_scope();
             }
         }
-
-        Ok(())
     }
 }
 
 impl Expr
 {
-    fn resolve_syms(&mut self, env: &mut Env) -> Result<(), ParseError>
+    /// Resolve the symbols in an expression, reporting every
+    /// unresolved name in it and its subexpressions
+    fn resolve_syms(&mut self, env: &mut Env, errors: &mut Vec<ParseError>)
     {
-        match self {
-            Expr::Int(_) => {}
-            Expr::Float32(_) => {}
+        match &mut self.kind {
+            ExprKind::Int { .. } => {}
+            ExprKind::Float32(_) => {}
 
-            Expr::String(str_const) => {
+            ExprKind::String(str_const) => {
                 // Get a global symbol for the string constant
                 let decl = env.get_string(str_const);
-                *self = Expr::Ref(decl);
+                self.kind = ExprKind::Ref(decl);
             }
 
-            Expr::Array(exprs) => {
+            ExprKind::Array(exprs) => {
                 for expr in exprs {
-                    expr.resolve_syms(env)?;
+                    expr.resolve_syms(env, errors);
                 }
             }
 
-            Expr::Ident(name) => {
+            ExprKind::Ident(name) => {
                 //dbg!(&name);
 
                 if let Some(decl) = env.lookup(name) {
-                    *self = Expr::Ref(decl);
+                    self.kind = ExprKind::Ref(decl);
                 }
                 else
                 {
-                    return ParseError::msg_only(&format!("reference to undeclared identifier \"{}\"", name));
+                    errors.push(undeclared_error(env, "reference to undeclared identifier", name, &self.span));
                 }
             }
 
-            Expr::Ref(_) => panic!(),
+            ExprKind::Ref(_) => panic!(),
 
-            Expr::Cast { new_type, child } => {
+            ExprKind::Cast { new_type, child } => {
                 if let Type::Named(name) = new_type {
                     if let Some(Decl::TypeDef { name, t }) = env.lookup(name) {
-                        *new_type = (**t).borrow().clone();
+                        *new_type = (*t).borrow().clone();
                     }
                     else
                     {
-                        return ParseError::msg_only(&format!("reference to unknown type \"{}\" in cast expression", name));
+                        errors.push(ParseError::at_span(
+                            &self.span,
+                            ErrorKind::Other,
+                            &format!("reference to unknown type \"{}\" in cast expression", name)
+                        ));
                     }
                 }
                 else
                 {
-                    resolve_types(new_type, env, None)?;
+                    errors.extend(resolve_types(new_type, env, None).err());
                 }
 
-                child.as_mut().resolve_syms(env)?;
+                child.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::SizeofExpr { child } => {
-                child.as_mut().resolve_syms(env)?;
+            ExprKind::SizeofExpr { child } => {
+                child.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::SizeofType { t } => {
+            ExprKind::SizeofType { t } => {
                 if let Type::Named(name) = t {
                     if let Some(Decl::TypeDef { name, t: dt }) = env.lookup(name) {
-                        *t = (**dt).borrow().clone();
+                        *t = (*dt).borrow().clone();
                     }
                     else
                     {
-                        *self = Expr::SizeofExpr {
-                            child: Box::new(Expr::Ident(name.clone()))
+                        self.kind = ExprKind::SizeofExpr {
+                            child: Box::new(Expr::new(ExprKind::Ident(name.clone()), self.span.clone()))
                         };
 
-                        self.resolve_syms(env)?;
+                        self.resolve_syms(env, errors);
                     }
                 }
                 else
                 {
-                    resolve_types(t, env, None)?;
+                    errors.extend(resolve_types(t, env, None).err());
                 }
             }
 
-            Expr::Arrow { base, field } => {
-                base.as_mut().resolve_syms(env)?;
+            ExprKind::Arrow { base, field } => {
+                base.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::Unary { op, child } => {
-                child.as_mut().resolve_syms(env)?;
+            ExprKind::Unary { op, child } => {
+                child.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::Binary { op, lhs, rhs } => {
-                lhs.as_mut().resolve_syms(env)?;
-                rhs.as_mut().resolve_syms(env)?;
+            ExprKind::Binary { op, lhs, rhs } => {
+                lhs.as_mut().resolve_syms(env, errors);
+                rhs.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::Ternary { test_expr, then_expr, else_expr } => {
-                test_expr.as_mut().resolve_syms(env)?;
-                then_expr.as_mut().resolve_syms(env)?;
-                else_expr.as_mut().resolve_syms(env)?;
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                test_expr.as_mut().resolve_syms(env, errors);
+                then_expr.as_mut().resolve_syms(env, errors);
+                else_expr.as_mut().resolve_syms(env, errors);
             }
 
-            Expr::Call { callee, args } => {
-                callee.resolve_syms(env)?;
+            ExprKind::Call { callee, args } => {
+                // Calls to unknown names get a more specific error
+                match &callee.kind {
+                    ExprKind::Ident(name) if env.lookup(name).is_none() => {
+                        errors.push(undeclared_error(env, "call to undefined function", name, &callee.span));
+                    }
+                    _ => callee.resolve_syms(env, errors)
+                }
+
                 for arg in args {
-                    arg.resolve_syms(env)?;
+                    arg.resolve_syms(env, errors);
                 }
             }
 
-            Expr::Asm { args, out_type, .. } => {
+            ExprKind::Asm { args, out_type, .. } => {
                 for arg in args {
-                    arg.resolve_syms(env)?;
+                    arg.resolve_syms(env, errors);
                 }
 
-                resolve_types(out_type, env, None)?;
+                errors.extend(resolve_types(out_type, env, None).err());
             }
 
             //_ => todo!()
         }
-
-        Ok(())
     }
 }
 
@@ -521,7 +771,7 @@ mod tests
 
         dbg!(src);
         let mut input = Input::new(&src, "src");
-        let mut unit = parse_unit(&mut input).unwrap();
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
         unit.resolve_syms().unwrap();
     }
 
@@ -558,6 +808,15 @@ mod tests
         parse_ok("char* global_str = \"foo\"; void main() {}");
     }
 
+    #[test]
+    fn typedefs()
+    {
+        parse_ok("typedef u64 size_t; size_t n = 0;");
+        parse_ok("typedef u8* string_t; string_t foo(string_t s) { return s; }");
+        parse_ok("typedef u64 (*fn_ptr)(u64); fn_ptr f;");
+        parse_ok("typedef u64 word_t; word_t foo() { word_t x = 1; return x; }");
+    }
+
     #[test]
     fn for_loop()
     {
@@ -573,4 +832,256 @@ mod tests
     {
         parse_ok("void foo() {} void main() { foo(); }");
     }
+
+    /// Resolve a unit and describe what the returned
+    /// expressions of its last function refer to
+    fn return_refs(src: &str) -> (Vec<String>, usize)
+    {
+        fn visit(stmt: &Stmt, out: &mut Vec<String>)
+        {
+            match &stmt.kind {
+                StmtKind::ReturnExpr(expr) => {
+                    out.push(match &expr.kind {
+                        ExprKind::Ref(Decl::Local { idx, .. }) => format!("local {}", idx),
+                        ExprKind::Ref(Decl::Arg { idx, .. }) => format!("arg {}", idx),
+                        ExprKind::Ref(Decl::Global { name, .. }) => format!("global {}", name),
+                        ExprKind::Ref(Decl::Fun { name, .. }) => format!("fun {}", name),
+                        _ => panic!()
+                    });
+                }
+                StmtKind::Block(stmts) => stmts.iter().for_each(|s| visit(s, out)),
+                StmtKind::If { then_stmt, else_stmt, .. } => {
+                    visit(then_stmt, out);
+                    else_stmt.iter().for_each(|s| visit(s, out));
+                }
+                StmtKind::For { body_stmt, .. } |
+                StmtKind::While { body_stmt, .. } |
+                StmtKind::DoWhile { body_stmt, .. } => visit(body_stmt, out),
+                _ => {}
+            }
+        }
+
+        let mut unit = crate::parser::parse_str(src).unwrap();
+        unit.resolve_syms().unwrap();
+        let fun = unit.fun_decls.last().unwrap();
+
+        let mut refs = Vec::default();
+        visit(&fun.body, &mut refs);
+        (refs, fun.num_locals)
+    }
+
+    #[test]
+    fn scopes()
+    {
+        // Parameters, globals and functions
+        assert_eq!(return_refs("u64 foo(u64 a, u64 b) { return b; }"), (vec!["arg 1".to_string()], 0));
+        assert_eq!(return_refs("u64 g; u64 foo() { return g; }").0, vec!["global g"]);
+        assert_eq!(return_refs("u64 f() { return 0; } u64 foo() { return f; }").0, vec!["fun f"]);
+
+        // A parameter shadows a global, a local shadows a parameter
+        assert_eq!(return_refs("u64 a; u64 foo(u64 a) { return a; }").0, vec!["arg 0"]);
+        assert_eq!(return_refs("u64 foo(u64 a) { u64 a = 1; return a; }"), (vec!["local 0".to_string()], 1));
+
+        // Nested blocks get new slots, and a name is visible again
+        // once the block that shadows it ends
+        assert_eq!(
+            return_refs("u64 foo(u64 a) { u64 x = 1; { u64 a = 2; { u64 x = 3; return x; } return a; } return x; }"),
+            (vec!["local 2".to_string(), "local 1".to_string(), "local 0".to_string()], 3)
+        );
+
+        // Sibling blocks reuse the same slots
+        assert_eq!(
+            return_refs("u64 foo(u64 c) { if (c) { u64 x = 1; return x; } else { u64 y = 2; u64 z = 3; return z; } }"),
+            (vec!["local 0".to_string(), "local 1".to_string()], 2)
+        );
+
+        // For loop variables are scoped to the loop
+        assert_eq!(
+            return_refs("u64 i; u64 foo() { for (u64 i = 0; i < 3; i = i + 1) { return i; } return i; }"),
+            (vec!["local 0".to_string(), "global i".to_string()], 1)
+        );
+
+        // Loop bodies are scopes too, and the frame size is the
+        // deepest nesting of live locals
+        assert_eq!(
+            return_refs("u64 foo(u64 c) { u64 n = 0; while (c) { u64 a = 1; u64 b = 2; return b; } do { u64 d = 3; return d; } while (c); return n; }"),
+            (vec!["local 2".to_string(), "local 1".to_string(), "local 0".to_string()], 3)
+        );
+
+        // Parameters don't use local slots
+        assert_eq!(return_refs("u64 foo(u64 a, u64 b, u64 c) { return c; }"), (vec!["arg 2".to_string()], 0));
+        assert_eq!(return_refs("u64 foo() { { { u64 x = 1; return x; } } }"), (vec!["local 0".to_string()], 1));
+    }
+
+    #[test]
+    fn resolve_errors()
+    {
+        let mut unit = crate::parser::parse_str("void foo() {\n    u64 x = 1;\n    x = y;\n}").unwrap();
+        let error = unit.resolve_syms().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UndeclaredIdentifier);
+        assert_eq!((error.line(), error.column()), (3, 9));
+
+        // Locals are only visible in their block
+        assert_eq!(
+            resolve_err("u64 foo() { { u64 x = 1; } return x; }"),
+            "reference to undeclared identifier \"x\""
+        );
+
+        let mut unit = crate::parser::parse_str("void foo() {\n    u64 x = 1;\n    u64 x = 2;\n}").unwrap();
+        let error = unit.resolve_syms().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::DuplicateDeclaration);
+        assert_eq!(error.message(), "redeclaration of \"x\" in the same scope");
+        assert_eq!((error.line(), error.column()), (3, 5));
+        assert!(resolve_err("void foo() { for (u64 i = 0;;) { u64 j; u64 j; } }").contains("redeclaration"));
+
+        // Redeclaring in a nested block is shadowing, not an error
+        parse_ok("void foo() { u64 x = 1; { u64 x = 2; } }");
+    }
+
+    #[test]
+    fn all_errors()
+    {
+        fn resolve_errors(src: &str) -> Vec<(u32, u32, String)>
+        {
+            let mut unit = crate::parser::parse_str(src).unwrap();
+            let errors = unit.resolve_syms_all_errors().unwrap_err();
+            errors.iter().map(|e| (e.line(), e.column(), e.message().to_string())).collect()
+        }
+
+        // Errors in different functions, scopes and subexpressions
+        let errors = resolve_errors(concat!(
+            "u64 foo() { return a + b; }\n",
+            "void bar() {\n",
+            "    { u64 x; u64 x; }\n",
+            "    qux(x);\n",
+            "}\n",
+        ));
+        assert_eq!(errors, vec![
+            (1, 20, "reference to undeclared identifier \"a\"".to_string()),
+            (1, 24, "reference to undeclared identifier \"b\"".to_string()),
+            (3, 14, "redeclaration of \"x\" in the same scope".to_string()),
+            (4, 5, "call to undefined function \"qux\"".to_string()),
+            (4, 9, "reference to undeclared identifier \"x\"".to_string()),
+        ]);
+
+        // Conflicting top-level declarations are all reported,
+        // along with the errors in function bodies
+        let errors = resolve_errors("u64 g;\nu8 g;\nvoid f() {}\nvoid f() {}\nvoid main() { h = 1; }");
+        let lines: Vec<u32> = errors.iter().map(|e| e.0).collect();
+        assert_eq!(lines, vec![2, 4, 5]);
+
+        // The first error is the one resolve_syms stops at
+        let mut unit = crate::parser::parse_str("void foo() { a = b; }").unwrap();
+        assert_eq!(unit.resolve_syms().unwrap_err().message(), "reference to undeclared identifier \"a\"");
+    }
+
+    fn resolve_err(src: &str) -> String
+    {
+        use crate::parsing::Input;
+        use crate::parser::parse_unit;
+
+        dbg!(src);
+        let mut input = Input::new(&src, "src");
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
+        unit.resolve_syms().unwrap_err().message().to_string()
+    }
+
+    #[test]
+    fn did_you_mean()
+    {
+        assert_eq!(edit_distance("pixle_buffer", "pixel_buffer"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+
+        // One-character typos in globals, functions and parameters
+        assert_eq!(
+            resolve_err("u64 pixel_buffer; void main() { pixle_buffer = 0; }"),
+            "reference to undeclared identifier \"pixle_buffer\", did you mean \"pixel_buffer\"?"
+        );
+        assert_eq!(
+            resolve_err("void draw_line() {} void main() { draw_lime(); }"),
+            "call to undefined function \"draw_lime\", did you mean \"draw_line\"?"
+        );
+        assert_eq!(
+            resolve_err("u64 foo(u64 count) { return coutn; }"),
+            "reference to undeclared identifier \"coutn\", did you mean \"count\"?"
+        );
+
+        // Nothing close enough, or the name is too short to guess
+        assert_eq!(
+            resolve_err("u64 width; void main() { height = 1; }"),
+            "reference to undeclared identifier \"height\""
+        );
+        assert_eq!(
+            resolve_err("u64 a; void main() { b = 1; }"),
+            "reference to undeclared identifier \"b\""
+        );
+
+        // Typedef names are not suggested for expressions
+        assert_eq!(
+            resolve_err("typedef u64 size_t; void main() { size_x = 1; }"),
+            "reference to undeclared identifier \"size_x\""
+        );
+
+        // A local in scope is preferred over an equally close global
+        assert_eq!(
+            resolve_err("u64 total_b; void main() { u64 total_a = 0; total_c = 1; }"),
+            "reference to undeclared identifier \"total_c\", did you mean \"total_a\"?"
+        );
+    }
+
+    #[test]
+    fn redeclarations()
+    {
+        fn resolve_full_err(src: &str) -> String
+        {
+            let mut unit = crate::parser::parse_str(src).unwrap();
+            let error = unit.resolve_syms().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::DuplicateDeclaration);
+            error.to_string()
+        }
+
+        // Globals
+        assert_eq!(
+            resolve_full_err("u64 x;\nu8 x;"),
+            "src:2:1: error: conflicting types for \"x\"\nsrc:1:1: note: previous declaration is here"
+        );
+        assert_eq!(resolve_err("u64 x; u64 x = 1;"), "redefinition of global variable \"x\"");
+        assert_eq!(resolve_err("u8* p; u64* p;"), "conflicting types for \"p\"");
+
+        // Functions
+        assert_eq!(
+            resolve_full_err("void main() {}\nu64 main(u64 a) { return a; }"),
+            "src:2:1: error: conflicting types for \"main\"\nsrc:1:1: note: previous declaration is here"
+        );
+        assert_eq!(
+            resolve_full_err("void f() {}\nvoid f() {}"),
+            "src:2:1: error: redefinition of function \"f\"\nsrc:1:1: note: previous declaration is here"
+        );
+        assert_eq!(resolve_err("u64 f(u64 a); u64 f(u8 a) { return a; }"), "conflicting types for \"f\"");
+        assert_eq!(resolve_err("u64 f(u64 a); u64 f(u64 a, ...);"), "conflicting types for \"f\"");
+        assert_eq!(resolve_err("u64 f(u64 a) { return a; } u64 f(u64 b); u64 f(u64 c) { return c; }"), "redefinition of function \"f\"");
+
+        // A name used for both a global and a function
+        assert_eq!(
+            resolve_full_err("u64 f;\nvoid f() {}"),
+            "src:2:1: error: \"f\" redeclared as a different kind of symbol\nsrc:1:1: note: previous declaration is here"
+        );
+        assert_eq!(resolve_err("void f(); u64 f;"), "\"f\" redeclared as a different kind of symbol");
+        assert_eq!(resolve_err("typedef u64 T; u64 T;"), "\"T\" redeclared as a different kind of symbol");
+
+        // Prototypes matching the definition, before or after it
+        parse_ok("u64 f(u64 a); u64 f(u64 a) { return a; }");
+        parse_ok("u64 f(u64 b); u64 f(u64 a); void main() { f(1); } u64 f(u64 a) { return a; }");
+        parse_ok("void f(u8* p, ...) {} void f(u8* q, ...);");
+        parse_ok("typedef u64 T; T f(T a); u64 f(u64 a) { return a; }");
+
+        // Host functions are only declared extern
+        parse_ok("extern u64 f(u64 a); extern u64 f(u64 b); void main() { f(1); }");
+        assert_eq!(resolve_err("extern u64 f(u64 a); u64 f(u64 a) { return a; }"), "conflicting extern declaration of \"f\"");
+        assert_eq!(resolve_err("u64 f(u64 a); extern u64 f(u64 a);"), "conflicting extern declaration of \"f\"");
+
+        // A prototype alone doesn't define the function
+        assert_eq!(resolve_err("u64 f(u64 a); void main() { f(1); }"), "call to undefined function \"f\"");
+    }
 }
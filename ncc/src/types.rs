@@ -1,12 +1,24 @@
 use crate::ast::*;
-use crate::parsing::{ParseError};
+use crate::parsing::{ParseError, ErrorKind};
+use crate::consts::wrap_to_type;
 use std::cmp::{max};
 use Type::*;
 
 // TODO: we should probably automatically insert type promotions
 // and type casting operations in assignments
 
-/// Check if a value of one type can be assigned to another
+/// Check if a value of one type can be assigned to another.
+///
+/// This decides the implicit conversions allowed in assignments,
+/// function arguments and return values:
+/// - any integer to an unsigned integer of any size
+/// - an unsigned integer to a signed integer at least as large
+/// - a signed integer to a signed integer of any size
+/// - an integer to a pointer
+/// - an array to a pointer to its element type
+/// - a function to a void pointer
+///
+/// Other types, including floats, must match exactly.
 fn assign_compat(lhs_type: &Type, rhs_type: &Type) -> bool
 {
     match (&lhs_type, &rhs_type)
@@ -34,119 +46,185 @@ fn assign_compat(lhs_type: &Type, rhs_type: &Type) -> bool
         (Pointer(base_type), Int(_)) => true,
 
         // Assigning an array to a pointer
-        (Pointer(base_type), Array { elem_type, .. }) => base_type.eq(&elem_type),
+        (Pointer(base_type), Array { elem_type, .. }) => base_type == elem_type,
 
         // Assigning a function to a void pointer
-        (Pointer(base_type), Fun { .. }) => base_type.eq(&Type::Void),
+        (Pointer(base_type), Fun { .. }) => **base_type == Type::Void,
 
-        _ => lhs_type.eq(&rhs_type)
+        _ => lhs_type == rhs_type
+    }
+}
+
+/// Type error at the location of an AST node
+fn type_error<T>(span: &Span, msg: &str) -> Result<T, ParseError>
+{
+    Err(ParseError::at_span(span, ErrorKind::TypeError, msg))
+}
+
+/// Check that an expression can be used as a condition
+fn check_cond(expr: &Expr) -> Result<(), ParseError>
+{
+    match expr.eval_type()? {
+        t if t.is_integer() || t.is_pointer() => Ok(()),
+        t => type_error(&expr.span, &format!("condition must have an integer or pointer type, not {}", t))
     }
 }
 
 impl Unit
 {
+    /// Check the types of all functions, stopping at the first error
     pub fn check_types(&mut self) -> Result<(), ParseError>
+    {
+        self.check_types_all_errors().map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Check the types of all functions, reporting every statement
+    /// with a type error, in source order
+    pub fn check_types_all_errors(&mut self) -> Result<(), Vec<ParseError>>
     {
         //
         // TODO: handle global variables
         // need to check init expr type?
         //
 
+        let mut errors = Vec::default();
+
         for fun in &mut self.fun_decls {
-            fun.check_types()?;
+            fun.check_types(&mut errors);
         }
 
-        Ok(())
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 }
 
 impl Function
 {
-    pub fn check_types(&mut self) -> Result<(), ParseError>
+    pub fn check_types(&mut self, errors: &mut Vec<ParseError>)
     {
-        self.body.check_types(&self.ret_type)?;
-        Ok(())
+        self.body.check_types(&self.ret_type, errors);
     }
 }
 
 impl Stmt
 {
-    pub fn check_types(&mut self, ret_type: &Type) -> Result<(), ParseError>
+    /// Check the types of a statement and the statements nested in it.
+    /// An error in one statement doesn't stop the others from being checked.
+    pub fn check_types(&mut self, ret_type: &Type, errors: &mut Vec<ParseError>)
     {
-        match self {
-            Stmt::Expr(expr) => {
-                expr.eval_type()?;
+        match &mut self.kind {
+            StmtKind::Expr(expr) => {
+                errors.extend(expr.eval_type().err());
             }
 
-            Stmt::Break | Stmt::Continue => {}
+            StmtKind::Break | StmtKind::Continue => {}
+
+            StmtKind::Assert { test_expr, .. } => {
+                errors.extend(check_cond(test_expr).err());
+            }
 
             // Return void
-            Stmt::ReturnVoid => {
-                if !ret_type.eq(&Type::Void) {
-                    return ParseError::msg_only("return void in function not returning void");
+            StmtKind::ReturnVoid => {
+                if !ret_type.is_void() {
+                    errors.push(ParseError::at_span(&self.span, ErrorKind::TypeError, "return void in function not returning void"));
                 }
             }
 
-            Stmt::ReturnExpr(expr) => {
-                let expr_type = expr.eval_type()?;
-
-                if !assign_compat(ret_type, &expr_type) {
-                    return ParseError::msg_only(&format!(
-                        "incompatible return type {}, expected {}",
-                        expr_type,
-                        ret_type
-                    ));
-                }
+            StmtKind::ReturnExpr(expr) => {
+                errors.extend(check_return(expr, ret_type).err());
             }
 
-            Stmt::If { test_expr, then_stmt, else_stmt } => {
-                test_expr.eval_type()?;
-                then_stmt.check_types(ret_type)?;
+            StmtKind::If { test_expr, then_stmt, else_stmt } => {
+                errors.extend(check_cond(test_expr).err());
+                then_stmt.check_types(ret_type, errors);
 
                 if else_stmt.is_some() {
-                    else_stmt.as_mut().unwrap().check_types(ret_type)?;
+                    else_stmt.as_mut().unwrap().check_types(ret_type, errors);
                 }
             }
 
-            Stmt::While { test_expr, body_stmt } => {
-                test_expr.eval_type()?;
-                body_stmt.check_types(ret_type)?;
+            StmtKind::While { test_expr, body_stmt } => {
+                errors.extend(check_cond(test_expr).err());
+                body_stmt.check_types(ret_type, errors);
             }
 
-            Stmt::DoWhile { test_expr, body_stmt } => {
-                test_expr.eval_type()?;
-                body_stmt.check_types(ret_type)?;
+            StmtKind::DoWhile { test_expr, body_stmt } => {
+                errors.extend(check_cond(test_expr).err());
+                body_stmt.check_types(ret_type, errors);
             }
 
-            Stmt::For { init_stmt, test_expr, incr_expr, body_stmt } => {
+            StmtKind::For { init_stmt, test_expr, incr_expr, body_stmt } => {
                 if init_stmt.is_some() {
-                    init_stmt.as_mut().unwrap().check_types(ret_type)?;
+                    init_stmt.as_mut().unwrap().check_types(ret_type, errors);
                 }
 
-                test_expr.eval_type()?;
-                incr_expr.eval_type()?;
-                body_stmt.check_types(ret_type)?;
+                errors.extend(check_cond(test_expr).err());
+                errors.extend(incr_expr.eval_type().err());
+                body_stmt.check_types(ret_type, errors);
             }
 
-            Stmt::Block(stmts) => {
+            StmtKind::Block(stmts) => {
                 for stmt in stmts {
-                    stmt.check_types(ret_type)?;
+                    stmt.check_types(ret_type, errors);
                 }
             }
 
             _ => panic!()
         }
+    }
+}
 
-        Ok(())
+/// Check that a returned value is compatible with the return type
+fn check_return(expr: &Expr, ret_type: &Type) -> Result<(), ParseError>
+{
+    let expr_type = expr.eval_type()?;
+
+    if !assign_compat(ret_type, &expr_type) {
+        return type_error(&expr.span, &format!(
+            "incompatible return type {}, expected {}",
+            expr_type,
+            ret_type
+        ));
+    }
+
+    check_literal_range(expr, ret_type)
+}
+
+/// Check that an integer literal converted to an integer type keeps
+/// its value, read as either a signed or an unsigned integer, so that
+/// e.g. both 255 and -1 can be assigned to a u8, but not 256
+fn check_literal_range(expr: &Expr, dst_type: &Type) -> Result<(), ParseError>
+{
+    let num_bits = match dst_type {
+        UInt(n) | Int(n) => *n,
+        _ => return Ok(()),
+    };
+
+    let value = match expr.kind {
+        ExprKind::Int { value, suffix: None } => value,
+        _ => return Ok(()),
+    };
+
+    if wrap_to_type(value, &Int(num_bits)) == value || wrap_to_type(value, &UInt(num_bits)) == value {
+        return Ok(());
     }
+
+    type_error(&expr.span, &format!("integer constant {} does not fit in type {}", value, dst_type))
 }
 
 impl Expr
 {
     pub fn eval_type(&self) -> Result<Type, ParseError>
     {
-        match self {
-            Expr::Int(val) => {
+        match &self.kind {
+            ExprKind::Int { suffix: Some(suffix), .. } => {
+                Ok(suffix.get_type())
+            }
+
+            ExprKind::Int { value: val, suffix: None } => {
                 // In C, if a value can fit inside an int, it has int type,
                 // otherwise it has type long int
                 if *val >= (i32::MIN as i128) && *val <= (i32::MAX as i128) {
@@ -158,21 +236,21 @@ impl Expr
                 }
             }
 
-            Expr::Float32(val) => {
+            ExprKind::Float32(val) => {
                 Ok(Float(32))
             }
 
-            Expr::String(_) => {
+            ExprKind::String(_) => {
                 // TODO: this type should be const char
                 Ok(Pointer(Box::new(UInt(8))))
             }
 
             // Array literal
-            Expr::Array(exprs) => {
+            ExprKind::Array(exprs) => {
                 if exprs.len() == 0 {
                     Ok(Array {
                         elem_type: Box::new(Int(32)),
-                        size_expr: Box::new(Expr::Int(0))
+                        size_expr: Box::new(Expr::new(ExprKind::Int { value: 0, suffix: None }, self.span.clone()))
                     })
                 }
                 else
@@ -188,8 +266,8 @@ impl Expr
                             }
 
                             _ => {
-                                if !elem_type.eq(&expr_type) {
-                                    return ParseError::msg_only("array element types do not match");
+                                if elem_type != expr_type {
+                                    return type_error(&expr.span, "array element types do not match");
                                 }
                             }
                         }
@@ -198,18 +276,18 @@ impl Expr
 
                     Ok(Array {
                         elem_type: Box::new(elem_type),
-                        size_expr: Box::new(Expr::Int(exprs.len() as i128))
+                        size_expr: Box::new(Expr::new(ExprKind::Int { value: exprs.len() as i128, suffix: None }, self.span.clone()))
                     })
                 }
             }
 
-            Expr::Ident(_) => panic!("IdentExpr made it past symbol resolution"),
+            ExprKind::Ident(_) => panic!("IdentExpr made it past symbol resolution"),
 
-            Expr::Ref(decl) => {
+            ExprKind::Ref(decl) => {
                 Ok(decl.get_type())
             }
 
-            Expr::Cast { new_type, child } => {
+            ExprKind::Cast { new_type, child } => {
                 let src_type = child.eval_type()?;
 
                 match (&new_type, &src_type) {
@@ -229,7 +307,7 @@ impl Expr
                     (UInt(64), Pointer(_)) => {},
                     (Pointer(_), UInt(64)) => {},
 
-                    _ => return ParseError::msg_only(&format!(
+                    _ => return type_error(&self.span, &format!(
                         "cannot cast type {} into {}",
                         src_type,
                         new_type
@@ -239,53 +317,79 @@ impl Expr
                 Ok(new_type.clone())
             }
 
-            Expr::SizeofExpr { .. } => {
+            ExprKind::SizeofExpr { .. } => {
                 Ok(UInt(64))
             }
 
-            Expr::SizeofType { .. } => {
+            ExprKind::SizeofType { .. } => {
                 Ok(UInt(64))
             }
 
-            Expr::Arrow { base, field } => {
+            ExprKind::Arrow { base, field } => {
                 let base_type = base.eval_type()?;
 
                 if let Pointer(s) = base_type {
-                    if let Struct { fields } = s.as_ref() {
+                    // Pointers to a typedef from inside itself
+                    let s = match *s {
+                        Ref(t) => t.borrow().clone(),
+                        t => t,
+                    };
+
+                    if let Struct { fields } = &s {
                         for (name, t) in fields {
                             if name == field {
                                 return Ok(t.clone())
                             }
                         }
 
-                        return ParseError::msg_only(&format!(
+                        return type_error(&self.span, &format!(
                             "unknown struct field \"{}\"",
                             field
                         ))
                     }
                 }
 
-                ParseError::msg_only(&format!(
+                type_error(&self.span, &format!(
                     "arrow operator only applicable to struct pointers"
                 ))
             }
 
-            Expr::Unary { op, child } => {
+            ExprKind::Unary { op, child } => {
                 let child_type = child.eval_type()?;
 
                 match op {
-                    UnOp::Minus => Ok(child_type),
-                    UnOp::Not => Ok(child_type),
-                    UnOp::BitNot => Ok(child_type),
+                    UnOp::Minus => {
+                        match child_type {
+                            t if t.is_numeric() => Ok(t),
+                            _ => type_error(&self.span, &format!("cannot negate value of type {}", child_type))
+                        }
+                    }
 
-                    UnOp::Deref => {
+                    UnOp::Not => {
+                        check_cond(child)?;
+                        Ok(child_type)
+                    }
+
+                    UnOp::BitNot => {
                         match child_type {
-                            Pointer(sub_type) => Ok(*sub_type.clone()),
-                            _ => panic!("{:?}", child_type)
+                            t if t.is_integer() => Ok(t),
+                            _ => type_error(&self.span, &format!("bitwise not applied to value of type {}", child_type))
+                        }
+                    }
+
+                    UnOp::Deref => {
+                        match child_type.pointee() {
+                            Some(sub_type) => Ok(sub_type.clone()),
+                            None => type_error(&self.span, &format!("cannot dereference value of type {}", child_type))
                         }
                     }
 
                     UnOp::AddressOf => {
+                        // Arrays and functions also have an address
+                        if !child.is_lvalue() && !matches!(child.kind, ExprKind::Ref(_)) {
+                            return type_error(&child.span, "cannot take the address of an rvalue");
+                        }
+
                         Ok(Pointer(Box::new(child_type)))
                     }
 
@@ -293,7 +397,7 @@ impl Expr
                 }
             }
 
-            Expr::Binary { op, lhs, rhs } => {
+            ExprKind::Binary { op, lhs, rhs } => {
                 use BinOp::*;
 
                 let lhs_type = lhs.eval_type()?;
@@ -303,14 +407,19 @@ impl Expr
                     // TODO: we need to automatically insert type casting operations
                     // when the cast is valid
                     Assign => {
+                        if !lhs.is_lvalue() {
+                            return type_error(&lhs.span, "left-hand side of assignment is not assignable");
+                        }
+
                         if !assign_compat(&lhs_type, &rhs_type) {
-                            return ParseError::msg_only(&format!(
+                            return type_error(&self.span, &format!(
                                 "rhs type {} not assignable to lhs of type {}",
                                 rhs_type,
                                 lhs_type
                             ))
                         }
 
+                        check_literal_range(rhs, &lhs_type)?;
                         Ok(lhs_type)
                     }
 
@@ -326,12 +435,44 @@ impl Expr
 
                             (Float(32), Float(32)) => Ok(Float(32)),
 
-                            (Pointer(b), UInt(n)) | (UInt(n), Pointer(b)) => Ok(Pointer(b)),
-                            (Pointer(b), Int(n)) | (Int(n), Pointer(b)) => Ok(Pointer(b)),
-                            (Array {elem_type, ..}, Int(n)) | (Int(n), Array {elem_type, ..}) => Ok(Pointer(elem_type)),
-                            (Array {elem_type, ..}, UInt(n)) | (UInt(n), Array {elem_type, ..}) => Ok(Pointer(elem_type)),
+                            // Offsets are scaled by the size of the pointed-to
+                            // type, which void and functions don't have
+                            (Pointer(b), UInt(_) | Int(_)) | (UInt(_) | Int(_), Pointer(b)) if matches!(*b, Void | Fun { .. }) => {
+                                let kind = if let Void = *b { "void pointer" } else { "function pointer" };
+                                type_error(&self.span, &format!("cannot do arithmetic on a {}", kind))
+                            }
+
+                            (Pointer(b), UInt(n)) | (Pointer(b), Int(n)) => Ok(Pointer(b)),
+                            (Array {elem_type, ..}, Int(n)) | (Array {elem_type, ..}, UInt(n)) => Ok(Pointer(elem_type)),
+
+                            // The pointer can only be on the right for additions
+                            (UInt(n), Pointer(b)) | (Int(n), Pointer(b)) if *op == Add => Ok(Pointer(b)),
+                            (Int(n), Array {elem_type, ..}) | (UInt(n), Array {elem_type, ..}) if *op == Add => Ok(Pointer(elem_type)),
+
+                            // Pointer difference, counted in elements
+                            (Pointer(a), Pointer(b)) if *op == Sub => {
+                                if a != b {
+                                    return type_error(&self.span, &format!(
+                                        "cannot subtract pointers of different types {}, {}",
+                                        lhs_type,
+                                        rhs_type
+                                    ));
+                                }
+
+                                if let Void = *a {
+                                    return type_error(&self.span, "cannot subtract pointers to void");
+                                }
+
+                                if let Fun { .. } = *a {
+                                    return type_error(&self.span, "cannot subtract function pointers");
+                                }
 
-                            _ => ParseError::msg_only(&format!(
+                                Ok(Int(64))
+                            }
+
+                            (Pointer(_), Pointer(_)) => type_error(&self.span, "cannot add two pointers"),
+
+                            _ => type_error(&self.span, &format!(
                                 "incompatible types in add/sub {}, {}",
                                 lhs_type,
                                 rhs_type
@@ -351,7 +492,7 @@ impl Expr
 
                             (Float(32), Float(32)) => Ok(Float(32)),
 
-                            _ => ParseError::msg_only(&format!(
+                            _ => type_error(&self.span, &format!(
                                 "incompatible types in arithmetic op {}, {}",
                                 lhs_type,
                                 rhs_type
@@ -370,7 +511,7 @@ impl Expr
                             // to insert an explicit cast operation
                             (Int(m), Int(n)) => Ok(Int(max(m, n))),
 
-                            _ => ParseError::msg_only(&format!(
+                            _ => type_error(&self.span, &format!(
                                 "incompatible types in bitwise op {}, {}",
                                 lhs_type,
                                 rhs_type
@@ -385,7 +526,7 @@ impl Expr
                             (Int(m), UInt(n)) => Ok(UInt(m)),
                             (UInt(m), Int(n)) => Ok(UInt(m)),
 
-                            _ => ParseError::msg_only(&format!(
+                            _ => type_error(&self.span, &format!(
                                 "incompatible types in arithmetic op {}, {}",
                                 lhs_type,
                                 rhs_type
@@ -395,6 +536,8 @@ impl Expr
 
                     // Logical and/or
                     And | Or => {
+                        check_cond(lhs)?;
+                        check_cond(rhs)?;
                         Ok(UInt(8))
                     }
 
@@ -411,38 +554,56 @@ impl Expr
                 }
             }
 
-            Expr::Ternary { test_expr, then_expr, else_expr } => {
-                // TODO: should we check that this is not an array type or some such?
-                test_expr.eval_type()?;
+            ExprKind::Ternary { test_expr, then_expr, else_expr } => {
+                check_cond(test_expr)?;
 
                 let then_type = then_expr.eval_type()?;
                 let else_type = else_expr.eval_type()?;
 
-                if !then_type.eq(&else_type) {
-                    return ParseError::msg_only("mismatched types in ternary expression")
+                if then_type != else_type {
+                    return type_error(&self.span, "mismatched types in ternary expression")
                 }
 
                 Ok(then_type)
             }
 
-            Expr::Call { callee, args } => {
+            ExprKind::Call { callee, args } => {
                 let fn_type = callee.eval_type()?;
 
                 match fn_type {
                     Type::Fun { ret_type, param_types, var_arg } => {
-                        if args.len() < param_types.len() {
-                            return ParseError::msg_only("argument count is less than function parameter count")
-                        }
-                        else if args.len() > param_types.len() && !var_arg {
-                            return ParseError::msg_only("argument count is greater than function parameter count")
+                        let num_params = param_types.len();
+
+                        if args.len() < num_params || (args.len() > num_params && !var_arg) {
+                            let fun_name = match &callee.kind {
+                                ExprKind::Ref(Decl::Fun { name, .. }) => format!("function \"{}\"", name),
+                                _ => "function".to_string(),
+                            };
+
+                            return Err(ParseError::at_span(&self.span, ErrorKind::ArgumentCount, &format!(
+                                "{} takes {}{} argument{} but {} {} given",
+                                fun_name,
+                                if var_arg { "at least " } else { "" },
+                                num_params,
+                                if num_params == 1 { "" } else { "s" },
+                                args.len(),
+                                if args.len() == 1 { "was" } else { "were" },
+                            )));
                         }
 
                         for (idx, param_type) in param_types.iter().enumerate() {
                             let arg_type = args[idx].eval_type()?;
 
                             if !assign_compat(&param_type, &arg_type) {
-                                return ParseError::msg_only("argument type not compatible with parameter type")
+                                return type_error(&args[idx].span, &format!(
+                                    "argument {} of type {} not compatible with parameter type {}",
+                                    idx + 1,
+                                    arg_type,
+                                    param_type
+                                ))
                             }
+
+                            check_literal_range(&args[idx], param_type)?;
                         }
 
                         // Evaluate the type of variadic arguments
@@ -452,11 +613,11 @@ impl Expr
 
                         Ok(*ret_type)
                     },
-                    _ => ParseError::msg_only(&format!("callee is not a function {:?}", callee))
+                    _ => type_error(&callee.span, &format!("callee of type {} is not a function", fn_type))
                 }
             }
 
-            Expr::Asm { args, out_type, .. } => {
+            ExprKind::Asm { args, out_type, .. } => {
                 for arg in args {
                     arg.eval_type()?;
                 }
@@ -481,7 +642,7 @@ mod tests
 
         dbg!(src);
         let mut input = Input::new(&src, "src");
-        let mut unit = parse_unit(&mut input).unwrap();
+        let (mut unit, _) = parse_unit(&mut input).unwrap();
         unit.resolve_syms().unwrap();
         unit.check_types().unwrap();
     }
@@ -496,6 +657,219 @@ mod tests
         unit.check_types().unwrap();
     }
 
+    /// Type check a unit that must fail, and produce the error
+    fn type_err(src: &str) -> String
+    {
+        dbg!(src);
+        let mut unit = crate::parser::parse_str(src).unwrap();
+        unit.resolve_syms().unwrap();
+        let error = unit.check_types().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeError);
+        error.to_string()
+    }
+
+    #[test]
+    fn implicit_conversions()
+    {
+        // Integer widening and narrowing to unsigned types
+        parse_ok("u64 foo(u8 a) { u64 b = a; return b; }");
+        parse_ok("u8 foo(u64 a) { u8 b = a; return b; }");
+        parse_ok("u64 foo(i32 a) { return a; }");
+        parse_ok("void foo(u64 a) {} void bar(u8 b) { foo(b); }");
+
+        // Unsigned to signed only if the destination is at least as large
+        parse_ok("i64 foo(u32 a) { i64 b = a; return b; }");
+        parse_ok("i32 foo(u32 a) { return a; }");
+        assert_eq!(
+            type_err("i32 foo(u64 a) { return a; }"),
+            "src:1:25: error: incompatible return type u64, expected i32"
+        );
+
+        // Signed integers of any size
+        parse_ok("i8 foo(i64 a) { return a; }");
+
+        // Integers, arrays and functions to pointers
+        parse_ok("u8* foo() { return 0; }");
+        parse_ok("u8 buf[4]; u8* foo() { return buf; }");
+        parse_ok("void cb() {} void* foo() { return cb; }");
+        type_err("u32 buf[4]; u8* foo() { return buf; }");
+
+        // Floats must match exactly
+        parse_ok("float foo(float a) { return a; }");
+        type_err("float foo(i32 a) { return a; }");
+        type_err("i32 foo(float a) { return a; }");
+        type_err("u64 foo(u8* p) { return p; }");
+    }
+
+    #[test]
+    fn lvalues()
+    {
+        // Variables, dereferences, indexing and fields can be assigned
+        parse_ok("void foo(u64 a) { u64 b; a = 1; b = 2; }");
+        parse_ok("u64 g; void foo() { g = 1; }");
+        parse_ok("void foo(u64* p) { *p = 1; *(p + 1) = 2; }");
+        parse_ok("void foo(u64* a, u64 i) { a[i] = 2; }");
+        parse_ok("u64 a[4]; void foo(u64 i) { a[i] = 2; }");
+        parse_ok("typedef struct { u64 x; } S; void foo(S* s) { s->x = 1; }");
+        parse_ok("void foo(u64 a) { ++a; --a; }");
+
+        // Literals, calls and arithmetic results can't be assigned
+        let lhs_err = "left-hand side of assignment is not assignable";
+        assert_eq!(type_err("void foo(u64 x) { 5 = x; }"), format!("src:1:19: error: {}", lhs_err));
+        assert_eq!(type_err("u64 f() { return 0; } void foo() { f() = 3; }"), format!("src:1:36: error: {}", lhs_err));
+        assert!(type_err("void foo(u64 a) { a + 1 = 2; }").ends_with(lhs_err));
+        assert!(type_err("void foo(u64 a) { (a = 1) = 2; }").ends_with(lhs_err));
+        assert!(type_err("void foo(u64 a) { -a = 2; }").ends_with(lhs_err));
+        assert!(type_err("void foo(u64 a) { (u32)a = 2; }").ends_with(lhs_err));
+        assert!(type_err("void foo(u64 a) { (a? a:a) = 2; }").ends_with(lhs_err));
+        assert!(type_err("void foo() { \"str\" = 0; }").ends_with(lhs_err));
+        assert!(type_err("u64 a[4]; u64 b[4]; void foo() { a = b; }").ends_with(lhs_err));
+        assert!(type_err("void f() {} void foo() { f = 0; }").ends_with(lhs_err));
+        assert!(type_err("void foo() { ++5; }").ends_with(lhs_err));
+        assert!(type_err("void foo(u64 a) { --(a + 1); }").ends_with(lhs_err));
+
+        // Only lvalues, arrays and functions have an address
+        parse_ok("u64 a[4]; void f() {} void foo(u64* p) { u64** q = &p; u64* r = &*p; &a; &f; }");
+        assert_eq!(type_err("void foo() { u64* p = &5; }"), "src:1:24: error: cannot take the address of an rvalue");
+        assert!(type_err("void foo(u64 a) { u64* p = &(a + 1); }").ends_with("cannot take the address of an rvalue"));
+    }
+
+    #[test]
+    fn type_errors()
+    {
+        assert_eq!(
+            type_err("u64 foo() {\n    return \"hello\" * 3;\n}"),
+            "src:2:12: error: incompatible types in arithmetic op u8[6], i32"
+        );
+
+        // Returns must match the function return type
+        assert_eq!(
+            type_err("u64 foo() {\n    return;\n}"),
+            "src:2:5: error: return void in function not returning void"
+        );
+        type_err("void foo() { return 1; }");
+
+        // Calls
+        assert_eq!(
+            type_err("void foo(u64 a, u8* p) {}\nvoid bar() { foo(1, 2.5f); }"),
+            "src:2:21: error: argument 2 of type float not compatible with parameter type u8*"
+        );
+        assert_eq!(
+            type_err("u64 g; void bar() { g(); }"),
+            "src:1:21: error: callee of type u64 is not a function"
+        );
+
+        // Function types in messages
+        assert_eq!(
+            type_err("u64 f(u64 a, u8* b) { return a; }\nvoid foo() { u64 x = f; }"),
+            "src:2:14: error: rhs type u64(u64, u8*) not assignable to lhs of type u64"
+        );
+        assert_eq!(
+            type_err("void foo(u64 a[4][2]) { a + 1.5f; }"),
+            "src:1:25: error: incompatible types in add/sub u64[4][2], float"
+        );
+
+        // Arithmetic
+        parse_ok("u8* foo(u8* p, u64 n) { return p + n - 1; }");
+        parse_ok("u64* foo(u64* p, int n) { return n + p; }");
+        parse_ok("i64 foo(u64* p, u64* q) { return p - q; }");
+        assert_eq!(
+            type_err("u64 foo(u8* p, u8* q) { return p + q; }"),
+            "src:1:32: error: cannot add two pointers"
+        );
+        assert!(type_err("u64 foo(u8* p, u64* q) { return p - q; }").ends_with("cannot subtract pointers of different types u8*, u64*"));
+        assert!(type_err("u64 foo(void* p, void* q) { return p - q; }").ends_with("cannot subtract pointers to void"));
+        assert!(type_err("u8* foo(u8* p, u64 n) { return n - p; }").ends_with("incompatible types in add/sub u64, u8*"));
+        assert!(type_err("void* foo(void* p) { return p + 1; }").ends_with("cannot do arithmetic on a void pointer"));
+        assert!(type_err("void* foo(void* p, i32 n) { return n + p; }").ends_with("cannot do arithmetic on a void pointer"));
+        assert!(type_err("void foo(void* p) { --p; }").ends_with("cannot do arithmetic on a void pointer"));
+        assert!(type_err("typedef u64 (*fn_ptr)(u64); void foo(fn_ptr f) { f = f + 1; }").ends_with("cannot do arithmetic on a function pointer"));
+        assert!(type_err("typedef u64 (*fn_ptr)(u64); u64 foo(fn_ptr f) { return f - f; }").ends_with("cannot subtract function pointers"));
+        type_err("u64 foo(u8* p, u8* q) { return p * q; }");
+        type_err("u64 foo(float f) { return f & 1; }");
+        type_err("u64 foo(float f) { return ~f; }");
+        type_err("u64 foo(u8* p) { return -p; }");
+        assert_eq!(
+            type_err("u64 foo(u64 a) { return *a; }"),
+            "src:1:25: error: cannot dereference value of type u64"
+        );
+
+        // Conditions must be integers or pointers
+        parse_ok("void foo(u8* p, u64 a) { if (p) {} while (a) {} for (;p;) {} if (!p || a && p) {} }");
+        assert_eq!(
+            type_err("void foo(float f) { if (f) {} }"),
+            "src:1:25: error: condition must have an integer or pointer type, not float"
+        );
+        type_err("void foo(float f) { while (f) {} }");
+        type_err("void foo(float f) { do {} while (f); }");
+        type_err("u64 foo(float f) { return f? 1:2; }");
+        type_err("u64 foo(float f, u64 a) { return a && f; }");
+        type_err("u64 foo(float f) { return !f; }");
+
+        // Errors in nested blocks
+        type_err("void foo(u64 a) { if (a) { while (a) { return a; } } }");
+    }
+
+    #[test]
+    fn recursive_typedefs()
+    {
+        // Pointers to the typedef from inside itself match the typedef
+        let node = "typedef struct { u64 v; node* next; } node;";
+        parse_ok(&format!("{} void foo(node* n) {{ n = n->next; n->next = n; }}", node));
+        parse_ok(&format!("{} node* foo(node* n) {{ return n->next->next; }}", node));
+        parse_ok(&format!("{} void foo(node* n) {{ node* m = n->next; m->next->v = 1; }}", node));
+
+        // Other struct types don't
+        let other = "typedef struct { u64 v; other* next; } other;";
+        assert!(type_err(&format!("{} {} void foo(node* n, other* o) {{ n->next = o->next; }}", node, other)).contains("not assignable"));
+    }
+
+    #[test]
+    fn call_arity()
+    {
+        fn arity_err(src: &str) -> String
+        {
+            dbg!(src);
+            let mut unit = crate::parser::parse_str(src).unwrap();
+            unit.resolve_syms().unwrap();
+            let error = unit.check_types().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentCount);
+            error.to_string()
+        }
+
+        parse_ok("void foo(u64 a, u64 b, u64 c) {} void bar() { foo(1, 2, 3); }");
+        assert_eq!(
+            arity_err("void foo(u64 a, u64 b, u64 c) {}\nvoid bar() { foo(1, 2); }"),
+            "src:2:14: error: function \"foo\" takes 3 arguments but 2 were given"
+        );
+        assert_eq!(
+            arity_err("void foo(u64 a) {} void bar() { foo(1, 2); }"),
+            "src:1:33: error: function \"foo\" takes 1 argument but 2 were given"
+        );
+        arity_err("void foo(u64 a) {} void bar() { foo(); }");
+        arity_err("void foo() {} void bar() { foo(1); }");
+
+        // Calls before the function definition are checked too
+        assert_eq!(
+            arity_err("void bar() { foo(1); } void foo(u64 a, u64 b) {}"),
+            "src:1:14: error: function \"foo\" takes 2 arguments but 1 was given"
+        );
+
+        // Variadic functions
+        parse_ok("void foo(u64 a, ...) {} void bar() { foo(1, 2, 3); }");
+        parse_ok("void foo(u64 a, ...) {} void bar() { foo(1); }");
+        assert_eq!(
+            arity_err("void foo(u64 a, u64 b, ...) {} void bar() { foo(1); }"),
+            "src:1:45: error: function \"foo\" takes at least 2 arguments but 1 was given"
+        );
+
+        // Calls to unknown names
+        let mut unit = crate::parser::parse_str("void bar() { foo(1); }").unwrap();
+        let error = unit.resolve_syms().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UndeclaredIdentifier);
+        assert_eq!(error.to_string(), "src:1:14: error: call to undefined function \"foo\"");
+    }
+
     #[test]
     fn calls()
     {
@@ -506,4 +880,70 @@ mod tests
         // FIXME:
         //parse_ok("u64 foo(u64 v, u8* p) { return v; } void main() { foo(1, null); }");
     }
+
+    #[test]
+    fn int_suffixes()
+    {
+        fn lit_type(src: &str) -> String
+        {
+            let expr = crate::parser::parse_str(&format!("u64 g = {};", src)).unwrap()
+                .global_vars[0].init_expr.clone().unwrap();
+            expr.eval_type().unwrap().to_string()
+        }
+
+        assert_eq!(lit_type("42"), "i32");
+        assert_eq!(lit_type("0x1_0000_0000"), "i64");
+        assert_eq!(lit_type("42u64"), "u64");
+        assert_eq!(lit_type("255u8"), "u8");
+        assert_eq!(lit_type("42ULL"), "u64");
+        assert_eq!(lit_type("-1i16"), "i16");
+
+        parse_ok("u8 foo() { u8 x = 255u8; return x + 1u8; }");
+        parse_ok("u64 g = 1u64; void main() { u64 x = g << 40u64; }");
+    }
+
+    #[test]
+    fn literal_range()
+    {
+        // Literals must fit in the destination type, as signed or unsigned
+        parse_ok("u8 foo() { return 255; }");
+        parse_ok("u8 foo() { u8 x = -128; x = -1; return x; }");
+        parse_ok("i8 foo() { return 255; }");
+        parse_ok("u32 foo() { return 0xFFFFFFFF; }");
+        assert_eq!(
+            type_err("u8 foo() { return 100000; }"),
+            "src:1:19: error: integer constant 100000 does not fit in type u8"
+        );
+        assert!(type_err("void foo() { i16 x = 65536; }").ends_with("integer constant 65536 does not fit in type i16"));
+        assert!(type_err("void foo(u8 a) {} void bar() { foo(256); }").ends_with("does not fit in type u8"));
+
+        // Explicit casts truncate
+        parse_ok("u8 foo() { return (u8)100000; }");
+    }
+
+    #[test]
+    fn all_errors()
+    {
+        let src = "u8 foo() { return 100000; }\n\
+                   void bar(float f) {\n\
+                       if (f) { return 1; }\n\
+                       while (1) { u64* p = f; }\n\
+                   }";
+        let mut unit = crate::parser::parse_str(src).unwrap();
+        unit.resolve_syms().unwrap();
+        let errors: Vec<String> = unit.check_types_all_errors().unwrap_err().iter().map(|e| e.to_string()).collect();
+
+        // Errors are reported in source order, including nested statements
+        assert_eq!(errors, vec![
+            "src:1:19: error: integer constant 100000 does not fit in type u8",
+            "src:3:5: error: condition must have an integer or pointer type, not float",
+            "src:3:17: error: incompatible return type i32, expected void",
+            "src:4:13: error: rhs type float not assignable to lhs of type u64*",
+        ]);
+
+        // The first error is also the one returned by check_types
+        let mut unit = crate::parser::parse_str(src).unwrap();
+        unit.resolve_syms().unwrap();
+        assert_eq!(unit.check_types().unwrap_err().to_string(), errors[0]);
+    }
 }
@@ -0,0 +1,10 @@
+// Run by test_main_args in vm/src/vm.rs
+
+u64 main(u64 argc, char** argv)
+{
+    // argv ends with a null pointer
+    if (argv[argc] != 0)
+        return 1000;
+
+    return argc;
+}
@@ -0,0 +1,9 @@
+// Run by test_main_args in vm/src/vm.rs
+
+u64 main(u64 argc, char** argv)
+{
+    if (argc < 2)
+        return 0;
+
+    return argv[1][0];
+}
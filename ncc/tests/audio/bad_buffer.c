@@ -0,0 +1,16 @@
+// The output is checked by test_audio_bad_buffer in vm/src/vm.rs
+
+#include <uvm/syscalls.h>
+#include <uvm/utils.h>
+
+// Return a buffer address outside of the heap
+i16* audio_cb(u16 num_channels, u32 num_samples)
+{
+    return (i16*)0xFFFFFFFFFFFFu64;
+}
+
+void main()
+{
+    audio_open_output(44100, 1, AUDIO_FORMAT_I16, audio_cb);
+    enable_event_loop();
+}
@@ -0,0 +1,32 @@
+// The samples are checked by test_audio_headless in vm/src/vm.rs
+
+#include <uvm/syscalls.h>
+#include <uvm/utils.h>
+
+#define SAMPLE_RATE 44100
+#define FREQ 440
+
+i16 audio_buffer[1024];
+
+// Number of samples generated so far
+u64 sample_idx = 0;
+
+// Generate a 440 Hz square wave, which is high for the first
+// half of each period and low for the second half
+i16* audio_cb(u16 num_channels, u32 num_samples)
+{
+    for (u32 i = 0; i < num_samples; ++i)
+    {
+        u64 half_periods = (sample_idx * 2 * FREQ) / SAMPLE_RATE;
+        audio_buffer[i] = (half_periods % 2 == 0)? 8000:-8000;
+        sample_idx = sample_idx + 1;
+    }
+
+    return audio_buffer;
+}
+
+void main()
+{
+    audio_open_output(SAMPLE_RATE, 1, AUDIO_FORMAT_I16, audio_cb);
+    enable_event_loop();
+}
@@ -0,0 +1,27 @@
+#include <assert.h>
+
+// Recursive calls, with the argument and return value
+// of each call on the stack of the caller
+u64 factorial(u64 n)
+{
+    if (n < 2)
+        return 1;
+
+    return n * factorial(n - 1);
+}
+
+// Arguments are passed in order
+int sub3(int a, int b, int c)
+{
+    return a - b - c;
+}
+
+int main()
+{
+    assert(factorial(0) == 1);
+    assert(factorial(1) == 1);
+    assert(factorial(10) == 3628800);
+    assert(sub3(10, 3, 2) == 5);
+    assert(sub3(sub3(9, 1, 1), 2, 1) == 4);
+    return 0;
+}
@@ -0,0 +1,21 @@
+// Exits from deep inside a call chain, see test_exit_codes in vm/src/vm.rs
+
+#include <stdlib.h>
+
+u64 depth = 0;
+
+u64 descend(u64 n)
+{
+    depth = depth + 1;
+
+    if (n == 0)
+        exit(3);
+
+    return descend(n - 1) + 1;
+}
+
+u64 main()
+{
+    descend(50);
+    return 100;
+}
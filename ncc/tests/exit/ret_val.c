@@ -0,0 +1,6 @@
+// The return value of main is the exit code, see test_exit_codes in vm/src/vm.rs
+
+u64 main()
+{
+    return 7;
+}
@@ -0,0 +1,8 @@
+// A void main exits with 0, see test_exit_codes in vm/src/vm.rs
+
+u64 x = 5;
+
+void main()
+{
+    x = x + 1;
+}
@@ -0,0 +1,37 @@
+// Write a file, read it back, and check that paths
+// outside of the filesystem root are rejected
+
+#include <stdio.h>
+#include <string.h>
+#include <uvm/syscalls.h>
+
+char buf[64];
+
+int main()
+{
+    char* msg = "hello file";
+    i64 fd = file_open("data.txt", FILE_MODE_WRITE);
+    if (fd < 0)
+    {
+        printf("open for writing failed\n");
+        return 0;
+    }
+    printf("written: %d\n", file_write(fd, msg, strlen(msg)));
+    file_close(fd);
+
+    fd = file_open("data.txt", FILE_MODE_APPEND);
+    file_write(fd, "!", 1);
+    file_close(fd);
+
+    fd = file_open("data.txt", FILE_MODE_READ);
+    i64 num_read = file_read(fd, buf, 63);
+    buf[num_read] = 0;
+    printf("read: %d %s\n", num_read, buf);
+    printf("closed: %d %d\n", file_close(fd), file_close(fd));
+
+    printf("parent: %d\n", file_open("../data.txt", FILE_MODE_READ));
+    printf("absolute: %d\n", file_open("/data.txt", FILE_MODE_WRITE));
+    printf("missing: %d\n", file_open("missing.txt", FILE_MODE_READ));
+
+    return 0;
+}
@@ -0,0 +1,58 @@
+#include <assert.h>
+
+#define N 8
+
+int arr[8] = { 5, -3, 8, 0, 2, 2, -7, 1 };
+
+// Sort an array in place with bubble sort
+void bubble_sort(int* a, int n)
+{
+    for (int i = 0; i < n - 1; ++i)
+    {
+        for (int j = 0; j < n - 1 - i; ++j)
+        {
+            if (a[j] > a[j + 1])
+            {
+                int tmp = a[j];
+                a[j] = a[j + 1];
+                a[j + 1] = tmp;
+            }
+        }
+    }
+}
+
+int main()
+{
+    bubble_sort(arr, N);
+    for (int i = 0; i < N - 1; ++i)
+        assert(arr[i] <= arr[i + 1]);
+    assert(arr[0] == -7);
+    assert(arr[N - 1] == 8);
+
+    // continue still runs the increment
+    int sum = 0;
+    for (int i = 0; i < 10; ++i)
+    {
+        if (i % 2 == 0)
+            continue;
+        sum = sum + i;
+    }
+    assert(sum == 25);
+
+    // break leaves the loop without the increment
+    int k = 0;
+    for (k = 0; k < 10; ++k)
+    {
+        if (k == 4)
+            break;
+    }
+    assert(k == 4);
+
+    // The loop variable is only in scope in the loop, so it
+    // can be declared again after it
+    for (int i = 0; i < 3; ++i) {}
+    int i = 42;
+    assert(i == 42);
+
+    return 0;
+}
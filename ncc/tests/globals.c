@@ -0,0 +1,47 @@
+#include <assert.h>
+#include <stddef.h>
+
+u64 counter = 0;
+i32 neg = -1;
+u8 small = 250;
+float f = 1.5f;
+u64* ptr = NULL;
+
+// Two functions sharing the same counter
+void inc_one()
+{
+    counter = counter + 1;
+}
+
+void inc_two()
+{
+    ++counter;
+    ++counter;
+}
+
+int main()
+{
+    inc_one();
+    inc_two();
+    inc_one();
+    assert(counter == 4);
+
+    neg = neg - 2;
+    assert(neg == -3);
+
+    // Stores are truncated to the size of the global
+    small = small + 10;
+    assert(small == 4);
+
+    f = f * 2.0f;
+    assert(f == 3.0f);
+
+    // Globals can be written through a pointer
+    ptr = &counter;
+    *ptr = 10;
+    assert(counter == 10);
+    inc_one();
+    assert(*ptr == 11);
+
+    return 0;
+}
@@ -0,0 +1,57 @@
+// Run by exec_tests_heap_exhaust in src/exec_tests.rs,
+// with a small memory size so that the heap fills up quickly
+
+#include <stdlib.h>
+#include <assert.h>
+
+#define BLOCK_SIZE 1000
+
+// Last block allocated, which points to the one allocated before it
+void** last_block = NULL;
+
+// Allocate blocks until malloc returns NULL.
+// Returns the number of blocks allocated.
+u64 alloc_all()
+{
+    u64 num_blocks = 0;
+
+    for (;;)
+    {
+        void** block = (void**)malloc(BLOCK_SIZE);
+        if (block == NULL)
+            return num_blocks;
+
+        *block = (void*)last_block;
+        last_block = block;
+        num_blocks = num_blocks + 1;
+    }
+}
+
+// Free the blocks, from the last allocated to the first
+void free_all()
+{
+    while (last_block != NULL)
+    {
+        void** prev = (void**)*last_block;
+        free((void*)last_block);
+        last_block = prev;
+    }
+}
+
+void main()
+{
+    u64 num_blocks = alloc_all();
+    assert(num_blocks > 100);
+    assert(malloc(BLOCK_SIZE) == NULL);
+
+    // Freeing everything makes the space reusable,
+    // by the same number of blocks
+    free_all();
+    assert(alloc_all() == num_blocks);
+    free_all();
+
+    // The freed blocks are merged into one large block
+    void* p = malloc(num_blocks * BLOCK_SIZE);
+    assert(p != NULL);
+    free(p);
+}
@@ -0,0 +1,20 @@
+// The host functions are registered by test_host_fn_compiled in vm/src/vm.rs
+extern void host_fill(u8* buf, u64 len, u8 val);
+extern u64 host_sum(u8* buf, u64 len);
+
+u8 buf[8];
+
+u64 main()
+{
+    // The host writes to memory and the program reads it back
+    host_fill(buf, 8, 3);
+
+    u64 total = 0;
+    for (int i = 0; i < 8; ++i)
+        total = total + buf[i];
+
+    // The program writes to memory and the host reads it back
+    buf[0] = 100;
+
+    return total * 1000 + host_sum(buf, 8);
+}
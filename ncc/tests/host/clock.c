@@ -0,0 +1,13 @@
+// The host functions are registered by test_fake_clock_compiled in vm/src/sys/time.rs
+
+#include <uvm/clock.h>
+
+u64 main()
+{
+    u64 t0 = time_ms();
+    sleep_ms(100);
+    sleep_ms(150);
+    u64 t1 = time_ms();
+
+    return t0 * 1000 + (t1 - t0);
+}
@@ -0,0 +1,42 @@
+#include <assert.h>
+
+int sign(int x)
+{
+    if (x < 0)
+        return -1;
+    else if (x > 0)
+        return 1;
+    else
+        return 0;
+}
+
+int clamp(int x, int max)
+{
+    if (x > max)
+    {
+        x = max;
+    }
+
+    return x;
+}
+
+int main()
+{
+    assert(sign(-5) == -1);
+    assert(sign(7) == 1);
+    assert(sign(0) == 0);
+
+    assert(clamp(3, 10) == 3);
+    assert(clamp(30, 10) == 10);
+
+    // The else branch binds to the nearest if
+    int n = 0;
+    if (sign(3) == 1)
+        if (sign(0) == 1)
+            n = 1;
+        else
+            n = 2;
+    assert(n == 2);
+
+    return 0;
+}
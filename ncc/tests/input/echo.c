@@ -0,0 +1,28 @@
+// Echo the input back, reading the first character on its own and
+// the rest line by line, into a buffer shorter than some lines
+
+#include <stdio.h>
+#include <uvm/syscalls.h>
+
+char line[8];
+
+int main()
+{
+    int ch = getchar();
+    if (ch == EOF)
+    {
+        printf("no input\n");
+        return 0;
+    }
+    printf("first: %d\n", ch);
+
+    int num_reads = 0;
+    while (read_line(line, 8) > 0)
+    {
+        printf("%s", line);
+        ++num_reads;
+    }
+
+    printf("reads: %d\n", num_reads);
+    return 0;
+}
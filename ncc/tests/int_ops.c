@@ -19,6 +19,12 @@ int main()
     assert(3 - 1 + 1 == 3);
     assert(3 - 1 - 2 == 0);
 
+    // Equal-precedence operators are left-associative
+    assert(8 - 3 - 2 == 3);
+    assert(16 / 4 / 2 == 2);
+    assert(17 % 10 % 4 == 3);
+    assert(1 << 4 >> 2 == 4);
+
     // Unary minus
     assert(3 - -1 == 4);
     assert(4 + -1 == 3);
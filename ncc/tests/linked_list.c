@@ -0,0 +1,73 @@
+#include <stdlib.h>
+#include <assert.h>
+
+typedef struct
+{
+    int val;
+    node* next;
+} node;
+
+// Add a value at the front of a list
+node* push_front(node* list, int val)
+{
+    node* n = (node*)malloc(sizeof(node));
+    assert(n != NULL);
+    n->val = val;
+    n->next = list;
+    return n;
+}
+
+// Reverse a list in place
+node* reverse(node* list)
+{
+    node* prev = NULL;
+
+    while (list != NULL)
+    {
+        node* next = list->next;
+        list->next = prev;
+        prev = list;
+        list = next;
+    }
+
+    return prev;
+}
+
+void free_list(node* list)
+{
+    while (list != NULL)
+    {
+        node* next = list->next;
+        free((void*)list);
+        list = next;
+    }
+}
+
+int main()
+{
+    node* list = NULL;
+    for (int i = 0; i < 100; ++i)
+        list = push_front(list, i);
+
+    assert(list->val == 99);
+    assert(list->next->val == 98);
+
+    list = reverse(list);
+
+    int count = 0;
+    for (node* n = list; n != NULL; n = n->next)
+    {
+        assert(n->val == count);
+        count = count + 1;
+    }
+    assert(count == 100);
+
+    free_list(list);
+
+    // The freed nodes are reused
+    node* n = (node*)malloc(sizeof(node));
+    assert(n != NULL);
+    free((void*)n);
+
+    return 0;
+}
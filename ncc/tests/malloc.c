@@ -6,8 +6,89 @@
 
 uint8_t* alloc_ptrs[NUM_ALLOCS];
 
+// Two blocks written in full must not overlap
+void test_no_overlap()
+{
+    uint8_t* a = (uint8_t*)malloc(13);
+    uint8_t* b = (uint8_t*)malloc(64);
+    assert(b >= a + 13 || a >= b + 64);
+
+    for (int i = 0; i < 13; ++i)
+        a[i] = 1;
+    for (int i = 0; i < 64; ++i)
+        b[i] = 2;
+
+    for (int i = 0; i < 13; ++i)
+        assert(a[i] == 1);
+    for (int i = 0; i < 64; ++i)
+        assert(b[i] == 2);
+
+    free((void*)b);
+    free((void*)a);
+}
+
+// Blocks are 8-byte aligned for any size
+void test_alignment()
+{
+    for (int i = 0; i < 20; ++i)
+    {
+        u64 p = (u64)malloc(i);
+        assert(p % 8 == 0);
+        free((void*)p);
+    }
+}
+
+// Freed blocks are reused, in any order of allocations and frees
+void test_reuse()
+{
+    u8* a = (u8*)malloc(32);
+    u8* b = (u8*)malloc(32);
+    u8* c = (u8*)malloc(32);
+
+    // Freed blocks are reused for smaller allocations
+    free((void*)b);
+    u8* d = (u8*)malloc(16);
+    assert(d == b);
+
+    // Adjacent free blocks are merged into a larger one
+    free((void*)d);
+    free((void*)a);
+    free((void*)c);
+    u8* e = (u8*)malloc(96);
+    assert(e == a);
+    free((void*)e);
+
+    // Freeing in reverse order merges the same way
+    a = (u8*)malloc(32);
+    b = (u8*)malloc(32);
+    free((void*)b);
+    free((void*)a);
+    assert((u8*)malloc(64) == a);
+
+    free(NULL);
+}
+
+// Running out of memory returns NULL. Filling the heap with
+// allocations is tested in tests/heap/exhaust.c, which runs
+// with a smaller memory size than this test.
+void test_exhaust()
+{
+    assert(malloc(0xFFFF_FFFF_FFFF_FFFF) == NULL);
+    assert(malloc(0x10_0000_0000_0000) == NULL);
+
+    // Smaller allocations still succeed
+    void* p = malloc(8);
+    assert(p != NULL);
+    free(p);
+}
+
 void main()
 {
+    test_no_overlap();
+    test_alignment();
+    test_reuse();
+    test_exhaust();
+
     for (int i = 0; i < NUM_ALLOCS; ++i)
     {
         alloc_ptrs[i] = (uint8_t*)malloc(128);
@@ -0,0 +1,43 @@
+// Connects to the server started by test_tcp in vm/src/vm.rs,
+// on the port given as first argument
+
+#include <stdio.h>
+#include <string.h>
+#include <uvm/syscalls.h>
+
+u8 buf[64];
+
+int main(int argc, char** argv)
+{
+    if (argc < 2)
+    {
+        puts("usage: client <port>\n");
+        return 1;
+    }
+
+    u16 port = 0;
+    for (char* p = argv[1]; *p != 0; p = p + 1)
+    {
+        port = port * 10 + (*p - '0');
+    }
+
+    i64 fd = tcp_connect("localhost", port);
+    if (fd < 0)
+    {
+        puts("connect failed\n");
+        return 1;
+    }
+
+    char* msg = "ping";
+    printf("sent: %d\n", tcp_write(fd, msg, strlen(msg)));
+
+    i64 num_read = tcp_read(fd, buf, 63);
+    buf[num_read] = 0;
+    printf("received: %s\n", buf);
+
+    // The server closes the connection after replying
+    printf("end: %d\n", tcp_read(fd, buf, 63));
+    printf("closed: %d %d\n", tcp_close(fd), tcp_close(fd));
+
+    return 0;
+}
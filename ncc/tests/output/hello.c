@@ -0,0 +1,17 @@
+// The output is captured by test_output_compiled in vm/src/vm.rs
+
+#include <stdio.h>
+#include <uvm/syscalls.h>
+
+int main()
+{
+    puts("hello world\n");
+    printf("%d %x %c%c %s 100%%\n", -42, 255, 'o', 'k', "done");
+
+    print_i64(7);
+    print_str(" ");
+    print_u64_hex(0xDEADBEEF);
+    print_endl();
+
+    return 0;
+}
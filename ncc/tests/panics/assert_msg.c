@@ -0,0 +1,18 @@
+// Failed assertion with a custom message, used to check
+// the message printed by assert statements
+
+#include <assert.h>
+
+void check(int n)
+{
+    // Passing asserts don't print anything
+    assert(n > 0, "n must be positive");
+    assert(n % 2 == 0, "n must be even");
+}
+
+int main()
+{
+    check(4);
+    check(3);
+    return 0;
+}
@@ -0,0 +1,20 @@
+// Failed assertion in a nested call, used to check
+// that panics print the call stack
+
+#include <assert.h>
+
+void b(int n)
+{
+    assert(n < 3);
+}
+
+void a(int n)
+{
+    b(n + 1);
+}
+
+int main()
+{
+    a(2);
+    return 0;
+}
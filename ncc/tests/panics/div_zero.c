@@ -0,0 +1,13 @@
+// Division by zero at runtime, used to check that
+// panics report the source position with -g
+
+int divide(int a, int b)
+{
+    return a / b;
+}
+
+int main()
+{
+    int x = divide(1, 0);
+    return x;
+}
@@ -0,0 +1,12 @@
+// Freeing a block twice, used to check that
+// the allocator detects double frees
+
+#include <stdlib.h>
+
+int main()
+{
+    void* p = malloc(16);
+    free(p);
+    free(p);
+    return 0;
+}
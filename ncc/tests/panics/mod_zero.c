@@ -0,0 +1,13 @@
+// Remainder by zero at runtime, which traps and
+// reports the source position with -g
+
+int remainder(int a, int b)
+{
+    return a % b;
+}
+
+int main()
+{
+    int x = remainder(7, 0);
+    return x;
+}
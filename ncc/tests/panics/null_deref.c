@@ -0,0 +1,12 @@
+// Null pointer dereference, used to check that
+// the VM catches it when run with --null-check
+
+int get(int* p)
+{
+    return *p;
+}
+
+int main()
+{
+    return get(NULL);
+}
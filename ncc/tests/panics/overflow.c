@@ -0,0 +1,18 @@
+// Signed division overflow, which wraps around by default
+// and traps when the VM is run with --overflow-check
+
+int divide(int a, int b)
+{
+    return a / b;
+}
+
+int main()
+{
+    int min = -2147483647 - 1;
+
+    // Without checks, the quotient wraps around to the minimum
+    if (divide(min, -1) != min)
+        return 1;
+
+    return 0;
+}
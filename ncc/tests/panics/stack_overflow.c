@@ -0,0 +1,20 @@
+// Unbounded mutual recursion, used to check that
+// the VM reports a stack overflow. The additions keep
+// the calls out of tail position, so each takes a frame.
+
+int odd(int n);
+
+int even(int n)
+{
+    return odd(n + 1) + 1;
+}
+
+int odd(int n)
+{
+    return even(n + 1) + 1;
+}
+
+int main()
+{
+    return even(0);
+}
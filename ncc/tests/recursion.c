@@ -0,0 +1,18 @@
+#include <assert.h>
+
+// Recursion 100 calls deep, which stays under the call depth
+// limit of the VM. test_call_depth_ncc in vm/src/vm.rs checks
+// that one frame less makes the program trap.
+int depth(int n)
+{
+    if (n == 1)
+        return 1;
+
+    return 1 + depth(n - 1);
+}
+
+int main()
+{
+    assert(depth(100) == 100);
+    return 0;
+}
@@ -0,0 +1,46 @@
+#include <assert.h>
+
+u64 sum_shadowed(u64 n)
+{
+    u64 total = 0;
+
+    for (u64 i = 0; i < n; ++i)
+    {
+        // Shadows the parameter for the rest of the loop body
+        u64 n = i * 2;
+        total = total + n;
+    }
+
+    // The parameter is visible again
+    return total + n;
+}
+
+int main()
+{
+    u64 x = 5;
+    u64 y = x + 3;
+    assert(y == 8);
+
+    // Shadowing in a nested block doesn't change the outer variable
+    {
+        u64 x = 100;
+        assert(x == 100);
+        x = x + 1;
+        assert(x == 101);
+    }
+    assert(x == 5);
+
+    // Sibling blocks reuse the same slots, but start from their own initializers
+    {
+        u64 a = 7;
+        assert(a + x == 12);
+    }
+    {
+        u64 b = 9;
+        assert(b == 9);
+    }
+
+    assert(sum_shadowed(4) == 0 + 2 + 4 + 6 + 4);
+
+    return 0;
+}
@@ -0,0 +1,41 @@
+// Tail-recursive loops, which run in constant stack space.
+// test_tail_calls in vm/src/vm.rs runs this with a call
+// depth limit of a few frames.
+
+u64 countdown(u64 n, u64 acc)
+{
+    if (n == 0)
+        return acc;
+
+    return countdown(n - 1, acc + 1);
+}
+
+// Mutual recursion between functions with different arguments
+u64 is_odd(u64 n);
+
+u64 is_even(u64 n, u64 unused)
+{
+    if (n == 0)
+        return 1;
+
+    return is_odd(n - 1);
+}
+
+u64 is_odd(u64 n)
+{
+    if (n == 0)
+        return 0;
+
+    return is_even(n - 1, n);
+}
+
+int main()
+{
+    if (countdown(10000000, 0) != 10000000)
+        return 1;
+
+    if (!is_even(100001, 0) && is_odd(100001))
+        return 0;
+
+    return 2;
+}
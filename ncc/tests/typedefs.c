@@ -67,5 +67,11 @@ int main()
 
     mat44_test(g_mat);
 
+    // Signed fields are sign-extended when loaded
+    node n_neg;
+    (&n_neg)->v = -5;
+    i64 wide = (&n_neg)->v;
+    assert(wide == -5);
+
     return 0;
 }
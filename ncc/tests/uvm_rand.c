@@ -0,0 +1,18 @@
+#include <assert.h>
+#include <uvm/syscalls.h>
+
+void main()
+{
+    // The VM generator starts with seed 0
+    assert(rand_u64() == 0xE220A8397B1DCDAF);
+
+    rand_seed(1234);
+    u64 a = rand_u64();
+    u64 b = rand_u64();
+    assert(a != b);
+
+    // Reseeding restarts the sequence
+    rand_seed(1234);
+    assert(rand_u64() == a);
+    assert(rand_u64() == b);
+}
@@ -0,0 +1,47 @@
+#include <assert.h>
+
+int main()
+{
+    // Sum of 1 to 100
+    int i = 1;
+    int sum = 0;
+    while (i <= 100)
+    {
+        sum = sum + i;
+        i = i + 1;
+    }
+    assert(sum == 5050);
+
+    // Sum of the even numbers, skipping the odd ones
+    i = 0;
+    sum = 0;
+    while (i < 100)
+    {
+        i = i + 1;
+        if (i % 2 == 1)
+            continue;
+        sum = sum + i;
+    }
+    assert(sum == 2550);
+
+    // break only leaves the innermost loop
+    int outer = 0;
+    int inner = 0;
+    while (outer < 5)
+    {
+        int j = 0;
+        while (1)
+        {
+            if (j == 3)
+                break;
+            j = j + 1;
+            inner = inner + 1;
+        }
+
+        outer = outer + 1;
+    }
+    assert(outer == 5);
+    assert(inner == 15);
+
+    return 0;
+}
@@ -0,0 +1,38 @@
+// Events are injected by test_window_events in vm/src/vm.rs
+
+#include <stdio.h>
+#include <uvm/syscalls.h>
+#include <uvm/window.h>
+
+window_event event;
+
+int main()
+{
+    window_create(64, 64, "Events", 0);
+    window_event* ev = &event;
+
+    for (;;)
+    {
+        if (!window_poll_event(0, ev))
+        {
+            continue;
+        }
+
+        if (ev->kind == WINDOW_EVENT_CLOSE)
+        {
+            puts("close\n");
+            return 0;
+        }
+
+        if (ev->kind == WINDOW_EVENT_KEYDOWN)
+            printf("keydown %d\n", ev->code);
+        else if (ev->kind == WINDOW_EVENT_KEYUP)
+            printf("keyup %d\n", ev->code);
+        else if (ev->kind == WINDOW_EVENT_MOUSEMOVE)
+            printf("mousemove %d %d\n", ev->x, ev->y);
+        else if (ev->kind == WINDOW_EVENT_MOUSEDOWN)
+            printf("mousedown %d %d %d\n", ev->code, ev->x, ev->y);
+        else if (ev->kind == WINDOW_EVENT_MOUSEUP)
+            printf("mouseup %d %d %d\n", ev->code, ev->x, ev->y);
+    }
+}
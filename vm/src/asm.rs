@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::mem::transmute;
 use crate::vm::{VM, MemBlock, Op};
+use crate::image::Program;
+use crate::debug::LineTable;
 
 #[derive(Debug)]
 pub struct ParseError
@@ -484,6 +486,9 @@ pub struct Assembler
     /// Set of syscalls referenced by this program
     syscall_set: HashSet<u16>,
 
+    /// Names of the host functions called by this program
+    host_fns: Vec<String>,
+
     // Generated code
     code: MemBlock,
 
@@ -498,6 +503,9 @@ pub struct Assembler
 
     /// Current section
     section: Section,
+
+    /// Source positions set with .loc
+    line_table: LineTable,
 }
 
 impl Assembler
@@ -530,15 +538,17 @@ impl Assembler
             const_map: HashMap::new(),
             syscall_map: syscall_map,
             syscall_set: HashSet::new(),
+            host_fns: Vec::new(),
             code: MemBlock::new(),
             data: MemBlock::new(),
             label_defs: HashMap::default(),
             label_refs: Vec::default(),
             section: Section::Code,
+            line_table: LineTable::default(),
         }
     }
 
-    fn parse_input(mut self, input: &mut Input) -> Result<VM, ParseError>
+    fn parse_input(mut self, input: &mut Input) -> Result<Program, ParseError>
     {
         // Until we've reached the end of the input
         loop
@@ -606,10 +616,26 @@ impl Assembler
             }
         }
 
-        Ok(VM::new(self.code, self.data, self.syscall_set))
+        // Keep the code labels for diagnostics
+        let mut symbols: Vec<(String, usize)> = self.label_defs.iter()
+            .filter(|(_, def)| def.section == Section::Code)
+            .map(|(name, def)| (name.clone(), def.pos))
+            .collect();
+        symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+        Ok(Program {
+            code: self.code,
+            data: self.data,
+            syscalls: self.syscall_set,
+            entry: 0,
+            symbols,
+            line_table: self.line_table,
+            host_fns: self.host_fns,
+        })
     }
 
-    pub fn parse_file(mut self, file_name: &str) -> Result<VM, ParseError>
+    /// Assemble a source file into a program
+    pub fn assemble_file(mut self, file_name: &str) -> Result<Program, ParseError>
     {
         match std::fs::read_to_string(file_name) {
             Err(_) => {
@@ -622,13 +648,24 @@ impl Assembler
         }
     }
 
-    /// Parse a string of source code
-    pub fn parse_str(mut self, src: &str) -> Result<VM, ParseError>
+    /// Assemble a string of source code into a program
+    pub fn assemble_str(mut self, src: &str) -> Result<Program, ParseError>
     {
         let mut input = Input::new(src.to_string());
         return self.parse_input(&mut input);
     }
 
+    pub fn parse_file(mut self, file_name: &str) -> Result<VM, ParseError>
+    {
+        Ok(self.assemble_file(file_name)?.into_vm())
+    }
+
+    /// Parse a string of source code
+    pub fn parse_str(mut self, src: &str) -> Result<VM, ParseError>
+    {
+        Ok(self.assemble_str(src)?.into_vm())
+    }
+
     /// Parse an integer argument
     fn parse_int_arg<T>(&self, input: &mut Input) -> Result<T, ParseError> where T: TryFrom<i128>
     {
@@ -897,6 +934,25 @@ impl Assembler
                 mem.push_u8(0);
             }
 
+            // Source position of the code that follows
+            // .loc "file_name", line, col;
+            "loc" => {
+                if self.section != Section::Code {
+                    return input.parse_error("source positions are only allowed in the code section");
+                }
+
+                if input.peek_ch() != '"' {
+                    return input.parse_error("expected file name string");
+                }
+                let file_name = input.parse_str()?;
+                input.expect_token(",")?;
+                let line: u32 = self.parse_int_arg(input)?;
+                input.expect_token(",")?;
+                let col: u32 = self.parse_int_arg(input)?;
+
+                self.line_table.add(self.code.len(), &file_name, line, col);
+            }
+
             // Absolute 64-bit address of a label
             "addr64" => {
                 let label_name = input.parse_ident()?;
@@ -936,6 +992,8 @@ impl Assembler
                 self.code.push_u8(idx);
             }
 
+            "get_argc" => self.code.push_op(Op::get_argc),
+
             "get_var_arg" => {
                 self.code.push_op(Op::get_var_arg);
             }
@@ -1141,6 +1199,36 @@ impl Assembler
                 self.code.push_u16(syscall_idx);
             }
 
+            "call_host" => {
+                let name = input.parse_ident()?;
+                input.expect_token(",")?;
+                let argc: u8 = self.parse_int_arg(input)?;
+
+                if self.syscall_map.contains_key(&name) {
+                    return input.parse_error(
+                        &format!("host function \"{}\" has the same name as a syscall", name)
+                    );
+                }
+
+                // Each host function gets an index the first time it's called
+                let host_fn_idx = match self.host_fns.iter().position(|f| *f == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.host_fns.push(name);
+                        self.host_fns.len() - 1
+                    }
+                };
+
+                let host_fn_idx: u16 = match host_fn_idx.try_into() {
+                    Ok(idx) => idx,
+                    Err(_) => return input.parse_error("too many host functions"),
+                };
+
+                self.code.push_op(Op::call_host);
+                self.code.push_u16(host_fn_idx);
+                self.code.push_u8(argc);
+            }
+
             "call" => {
                 let label_name = input.parse_ident()?;
                 input.expect_token(",")?;
@@ -1151,6 +1239,16 @@ impl Assembler
                 self.code.push_u8(argc);
             }
 
+            "tail_call" => {
+                let label_name = input.parse_ident()?;
+                input.expect_token(",")?;
+                let argc: u8 = self.parse_int_arg(input)?;
+
+                self.code.push_op(Op::tail_call);
+                self.add_label_ref(input, label_name, LabelRefKind::Offset32(1));
+                self.code.push_u8(argc);
+            }
+
             "ret" => self.code.push_op(Op::ret),
             "exit" => self.code.push_op(Op::exit),
 
@@ -1335,6 +1433,26 @@ mod tests
         parse_ok(".data; STR_LABEL: .stringz \"hi!\"; .code; push_p32 STR_LABEL;");
     }
 
+    #[test]
+    fn test_loc()
+    {
+        let program = Assembler::new().assemble_str(
+            "push_0; .loc \"foo.c\", 3, 5; push_1; push_2; .loc \"foo.c\", 4, 1; add_u64; exit;"
+        ).unwrap();
+
+        let lines = &program.line_table;
+        assert_eq!(lines.lookup(0), None);
+        assert_eq!(lines.lookup(1).unwrap().to_string(), "foo.c:3:5");
+        assert_eq!(lines.lookup(2).unwrap().to_string(), "foo.c:3:5");
+        assert_eq!(lines.lookup(3).unwrap().to_string(), "foo.c:4:1");
+
+        parse_ok(".loc \"a\\\"b.c\", 1, 1; push_0; exit;");
+        parse_fails(".loc foo.c, 1, 1;");
+        parse_fails(".loc \"foo.c\", 1;");
+        parse_fails(".loc \"foo.c\", -1, 1;");
+        parse_fails(".data; .loc \"foo.c\", 1, 1;");
+    }
+
     #[test]
     fn test_invalid()
     {
@@ -1352,6 +1470,9 @@ mod tests
         parse_fails("push_i8 555");
         parse_fails("push_i855;");
         parse_fails("push_i8 55; comment without hash");
+        parse_fails("call_host print_i64, 1;");
+        parse_fails("call_host foo, 256;");
+        parse_fails("call_host foo;");
     }
 
     #[test]
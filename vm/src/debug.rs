@@ -0,0 +1,393 @@
+// Debug information used to describe code addresses in error messages
+//
+// The line table is a list of entries sorted by code address. Each
+// entry gives the source position of the code from its address up
+// to the address of the next entry, so consecutive instructions
+// coming from the same source position share a single entry.
+//
+// In program images, the table is encoded compactly using unsigned
+// LEB128 varints, with each entry stored relative to the previous:
+//
+//   num_files      varint
+//   files          num_files * (name_len: varint, name)
+//   num_entries    varint
+//   entries        num_entries * (addr_delta: varint, file: varint,
+//                                 line_delta: zigzag varint, col: varint)
+
+use std::fmt;
+
+/// Source position of the code starting at a given address
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LineEntry
+{
+    pub addr: usize,
+
+    /// Index into the list of file names
+    pub file: usize,
+
+    pub line: u32,
+    pub col: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable
+{
+    /// Source file names
+    pub files: Vec<String>,
+
+    /// Entries sorted by address
+    pub entries: Vec<LineEntry>,
+}
+
+/// Source position, as reported to the user
+#[derive(Debug, PartialEq)]
+pub struct SrcLoc<'a>
+{
+    pub file: &'a str,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl fmt::Display for SrcLoc<'_>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: u64)
+{
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+
+        if val == 0 {
+            out.push(byte);
+            break;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64>
+{
+    let mut val: u64 = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+
+        if shift >= 64 {
+            return None;
+        }
+        val |= ((byte & 0x7F) as u64) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Some(val);
+        }
+    }
+}
+
+impl LineTable
+{
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+    /// Record the source position of the code starting at an address.
+    /// Addresses must be added in increasing order.
+    pub fn add(&mut self, addr: usize, file_name: &str, line: u32, col: u32)
+    {
+        let file = match self.files.iter().position(|f| f == file_name) {
+            Some(idx) => idx,
+            None => {
+                self.files.push(file_name.to_string());
+                self.files.len() - 1
+            }
+        };
+
+        let entry = LineEntry { addr, file, line, col };
+
+        match self.entries.last_mut() {
+            // No code was generated for the previous position
+            Some(last) if last.addr == addr => *last = entry,
+            _ => self.entries.push(entry),
+        }
+
+        // Merge with the previous entry if the position didn't change
+        let num_entries = self.entries.len();
+        if num_entries >= 2 {
+            let prev = self.entries[num_entries - 2];
+            if (prev.file, prev.line, prev.col) == (file, line, col) {
+                self.entries.pop();
+            }
+        }
+    }
+
+    /// Find the source position of the code at a given address
+    pub fn lookup(&self, addr: usize) -> Option<SrcLoc<'_>>
+    {
+        let idx = self.entries.partition_point(|entry| entry.addr <= addr);
+        if idx == 0 {
+            return None;
+        }
+
+        let entry = &self.entries[idx - 1];
+        Some(SrcLoc {
+            file: &self.files[entry.file],
+            line: entry.line,
+            col: entry.col,
+        })
+    }
+
+    /// Encode the table for storage in a program image
+    pub fn encode(&self) -> Vec<u8>
+    {
+        let mut out = Vec::new();
+
+        write_varint(&mut out, self.files.len() as u64);
+        for file in &self.files {
+            write_varint(&mut out, file.len() as u64);
+            out.extend(file.as_bytes());
+        }
+
+        write_varint(&mut out, self.entries.len() as u64);
+        let mut prev_addr = 0;
+        let mut prev_line = 0;
+        for entry in &self.entries {
+            let line_delta = entry.line as i64 - prev_line as i64;
+            let zigzag = ((line_delta << 1) ^ (line_delta >> 63)) as u64;
+
+            write_varint(&mut out, (entry.addr - prev_addr) as u64);
+            write_varint(&mut out, entry.file as u64);
+            write_varint(&mut out, zigzag);
+            write_varint(&mut out, entry.col as u64);
+
+            prev_addr = entry.addr;
+            prev_line = entry.line;
+        }
+
+        out
+    }
+
+    /// Decode a table from a program image. Returns None if the
+    /// encoding is invalid or an address lies outside of the code.
+    pub fn decode(bytes: &[u8], code_len: usize) -> Option<LineTable>
+    {
+        let mut pos = 0;
+        let mut table = LineTable::default();
+
+        let num_files = read_varint(bytes, &mut pos)?;
+        for _ in 0..num_files {
+            let len = usize::try_from(read_varint(bytes, &mut pos)?).ok()?;
+            let name = bytes.get(pos..pos.checked_add(len)?)?;
+            table.files.push(String::from_utf8(name.to_vec()).ok()?);
+            pos += len;
+        }
+
+        let num_entries = read_varint(bytes, &mut pos)?;
+        let mut addr: usize = 0;
+        let mut line: i64 = 0;
+        for _ in 0..num_entries {
+            let addr_delta = usize::try_from(read_varint(bytes, &mut pos)?).ok()?;
+            let file = usize::try_from(read_varint(bytes, &mut pos)?).ok()?;
+            let zigzag = read_varint(bytes, &mut pos)?;
+            let col = u32::try_from(read_varint(bytes, &mut pos)?).ok()?;
+
+            addr = addr.checked_add(addr_delta)?;
+            line = line.checked_add(((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64))?;
+
+            if addr >= code_len || file >= table.files.len() {
+                return None;
+            }
+
+            table.entries.push(LineEntry {
+                addr,
+                file,
+                line: u32::try_from(line).ok()?,
+                col,
+            });
+        }
+
+        if pos != bytes.len() {
+            return None;
+        }
+
+        Some(table)
+    }
+}
+
+/// Number of innermost and outermost frames printed in call
+/// stacks which are too deep to print in full
+const TRACE_INNER_FRAMES: usize = 10;
+const TRACE_OUTER_FRAMES: usize = 5;
+
+/// Symbols and source positions, used to describe code addresses
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo
+{
+    /// Code labels and their addresses
+    pub symbols: Vec<(String, usize)>,
+
+    pub line_table: LineTable,
+}
+
+impl DebugInfo
+{
+    /// Describe a location in a function, e.g. "foo at bar.c:3:5"
+    fn describe(&self, fun_addr: usize, pc: usize) -> String
+    {
+        let fun_name = match self.symbols.iter().find(|(_, addr)| *addr == fun_addr) {
+            Some((name, _)) => name.clone(),
+            None => format!("<function at {}>", fun_addr),
+        };
+
+        match self.line_table.lookup(pc) {
+            Some(loc) => format!("{} at {}", fun_name, loc),
+            None => format!("{} at address {}", fun_name, pc),
+        }
+    }
+
+    /// Format a call stack given the function address and the current
+    /// address of each frame, starting with the innermost frame
+    pub fn format_call_stack(&self, frames: &[(usize, usize)]) -> String
+    {
+        let mut out = String::new();
+
+        if let Some(&(_, pc)) = frames.first() {
+            if let Some(loc) = self.line_table.lookup(pc) {
+                out.push_str(&format!("panicked at {}\n", loc));
+            }
+        }
+
+        out.push_str("call stack:\n");
+
+        let num_frames = frames.len();
+        for (idx, &(fun_addr, pc)) in frames.iter().enumerate() {
+            // For deep recursion, skip the frames in the middle
+            if num_frames > TRACE_INNER_FRAMES + TRACE_OUTER_FRAMES {
+                if idx == TRACE_INNER_FRAMES {
+                    let num_skipped = num_frames - TRACE_INNER_FRAMES - TRACE_OUTER_FRAMES;
+                    out.push_str(&format!("    ... {} frames omitted ...\n", num_skipped));
+                }
+                if idx >= TRACE_INNER_FRAMES && idx < num_frames - TRACE_OUTER_FRAMES {
+                    continue;
+                }
+            }
+
+            out.push_str(&format!("    #{} {}\n", idx, self.describe(fun_addr, pc)));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn loc(file: &str, line: u32, col: u32) -> Option<SrcLoc<'_>>
+    {
+        Some(SrcLoc { file, line, col })
+    }
+
+    #[test]
+    fn test_varint()
+    {
+        for val in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, val);
+            let mut pos = 0;
+            assert_eq!(read_varint(&bytes, &mut pos), Some(val));
+            assert_eq!(pos, bytes.len());
+        }
+
+        assert_eq!(read_varint(&[0x80], &mut 0), None);
+        assert_eq!(read_varint(&[0xFF; 11], &mut 0), None);
+    }
+
+    #[test]
+    fn test_lookup()
+    {
+        let mut table = LineTable::default();
+        assert_eq!(table.lookup(0), None);
+
+        table.add(4, "foo.c", 3, 5);
+        table.add(10, "foo.c", 4, 5);
+        table.add(10, "foo.c", 5, 9);
+        table.add(16, "foo.c", 5, 9);
+        table.add(20, "bar.h", 12, 1);
+
+        // Positions without code and repeated positions are merged
+        assert_eq!(table.entries.len(), 3);
+        assert_eq!(table.files, vec!["foo.c", "bar.h"]);
+
+        assert_eq!(table.lookup(0), None);
+        assert_eq!(table.lookup(4), loc("foo.c", 3, 5));
+        assert_eq!(table.lookup(9), loc("foo.c", 3, 5));
+        assert_eq!(table.lookup(10), loc("foo.c", 5, 9));
+        assert_eq!(table.lookup(19), loc("foo.c", 5, 9));
+        assert_eq!(table.lookup(1000), loc("bar.h", 12, 1));
+        assert_eq!(table.lookup(20).unwrap().to_string(), "bar.h:12:1");
+    }
+
+    #[test]
+    fn test_encoding()
+    {
+        let mut table = LineTable::default();
+        table.add(0, "foo.c", 100, 5);
+        table.add(3, "foo.c", 98, 1);
+        table.add(9, "include/stdio.h", 7, 12);
+        table.add(200, "foo.c", 100_000, 3);
+
+        let bytes = table.encode();
+        assert_eq!(LineTable::decode(&bytes, 201), Some(table.clone()));
+
+        // File names take 23 bytes and the entries 22 bytes, since
+        // small deltas only need one byte per field
+        assert_eq!(bytes.len(), 23 + 22);
+
+        // Addresses outside of the code
+        assert_eq!(LineTable::decode(&bytes, 200), None);
+
+        // Truncated and trailing data
+        assert_eq!(LineTable::decode(&bytes[..bytes.len() - 1], 201), None);
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(LineTable::decode(&extra, 201), None);
+
+        assert_eq!(LineTable::decode(&LineTable::default().encode(), 0), Some(LineTable::default()));
+    }
+
+    #[test]
+    fn test_call_stack()
+    {
+        let mut line_table = LineTable::default();
+        line_table.add(10, "foo.c", 7, 5);
+        line_table.add(20, "foo.c", 2, 5);
+        let debug_info = DebugInfo {
+            symbols: vec![("main".to_string(), 0), ("foo".to_string(), 20)],
+            line_table,
+        };
+
+        assert_eq!(
+            debug_info.format_call_stack(&[(20, 24), (0, 12)]),
+            "panicked at foo.c:2:5\ncall stack:\n    #0 foo at foo.c:2:5\n    #1 main at foo.c:7:5\n"
+        );
+
+        // Deep recursion only prints the innermost and outermost frames
+        let mut frames = vec![(20, 24); 100];
+        frames.push((0, 12));
+        let text = debug_info.format_call_stack(&frames);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2 + TRACE_INNER_FRAMES + 1 + TRACE_OUTER_FRAMES);
+        assert_eq!(lines[2 + TRACE_INNER_FRAMES], "    ... 86 frames omitted ...");
+        assert_eq!(lines[lines.len() - 2], "    #99 foo at foo.c:2:5");
+        assert_eq!(lines[lines.len() - 1], "    #100 main at foo.c:7:5");
+    }
+}
@@ -0,0 +1,122 @@
+// Hooks for stepping through the execution of a program
+//
+// VM::call_with_debugger runs a function like call_with_fuel, but
+// calls the debugger before each instruction is executed, and when
+// execution panics. Debuggers can inspect the VM, but not modify it.
+
+use crate::vm::{VM, Op};
+
+pub trait Debugger
+{
+    /// Called before executing the instruction at pc
+    fn on_step(&mut self, vm: &VM, pc: usize, op: Op);
+
+    /// Called when execution panics, before the state of
+    /// the interrupted call is discarded
+    fn on_panic(&mut self, vm: &VM, msg: &str)
+    {
+    }
+}
+
+/// Debugger which traces each instruction and the
+/// top of the stack to stderr
+pub struct PrintDebugger;
+
+impl Debugger for PrintDebugger
+{
+    fn on_step(&mut self, vm: &VM, pc: usize, op: Op)
+    {
+        match vm.stack().last() {
+            Some(top) => eprintln!("{:>6}  {:<12} top: {}", pc, format!("{:?}", op), top.as_i64()),
+            None => eprintln!("{:>6}  {:?}", pc, op),
+        }
+    }
+
+    fn on_panic(&mut self, vm: &VM, msg: &str)
+    {
+        eprintln!("panic: {}", msg);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::vm::{RunResult, ExitReason, Value};
+
+    /// Debugger which records each step and panic
+    #[derive(Default)]
+    struct Recorder
+    {
+        steps: Vec<(usize, Op, usize)>,
+        panics: Vec<String>,
+    }
+
+    impl Debugger for Recorder
+    {
+        fn on_step(&mut self, vm: &VM, pc: usize, op: Op)
+        {
+            self.steps.push((pc, op, vm.stack_size()));
+        }
+
+        fn on_panic(&mut self, vm: &VM, msg: &str)
+        {
+            self.panics.push(msg.to_string());
+        }
+    }
+
+    #[test]
+    fn test_steps()
+    {
+        let mut vm = Assembler::new().parse_str("push_i8 1; push_i8 2; add_u64; dup; exit;").unwrap();
+        let mut recorder = Recorder::default();
+        let result = vm.call_with_debugger(0, &[], &mut recorder);
+        assert_eq!(result, RunResult::Done(ExitReason::Exit(Value::from(3u64))));
+
+        // Each step sees the stack before the instruction runs
+        assert_eq!(recorder.steps, vec![
+            (0, Op::push_i8, 0),
+            (2, Op::push_i8, 1),
+            (4, Op::add_u64, 2),
+            (5, Op::dup, 1),
+            (6, Op::exit, 2),
+        ]);
+        assert!(recorder.panics.is_empty());
+
+        // The built-in debugger doesn't change the result
+        assert_eq!(vm.call_with_debugger(0, &[], &mut PrintDebugger), result);
+    }
+
+    #[test]
+    fn test_panic()
+    {
+        let mut vm = Assembler::new().parse_str("push_i8 1; pop; ret;").unwrap();
+        let mut recorder = Recorder::default();
+        let result = vm.call_with_debugger(0, &[], &mut recorder);
+        assert!(matches!(result, RunResult::Panic(_)));
+
+        assert_eq!(recorder.steps.len(), 3);
+        assert_eq!(recorder.panics.len(), 1);
+        assert_eq!(result, RunResult::Panic(recorder.panics[0].clone()));
+
+        // The VM can still be used after the panic
+        assert_eq!(vm.stack_size(), 0);
+    }
+
+    #[test]
+    fn test_trap()
+    {
+        let mut vm = Assembler::new().parse_str("push_i8 1; push_0; div_u64; exit;").unwrap();
+        let mut recorder = Recorder::default();
+        let result = vm.call_with_debugger(0, &[], &mut recorder);
+
+        // The debugger is notified of traps like panics
+        let trap = match result {
+            RunResult::Trap(trap) => trap,
+            result => panic!("expected a trap, got {:?}", result),
+        };
+        assert_eq!(recorder.panics, vec![trap.to_string()]);
+        assert_eq!(vm.stack_size(), 0);
+    }
+}
@@ -0,0 +1,178 @@
+// Disassembler for the code of assembled programs and images
+//
+// Each instruction is printed on its own line, prefixed with its
+// code address, and code labels are printed on a line of their own
+// before the instruction they point to:
+//
+//   FN:
+//   0012  get_arg    0
+//   0014  jz         +7 -> 0026
+//   0019  call       -13 -> 0012 FN, 1
+//
+// Branch offsets are relative to the end of the instruction, so both
+// the offset and the absolute target address are shown.
+
+use std::io::{self, Write};
+use crate::vm::Op;
+use crate::image::{Program, Reader, decode_op};
+use crate::sys::constants::SYSCALL_DESCS;
+
+/// Width of the mnemonic column
+const MNEMONIC_WIDTH: usize = 10;
+
+/// Format the operands of the instruction at the reader position,
+/// advancing the reader past them. Returns None if the code ends
+/// in the middle of the instruction.
+fn format_operands(program: &Program, op: Op, reader: &mut Reader) -> Option<String>
+{
+    let operands = match op {
+        Op::push_i8 => format!("{}", reader.read_u8().ok()? as i8),
+        Op::push_u32 => format!("{}", reader.read_u32().ok()?),
+        Op::push_u64 => format!("{}", reader.read_u64().ok()?),
+
+        Op::getn | Op::get_arg | Op::set_arg | Op::get_local | Op::set_local => {
+            format!("{}", reader.read_u8().ok()?)
+        }
+
+        Op::jmp | Op::jz | Op::jnz | Op::call | Op::tail_call => {
+            let offset = reader.read_i32().ok()?;
+            let argc = if let Op::call | Op::tail_call = op { Some(reader.read_u8().ok()?) } else { None };
+            let target = reader.pos as i64 + offset as i64;
+
+            let mut text = format!("{:+} -> {:04}", offset, target);
+            if let Some(name) = usize::try_from(target).ok().and_then(|addr| program.symbol_at(addr)) {
+                text.push_str(&format!(" {}", name));
+            }
+            if let Some(argc) = argc {
+                text.push_str(&format!(", {}", argc));
+            }
+            text
+        }
+
+        Op::syscall => {
+            let idx = reader.read_u16().ok()?;
+            match SYSCALL_DESCS.get(idx as usize) {
+                Some(Some(desc)) => desc.name.to_string(),
+                _ => format!("<unknown syscall {}>", idx),
+            }
+        }
+
+        Op::call_host => {
+            let idx = reader.read_u16().ok()?;
+            let argc = reader.read_u8().ok()?;
+            match program.host_fns.get(idx as usize) {
+                Some(name) => format!("{}, {}", name, argc),
+                None => format!("<unknown host function {}>, {}", idx, argc),
+            }
+        }
+
+        _ => String::new(),
+    };
+
+    Some(operands)
+}
+
+/// Write the disassembled code of a program
+pub fn disassemble(program: &Program, out: &mut dyn Write) -> io::Result<()>
+{
+    let code = program.code.as_bytes();
+    let mut reader = Reader { bytes: code, pos: 0 };
+
+    while reader.pos < code.len() {
+        let addr = reader.pos;
+
+        for (name, _) in program.symbols.iter().filter(|(_, sym_addr)| *sym_addr == addr) {
+            writeln!(out, "{}:", name)?;
+        }
+
+        let byte = code[addr];
+        reader.pos += 1;
+
+        let op = match decode_op(byte) {
+            Some(op) => op,
+            None => {
+                writeln!(out, "{:04}  <invalid opcode 0x{:02X}>", addr, byte)?;
+                continue;
+            }
+        };
+
+        let mnemonic = format!("{:?}", op);
+        match format_operands(program, op, &mut reader) {
+            Some(operands) if operands.is_empty() => writeln!(out, "{:04}  {}", addr, mnemonic)?,
+            Some(operands) => writeln!(out, "{:04}  {:<width$} {}", addr, mnemonic, operands, width = MNEMONIC_WIDTH)?,
+            None => {
+                writeln!(out, "{:04}  {:<width$} <truncated>", addr, mnemonic, width = MNEMONIC_WIDTH)?;
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::vm::MemBlock;
+
+    fn disassemble_str(src: &str) -> String
+    {
+        let program = Assembler::new().assemble_str(src).unwrap();
+        let mut out = Vec::new();
+        disassemble(&program, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_golden()
+    {
+        let src = concat!(
+            "push 10; call DEC, 1; syscall print_i64; push_0; exit;",
+            "DEC: get_arg 0; dup; jz ZERO; push_i8 -1; add_u64; call DEC, 1; ret;",
+            "ZERO: push_u64 0x1_0000_0000; call_host foo, 2; ret;",
+        );
+
+        let expected = concat!(
+            "0000  push_i8    10\n",
+            "0002  call       +5 -> 0013 DEC, 1\n",
+            "0008  syscall    print_i64\n",
+            "0011  push_0\n",
+            "0012  exit\n",
+            "DEC:\n",
+            "0013  get_arg    0\n",
+            "0015  dup\n",
+            "0016  jz         +10 -> 0031 ZERO\n",
+            "0021  push_i8    -1\n",
+            "0023  add_u64\n",
+            "0024  call       -17 -> 0013 DEC, 1\n",
+            "0030  ret\n",
+            "ZERO:\n",
+            "0031  push_u64   4294967296\n",
+            "0040  call_host  foo, 2\n",
+            "0044  ret\n",
+        );
+
+        assert_eq!(disassemble_str(src), expected);
+    }
+
+    #[test]
+    fn test_invalid_code()
+    {
+        assert_eq!(disassemble_str(""), "");
+
+        let disassemble_bytes = |bytes: Vec<u8>| {
+            let mut program = Assembler::new().assemble_str("").unwrap();
+            program.code = MemBlock::from_bytes(bytes);
+            let mut out = Vec::new();
+            disassemble(&program, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(disassemble_bytes(vec![Op::exit as u8, 0xFE]), "0000  exit\n0001  <invalid opcode 0xFE>\n");
+
+        // Code ending in the middle of an instruction
+        assert_eq!(disassemble_bytes(vec![Op::push_u32 as u8, 1]), "0000  push_u32   <truncated>\n");
+    }
+}
@@ -0,0 +1,702 @@
+// Binary program image format
+//
+// An assembled program can be saved as an image and run later
+// without going through the assembler again. All integers are
+// stored in little-endian byte order.
+//
+//   magic          b"UVM\0"
+//   version        u32
+//   entry          u64, code address where execution starts
+//   num_sections   u32
+//   section table  num_sections * (kind: u32, offset: u64, len: u64)
+//   section contents, located by the offsets in the table
+//
+// The code section is required, and every section kind may only
+// appear once. The syscalls section is a list of u16 syscall
+// indices. The symbols section is a list of code labels for
+// diagnostics, each written as (addr: u64, name_len: u32, name).
+// The optional lines section holds the source positions of the
+// code, in the encoding described in debug.rs. The optional host
+// functions section lists the names of the functions called with
+// call_host, each written as (name_len: u32, name), in the order
+// of their indices.
+//
+// Images may come from untrusted sources, so loading validates
+// every instruction, operand, branch target and syscall before
+// the code can be executed.
+
+use std::fmt;
+use std::collections::HashSet;
+use std::mem::transmute;
+use crate::vm::{VM, MemBlock, Op, DEFAULT_MEM_SIZE};
+use crate::debug::{LineTable, DebugInfo};
+use crate::sys::constants::SYSCALL_DESCS;
+
+/// Magic bytes at the start of every image
+pub const IMAGE_MAGIC: [u8; 4] = *b"UVM\0";
+
+/// Current version of the image format
+const IMAGE_VERSION: u32 = 3;
+
+// Section kinds
+const SECTION_CODE: u32 = 0;
+const SECTION_DATA: u32 = 1;
+const SECTION_SYSCALLS: u32 = 2;
+const SECTION_SYMBOLS: u32 = 3;
+const SECTION_LINES: u32 = 4;
+const SECTION_HOST_FNS: u32 = 5;
+
+/// Size of the fixed header and of one section table entry in bytes
+const HEADER_SIZE: usize = 20;
+const SECTION_ENTRY_SIZE: usize = 20;
+
+#[derive(Debug)]
+pub struct LoadError
+{
+    msg: String,
+}
+
+impl LoadError
+{
+    fn new<T>(msg: &str) -> Result<T, LoadError>
+    {
+        Err(LoadError { msg: msg.to_string() })
+    }
+}
+
+impl fmt::Display for LoadError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid program image: {}", self.msg)
+    }
+}
+
+/// Assembled program, ready to be saved or run
+pub struct Program
+{
+    // Code memory space
+    pub code: MemBlock,
+
+    // Initial contents of the heap
+    pub data: MemBlock,
+
+    /// Set of syscalls referenced by this program
+    pub syscalls: HashSet<u16>,
+
+    /// Code address where execution starts
+    pub entry: usize,
+
+    /// Code labels and their addresses, for diagnostics
+    pub symbols: Vec<(String, usize)>,
+
+    /// Source positions of the code, empty without debug info
+    pub line_table: LineTable,
+
+    /// Names of the host functions called by the program
+    pub host_fns: Vec<String>,
+}
+
+/// Cursor to read little-endian values out of a byte slice
+pub(crate) struct Reader<'a>
+{
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    pub(crate) fn read_bytes(&mut self, num_bytes: usize) -> Result<&'a [u8], LoadError>
+    {
+        match self.pos.checked_add(num_bytes) {
+            Some(end) if end <= self.bytes.len() => {
+                let bytes = &self.bytes[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            _ => LoadError::new("unexpected end of data")
+        }
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, LoadError>
+    {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, LoadError>
+    {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, LoadError>
+    {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn read_i32(&mut self) -> Result<i32, LoadError>
+    {
+        Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, LoadError>
+    {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Read a u64 that must fit in a usize
+    fn read_usize(&mut self) -> Result<usize, LoadError>
+    {
+        match usize::try_from(self.read_u64()?) {
+            Ok(val) => Ok(val),
+            Err(_) => LoadError::new("value out of range")
+        }
+    }
+}
+
+/// Decode an opcode byte, rejecting values which aren't valid opcodes
+pub(crate) fn decode_op(byte: u8) -> Option<Op>
+{
+    // Short opcodes are numbered contiguously from 0 up to exit
+    if byte <= Op::exit as u8 {
+        Some(unsafe { transmute::<u8, Op>(byte) })
+    } else {
+        None
+    }
+}
+
+/// Check that the code only contains valid instructions, so that it
+/// is safe to execute. Branches must land on instruction boundaries
+/// inside the code, syscalls must be known and declared, and host
+/// functions must be declared.
+fn validate_code(code: &[u8], syscalls: &HashSet<u16>, num_host_fns: usize, entry: usize) -> Result<(), LoadError>
+{
+    let mut reader = Reader { bytes: code, pos: 0 };
+    let mut insn_starts = HashSet::new();
+    let mut branch_targets = Vec::new();
+
+    while reader.pos < code.len() {
+        insn_starts.insert(reader.pos);
+
+        let op = match decode_op(reader.read_u8()?) {
+            Some(op) => op,
+            None => return LoadError::new(&format!("invalid opcode at {}", reader.pos - 1)),
+        };
+
+        match op {
+            Op::push_i8 | Op::getn | Op::get_arg | Op::set_arg | Op::get_local | Op::set_local => {
+                reader.read_u8()?;
+            }
+
+            Op::push_u32 => {
+                reader.read_u32()?;
+            }
+
+            Op::push_u64 => {
+                reader.read_u64()?;
+            }
+
+            Op::jmp | Op::jz | Op::jnz | Op::call | Op::tail_call => {
+                let offset = reader.read_i32()? as i64;
+                if let Op::call | Op::tail_call = op {
+                    reader.read_u8()?;
+                }
+
+                // Offsets are relative to the end of the instruction
+                let target = reader.pos as i64 + offset;
+                branch_targets.push(target);
+            }
+
+            Op::syscall => {
+                let syscall_idx = reader.read_u16()?;
+                let known = matches!(SYSCALL_DESCS.get(syscall_idx as usize), Some(Some(_)));
+
+                if !known || !syscalls.contains(&syscall_idx) {
+                    return LoadError::new(&format!("invalid syscall {}", syscall_idx));
+                }
+            }
+
+            Op::call_host => {
+                let host_fn_idx = reader.read_u16()?;
+                reader.read_u8()?;
+
+                if host_fn_idx as usize >= num_host_fns {
+                    return LoadError::new(&format!("invalid host function {}", host_fn_idx));
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    for target in branch_targets {
+        if target < 0 || !insn_starts.contains(&(target as usize)) {
+            return LoadError::new(&format!("invalid branch target {}", target));
+        }
+    }
+
+    // An empty program has nothing to run
+    if !(insn_starts.contains(&entry) || code.is_empty() && entry == 0) {
+        return LoadError::new(&format!("invalid entry point {}", entry));
+    }
+
+    Ok(())
+}
+
+impl Program
+{
+    /// Create a VM to run this program, with the default maximum heap size
+    pub fn into_vm(self) -> VM
+    {
+        match self.into_vm_with_mem_size(DEFAULT_MEM_SIZE) {
+            Ok(vm) => vm,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Create a VM to run this program, with a maximum heap size in bytes.
+    /// Fails if the data section doesn't fit in the heap.
+    pub fn into_vm_with_mem_size(self, mem_size: usize) -> Result<VM, LoadError>
+    {
+        if self.data.len() > mem_size {
+            return LoadError::new(&format!(
+                "data section of {} bytes doesn't fit in memory size of {} bytes",
+                self.data.len(),
+                mem_size
+            ));
+        }
+
+        let debug_info = DebugInfo {
+            symbols: self.symbols,
+            line_table: self.line_table,
+        };
+
+        Ok(VM::new(self.code, self.data, mem_size, self.syscalls, self.host_fns, debug_info))
+    }
+
+    /// Get the name of the code label at a given address, if any
+    pub fn symbol_at(&self, addr: usize) -> Option<&str>
+    {
+        self.symbols.iter().find(|(_, pos)| *pos == addr).map(|(name, _)| name.as_str())
+    }
+
+    /// Serialize the program into an image
+    pub fn serialize(&self) -> Vec<u8>
+    {
+        // Syscalls are sorted so that the output is deterministic
+        let mut syscalls: Vec<u16> = self.syscalls.iter().copied().collect();
+        syscalls.sort();
+        let syscalls_bytes: Vec<u8> = syscalls.iter().flat_map(|idx| idx.to_le_bytes()).collect();
+
+        let mut symbols_bytes = Vec::new();
+        for (name, addr) in &self.symbols {
+            symbols_bytes.extend((*addr as u64).to_le_bytes());
+            symbols_bytes.extend((name.len() as u32).to_le_bytes());
+            symbols_bytes.extend(name.as_bytes());
+        }
+
+        let lines_bytes = self.line_table.encode();
+
+        let mut host_fns_bytes = Vec::new();
+        for name in &self.host_fns {
+            host_fns_bytes.extend((name.len() as u32).to_le_bytes());
+            host_fns_bytes.extend(name.as_bytes());
+        }
+
+        let mut sections: Vec<(u32, &[u8])> = vec![
+            (SECTION_CODE, self.code.as_bytes()),
+            (SECTION_DATA, self.data.as_bytes()),
+            (SECTION_SYSCALLS, &syscalls_bytes),
+            (SECTION_SYMBOLS, &symbols_bytes),
+        ];
+
+        // Programs without debug info don't get a lines section
+        if !self.line_table.is_empty() {
+            sections.push((SECTION_LINES, &lines_bytes));
+        }
+
+        if !self.host_fns.is_empty() {
+            sections.push((SECTION_HOST_FNS, &host_fns_bytes));
+        }
+
+        let mut out = Vec::new();
+        out.extend(IMAGE_MAGIC);
+        out.extend(IMAGE_VERSION.to_le_bytes());
+        out.extend((self.entry as u64).to_le_bytes());
+        out.extend((sections.len() as u32).to_le_bytes());
+
+        // Section contents follow the section table
+        let mut offset = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+        for (kind, bytes) in &sections {
+            out.extend(kind.to_le_bytes());
+            out.extend((offset as u64).to_le_bytes());
+            out.extend((bytes.len() as u64).to_le_bytes());
+            offset += bytes.len();
+        }
+
+        for (_, bytes) in &sections {
+            out.extend(*bytes);
+        }
+
+        out
+    }
+
+    /// Load a program from an image, validating its contents
+    pub fn deserialize(bytes: &[u8]) -> Result<Program, LoadError>
+    {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.read_bytes(4).ok() != Some(&IMAGE_MAGIC[..]) {
+            return LoadError::new("bad magic bytes");
+        }
+
+        let version = reader.read_u32()?;
+        if version != IMAGE_VERSION {
+            return LoadError::new(&format!("unsupported version {}", version));
+        }
+
+        let entry = reader.read_usize()?;
+        let num_sections = reader.read_u32()?;
+
+        let mut code = None;
+        let mut data = None;
+        let mut syscalls = None;
+        let mut symbols = None;
+        let mut lines = None;
+        let mut host_fns = None;
+
+        for _ in 0..num_sections {
+            let kind = reader.read_u32()?;
+            let offset = reader.read_usize()?;
+            let len = reader.read_usize()?;
+
+            // Check that the section lies within the image
+            let contents = match offset.checked_add(len) {
+                Some(end) if end <= bytes.len() => &bytes[offset..end],
+                _ => return LoadError::new(&format!("section {} out of bounds", kind)),
+            };
+
+            let slot = match kind {
+                SECTION_CODE => &mut code,
+                SECTION_DATA => &mut data,
+                SECTION_SYSCALLS => &mut syscalls,
+                SECTION_SYMBOLS => &mut symbols,
+                SECTION_LINES => &mut lines,
+                SECTION_HOST_FNS => &mut host_fns,
+                _ => return LoadError::new(&format!("unknown section kind {}", kind)),
+            };
+
+            if slot.is_some() {
+                return LoadError::new(&format!("duplicate section {}", kind));
+            }
+            *slot = Some(contents);
+        }
+
+        let code = match code {
+            Some(code) => code,
+            None => return LoadError::new("missing code section"),
+        };
+
+        let syscalls_bytes = syscalls.unwrap_or(&[]);
+        if syscalls_bytes.len() % 2 != 0 {
+            return LoadError::new("syscalls section has odd length");
+        }
+        let mut syscalls = HashSet::new();
+        let mut reader = Reader { bytes: syscalls_bytes, pos: 0 };
+        while reader.pos < syscalls_bytes.len() {
+            syscalls.insert(reader.read_u16()?);
+        }
+
+        let symbols_bytes = symbols.unwrap_or(&[]);
+        let mut symbols = Vec::new();
+        let mut reader = Reader { bytes: symbols_bytes, pos: 0 };
+        while reader.pos < symbols_bytes.len() {
+            let addr = reader.read_usize()?;
+            let name_len = reader.read_u32()? as usize;
+            let name = match std::str::from_utf8(reader.read_bytes(name_len)?) {
+                Ok(name) => name.to_string(),
+                Err(_) => return LoadError::new("symbol name is not valid UTF-8"),
+            };
+
+            if addr > code.len() {
+                return LoadError::new(&format!("symbol \"{}\" out of bounds", name));
+            }
+            symbols.push((name, addr));
+        }
+
+        let line_table = match lines {
+            Some(lines_bytes) => match LineTable::decode(lines_bytes, code.len()) {
+                Some(line_table) => line_table,
+                None => return LoadError::new("invalid lines section"),
+            }
+            None => LineTable::default(),
+        };
+
+        let host_fns_bytes = host_fns.unwrap_or(&[]);
+        let mut host_fns = Vec::new();
+        let mut reader = Reader { bytes: host_fns_bytes, pos: 0 };
+        while reader.pos < host_fns_bytes.len() {
+            let name_len = reader.read_u32()? as usize;
+            match std::str::from_utf8(reader.read_bytes(name_len)?) {
+                Ok(name) => host_fns.push(name.to_string()),
+                Err(_) => return LoadError::new("host function name is not valid UTF-8"),
+            }
+        }
+
+        if host_fns.len() > u16::MAX as usize + 1 {
+            return LoadError::new("too many host functions");
+        }
+
+        validate_code(code, &syscalls, host_fns.len(), entry)?;
+
+        Ok(Program {
+            code: MemBlock::from_bytes(code.to_vec()),
+            data: MemBlock::from_bytes(data.unwrap_or(&[]).to_vec()),
+            syscalls,
+            entry,
+            symbols,
+            line_table,
+            host_fns,
+        })
+    }
+
+    /// Check if a file starts with the image magic bytes
+    pub fn is_image_file(file_name: &str) -> bool
+    {
+        use std::io::Read;
+
+        let mut magic = [0; 4];
+        match std::fs::File::open(file_name) {
+            Ok(mut file) => file.read_exact(&mut magic).is_ok() && magic == IMAGE_MAGIC,
+            Err(_) => false
+        }
+    }
+
+    /// Load a program from an image file
+    pub fn load_file(file_name: &str) -> Result<Program, LoadError>
+    {
+        match std::fs::read(file_name) {
+            Ok(bytes) => Program::deserialize(&bytes),
+            Err(_) => LoadError::new(&format!("could not open image file \"{}\"", file_name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+
+    fn assemble(src: &str) -> Program
+    {
+        dbg!(src);
+        Assembler::new().assemble_str(src).unwrap()
+    }
+
+    fn load_fails(bytes: &[u8])
+    {
+        assert!(Program::deserialize(bytes).is_err());
+    }
+
+    /// Serialize a program, load it back and check that nothing changed
+    fn round_trip(program: &Program)
+    {
+        let bytes = program.serialize();
+        let loaded = Program::deserialize(&bytes).unwrap();
+
+        assert_eq!(loaded.code.as_bytes(), program.code.as_bytes());
+        assert_eq!(loaded.data.as_bytes(), program.data.as_bytes());
+        assert_eq!(loaded.syscalls, program.syscalls);
+        assert_eq!(loaded.entry, program.entry);
+        assert_eq!(loaded.symbols, program.symbols);
+        assert_eq!(loaded.line_table, program.line_table);
+        assert_eq!(loaded.host_fns, program.host_fns);
+        assert_eq!(loaded.serialize(), bytes);
+    }
+
+    #[test]
+    fn test_round_trip()
+    {
+        round_trip(&assemble(""));
+        round_trip(&assemble("push_i8 1; exit;"));
+        round_trip(&assemble("push_u64 0xFFFF_FFFF_FFFF; push_u32 7; push_i8 -3; exit;"));
+        round_trip(&assemble(".data; LABEL: .zero 256; .code; push LABEL; push 255; push 0; syscall memset; push 0; exit;"));
+        round_trip(&assemble("push 10; call DEC, 1; exit; DEC: get_arg 0; dup; jz ZERO; push 1; sub_u64; call DEC, 1; ret; ZERO: ret;"));
+
+        // Code labels are kept as symbols
+        let program = assemble("call FN, 0; exit; FN: push_i8 33; ret;");
+        assert_eq!(program.symbol_at(7), Some("FN"));
+        round_trip(&program);
+
+        // Source positions are kept in the optional lines section
+        let program = assemble(".loc \"foo.c\", 1, 1; push_i8 1; .loc \"foo.c\", 2, 5; exit;");
+        assert!(!program.line_table.is_empty());
+        round_trip(&program);
+
+        // Host function names are kept in the optional host functions section
+        let program = assemble("push_i8 1; call_host foo, 1; call_host bar, 0; call_host foo, 1; exit;");
+        assert_eq!(program.host_fns, vec!["foo", "bar"]);
+        round_trip(&program);
+
+        for file_name in ["empty", "factorial", "fib", "fizzbuzz", "loop", "memcpy", "gradient", "circle"] {
+            let file_name = format!("examples/{}.asm", file_name);
+            dbg!(&file_name);
+            round_trip(&Assembler::new().assemble_file(&file_name).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip()
+    {
+        let src = "push 10; call DEC, 1; exit; DEC: get_arg 0; dup; jz ZERO; push 1; sub_u64; call DEC, 1; ret; ZERO: ret;";
+        let program = assemble(src);
+
+        let path = std::env::temp_dir().join(format!("uvm_image_{}.uvm", std::process::id()));
+        let file_name = path.to_str().unwrap();
+        std::fs::write(&path, program.serialize()).unwrap();
+        assert!(Program::is_image_file(file_name));
+
+        let loaded = Program::load_file(file_name).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.code.as_bytes(), program.code.as_bytes());
+        assert_eq!(loaded.symbols, program.symbols);
+
+        // Assembly source files aren't mistaken for images
+        assert!(!Program::is_image_file("examples/fib.asm"));
+        assert!(Program::load_file("examples/missing.uvm").is_err());
+    }
+
+    #[test]
+    fn test_header()
+    {
+        let bytes = assemble("push_i8 1; exit;").serialize();
+
+        load_fails(&[]);
+        load_fails(b"UVM");
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        load_fails(&bad_magic);
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = IMAGE_VERSION as u8 + 1;
+        load_fails(&bad_version);
+
+        // Entry point past the end of the code
+        let mut bad_entry = bytes.clone();
+        bad_entry[8] = 100;
+        load_fails(&bad_entry);
+
+        // Entry point inside an instruction
+        let mut bad_entry = bytes.clone();
+        bad_entry[8] = 1;
+        load_fails(&bad_entry);
+    }
+
+    #[test]
+    fn test_sections()
+    {
+        let bytes = assemble("push_i8 1; exit;").serialize();
+
+        // Section table entries start after the header
+        let entry_pos = |idx: usize| HEADER_SIZE + idx * SECTION_ENTRY_SIZE;
+
+        // Code section extending past the end of the image
+        let mut bad_len = bytes.clone();
+        bad_len[entry_pos(0) + 12] = 0xFF;
+        load_fails(&bad_len);
+
+        // Offset that overflows when the length is added
+        let mut bad_offset = bytes.clone();
+        bad_offset[entry_pos(0) + 4..entry_pos(0) + 12].copy_from_slice(&u64::MAX.to_le_bytes());
+        load_fails(&bad_offset);
+
+        // Unknown and duplicate section kinds
+        let mut bad_kind = bytes.clone();
+        bad_kind[entry_pos(1)] = 9;
+        load_fails(&bad_kind);
+        let mut dup_kind = bytes.clone();
+        dup_kind[entry_pos(1)] = SECTION_CODE as u8;
+        load_fails(&dup_kind);
+
+        // Missing code section
+        let mut no_code = bytes.clone();
+        no_code[16] = 0;
+        load_fails(&no_code);
+
+        // More sections than the table holds
+        let mut many_sections = bytes.clone();
+        many_sections[16] = 200;
+        load_fails(&many_sections);
+    }
+
+    #[test]
+    fn test_invalid_code()
+    {
+        fn with_code(code: &[u8], syscalls: &[u16]) -> Vec<u8>
+        {
+            Program {
+                code: MemBlock::from_bytes(code.to_vec()),
+                data: MemBlock::new(),
+                syscalls: syscalls.iter().copied().collect(),
+                entry: 0,
+                symbols: Vec::new(),
+                line_table: LineTable::default(),
+                host_fns: vec!["f".to_string()],
+            }.serialize()
+        }
+
+        let exit = Op::exit as u8;
+        assert!(Program::deserialize(&with_code(&[Op::push_1 as u8, exit], &[])).is_ok());
+
+        // Invalid opcodes
+        load_fails(&with_code(&[exit + 1], &[]));
+        load_fails(&with_code(&[Op::OP_EXT as u8], &[]));
+
+        // Truncated operands
+        load_fails(&with_code(&[Op::push_u64 as u8, 1, 2, 3], &[]));
+        load_fails(&with_code(&[Op::get_arg as u8], &[]));
+        load_fails(&with_code(&[Op::call as u8, 0, 0, 0, 0], &[]));
+
+        // Branches must land on an instruction inside the code
+        assert!(Program::deserialize(&with_code(&[Op::jmp as u8, 0xFB, 0xFF, 0xFF, 0xFF], &[])).is_ok());
+        load_fails(&with_code(&[Op::jmp as u8, 0x10, 0, 0, 0, exit], &[]));
+        load_fails(&with_code(&[Op::jz as u8, 0xFA, 0xFF, 0xFF, 0xFF], &[]));
+        load_fails(&with_code(&[Op::jnz as u8, 0xFC, 0xFF, 0xFF, 0xFF], &[]));
+        load_fails(&with_code(&[Op::call as u8, 0, 0, 0, 0, 0], &[]));
+
+        // Syscalls must exist and be declared
+        let memset = [Op::syscall as u8, 4, 0, exit];
+        assert!(Program::deserialize(&with_code(&memset, &[4])).is_ok());
+        load_fails(&with_code(&memset, &[]));
+        load_fails(&with_code(&[Op::syscall as u8, 0xFF, 0x7F, exit], &[0x7FFF]));
+
+        // Host functions must be declared
+        assert!(Program::deserialize(&with_code(&[Op::call_host as u8, 0, 0, 0, exit], &[])).is_ok());
+        load_fails(&with_code(&[Op::call_host as u8, 1, 0, 0, exit], &[]));
+        load_fails(&with_code(&[Op::call_host as u8, 0, 0], &[]));
+    }
+
+    #[test]
+    fn test_corrupted()
+    {
+        let fizzbuzz = Assembler::new().assemble_file("examples/fizzbuzz.asm").unwrap().serialize();
+        let with_lines = assemble(".loc \"foo.c\", 1, 1; push_i8 1; .loc \"foo.c\", 2, 5; exit;").serialize();
+
+        for bytes in [fizzbuzz, with_lines] {
+            // Every truncation is rejected
+            for len in 0..bytes.len() {
+                load_fails(&bytes[..len]);
+            }
+
+            // Corrupting any byte either fails to load or produces a
+            // program which passed validation, but never panics
+            for idx in 0..bytes.len() {
+                for val in [0x00, 0x01, 0x7F, 0x80, 0xFF] {
+                    let mut corrupted = bytes.clone();
+                    corrupted[idx] ^= val;
+                    let _ = Program::deserialize(&corrupted);
+                }
+            }
+        }
+    }
+}
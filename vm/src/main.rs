@@ -6,16 +6,30 @@
 mod vm;
 mod sys;
 mod asm;
+mod image;
+mod verify;
+mod debug;
+mod debugger;
+mod profiler;
+mod disassembler;
 
 extern crate sdl2;
 use std::env;
+use std::str::FromStr;
 use std::thread::sleep;
 use std::time::Duration;
 use std::process::exit;
-use std::sync::{Arc, Mutex};
-use crate::vm::{VM, Value, MemBlock, ExitReason};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::vm::{VM, Value, MemBlock, ExitReason, Trap, DEFAULT_MEM_SIZE, NULL_GUARD_SIZE, DEFAULT_MAX_CALL_DEPTH};
 use crate::asm::{Assembler};
+use crate::image::{Program};
+use crate::verify::{verify};
+use crate::disassembler::{disassemble};
 use crate::sys::{SysState};
+use crate::sys::fs::{FsAccess};
+use crate::sys::time::{Clock, SystemClock, register_clock_fns};
 
 /// Command-line options
 #[derive(Debug, Clone)]
@@ -24,17 +38,72 @@ struct Options
     // Only parse/validate the input, but don't run it
     parse_only: bool,
 
+    // Write the assembled program as an image instead of running it
+    out_file: Option<String>,
+
+    // Print the disassembled code instead of running it
+    disassemble: bool,
+
+    // Maximum heap size in bytes
+    mem_size: usize,
+
+    // Trap on accesses to the word ncc reserves for null pointers
+    null_check: bool,
+
+    // Maximum number of stack frames
+    max_call_depth: usize,
+
+    // Trap on signed division overflow
+    overflow_check: bool,
+
+    // Files which the program can open
+    fs_access: FsAccess,
+
+    // Allow network access
+    allow_net: bool,
+
+    // Address the program listens for connections on
+    net_listen_addr: IpAddr,
+
     rest: Vec<String>,
 }
 
+/// Get the value following an option
+fn option_value(args: &[String], idx: usize, option: &str) -> Result<String, String>
+{
+    match args.get(idx) {
+        Some(value) => Ok(value.clone()),
+        None => Err(format!("missing value for option {}", option)),
+    }
+}
+
+/// Parse the value following an option
+fn parse_option_value<T: FromStr>(args: &[String], idx: usize, option: &str) -> Result<T, String>
+{
+    let value = option_value(args, idx, option)?;
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(format!("invalid value \"{}\" for option {}", value, option)),
+    }
+}
+
 // TODO: parse permissions
 // --allow <permissions>
 // --deny <permissions>
 // --allow-all
-fn parse_args(args: Vec<String>) -> Options
+fn parse_args(args: Vec<String>) -> Result<Options, String>
 {
     let mut opts = Options {
         parse_only: false,
+        out_file: None,
+        disassemble: false,
+        mem_size: DEFAULT_MEM_SIZE,
+        null_check: false,
+        max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        overflow_check: false,
+        fs_access: FsAccess::Full,
+        allow_net: false,
+        net_listen_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
         rest: Vec::default(),
     };
 
@@ -60,18 +129,131 @@ fn parse_args(args: Vec<String>) -> Options
                 opts.parse_only = true;
             }
 
-            _ => panic!("unknown option {}", arg)
+            "-o" => {
+                opts.out_file = Some(option_value(&args, idx, arg)?);
+                idx += 1;
+            }
+
+            "--disassemble" => {
+                opts.disassemble = true;
+            }
+
+            "--null-check" => {
+                opts.null_check = true;
+            }
+
+            "--overflow-check" => {
+                opts.overflow_check = true;
+            }
+
+            "--mem-size" => {
+                opts.mem_size = parse_option_value(&args, idx, arg)?;
+                idx += 1;
+            }
+
+            "--max-call-depth" => {
+                opts.max_call_depth = parse_option_value(&args, idx, arg)?;
+                idx += 1;
+            }
+
+            "--fs-root" => {
+                opts.fs_access = FsAccess::Sandbox(option_value(&args, idx, arg)?.into());
+                idx += 1;
+            }
+
+            "--no-fs" => {
+                opts.fs_access = FsAccess::Disabled;
+            }
+
+            "--allow-net" => {
+                opts.allow_net = true;
+            }
+
+            "--net-listen-addr" => {
+                opts.net_listen_addr = parse_option_value(&args, idx, arg)?;
+                idx += 1;
+            }
+
+            _ => return Err(format!("unknown option {}", arg))
+        }
+    }
+
+    Ok(opts)
+}
+
+/// Interrupt flag of the clock given to the program
+static CLOCK_INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+const SIGINT: i32 = 2;
+
+extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+/// Handle Ctrl-C by cutting short the sleep of the program,
+/// which then exits
+extern "C" fn on_sigint(_signum: i32)
+{
+    if let Some(interrupt) = CLOCK_INTERRUPT.get() {
+        interrupt.store(true, Ordering::Relaxed);
+    }
+}
+
+/// System clock whose sleeps are cut short by Ctrl-C. The handler
+/// is only installed while sleeping, so that Ctrl-C still stops a
+/// program which is busy right away.
+struct SigintClock
+{
+    clock: SystemClock,
+}
+
+impl Clock for SigintClock
+{
+    fn now_ms(&mut self) -> u64
+    {
+        self.clock.now_ms()
+    }
+
+    fn sleep_ms(&mut self, ms: u64) -> bool
+    {
+        let prev_handler = unsafe {
+            signal(SIGINT, on_sigint as extern "C" fn(i32) as usize)
+        };
+
+        let completed = self.clock.sleep_ms(ms);
+
+        unsafe {
+            signal(SIGINT, prev_handler);
         }
+
+        completed
     }
+}
 
-    opts
+/// Exit status of a program stopped by a trap, the same
+/// as for a program which panics
+const TRAP_EXIT_CODE: i32 = 101;
+
+/// Get the exit reason of a call, or report the trap which
+/// stopped the program along with its call stack, and exit
+fn check_trap(vm: &VM, result: Result<ExitReason, Trap>) -> ExitReason
+{
+    match result {
+        Ok(exit_reason) => exit_reason,
+        Err(trap) => {
+            eprintln!("Error: {}", trap);
+            eprint!("{}", vm.format_call_stack(&trap.call_stack));
+            exit(TRAP_EXIT_CODE);
+        }
+    }
 }
 
-fn run_program(mutex: &mut Arc<Mutex<VM>>) -> Value
+fn run_program(mutex: &mut Arc<Mutex<VM>>, entry: usize, args: &[Value]) -> Value
 {
     let mut vm = mutex.lock().unwrap();
 
-    match vm.call(0, &[])
+    let result = vm.call(entry as u64, args);
+    match check_trap(&vm, result)
     {
         ExitReason::Exit(val) => {
             return val;
@@ -88,14 +270,21 @@ fn run_program(mutex: &mut Arc<Mutex<VM>>) -> Value
     {
         let mut vm = mutex.lock().unwrap();
 
-        if let ExitReason::Exit(val) = sys::window::process_events(&mut vm) {
+        let result = sys::window::process_events(&mut vm);
+        if let ExitReason::Exit(val) = check_trap(&vm, result) {
+            return val;
+        }
+
+        // Generate the samples the audio device will play next
+        let result = sys::audio::fill_audio(&mut vm);
+        if let ExitReason::Exit(val) = check_trap(&vm, result) {
             return val;
         }
 
         let next_cb_time = sys::time::time_until_next_cb(&mut vm);
 
-        // Unlock the VM mutex before going to sleep, so that other threads,
-        // such as the audio thread, may use the VM
+        // Unlock the VM mutex before going to sleep, so that other threads
+        // may use the VM
         drop(vm);
 
         // Sleep until the next callback
@@ -113,7 +302,8 @@ fn run_program(mutex: &mut Arc<Mutex<VM>>) -> Value
         // For each callback to run
         for pc in sys::time::get_cbs_to_run(&mut vm)
         {
-            match vm.call(pc, &[])
+            let result = vm.call(pc, &[]);
+            match check_trap(&vm, result)
             {
                 ExitReason::Exit(val) => {
                     return val;
@@ -127,32 +317,130 @@ fn run_program(mutex: &mut Arc<Mutex<VM>>) -> Value
 
 fn main()
 {
-    let opts = parse_args(env::args().collect());
+    let opts = match parse_args(env::args().collect()) {
+        Ok(opts) => opts,
+        Err(error) => {
+            println!("Error: {}", error);
+            exit(-1);
+        }
+    };
     //println!("{:?}", opts);
 
-    if opts.rest.len() != 1 {
-        panic!("must specify exactly one input file to run");
+    if opts.rest.is_empty() {
+        panic!("must specify an input file to run");
     }
 
     let file_name = &opts.rest[0];
 
-    // Parse/compile the program
-    let asm = Assembler::new();
-    let result = asm.parse_file(file_name);
+    // Load a program image, or parse/compile the program
+    let program = if Program::is_image_file(file_name) {
+        Program::load_file(file_name).map_err(|error| error.to_string())
+    } else {
+        let asm = Assembler::new();
+        asm.assemble_file(file_name).map_err(|error| error.to_string())
+    };
 
-    if let Err(error) = &result {
+    if let Err(error) = &program {
         println!("Error: {}", error);
         exit(-1);
     }
 
+    let program = program.unwrap();
+
+    // Catch stack errors in the generated code before running it
+    if cfg!(debug_assertions) {
+        if let Err(error) = verify(&program) {
+            println!("Error: {}", error);
+            exit(-1);
+        }
+    }
+
+    if opts.disassemble {
+        disassemble(&program, &mut std::io::stdout()).unwrap();
+        exit(0);
+    }
+
+    // Save the program image
+    if let Some(out_file) = &opts.out_file {
+        if std::fs::write(out_file, program.serialize()).is_err() {
+            println!("Error: could not write image file \"{}\"", out_file);
+            exit(-1);
+        }
+        exit(0);
+    }
+
     // Run the program
     if opts.parse_only {
         exit(0);
     }
 
-    let vm = result.unwrap();
+    let entry = program.entry;
+    let mut vm = match program.into_vm_with_mem_size(opts.mem_size) {
+        Ok(vm) => vm,
+        Err(error) => {
+            println!("Error: {}", error);
+            exit(-1);
+        }
+    };
+
+    // The standalone VM only provides the clock host functions
+    let clock = SystemClock::new();
+    CLOCK_INTERRUPT.set(clock.interrupt_handle()).unwrap();
+    register_clock_fns(&mut vm, SigintClock { clock }).unwrap();
+    if let Some(name) = vm.missing_host_fns().first() {
+        println!("Error: host function \"{}\" is not registered", name);
+        exit(-1);
+    }
+
+    if opts.null_check {
+        vm.set_null_guard(NULL_GUARD_SIZE);
+    }
+    vm.set_max_call_depth(opts.max_call_depth);
+    vm.set_overflow_check(opts.overflow_check);
+    vm.sys_state.fs_state.set_access(opts.fs_access);
+    vm.sys_state.net_state.set_enabled(opts.allow_net);
+    vm.sys_state.net_state.set_listen_addr(opts.net_listen_addr);
+
+    // The program gets its own file name as argv[0], followed by
+    // the arguments after it on the command line
+    let args = match vm.write_args(&opts.rest) {
+        Some(args) => args,
+        None => {
+            println!("Error: program arguments don't fit in memory");
+            exit(-1);
+        }
+    };
+
     let mut mutex = SysState::get_mutex(vm);
-    let ret_val = run_program(&mut mutex);
+    let ret_val = run_program(&mut mutex, entry, &args);
 
     exit(ret_val.as_i32());
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String>
+    {
+        parse_args(args.split_whitespace().map(String::from).collect())
+    }
+
+    #[test]
+    fn test_parse_args()
+    {
+        let opts = parse("uvm --mem-size 4096 --max-call-depth 10 --net-listen-addr 0.0.0.0 prog.asm arg").unwrap();
+        assert_eq!(opts.mem_size, 4096);
+        assert_eq!(opts.max_call_depth, 10);
+        assert_eq!(opts.net_listen_addr, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(opts.rest, vec!["prog.asm", "arg"]);
+
+        // Bad options are reported instead of panicking
+        assert_eq!(parse("uvm --mem-size abc prog.asm").unwrap_err(), "invalid value \"abc\" for option --mem-size");
+        assert_eq!(parse("uvm --max-call-depth -1 prog.asm").unwrap_err(), "invalid value \"-1\" for option --max-call-depth");
+        assert_eq!(parse("uvm --net-listen-addr foo prog.asm").unwrap_err(), "invalid value \"foo\" for option --net-listen-addr");
+        assert_eq!(parse("uvm --mem-size").unwrap_err(), "missing value for option --mem-size");
+        assert_eq!(parse("uvm --foo prog.asm").unwrap_err(), "unknown option --foo");
+    }
+}
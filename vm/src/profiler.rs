@@ -0,0 +1,104 @@
+// Counts of the instructions executed by a program
+//
+// The profiler is a debugger which counts each instruction it is
+// notified of. Run a function with VM::call_with_debugger to profile
+// it, and reset the counts in between calls to profile them separately.
+
+use std::collections::HashMap;
+use crate::vm::{VM, Op};
+use crate::debugger::Debugger;
+
+#[derive(Debug, Default)]
+pub struct Profiler
+{
+    counts: HashMap<Op, u64>,
+}
+
+impl Profiler
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Number of times each opcode was executed
+    pub fn counts(&self) -> &HashMap<Op, u64>
+    {
+        &self.counts
+    }
+
+    /// Number of times one opcode was executed
+    pub fn count(&self, op: Op) -> u64
+    {
+        self.counts.get(&op).copied().unwrap_or(0)
+    }
+
+    /// Total number of instructions executed
+    pub fn total(&self) -> u64
+    {
+        self.counts.values().sum()
+    }
+
+    /// Opcodes sorted from the most to the least executed
+    pub fn hottest(&self) -> Vec<(Op, u64)>
+    {
+        let mut counts: Vec<(Op, u64)> = self.counts.iter().map(|(op, count)| (*op, *count)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| (a.0 as u8).cmp(&(b.0 as u8))));
+        counts
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.counts.clear();
+    }
+}
+
+impl Debugger for Profiler
+{
+    fn on_step(&mut self, vm: &VM, pc: usize, op: Op)
+    {
+        *self.counts.entry(op).or_insert(0) += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::vm::{RunResult, ExitReason, Value};
+
+    #[test]
+    fn test_while_loop()
+    {
+        // Count to 100 with the same code ncc generates for a while loop
+        let src = concat!(
+            "push_0;",
+            "LOOP: get_local 0; push 100; lt_i64; jz BREAK;",
+            "get_local 0; push_1; add_u64; set_local 0; jmp LOOP;",
+            "BREAK: get_local 0; exit;",
+        );
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let mut profiler = Profiler::new();
+        let result = vm.call_with_debugger(0, &[], &mut profiler);
+        assert_eq!(result, RunResult::Done(ExitReason::Exit(Value::from(100u64))));
+
+        // The test runs once more than the body
+        assert_eq!(profiler.count(Op::jmp), 100);
+        assert_eq!(profiler.count(Op::jz), 101);
+        assert_eq!(profiler.count(Op::lt_i64), 101);
+        assert_eq!(profiler.count(Op::add_u64), 100);
+        assert_eq!(profiler.count(Op::exit), 1);
+        assert_eq!(profiler.count(Op::div_u64), 0);
+        assert_eq!(profiler.total(), 1 + 101 * 4 + 100 * 5 + 2);
+        assert_eq!(profiler.hottest()[0], (Op::get_local, 202));
+
+        // Counts accumulate until reset
+        vm.call_with_debugger(0, &[], &mut profiler);
+        assert_eq!(profiler.count(Op::jmp), 200);
+        profiler.reset();
+        assert!(profiler.counts().is_empty());
+        vm.call_with_debugger(0, &[], &mut profiler);
+        assert_eq!(profiler.count(Op::jmp), 100);
+    }
+}
@@ -1,21 +1,71 @@
 use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioDevice};
+use std::collections::VecDeque;
 use std::sync::{Arc, Weak, Mutex};
-use crate::vm::{Value, VM, ExitReason};
+use crate::vm::{Value, VM, ExitReason, Trap};
 use crate::sys::{get_sdl_context};
 use crate::sys::constants::*;
 
-#[derive(Clone)]
-struct AudioCB
+/// Number of samples per channel requested from the audio
+/// callback of the program at a time
+pub const AUDIO_CB_SAMPLES: usize = 1024;
+
+/// Number of samples per channel generated ahead of the audio
+/// device. More samples cause more latency, fewer cause gaps
+/// in the sound if the VM is late to generate the next ones.
+const MAX_BUFFERED_SAMPLES: usize = 2 * AUDIO_CB_SAMPLES;
+
+/// Queue of interleaved samples, filled on the VM thread and
+/// drained by the audio device. Clones share the same queue.
+#[derive(Clone, Default)]
+pub struct SampleQueue
+{
+    samples: Arc<Mutex<VecDeque<i16>>>,
+}
+
+impl SampleQueue
 {
-    // Weak reference to the VM, guarded by a mutex
-    // We use this to call the VM to generate audio
-    vm: Weak<Mutex<VM>>,
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.samples.lock().unwrap().len()
+    }
 
-    // Callback function pointer
-    cb: u64,
+    fn push(&self, samples: &[i16])
+    {
+        self.samples.lock().unwrap().extend(samples);
+    }
+
+    /// Fill a buffer with the oldest samples, padding it with
+    /// silence if there aren't enough. Returns the number of
+    /// samples taken from the queue.
+    pub fn pop(&self, out: &mut [i16]) -> usize
+    {
+        let mut samples = self.samples.lock().unwrap();
+        let num_samples = out.len().min(samples.len());
+
+        for (dst, src) in out.iter_mut().zip(samples.drain(..num_samples)) {
+            *dst = src;
+        }
+        out[num_samples..].fill(0);
+
+        num_samples
+    }
+}
+
+/// Plays the samples generated by programs
+pub trait AudioBackend: Send
+{
+    /// Start playing the samples pushed to the queue
+    fn open(&mut self, sample_rate: u32, num_channels: u16, queue: SampleQueue);
+}
 
-    // Number of audio output channels
-    num_channels: usize,
+struct AudioCB
+{
+    queue: SampleQueue,
 }
 
 impl AudioCallback for AudioCB
@@ -25,22 +75,7 @@ impl AudioCallback for AudioCB
 
     fn callback(&mut self, out: &mut [i16])
     {
-        out.fill(0);
-
-        let output_len = out.len();
-        assert!(output_len % self.num_channels == 0);
-        let samples_per_chan = output_len / self.num_channels;
-
-        let arc = self.vm.upgrade().unwrap();
-        let mut vm = arc.lock().unwrap();
-
-        match vm.call(self.cb, &[Value::from(self.num_channels), Value::from(samples_per_chan)]) {
-            ExitReason::Return(ptr) => {
-                let mem_slice: &[i16] = vm.get_heap_slice(ptr.as_usize(), output_len);
-                out.copy_from_slice(&mem_slice);
-            }
-            _ => panic!()
-        }
+        self.queue.pop(out);
     }
 }
 
@@ -50,9 +85,132 @@ impl AudioCallback for AudioCB
 /// the Send trait, and so can't be referenced from another thread
 static mut DEVICE: Option<AudioDevice<AudioCB>> = None;
 
-// NOTE: this can only be called from the main thread since it uses SDL
-// However, it creates a new thread to generate audio sample, this thread
-// could be given a reference to another VM instance
+/// Backend playing sound with SDL
+pub struct SdlAudio;
+
+impl AudioBackend for SdlAudio
+{
+    // NOTE: this can only be called from the main thread since it uses SDL
+    fn open(&mut self, sample_rate: u32, num_channels: u16, queue: SampleQueue)
+    {
+        let sdl = get_sdl_context();
+        let audio_subsystem = sdl.audio().unwrap();
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(num_channels as u8),
+            samples: Some(AUDIO_CB_SAMPLES as u16)
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            AudioCB { queue }
+        }).unwrap();
+
+        // Start playback
+        device.resume();
+
+        // Keep the audio device alive
+        unsafe {
+            DEVICE = Some(device);
+        }
+    }
+}
+
+#[derive(Default)]
+struct HeadlessAudioState
+{
+    sample_rate: u32,
+    num_channels: u16,
+    queue: Option<SampleQueue>,
+}
+
+/// Backend without an audio device, from which samples are taken
+/// with take_samples, e.g. to check the sound a program makes in
+/// tests. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct HeadlessAudio
+{
+    state: Arc<Mutex<HeadlessAudioState>>,
+}
+
+impl HeadlessAudio
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Sample rate and number of channels, once the output is opened
+    pub fn format(&self) -> Option<(u32, u16)>
+    {
+        let state = self.state.lock().unwrap();
+        state.queue.as_ref().map(|_| (state.sample_rate, state.num_channels))
+    }
+
+    /// Take up to num_samples of the samples buffered so far,
+    /// like an audio device would
+    pub fn take_samples(&self, num_samples: usize) -> Vec<i16>
+    {
+        let state = self.state.lock().unwrap();
+        let queue = match &state.queue {
+            Some(queue) => queue,
+            None => return Vec::default(),
+        };
+
+        let mut samples = vec![0; num_samples];
+        let num_taken = queue.pop(&mut samples);
+        samples.truncate(num_taken);
+        samples
+    }
+}
+
+impl AudioBackend for HeadlessAudio
+{
+    fn open(&mut self, sample_rate: u32, num_channels: u16, queue: SampleQueue)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.sample_rate = sample_rate;
+        state.num_channels = num_channels;
+        state.queue = Some(queue);
+    }
+}
+
+pub struct AudioState
+{
+    backend: Box<dyn AudioBackend>,
+
+    queue: SampleQueue,
+
+    // Callback function pointer and number of channels,
+    // once the output is opened
+    output: Option<(u64, usize)>,
+
+    // Set when the callback returns an invalid buffer,
+    // after which the output plays silence
+    stopped: bool,
+}
+
+impl AudioState
+{
+    pub fn new() -> Self
+    {
+        Self {
+            backend: Box::new(SdlAudio),
+            queue: SampleQueue::new(),
+            output: None,
+            stopped: false,
+        }
+    }
+
+    /// Play sound with a given backend instead of SDL.
+    /// This must be done before the program opens its output.
+    pub fn set_backend(&mut self, backend: impl AudioBackend + 'static)
+    {
+        assert!(self.output.is_none(), "the audio output is already open");
+        self.backend = Box::new(backend);
+    }
+}
+
 pub fn audio_open_output(vm: &mut VM, sample_rate: Value, num_channels: Value, format: Value, cb: Value) -> Value
 {
     let sample_rate = sample_rate.as_u32();
@@ -73,32 +231,53 @@ pub fn audio_open_output(vm: &mut VM, sample_rate: Value, num_channels: Value, f
         panic!("for now, only i16, 16-bit signed audio format supported");
     }
 
-    let sdl = get_sdl_context();
-    let audio_subsystem = sdl.audio().unwrap();
+    let audio_state = &mut vm.sys_state.audio_state;
+    if audio_state.output.is_some() {
+        panic!("for now, only one audio output supported");
+    }
+
+    audio_state.output = Some((cb, num_channels.into()));
+    audio_state.backend.open(sample_rate, num_channels, audio_state.queue.clone());
+
+    // TODO: return the device_id (u32)
+    Value::from(0)
+}
 
-    let desired_spec = AudioSpecDesired {
-        freq: Some(sample_rate as i32),
-        channels: Some(num_channels as u8), // mono
-        samples: Some(1024) // buffer size, 1024 samples
+/// Call the audio callback of the program until enough samples are
+/// buffered ahead of the audio device. This runs on the VM thread,
+/// so that the audio device never waits for the VM.
+pub fn fill_audio(vm: &mut VM) -> Result<ExitReason, Trap>
+{
+    let (cb, num_channels) = match vm.sys_state.audio_state.output {
+        Some(output) if !vm.sys_state.audio_state.stopped => output,
+        _ => return Ok(ExitReason::default()),
     };
 
-    let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        // initialize the audio callback
-        AudioCB {
-            vm: vm.sys_state.mutex.clone(),
-            cb: cb,
-            num_channels: num_channels.into()
-        }
-    }).unwrap();
+    let num_samples = AUDIO_CB_SAMPLES * num_channels;
+
+    while vm.sys_state.audio_state.queue.len() < MAX_BUFFERED_SAMPLES * num_channels {
+        let buf_ptr = match vm.call(cb, &[Value::from(num_channels), Value::from(AUDIO_CB_SAMPLES)])? {
+            ExitReason::Return(buf_ptr) => buf_ptr.as_usize(),
+            exit => return Ok(exit),
+        };
 
-    // Start playback
-    device.resume();
+        // The callback runs outside of the program, so a bad buffer
+        // can't panic the VM. Stop generating samples instead.
+        let bytes = match vm.heap_bytes(buf_ptr, 2 * num_samples) {
+            Some(bytes) => bytes,
+            None => {
+                eprintln!("audio callback returned invalid buffer address {}, stopping the audio output", buf_ptr);
+                vm.sys_state.audio_state.stopped = true;
+                return Ok(ExitReason::default());
+            }
+        };
 
-    // Keep the audio device alive
-    unsafe {
-        DEVICE = Some(device);
+        let samples: Vec<i16> = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        vm.sys_state.audio_state.queue.push(&samples);
     }
 
-    // TODO: return the device_id (u32)
-    Value::from(0)
+    Ok(ExitReason::default())
 }
@@ -4,7 +4,7 @@
 
 #![allow(unused)]
 
-pub const SYSCALL_TBL_LEN: usize = 21;
+pub const SYSCALL_TBL_LEN: usize = 37;
 
 pub const TIME_CURRENT_MS: u16 = 0;
 pub const WINDOW_CREATE: u16 = 1;
@@ -27,6 +27,22 @@ pub const VM_RESIZE_HEAP: u16 = 17;
 pub const AUDIO_OPEN_OUTPUT: u16 = 18;
 pub const WINDOW_ON_TEXTINPUT: u16 = 19;
 pub const PRINT_F32: u16 = 20;
+pub const READ_CHAR: u16 = 21;
+pub const READ_LINE: u16 = 22;
+pub const FILE_OPEN: u16 = 23;
+pub const FILE_READ: u16 = 24;
+pub const FILE_WRITE: u16 = 25;
+pub const FILE_CLOSE: u16 = 26;
+pub const RAND_SEED: u16 = 27;
+pub const RAND_U64: u16 = 28;
+pub const PRINT_U64_HEX: u16 = 29;
+pub const WINDOW_POLL_EVENT: u16 = 30;
+pub const TCP_CONNECT: u16 = 31;
+pub const TCP_LISTEN: u16 = 32;
+pub const TCP_ACCEPT: u16 = 33;
+pub const TCP_READ: u16 = 34;
+pub const TCP_WRITE: u16 = 35;
+pub const TCP_CLOSE: u16 = 36;
 
 pub struct SysCallDesc
 {
@@ -58,6 +74,22 @@ pub const SYSCALL_DESCS: [Option<SysCallDesc>; SYSCALL_TBL_LEN] = [
     Some(SysCallDesc { name: "audio_open_output", const_idx: 18, argc: 4, has_ret: true }),
     Some(SysCallDesc { name: "window_on_textinput", const_idx: 19, argc: 2, has_ret: false }),
     Some(SysCallDesc { name: "print_f32", const_idx: 20, argc: 1, has_ret: false }),
+    Some(SysCallDesc { name: "read_char", const_idx: 21, argc: 0, has_ret: true }),
+    Some(SysCallDesc { name: "read_line", const_idx: 22, argc: 2, has_ret: true }),
+    Some(SysCallDesc { name: "file_open", const_idx: 23, argc: 2, has_ret: true }),
+    Some(SysCallDesc { name: "file_read", const_idx: 24, argc: 3, has_ret: true }),
+    Some(SysCallDesc { name: "file_write", const_idx: 25, argc: 3, has_ret: true }),
+    Some(SysCallDesc { name: "file_close", const_idx: 26, argc: 1, has_ret: true }),
+    Some(SysCallDesc { name: "rand_seed", const_idx: 27, argc: 1, has_ret: false }),
+    Some(SysCallDesc { name: "rand_u64", const_idx: 28, argc: 0, has_ret: true }),
+    Some(SysCallDesc { name: "print_u64_hex", const_idx: 29, argc: 1, has_ret: false }),
+    Some(SysCallDesc { name: "window_poll_event", const_idx: 30, argc: 2, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_connect", const_idx: 31, argc: 2, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_listen", const_idx: 32, argc: 1, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_accept", const_idx: 33, argc: 1, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_read", const_idx: 34, argc: 3, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_write", const_idx: 35, argc: 3, has_ret: true }),
+    Some(SysCallDesc { name: "tcp_close", const_idx: 36, argc: 1, has_ret: true }),
 ];
 
 pub const KEY_BACKSPACE: u16 = 8;
@@ -113,4 +145,13 @@ pub const KEY_RIGHT: u16 = 16002;
 pub const KEY_UP: u16 = 16003;
 pub const KEY_DOWN: u16 = 16004;
 pub const KEY_SHIFT: u16 = 16005;
+pub const WINDOW_EVENT_CLOSE: u32 = 1;
+pub const WINDOW_EVENT_KEYDOWN: u32 = 2;
+pub const WINDOW_EVENT_KEYUP: u32 = 3;
+pub const WINDOW_EVENT_MOUSEMOVE: u32 = 4;
+pub const WINDOW_EVENT_MOUSEDOWN: u32 = 5;
+pub const WINDOW_EVENT_MOUSEUP: u32 = 6;
 pub const AUDIO_FORMAT_I16: u16 = 0;
+pub const FILE_MODE_READ: u64 = 0;
+pub const FILE_MODE_WRITE: u64 = 1;
+pub const FILE_MODE_APPEND: u64 = 2;
@@ -0,0 +1,184 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf, Component};
+use crate::vm::{VM, Value};
+use crate::sys::read_heap_str;
+use crate::sys::constants::*;
+
+/// Which files programs are allowed to open
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsAccess
+{
+    /// Files can't be opened
+    Disabled,
+
+    /// Any path can be opened
+    Full,
+
+    /// Paths are relative to a root directory, and can't leave it
+    Sandbox(PathBuf),
+}
+
+pub struct FsState
+{
+    access: FsAccess,
+
+    // Open files, indexed by file descriptor
+    files: Vec<Option<File>>,
+}
+
+impl FsState
+{
+    pub fn new() -> Self
+    {
+        Self {
+            access: FsAccess::Full,
+            files: Vec::default(),
+        }
+    }
+
+    /// Restrict the files which programs can open. Files which are
+    /// already open stay open.
+    pub fn set_access(&mut self, access: FsAccess)
+    {
+        self.access = access;
+    }
+
+    /// Resolve a path given by the program, or None if access is denied
+    fn resolve_path(&self, path: &str) -> Option<PathBuf>
+    {
+        let root = match &self.access {
+            FsAccess::Disabled => return None,
+            FsAccess::Full => return Some(PathBuf::from(path)),
+            FsAccess::Sandbox(root) => root,
+        };
+
+        // Only plain relative paths can be used in the sandbox
+        let path = Path::new(path);
+        if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return None;
+        }
+
+        // Symbolic links inside the sandbox can't lead out of it
+        let full_path = root.join(path);
+        let real_path = match full_path.canonicalize() {
+            Ok(real_path) => real_path,
+
+            // Opening a dangling link would create its target,
+            // so only paths which don't exist at all are allowed
+            Err(_) if full_path.symlink_metadata().is_ok() => return None,
+            Err(_) => full_path.parent()?.canonicalize().ok()?,
+        };
+        if !real_path.starts_with(root.canonicalize().ok()?) {
+            return None;
+        }
+
+        Some(full_path)
+    }
+
+    fn get_file(&mut self, fd: Value) -> Option<&mut File>
+    {
+        let fd = usize::try_from(fd.as_i64()).ok()?;
+        self.files.get_mut(fd)?.as_mut()
+    }
+}
+
+pub fn file_open(vm: &mut VM, path_ptr: Value, mode: Value) -> Value
+{
+    let path = match read_heap_str(vm, path_ptr.as_usize()) {
+        Some(path) => path,
+        None => return Value::from(-1i64),
+    };
+
+    let fs_state = &mut vm.sys_state.fs_state;
+    let path = match fs_state.resolve_path(&path) {
+        Some(path) => path,
+        None => return Value::from(-1i64),
+    };
+
+    let mut options = OpenOptions::new();
+    match mode.as_u64() {
+        FILE_MODE_READ => options.read(true),
+        FILE_MODE_WRITE => options.write(true).create(true).truncate(true),
+        FILE_MODE_APPEND => options.append(true).create(true),
+        _ => return Value::from(-1i64),
+    };
+
+    let file = match options.open(path) {
+        Ok(file) => file,
+        Err(_) => return Value::from(-1i64),
+    };
+
+    // Reuse the lowest free file descriptor
+    let fd = match fs_state.files.iter().position(|f| f.is_none()) {
+        Some(fd) => fd,
+        None => {
+            fs_state.files.push(None);
+            fs_state.files.len() - 1
+        }
+    };
+    fs_state.files[fd] = Some(file);
+
+    Value::from(fd as i64)
+}
+
+pub fn file_read(vm: &mut VM, fd: Value, buf_ptr: Value, num_bytes: Value) -> Value
+{
+    if vm.heap_bytes(buf_ptr.as_usize(), num_bytes.as_usize()).is_none() {
+        return Value::from(-1i64);
+    }
+
+    // Copy through a temporary buffer, since the file and
+    // the heap can't be borrowed at the same time
+    let mut data = vec![0; num_bytes.as_usize()];
+
+    let file = match vm.sys_state.fs_state.get_file(fd) {
+        Some(file) => file,
+        None => return Value::from(-1i64),
+    };
+
+    // Keep reading until the buffer is full or the file ends
+    let mut num_read = 0;
+    while num_read < data.len() {
+        match file.read(&mut data[num_read..]) {
+            Ok(0) => break,
+            Ok(n) => num_read += n,
+            Err(_) => return Value::from(-1i64),
+        }
+    }
+
+    let buf = vm.heap_bytes(buf_ptr.as_usize(), num_read).unwrap();
+    buf.copy_from_slice(&data[..num_read]);
+
+    Value::from(num_read as i64)
+}
+
+pub fn file_write(vm: &mut VM, fd: Value, buf_ptr: Value, num_bytes: Value) -> Value
+{
+    let data = match vm.heap_bytes(buf_ptr.as_usize(), num_bytes.as_usize()) {
+        Some(bytes) => bytes.to_vec(),
+        None => return Value::from(-1i64),
+    };
+
+    let file = match vm.sys_state.fs_state.get_file(fd) {
+        Some(file) => file,
+        None => return Value::from(-1i64),
+    };
+
+    match file.write_all(&data) {
+        Ok(_) => Value::from(data.len() as i64),
+        Err(_) => Value::from(-1i64),
+    }
+}
+
+pub fn file_close(vm: &mut VM, fd: Value) -> Value
+{
+    let fs_state = &mut vm.sys_state.fs_state;
+    if fs_state.get_file(fd).is_none() {
+        return Value::from(-1i64);
+    }
+
+    // Dropping the file closes it
+    fs_state.files[fd.as_usize()] = None;
+    Value::from(0i64)
+}
@@ -1,17 +1,23 @@
 pub mod window;
 pub mod audio;
 pub mod time;
+pub mod fs;
+pub mod net;
+pub mod rand;
 pub mod constants;
 
 extern crate sdl2;
 use std::collections::HashMap;
 use std::io::Write;
-use std::io::{stdout, stdin};
+use std::io::{stdout, stdin, BufRead, BufReader, Read};
 use std::sync::{Arc, Weak, Mutex};
 use crate::vm::{Value, VM};
 use window::*;
 use audio::*;
 use time::*;
+use fs::*;
+use net::*;
+use rand::*;
 use constants::*;
 
 /// System call function signature
@@ -25,7 +31,9 @@ pub enum SysCallFn
     Fn1_0(fn(&mut VM, a0: Value)),
     Fn1_1(fn(&mut VM, a0: Value) -> Value),
     Fn2_0(fn(&mut VM, a0: Value, a1: Value)),
+    Fn2_1(fn(&mut VM, a0: Value, a1: Value) -> Value),
     Fn3_0(fn(&mut VM, a0: Value, a1: Value, a2: Value)),
+    Fn3_1(fn(&mut VM, a0: Value, a1: Value, a2: Value) -> Value),
     Fn4_0(fn(&mut VM, a0: Value, a1: Value, a2: Value, a3: Value)),
     Fn4_1(fn(&mut VM, a0: Value, a1: Value, a2: Value, a3: Value) -> Value),
 }
@@ -40,7 +48,9 @@ impl SysCallFn
             Self::Fn1_0(_) => 1,
             Self::Fn1_1(_) => 1,
             Self::Fn2_0(_) => 2,
+            Self::Fn2_1(_) => 2,
             Self::Fn3_0(_) => 3,
+            Self::Fn3_1(_) => 3,
             Self::Fn4_0(_) => 4,
             Self::Fn4_1(_) => 4,
         }
@@ -54,7 +64,9 @@ impl SysCallFn
             Self::Fn1_0(_) => false,
             Self::Fn1_1(_) => true,
             Self::Fn2_0(_) => false,
+            Self::Fn2_1(_) => true,
             Self::Fn3_0(_) => false,
+            Self::Fn3_1(_) => true,
             Self::Fn4_0(_) => false,
             Self::Fn4_1(_) => true,
         }
@@ -79,6 +91,53 @@ pub fn get_sdl_context() -> &'static mut sdl2::Sdl
     }
 }
 
+/// Source of the bytes read by the input syscalls
+pub type InputStream = Box<dyn BufRead + Send>;
+
+/// Destination of the bytes written by the print syscalls
+pub type OutputStream = Box<dyn Write + Send>;
+
+/// Maximum length of the strings printed by print_str, so that
+/// a missing null terminator doesn't scan the whole heap
+pub const PRINT_STR_MAX_LEN: usize = 1 << 20;
+
+/// Output stream which keeps what is written in memory, e.g. to
+/// capture the output of a program in tests. Clones share the
+/// same buffer.
+#[derive(Clone, Default)]
+pub struct OutputBuffer
+{
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl OutputBuffer
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Everything written so far
+    pub fn contents(&self) -> Vec<u8>
+    {
+        self.bytes.lock().unwrap().clone()
+    }
+}
+
+impl Write for OutputBuffer
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        self.bytes.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
 pub struct SysState
 {
     /// Map of indices to syscall functions
@@ -89,6 +148,27 @@ pub struct SysState
 
     /// Time module state
     pub time_state: TimeState,
+
+    /// Window module state
+    pub window_state: WindowState,
+
+    /// Audio module state
+    pub audio_state: AudioState,
+
+    /// Filesystem module state
+    pub fs_state: FsState,
+
+    /// Network module state
+    pub net_state: NetState,
+
+    /// Pseudo-random number generator state
+    pub rand_state: RandState,
+
+    /// Standard input, unless replaced with set_input
+    input: InputStream,
+
+    /// Standard output, unless replaced with set_output
+    output: OutputStream,
 }
 
 impl SysState
@@ -99,6 +179,13 @@ impl SysState
             syscalls: [None; SYSCALL_TBL_LEN],
             mutex: Weak::new(),
             time_state: TimeState::new(),
+            window_state: WindowState::new(),
+            audio_state: AudioState::new(),
+            fs_state: FsState::new(),
+            net_state: NetState::new(),
+            rand_state: RandState::new(),
+            input: Box::new(BufReader::new(stdin())),
+            output: Box::new(stdout()),
         };
 
         sys_state.init_syscalls();
@@ -117,6 +204,20 @@ impl SysState
         vm_arc
     }
 
+    /// Read the input of the program from a given stream instead
+    /// of standard input, e.g. to feed it a string in tests
+    pub fn set_input(&mut self, input: impl BufRead + Send + 'static)
+    {
+        self.input = Box::new(input);
+    }
+
+    /// Write the output of the program to a given stream instead
+    /// of standard output, e.g. to capture it in tests
+    pub fn set_output(&mut self, output: impl Write + Send + 'static)
+    {
+        self.output = Box::new(output);
+    }
+
     pub fn reg_syscall(&mut self, const_idx: u16, fun: SysCallFn)
     {
         let desc = SYSCALL_DESCS[const_idx as usize].as_ref().unwrap();
@@ -155,18 +256,36 @@ impl SysState
         self.reg_syscall(MEMCPY, SysCallFn::Fn3_0(memcpy));
         self.reg_syscall(VM_HEAP_SIZE, SysCallFn::Fn0_1(vm_heap_size));
         self.reg_syscall(VM_RESIZE_HEAP, SysCallFn::Fn1_1(vm_resize_heap));
+        self.reg_syscall(RAND_SEED, SysCallFn::Fn1_0(rand_seed));
+        self.reg_syscall(RAND_U64, SysCallFn::Fn0_1(rand_u64));
 
         self.reg_syscall(PRINT_I64, SysCallFn::Fn1_0(print_i64));
         self.reg_syscall(PRINT_F32, SysCallFn::Fn1_0(print_f32));
+        self.reg_syscall(PRINT_U64_HEX, SysCallFn::Fn1_0(print_u64_hex));
         self.reg_syscall(PRINT_STR, SysCallFn::Fn1_0(print_str));
         self.reg_syscall(PRINT_ENDL, SysCallFn::Fn0_0(print_endl));
         self.reg_syscall(READ_I64, SysCallFn::Fn0_1(read_i64));
+        self.reg_syscall(READ_CHAR, SysCallFn::Fn0_1(read_char));
+        self.reg_syscall(READ_LINE, SysCallFn::Fn2_1(read_line));
 
         self.reg_syscall(TIME_CURRENT_MS, SysCallFn::Fn0_1(time_current_ms));
         self.reg_syscall(TIME_DELAY_CB, SysCallFn::Fn2_0(time_delay_cb));
 
+        self.reg_syscall(FILE_OPEN, SysCallFn::Fn2_1(file_open));
+        self.reg_syscall(FILE_READ, SysCallFn::Fn3_1(file_read));
+        self.reg_syscall(FILE_WRITE, SysCallFn::Fn3_1(file_write));
+        self.reg_syscall(FILE_CLOSE, SysCallFn::Fn1_1(file_close));
+
+        self.reg_syscall(TCP_CONNECT, SysCallFn::Fn2_1(tcp_connect));
+        self.reg_syscall(TCP_LISTEN, SysCallFn::Fn1_1(tcp_listen));
+        self.reg_syscall(TCP_ACCEPT, SysCallFn::Fn1_1(tcp_accept));
+        self.reg_syscall(TCP_READ, SysCallFn::Fn3_1(tcp_read));
+        self.reg_syscall(TCP_WRITE, SysCallFn::Fn3_1(tcp_write));
+        self.reg_syscall(TCP_CLOSE, SysCallFn::Fn1_1(tcp_close));
+
         self.reg_syscall(WINDOW_CREATE, SysCallFn::Fn4_1(window_create));
         self.reg_syscall(WINDOW_DRAW_FRAME, SysCallFn::Fn2_0(window_draw_frame));
+        self.reg_syscall(WINDOW_POLL_EVENT, SysCallFn::Fn2_1(window_poll_event));
         self.reg_syscall(WINDOW_ON_MOUSEMOVE, SysCallFn::Fn2_0(window_on_mousemove));
         self.reg_syscall(WINDOW_ON_MOUSEDOWN, SysCallFn::Fn2_0(window_on_mousedown));
         self.reg_syscall(WINDOW_ON_MOUSEUP, SysCallFn::Fn2_0(window_on_mouseup));
@@ -186,10 +305,7 @@ fn vm_heap_size(vm: &mut VM) -> Value
 fn vm_resize_heap(vm: &mut VM, num_bytes: Value) -> Value
 {
     let num_bytes = num_bytes.as_usize();
-    vm.resize_heap(num_bytes);
-
-    // Success
-    Value::from(true)
+    Value::from(vm.resize_heap(num_bytes))
 }
 
 fn memset(vm: &mut VM, dst_ptr: Value, val: Value, num_bytes: Value)
@@ -218,52 +334,121 @@ fn memcpy(vm: &mut VM, dst_ptr: Value, src_ptr: Value, num_bytes: Value)
     let src_ptr = src_ptr.as_usize();
     let num_bytes = num_bytes.as_usize();
 
-    // TODO: panic if slices are overlapping
+    vm.copy_heap(dst_ptr, src_ptr, num_bytes);
+}
 
-    let dst_ptr: *mut u8 = vm.get_heap_ptr(dst_ptr);
-    let src_ptr: *mut u8 = vm.get_heap_ptr(src_ptr);
+/// Read a null-terminated UTF-8 string, without panicking if
+/// it lies outside of the heap
+fn read_heap_str(vm: &mut VM, str_ptr: usize) -> Option<String>
+{
+    let max_len = vm.heap_size().checked_sub(str_ptr)?;
+    let bytes = vm.heap_bytes(str_ptr, max_len)?;
+    let len = bytes.iter().position(|b| *b == 0)?;
+    String::from_utf8(bytes[..len].to_vec()).ok()
+}
 
-    unsafe {
-        std::ptr::copy_nonoverlapping(src_ptr, dst_ptr, num_bytes);
-    }
+/// Write bytes to the output of the program
+fn write_output(vm: &mut VM, bytes: &[u8])
+{
+    let output = &mut vm.sys_state.output;
+    output.write_all(bytes).expect("failed to write output");
+    output.flush().expect("failed to write output");
 }
 
 fn print_i64(vm: &mut VM, v: Value)
 {
     let v = v.as_i64();
-    print!("{}", v);
-    stdout().flush().unwrap();
+    write_output(vm, v.to_string().as_bytes());
 }
 
 fn print_f32(vm: &mut VM, v: Value)
 {
     let v = v.as_f32();
-    print!("{}", v);
-    stdout().flush().unwrap();
+    write_output(vm, v.to_string().as_bytes());
 }
 
-/// Print a null-terminated UTF-8 string to stdout
+fn print_u64_hex(vm: &mut VM, v: Value)
+{
+    let v = v.as_u64();
+    write_output(vm, format!("{:x}", v).as_bytes());
+}
+
+/// Print a null-terminated string. The bytes are written as they
+/// are, so that UTF-8 characters can be printed one byte at a time.
 fn print_str(vm: &mut VM, str_ptr: Value)
 {
-    let rust_str = vm.get_heap_str(str_ptr.as_usize());
-    print!("{}", rust_str);
-    stdout().flush().unwrap();
+    let str_ptr = str_ptr.as_usize();
+    let max_len = vm.heap_size().saturating_sub(str_ptr).min(PRINT_STR_MAX_LEN + 1);
+
+    let bytes = match vm.heap_bytes(str_ptr, max_len) {
+        Some(bytes) => bytes,
+        None => panic!("print_str: invalid string address {}", str_ptr),
+    };
+
+    let str_len = match bytes.iter().position(|b| *b == 0) {
+        Some(str_len) => str_len,
+        None => panic!("print_str: string at address {} is not null-terminated", str_ptr),
+    };
+
+    let bytes = bytes[..str_len].to_vec();
+    write_output(vm, &bytes);
 }
 
-/// Print a newline characted to stdout
+/// Print a newline character
 fn print_endl(vm: &mut VM)
 {
-    println!();
-    stdout().flush().unwrap();
+    write_output(vm, b"\n");
 }
 
 fn read_i64(vm: &mut VM) -> Value
 {
     let mut line_buf = String::new();
-    stdin()
+    vm.sys_state.input
         .read_line(&mut line_buf)
         .expect("failed to read input line");
     let val: i64 = line_buf.trim().parse().expect("expected i64 input");
 
     return Value::from(val);
 }
+
+/// Read one byte of input, or -1 at the end of the input
+fn read_char(vm: &mut VM) -> Value
+{
+    let mut byte = [0];
+    let num_read = vm.sys_state.input
+        .read(&mut byte)
+        .expect("failed to read input");
+
+    if num_read == 0 {
+        return Value::from(-1 as i64);
+    }
+
+    Value::from(byte[0])
+}
+
+/// Read a line of input, including the newline, into a buffer.
+/// Returns the number of bytes read, which is 0 at the end of the input,
+/// or -1 if the buffer can't hold a byte and the null terminator.
+fn read_line(vm: &mut VM, buf_ptr: Value, buf_size: Value) -> Value
+{
+    let buf_ptr = buf_ptr.as_usize();
+    let buf_size = buf_size.as_usize();
+
+    // Check that the buffer is valid before consuming any input
+    if buf_size < 2 || vm.heap_bytes(buf_ptr, buf_size).is_none() {
+        return Value::from(-1i64);
+    }
+
+    // Leave room for the null terminator
+    let mut line = Vec::new();
+    (&mut vm.sys_state.input)
+        .take((buf_size - 1) as u64)
+        .read_until(b'\n', &mut line)
+        .expect("failed to read input");
+
+    let buf = vm.heap_bytes(buf_ptr, buf_size).unwrap();
+    buf[..line.len()].copy_from_slice(&line);
+    buf[line.len()] = 0;
+
+    Value::from(line.len())
+}
@@ -0,0 +1,265 @@
+use std::io::{Read, Write, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::vm::{VM, Value};
+use crate::sys::read_heap_str;
+
+/// Longest time a network operation waits before failing
+pub const NET_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval at which tcp_accept checks for new connections
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+enum Socket
+{
+    Stream(TcpStream),
+    Listener(TcpListener),
+}
+
+pub struct NetState
+{
+    // Network access is disabled unless enabled by the embedder
+    enabled: bool,
+
+    // Address tcp_listen binds to, only the loopback
+    // interface unless the embedder chooses another
+    listen_addr: IpAddr,
+
+    // Open sockets, indexed by socket descriptor
+    sockets: Vec<Option<Socket>>,
+}
+
+impl NetState
+{
+    pub fn new() -> Self
+    {
+        Self {
+            enabled: false,
+            listen_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            sockets: Vec::default(),
+        }
+    }
+
+    /// Allow or deny network access. Sockets which are
+    /// already open stay open.
+    pub fn set_enabled(&mut self, enabled: bool)
+    {
+        self.enabled = enabled;
+    }
+
+    /// Set the address tcp_listen binds to, e.g. 0.0.0.0
+    /// to accept connections on all network interfaces
+    pub fn set_listen_addr(&mut self, listen_addr: IpAddr)
+    {
+        self.listen_addr = listen_addr;
+    }
+
+    /// Store a new socket, reusing the lowest free descriptor
+    fn add_socket(&mut self, socket: Socket) -> Value
+    {
+        let fd = match self.sockets.iter().position(|s| s.is_none()) {
+            Some(fd) => fd,
+            None => {
+                self.sockets.push(None);
+                self.sockets.len() - 1
+            }
+        };
+        self.sockets[fd] = Some(socket);
+
+        Value::from(fd as i64)
+    }
+
+    fn get_socket(&mut self, fd: Value) -> Option<&mut Socket>
+    {
+        let fd = usize::try_from(fd.as_i64()).ok()?;
+        self.sockets.get_mut(fd)?.as_mut()
+    }
+
+    fn get_stream(&mut self, fd: Value) -> Option<&mut TcpStream>
+    {
+        match self.get_socket(fd)? {
+            Socket::Stream(stream) => Some(stream),
+            Socket::Listener(_) => None,
+        }
+    }
+}
+
+/// Set the timeouts of a new connection
+fn init_stream(stream: &TcpStream) -> Option<()>
+{
+    stream.set_read_timeout(Some(NET_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(NET_TIMEOUT)).ok()?;
+    stream.set_nodelay(true).ok()
+}
+
+fn connect(host: &str, port: u16) -> Option<TcpStream>
+{
+    // Try each address the host name resolves to
+    for addr in (host, port).to_socket_addrs().ok()? {
+        if let Ok(stream) = TcpStream::connect_timeout(&addr, NET_TIMEOUT) {
+            init_stream(&stream)?;
+            return Some(stream);
+        }
+    }
+
+    None
+}
+
+pub fn tcp_connect(vm: &mut VM, host_ptr: Value, port: Value) -> Value
+{
+    if !vm.sys_state.net_state.enabled {
+        return Value::from(-1i64);
+    }
+
+    let host = match read_heap_str(vm, host_ptr.as_usize()) {
+        Some(host) => host,
+        None => return Value::from(-1i64),
+    };
+
+    let port = match u16::try_from(port.as_u64()) {
+        Ok(port) => port,
+        Err(_) => return Value::from(-1i64),
+    };
+
+    match connect(&host, port) {
+        Some(stream) => vm.sys_state.net_state.add_socket(Socket::Stream(stream)),
+        None => Value::from(-1i64),
+    }
+}
+
+pub fn tcp_listen(vm: &mut VM, port: Value) -> Value
+{
+    let net_state = &mut vm.sys_state.net_state;
+    if !net_state.enabled {
+        return Value::from(-1i64);
+    }
+
+    let port = match u16::try_from(port.as_u64()) {
+        Ok(port) => port,
+        Err(_) => return Value::from(-1i64),
+    };
+
+    // The listener doesn't block, so tcp_accept can time out
+    let listener = match TcpListener::bind((net_state.listen_addr, port)) {
+        Ok(listener) if listener.set_nonblocking(true).is_ok() => listener,
+        _ => return Value::from(-1i64),
+    };
+
+    net_state.add_socket(Socket::Listener(listener))
+}
+
+pub fn tcp_accept(vm: &mut VM, fd: Value) -> Value
+{
+    let net_state = &mut vm.sys_state.net_state;
+    let listener = match net_state.get_socket(fd) {
+        Some(Socket::Listener(listener)) => listener,
+        _ => return Value::from(-1i64),
+    };
+
+    let deadline = Instant::now() + NET_TIMEOUT;
+    let stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(_) => return Value::from(-1i64),
+        }
+    };
+
+    // Accepted connections may inherit the nonblocking mode
+    if stream.set_nonblocking(false).is_err() || init_stream(&stream).is_none() {
+        return Value::from(-1i64);
+    }
+
+    net_state.add_socket(Socket::Stream(stream))
+}
+
+pub fn tcp_read(vm: &mut VM, fd: Value, buf_ptr: Value, num_bytes: Value) -> Value
+{
+    if vm.heap_bytes(buf_ptr.as_usize(), num_bytes.as_usize()).is_none() {
+        return Value::from(-1i64);
+    }
+
+    // Copy through a temporary buffer, since the socket and
+    // the heap can't be borrowed at the same time
+    let mut data = vec![0; num_bytes.as_usize()];
+
+    let stream = match vm.sys_state.net_state.get_stream(fd) {
+        Some(stream) => stream,
+        None => return Value::from(-1i64),
+    };
+
+    let num_read = match stream.read(&mut data) {
+        Ok(num_read) => num_read,
+        Err(_) => return Value::from(-1i64),
+    };
+
+    let buf = vm.heap_bytes(buf_ptr.as_usize(), num_read).unwrap();
+    buf.copy_from_slice(&data[..num_read]);
+
+    Value::from(num_read as i64)
+}
+
+pub fn tcp_write(vm: &mut VM, fd: Value, buf_ptr: Value, num_bytes: Value) -> Value
+{
+    let data = match vm.heap_bytes(buf_ptr.as_usize(), num_bytes.as_usize()) {
+        Some(bytes) => bytes.to_vec(),
+        None => return Value::from(-1i64),
+    };
+
+    let stream = match vm.sys_state.net_state.get_stream(fd) {
+        Some(stream) => stream,
+        None => return Value::from(-1i64),
+    };
+
+    match stream.write_all(&data) {
+        Ok(_) => Value::from(data.len() as i64),
+        Err(_) => Value::from(-1i64),
+    }
+}
+
+pub fn tcp_close(vm: &mut VM, fd: Value) -> Value
+{
+    let net_state = &mut vm.sys_state.net_state;
+    if net_state.get_socket(fd).is_none() {
+        return Value::from(-1i64);
+    }
+
+    // Dropping the socket closes it
+    net_state.sockets[fd.as_usize()] = None;
+    Value::from(0i64)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+
+    /// Address of the listener opened by a program listening on port 0
+    fn listen_addr(configure: impl FnOnce(&mut NetState)) -> IpAddr
+    {
+        let mut vm = Assembler::new().parse_str("push 0; syscall tcp_listen; exit;").unwrap();
+        vm.sys_state.net_state.set_enabled(true);
+        configure(&mut vm.sys_state.net_state);
+        vm.call(0, &[]).unwrap();
+
+        match vm.sys_state.net_state.get_socket(Value::from(0i64)) {
+            Some(Socket::Listener(listener)) => listener.local_addr().unwrap().ip(),
+            _ => panic!("no listener"),
+        }
+    }
+
+    #[test]
+    fn test_listen_addr()
+    {
+        // Only local connections are accepted by default
+        assert_eq!(listen_addr(|_| {}), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        // The embedder can listen on all interfaces
+        let any = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        assert_eq!(listen_addr(|net_state| net_state.set_listen_addr(any)), any);
+    }
+}
@@ -0,0 +1,92 @@
+// Pseudo-random number generator
+//
+// Numbers are generated with SplitMix64, which is fast, has a 64-bit
+// state which any seed is valid for, and only uses wrapping integer
+// arithmetic, so a given seed produces the same sequence everywhere.
+// Each VM has its own generator.
+
+use crate::vm::{VM, Value};
+
+pub struct RandState
+{
+    state: u64,
+}
+
+impl RandState
+{
+    pub fn new() -> Self
+    {
+        Self { state: 0 }
+    }
+
+    pub fn seed(&mut self, seed: u64)
+    {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64
+    {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+pub fn rand_seed(vm: &mut VM, seed: Value)
+{
+    vm.sys_state.rand_state.seed(seed.as_u64());
+}
+
+pub fn rand_u64(vm: &mut VM) -> Value
+{
+    Value::from(vm.sys_state.rand_state.next_u64())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+    use crate::vm::ExitReason;
+
+    // First outputs of the reference SplitMix64 implementation for seed 0
+    const SEED_0_OUTPUTS: [u64; 3] = [0xE220_A839_7B1D_CDAF, 0x6E78_9E6A_A1B9_65F4, 0x06C4_5D18_8009_454F];
+
+    #[test]
+    fn test_sequence()
+    {
+        let mut rand = RandState::new();
+        for val in SEED_0_OUTPUTS {
+            assert_eq!(rand.next_u64(), val);
+        }
+
+        // Reseeding restarts the sequence
+        rand.seed(0);
+        assert_eq!(rand.next_u64(), SEED_0_OUTPUTS[0]);
+        rand.seed(1);
+        assert_ne!(rand.next_u64(), SEED_0_OUTPUTS[0]);
+    }
+
+    #[test]
+    fn test_syscalls()
+    {
+        let next = |vm: &mut VM| match vm.call(0, &[]).unwrap() {
+            ExitReason::Exit(val) => val.as_u64(),
+            _ => panic!(),
+        };
+
+        // Each VM has its own generator
+        let mut vm_a = Assembler::new().parse_str("syscall rand_u64; exit;").unwrap();
+        let mut vm_b = Assembler::new().parse_str("syscall rand_u64; exit;").unwrap();
+        assert_eq!(next(&mut vm_a), SEED_0_OUTPUTS[0]);
+        assert_eq!(next(&mut vm_a), SEED_0_OUTPUTS[1]);
+        assert_eq!(next(&mut vm_b), SEED_0_OUTPUTS[0]);
+
+        let mut vm = Assembler::new().parse_str("push 42; syscall rand_seed; syscall rand_u64; exit;").unwrap();
+        let first = next(&mut vm);
+        assert_eq!(next(&mut vm), first);
+    }
+}
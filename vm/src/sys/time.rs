@@ -1,5 +1,7 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::vm::{VM, Value};
+use std::time::{SystemTime, UNIX_EPOCH, Instant, Duration};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::vm::{VM, Value, HostFnError};
 
 // Callback function to be run at a given time stamp
 #[derive(Debug, Copy, Clone)]
@@ -91,3 +93,195 @@ pub fn get_cbs_to_run(vm: &mut VM) -> Vec<u64>
 
     return pcs_to_run;
 }
+
+/// Source of the time given to programs by the time_ms
+/// and sleep_ms host functions
+pub trait Clock: Send
+{
+    /// Milliseconds elapsed since the clock was created
+    fn now_ms(&mut self) -> u64;
+
+    /// Returns false if the sleep was cut short by an interrupt
+    fn sleep_ms(&mut self, ms: u64) -> bool;
+}
+
+/// Exit value of a program interrupted while sleeping,
+/// as for a shell command stopped by SIGINT
+pub const INTERRUPTED_EXIT_CODE: u64 = 130;
+
+/// Longest uninterrupted sleep, so that an interrupt is
+/// noticed quickly when a program sleeps for a long time
+const SLEEP_SLICE_MS: u64 = 10;
+
+/// Monotonic clock measuring real time
+pub struct SystemClock
+{
+    start: Instant,
+    interrupted: Arc<AtomicBool>,
+}
+
+impl SystemClock
+{
+    pub fn new() -> Self
+    {
+        Self {
+            start: Instant::now(),
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Flag which can be set from another thread to cut
+    /// sleeps short, e.g. when shutting down
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool>
+    {
+        self.interrupted.clone()
+    }
+}
+
+impl Clock for SystemClock
+{
+    fn now_ms(&mut self) -> u64
+    {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    fn sleep_ms(&mut self, ms: u64) -> bool
+    {
+        let end = Instant::now() + Duration::from_millis(ms);
+
+        while !self.interrupted.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            std::thread::sleep((end - now).min(Duration::from_millis(SLEEP_SLICE_MS)));
+        }
+
+        false
+    }
+}
+
+/// Clock which only advances when the program sleeps, for deterministic
+/// tests. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct FakeClock
+{
+    time_ms: Arc<AtomicU64>,
+}
+
+impl FakeClock
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn advance_ms(&self, ms: u64)
+    {
+        self.time_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for FakeClock
+{
+    fn now_ms(&mut self) -> u64
+    {
+        self.time_ms.load(Ordering::Relaxed)
+    }
+
+    fn sleep_ms(&mut self, ms: u64) -> bool
+    {
+        self.advance_ms(ms);
+        true
+    }
+}
+
+/// Register the time_ms and sleep_ms host functions, which
+/// ncc programs declare in <uvm/clock.h>. A program whose sleep
+/// is interrupted exits with INTERRUPTED_EXIT_CODE.
+pub fn register_clock_fns(vm: &mut VM, clock: impl Clock + 'static) -> Result<(), HostFnError>
+{
+    let clock = Arc::new(Mutex::new(clock));
+
+    let time_clock = clock.clone();
+    vm.register_host_fn("time_ms", 0, move |vm, args| {
+        Value::from(time_clock.lock().unwrap().now_ms())
+    })?;
+
+    vm.register_host_fn("sleep_ms", 1, move |vm, args| {
+        if !clock.lock().unwrap().sleep_ms(args[0].as_u64()) {
+            vm.request_exit(Value::from(INTERRUPTED_EXIT_CODE));
+        }
+        Value::from(0u64)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::vm::ExitReason;
+    use crate::vm::tests::compile_ncc;
+    use crate::asm::Assembler;
+
+    #[test]
+    fn test_system_clock()
+    {
+        let mut clock = SystemClock::new();
+        let t0 = clock.now_ms();
+        assert!(clock.sleep_ms(20));
+        assert!(clock.now_ms() >= t0 + 20);
+
+        // Interrupted sleeps return right away
+        clock.interrupt_handle().store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        assert!(!clock.sleep_ms(60_000));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_interrupt_sleep()
+    {
+        // A program sleeping for a minute, then exiting with 7
+        let src = "push 60_000; call_host sleep_ms, 1; pop; push 7; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let clock = SystemClock::new();
+        let interrupt = clock.interrupt_handle();
+        register_clock_fns(&mut vm, clock).unwrap();
+
+        // Interrupt the sleep from another thread
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(INTERRUPTED_EXIT_CODE)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        interrupter.join().unwrap();
+
+        // The VM can run the program again afterwards,
+        // and later sleeps are cut short right away
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(INTERRUPTED_EXIT_CODE)));
+    }
+
+    #[test]
+    fn test_fake_clock_compiled()
+    {
+        // Compile a C program which sleeps between two reads of the clock
+        let mut vm = compile_ncc("tests/host/clock.c");
+        let clock = FakeClock::new();
+        clock.advance_ms(1_000);
+        register_clock_fns(&mut vm, clock.clone()).unwrap();
+        assert!(vm.missing_host_fns().is_empty());
+
+        // Start time * 1000 + elapsed time
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(1_000_250u64)));
+        assert_eq!(clock.clone().now_ms(), 1_250);
+
+        // The host functions can only be registered once
+        assert!(register_clock_fns(&mut vm, FakeClock::new()).is_err());
+    }
+}
@@ -11,9 +11,11 @@ use sdl2::render::TextureAccess;
 use sdl2::pixels::PixelFormatEnum;
 
 use std::time::Duration;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
 use crate::sys::{SysState, get_sdl_context};
-use crate::vm::{VM, Value, ExitReason};
+use crate::vm::{VM, Value, ExitReason, Trap};
 
 /// SDL video subsystem
 /// This is a global variable because it doesn't implement
@@ -69,100 +71,361 @@ fn get_window(window_id: u32) -> &'static mut Window<'static>
     }
 
     unsafe {
-        WINDOW.as_mut().unwrap()
+        match WINDOW.as_mut() {
+            Some(window) => window,
+            None => panic!("window callbacks are only supported with the SDL backend"),
+        }
     }
 }
 
-pub fn window_create(vm: &mut VM, width: Value, height: Value, title: Value, flags: Value) -> Value
+/// Size in bytes of the event struct written by window_poll_event
+pub const WINDOW_EVENT_SIZE: usize = 16;
+
+/// Events reported by window_poll_event
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WindowEvent
 {
-    unsafe {
-        if WINDOW.is_some() {
-            panic!("for now, only one window supported");
+    Close,
+    KeyDown(u16),
+    KeyUp(u16),
+    MouseMove { x: i32, y: i32 },
+    MouseDown { button: u8, x: i32, y: i32 },
+    MouseUp { button: u8, x: i32, y: i32 },
+}
+
+impl WindowEvent
+{
+    /// Encode the event as four little-endian 32-bit fields:
+    /// kind, key code or button, x and y
+    fn encode(&self) -> [u8; WINDOW_EVENT_SIZE]
+    {
+        use crate::sys::constants::*;
+
+        let (kind, code, x, y) = match *self {
+            Self::Close => (WINDOW_EVENT_CLOSE, 0, 0, 0),
+            Self::KeyDown(keycode) => (WINDOW_EVENT_KEYDOWN, keycode as u32, 0, 0),
+            Self::KeyUp(keycode) => (WINDOW_EVENT_KEYUP, keycode as u32, 0, 0),
+            Self::MouseMove { x, y } => (WINDOW_EVENT_MOUSEMOVE, 0, x, y),
+            Self::MouseDown { button, x, y } => (WINDOW_EVENT_MOUSEDOWN, button as u32, x, y),
+            Self::MouseUp { button, x, y } => (WINDOW_EVENT_MOUSEUP, button as u32, x, y),
+        };
+
+        let mut bytes = [0; WINDOW_EVENT_SIZE];
+        bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+        bytes[4..8].copy_from_slice(&code.to_le_bytes());
+        bytes[8..12].copy_from_slice(&x.to_le_bytes());
+        bytes[12..16].copy_from_slice(&y.to_le_bytes());
+        bytes
+    }
+}
+
+/// Displays the frames drawn by programs and produces the
+/// events of their window
+pub trait WindowBackend: Send
+{
+    fn create(&mut self, width: u32, height: u32, title: &str);
+
+    /// Display a frame of width * height pixels in BGRA byte order
+    fn draw_frame(&mut self, pixels: &[u8]);
+
+    /// Next pending event, without waiting for one
+    fn poll_event(&mut self) -> Option<WindowEvent>;
+}
+
+/// Backend showing an SDL window on the screen
+pub struct SdlWindow;
+
+impl WindowBackend for SdlWindow
+{
+    fn create(&mut self, width: u32, height: u32, title: &str)
+    {
+        let video_subsystem = get_video_subsystem();
+
+        let window = video_subsystem.window(title, width, height)
+            .hidden()
+            .position_centered()
+            .build()
+            .unwrap();
+
+        let mut canvas = window.into_canvas().build().unwrap();
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.present();
+
+        let texture_creator = canvas.texture_creator();
+
+        let window = Window {
+            width,
+            height,
+            window_id: 0,
+            canvas,
+            texture_creator,
+            texture: None,
+            cb_mousemove: 0,
+            cb_mousedown: 0,
+            cb_mouseup: 0,
+            cb_keydown: 0,
+            cb_keyup: 0,
+            cb_textinput: 0,
+        };
+
+        unsafe {
+            WINDOW = Some(window)
         }
     }
 
-    let width: u32 = width.as_usize().try_into().unwrap();
-    let height: u32 = height.as_usize().try_into().unwrap();
-    let title_str = vm.get_heap_str(title.as_usize()).to_owned();
+    fn draw_frame(&mut self, pixels: &[u8])
+    {
+        let window = get_window(0);
+
+        // If no frame has been drawn yet
+        if window.texture.is_none() {
+            // Creat the texture to render into
+            // Pixels use the BGRA byte order (0xAA_RR_GG_BB on a little-endian machine)
+            window.texture = Some(window.texture_creator.create_texture(
+                PixelFormatEnum::BGRA32,
+                TextureAccess::Streaming,
+                window.width,
+                window.height
+            ).unwrap());
+
+            // We show and raise the window at the moment the first frame is drawn
+            // This avoids showing a blank window too early
+            window.canvas.window_mut().show();
+            window.canvas.window_mut().raise();
+        }
 
-    let video_subsystem = get_video_subsystem();
+        // Update the texture
+        let pitch = 4 * window.width as usize;
+        window.texture.as_mut().unwrap().update(None, pixels, pitch).unwrap();
 
-    let window = video_subsystem.window(&title_str, width, height)
-        .hidden()
-        .position_centered()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-
-    let texture_creator = canvas.texture_creator();
-
-    let window = Window {
-        width,
-        height,
-        window_id: 0,
-        canvas,
-        texture_creator,
-        texture: None,
-        cb_mousemove: 0,
-        cb_mousedown: 0,
-        cb_mouseup: 0,
-        cb_keydown: 0,
-        cb_keyup: 0,
-        cb_textinput: 0,
-    };
+        // Copy the texture into the canvas
+        window.canvas.copy(
+            &window.texture.as_ref().unwrap(),
+            None,
+            None
+        ).unwrap();
 
-    unsafe {
-        WINDOW = Some(window)
+        // Update the screen with any rendering performed since the previous call
+        window.canvas.present();
     }
 
+    fn poll_event(&mut self) -> Option<WindowEvent>
+    {
+        let mut event_pump = get_sdl_context().event_pump().unwrap();
+
+        // Skip the events which programs can't poll for
+        while let Some(event) = event_pump.poll_event() {
+            match event {
+                Event::Quit { .. } => return Some(WindowEvent::Close),
+
+                Event::KeyDown { keycode: Some(keycode), .. } => {
+                    if let Some(keycode) = translate_keycode(keycode) {
+                        return Some(WindowEvent::KeyDown(keycode));
+                    }
+                }
+
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if let Some(keycode) = translate_keycode(keycode) {
+                        return Some(WindowEvent::KeyUp(keycode));
+                    }
+                }
+
+                Event::MouseMotion { x, y, .. } => {
+                    return Some(WindowEvent::MouseMove { x, y });
+                }
+
+                Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                    if let Some(button) = translate_button(mouse_btn) {
+                        return Some(WindowEvent::MouseDown { button, x, y });
+                    }
+                }
+
+                Event::MouseButtonUp { mouse_btn, x, y, .. } => {
+                    if let Some(button) = translate_button(mouse_btn) {
+                        return Some(WindowEvent::MouseUp { button, x, y });
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Default)]
+struct HeadlessState
+{
+    title: String,
+    width: u32,
+    height: u32,
+    frame: Vec<u8>,
+    num_frames: u64,
+    events: VecDeque<WindowEvent>,
+}
+
+/// Backend without a display, which keeps the last frame in memory
+/// and produces the events given to push_event, e.g. to check what
+/// a program draws in tests. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct HeadlessWindow
+{
+    state: Arc<Mutex<HeadlessState>>,
+}
+
+impl HeadlessWindow
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Queue an event for the program to poll
+    pub fn push_event(&self, event: WindowEvent)
+    {
+        self.state.lock().unwrap().events.push_back(event);
+    }
+
+    pub fn title(&self) -> String
+    {
+        self.state.lock().unwrap().title.clone()
+    }
+
+    /// Number of frames drawn so far
+    pub fn num_frames(&self) -> u64
+    {
+        self.state.lock().unwrap().num_frames
+    }
+
+    /// BGRA bytes of a pixel of the last frame drawn,
+    /// or None if it is outside of the frame
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]>
+    {
+        let state = self.state.lock().unwrap();
+        if x >= state.width || y >= state.height {
+            return None;
+        }
+
+        let idx = 4 * (y as usize * state.width as usize + x as usize);
+        state.frame.get(idx..idx + 4).map(|bytes| bytes.try_into().unwrap())
+    }
+}
+
+impl WindowBackend for HeadlessWindow
+{
+    fn create(&mut self, width: u32, height: u32, title: &str)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.title = title.to_string();
+        state.width = width;
+        state.height = height;
+    }
+
+    fn draw_frame(&mut self, pixels: &[u8])
+    {
+        let mut state = self.state.lock().unwrap();
+        state.frame = pixels.to_vec();
+        state.num_frames += 1;
+    }
+
+    fn poll_event(&mut self) -> Option<WindowEvent>
+    {
+        self.state.lock().unwrap().events.pop_front()
+    }
+}
+
+pub struct WindowState
+{
+    backend: Box<dyn WindowBackend>,
+
+    // Size of the window, once it is created
+    size: Option<(u32, u32)>,
+}
+
+impl WindowState
+{
+    pub fn new() -> Self
+    {
+        Self {
+            backend: Box::new(SdlWindow),
+            size: None,
+        }
+    }
+
+    /// Display the window with a given backend instead of SDL.
+    /// This must be done before the program creates its window.
+    pub fn set_backend(&mut self, backend: impl WindowBackend + 'static)
+    {
+        assert!(self.size.is_none(), "the window is already created");
+        self.backend = Box::new(backend);
+    }
+
+    /// Size of the window with a given id
+    fn get_size(&self, window_id: Value) -> (u32, u32)
+    {
+        match self.size {
+            Some(size) if window_id.as_u32() == 0 => size,
+            _ => panic!("unknown window id {}", window_id.as_u32()),
+        }
+    }
+}
+
+pub fn window_create(vm: &mut VM, width: Value, height: Value, title: Value, flags: Value) -> Value
+{
+    if vm.sys_state.window_state.size.is_some() {
+        panic!("for now, only one window supported");
+    }
+
+    let width: u32 = width.as_usize().try_into().unwrap();
+    let height: u32 = height.as_usize().try_into().unwrap();
+    let title_str = vm.get_heap_str(title.as_usize()).to_owned();
+
+    let window_state = &mut vm.sys_state.window_state;
+    window_state.backend.create(width, height, &title_str);
+    window_state.size = Some((width, height));
+
     // TODO: return unique window id
     Value::from(0)
 }
 
 pub fn window_draw_frame(vm: &mut VM, window_id: Value, src_addr: Value)
 {
-    // Get the address to copy pixel data from
-    let data_ptr = vm.get_heap_ptr(src_addr.as_usize());
+    let (width, height) = vm.sys_state.window_state.get_size(window_id);
+    let data_len = 4 * width as usize * height as usize;
+
+    // Copy the frame out of the heap so the backend can be borrowed
+    let pixels = match vm.heap_bytes(src_addr.as_usize(), data_len) {
+        Some(pixels) => pixels.to_vec(),
+        None => panic!(
+            "window_draw_frame: frame of {} bytes at address {} is outside of the heap",
+            data_len,
+            src_addr.as_usize()
+        ),
+    };
 
-    let window = get_window(window_id.as_u32());
+    vm.sys_state.window_state.backend.draw_frame(&pixels);
+}
+
+pub fn window_poll_event(vm: &mut VM, window_id: Value, event_ptr: Value) -> Value
+{
+    vm.sys_state.window_state.get_size(window_id);
 
-    // If no frame has been drawn yet
-    if window.texture.is_none() {
-        // Creat the texture to render into
-        // Pixels use the BGRA byte order (0xAA_RR_GG_BB on a little-endian machine)
-        window.texture = Some(window.texture_creator.create_texture(
-            PixelFormatEnum::BGRA32,
-            TextureAccess::Streaming,
-            window.width,
-            window.height
-        ).unwrap());
-
-        // We show and raise the window at the moment the first frame is drawn
-        // This avoids showing a blank window too early
-        window.canvas.window_mut().show();
-        window.canvas.window_mut().raise();
+    if vm.heap_bytes(event_ptr.as_usize(), WINDOW_EVENT_SIZE).is_none() {
+        panic!("window_poll_event: invalid event address {}", event_ptr.as_usize());
     }
 
-    // Update the texture
-    let pitch = 4 * window.width as usize;
-    let data_len = (4 * window.width * window.height) as usize;
-    let pixel_slice = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
-    window.texture.as_mut().unwrap().update(None, pixel_slice, pitch).unwrap();
+    let event = match vm.sys_state.window_state.backend.poll_event() {
+        Some(event) => event,
+        None => return Value::from(false),
+    };
 
-    // Copy the texture into the canvas
-    window.canvas.copy(
-        &window.texture.as_ref().unwrap(),
-        None,
-        None
-    ).unwrap();
+    let event_bytes = vm.heap_bytes(event_ptr.as_usize(), WINDOW_EVENT_SIZE).unwrap();
+    event_bytes.copy_from_slice(&event.encode());
 
-    // Update the screen with any rendering performed since the previous call
-    window.canvas.present();
+    Value::from(true)
 }
 
 pub fn window_on_mousemove(vm: &mut VM, window_id: Value, cb: Value)
@@ -204,7 +467,7 @@ pub fn window_on_textinput(vm: &mut VM, window_id: Value, cb: Value)
 }
 
 /// Process SDL events
-pub fn process_events(vm: &mut VM) -> ExitReason
+pub fn process_events(vm: &mut VM) -> Result<ExitReason, Trap>
 {
     let mut event_pump = get_sdl_context().event_pump().unwrap();
 
@@ -214,44 +477,44 @@ pub fn process_events(vm: &mut VM) -> ExitReason
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. } => {
-                return ExitReason::Exit(Value::from(0));
+                return Ok(ExitReason::Exit(Value::from(0)));
             }
 
             Event::MouseMotion { window_id, x, y, .. } => {
-                if let ExitReason::Exit(val) = window_call_mousemove(vm, window_id, x, y) {
-                    return ExitReason::Exit(val);
+                if let ExitReason::Exit(val) = window_call_mousemove(vm, window_id, x, y)? {
+                    return Ok(ExitReason::Exit(val));
                 }
             }
 
             Event::MouseButtonDown { window_id, which, mouse_btn, x, y, .. } => {
-                if let ExitReason::Exit(val) = window_call_mousedown(vm, window_id, mouse_btn, x, y) {
-                    return ExitReason::Exit(val);
+                if let ExitReason::Exit(val) = window_call_mousedown(vm, window_id, mouse_btn, x, y)? {
+                    return Ok(ExitReason::Exit(val));
                 }
             }
 
             Event::MouseButtonUp { window_id, which, mouse_btn, x, y, .. } => {
-                if let ExitReason::Exit(val) = window_call_mouseup(vm, window_id, mouse_btn, x, y) {
-                    return ExitReason::Exit(val);
+                if let ExitReason::Exit(val) = window_call_mouseup(vm, window_id, mouse_btn, x, y)? {
+                    return Ok(ExitReason::Exit(val));
                 }
             }
 
             Event::KeyDown { window_id, keycode: Some(keycode), .. } => {
-                if let ExitReason::Exit(val) = window_call_keydown(vm, window_id, keycode) {
-                    return ExitReason::Exit(val);
+                if let ExitReason::Exit(val) = window_call_keydown(vm, window_id, keycode)? {
+                    return Ok(ExitReason::Exit(val));
                 }
             }
 
             Event::KeyUp { window_id, keycode: Some(keycode), .. } => {
-                if let ExitReason::Exit(val) = window_call_keyup(vm, window_id, keycode) {
-                    return ExitReason::Exit(val);
+                if let ExitReason::Exit(val) = window_call_keyup(vm, window_id, keycode)? {
+                    return Ok(ExitReason::Exit(val));
                 }
             }
 
             Event::TextInput { window_id, text, .. } => {
                 // For each UTF-8 byte of input
                 for ch in text.bytes() {
-                    if let ExitReason::Exit(val) = window_call_textinput(vm, window_id, ch) {
-                        return ExitReason::Exit(val);
+                    if let ExitReason::Exit(val) = window_call_textinput(vm, window_id, ch)? {
+                        return Ok(ExitReason::Exit(val));
                     }
                 }
             }
@@ -260,7 +523,7 @@ pub fn process_events(vm: &mut VM) -> ExitReason
         }
     }
 
-    return ExitReason::default();
+    return Ok(ExitReason::default());
 }
 
 // TODO: functions to process window-related events
@@ -270,13 +533,13 @@ pub fn process_events(vm: &mut VM) -> ExitReason
 
 // TODO: this is just for testing
 // we should handle window-related events here instead
-fn window_call_mousemove(vm: &mut VM, window_id: u32, x: i32, y: i32) -> ExitReason
+fn window_call_mousemove(vm: &mut VM, window_id: u32, x: i32, y: i32) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_mousemove;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     vm.call(cb, &[Value::from(window.window_id), Value::from(x), Value::from(y)])
@@ -292,13 +555,13 @@ MouseButtonDown {
     y: i32,
 },
 */
-fn window_call_mousedown(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i32, y: i32) -> ExitReason
+fn window_call_mousedown(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i32, y: i32) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_mousedown;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     // TODO: ignore SDL_TOUCH_MOUSEID
@@ -306,15 +569,9 @@ fn window_call_mousedown(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x:
     // or only support mouse id 0?
     //println!("mouse_id={}", mouse_id);
 
-    let btn_id = match mouse_btn {
-        MouseButton::Left => 0,
-        MouseButton::Middle => 1,
-        MouseButton::Right => 2,
-        MouseButton::X1 => 3,
-        MouseButton::X2 => 4,
-        MouseButton::Unknown => {
-            return ExitReason::default();
-        }
+    let btn_id = match translate_button(mouse_btn) {
+        Some(btn_id) => btn_id,
+        None => return Ok(ExitReason::default()),
     };
 
     vm.call(cb, &[
@@ -325,13 +582,13 @@ fn window_call_mousedown(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x:
     ])
 }
 
-fn window_call_mouseup(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i32, y: i32) -> ExitReason
+fn window_call_mouseup(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i32, y: i32) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_mouseup;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     // TODO: ignore SDL_TOUCH_MOUSEID
@@ -339,15 +596,9 @@ fn window_call_mouseup(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i
     // or only support mouse id 0?
     //println!("mouse_id={}", mouse_id);
 
-    let btn_id = match mouse_btn {
-        MouseButton::Left => 0,
-        MouseButton::Middle => 1,
-        MouseButton::Right => 2,
-        MouseButton::X1 => 3,
-        MouseButton::X2 => 4,
-        MouseButton::Unknown => {
-            return ExitReason::default();
-        }
+    let btn_id = match translate_button(mouse_btn) {
+        Some(btn_id) => btn_id,
+        None => return Ok(ExitReason::default()),
     };
 
     vm.call(cb, &[
@@ -358,6 +609,18 @@ fn window_call_mouseup(vm: &mut VM, window_id: u32, mouse_btn: MouseButton, x: i
     ])
 }
 
+fn translate_button(mouse_btn: MouseButton) -> Option<u8>
+{
+    match mouse_btn {
+        MouseButton::Left => Some(0),
+        MouseButton::Middle => Some(1),
+        MouseButton::Right => Some(2),
+        MouseButton::X1 => Some(3),
+        MouseButton::X2 => Some(4),
+        MouseButton::Unknown => None,
+    }
+}
+
 fn translate_keycode(sdl_keycode: Keycode) -> Option<u16>
 {
     use crate::sys::constants::*;
@@ -426,13 +689,13 @@ fn translate_keycode(sdl_keycode: Keycode) -> Option<u16>
     }
 }
 
-fn window_call_keydown(vm: &mut VM, window_id: u32, keycode: Keycode) -> ExitReason
+fn window_call_keydown(vm: &mut VM, window_id: u32, keycode: Keycode) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_keydown;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     let keycode = translate_keycode(keycode);
@@ -440,17 +703,17 @@ fn window_call_keydown(vm: &mut VM, window_id: u32, keycode: Keycode) -> ExitRea
     if let Some(keycode) = keycode {
         vm.call(cb, &[Value::from(window.window_id), Value::from(keycode)])
     } else {
-        ExitReason::default()
+        Ok(ExitReason::default())
     }
 }
 
-fn window_call_keyup(vm: &mut VM, window_id: u32, keycode: Keycode) -> ExitReason
+fn window_call_keyup(vm: &mut VM, window_id: u32, keycode: Keycode) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_keyup;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     let keycode = translate_keycode(keycode);
@@ -458,17 +721,17 @@ fn window_call_keyup(vm: &mut VM, window_id: u32, keycode: Keycode) -> ExitReaso
     if let Some(keycode) = keycode {
         vm.call(cb, &[Value::from(window.window_id), Value::from(keycode)])
     } else {
-        ExitReason::default()
+        Ok(ExitReason::default())
     }
 }
 
-fn window_call_textinput(vm: &mut VM, window_id: u32, utf8_byte: u8) -> ExitReason
+fn window_call_textinput(vm: &mut VM, window_id: u32, utf8_byte: u8) -> Result<ExitReason, Trap>
 {
     let window = get_window(0);
     let cb = window.cb_textinput;
 
     if cb == 0 {
-        return ExitReason::default();
+        return Ok(ExitReason::default());
     }
 
     vm.call(cb, &[Value::from(window.window_id), Value::from(utf8_byte)])
@@ -0,0 +1,453 @@
+// Static verification of the stack effects of a program
+//
+// Each function is analyzed separately, starting from the program
+// entry point and from the target of every call instruction. The
+// stack depth is tracked relative to the base pointer of the frame
+// along all control-flow paths. The verifier rejects code where:
+//
+// - an instruction pops more values than the frame holds
+// - get_local, set_local or getn refer past the top of the stack
+// - two paths reach the same instruction with different depths
+// - ret or exit is reached without a value to return
+// - execution can run past the end of the code
+// - a branch or call lands in the middle of an instruction
+//
+// Code which is only reachable through addresses computed at run
+// time, such as callbacks registered with syscalls, is not verified.
+
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+use crate::vm::Op;
+use crate::image::{Program, Reader, decode_op};
+use crate::sys::constants::SYSCALL_DESCS;
+
+#[derive(Debug)]
+pub struct VerifyError
+{
+    /// Address of the offending instruction
+    pub pc: usize,
+
+    msg: String,
+}
+
+impl fmt::Display for VerifyError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stack verification failed: {}", self.msg)
+    }
+}
+
+/// Decoded instruction, with its immediate operand and the
+/// targets it can transfer control to
+struct Insn
+{
+    op: Op,
+
+    /// Index operand of getn, get_local, set_local, or the
+    /// argument count of call and call_host
+    idx: usize,
+
+    /// Branch or call target
+    target: Option<usize>,
+
+    /// Address of the next instruction
+    next: usize,
+}
+
+fn decode_insn(code: &[u8], pc: usize) -> Result<Insn, String>
+{
+    let mut reader = Reader { bytes: code, pos: pc };
+    let eof = |_| "unexpected end of code".to_string();
+
+    let op = match decode_op(reader.read_u8().map_err(eof)?) {
+        Some(op) => op,
+        None => return Err("invalid opcode".to_string()),
+    };
+
+    let mut idx = 0;
+    let mut target = None;
+
+    match op {
+        Op::push_i8 | Op::getn | Op::get_arg | Op::set_arg | Op::get_local | Op::set_local => {
+            idx = reader.read_u8().map_err(eof)? as usize;
+        }
+
+        Op::push_u32 => {
+            reader.read_u32().map_err(eof)?;
+        }
+
+        Op::push_u64 => {
+            reader.read_u64().map_err(eof)?;
+        }
+
+        Op::jmp | Op::jz | Op::jnz | Op::call | Op::tail_call => {
+            let offset = reader.read_i32().map_err(eof)? as i64;
+            if let Op::call | Op::tail_call = op {
+                idx = reader.read_u8().map_err(eof)? as usize;
+            }
+
+            // Offsets are relative to the end of the instruction
+            let addr = reader.pos as i64 + offset;
+            if addr < 0 || addr as usize >= code.len() {
+                return Err(format!("branch target {} outside of the code", addr));
+            }
+            target = Some(addr as usize);
+        }
+
+        Op::syscall => {
+            idx = reader.read_u16().map_err(eof)? as usize;
+        }
+
+        Op::call_host => {
+            reader.read_u16().map_err(eof)?;
+            idx = reader.read_u8().map_err(eof)? as usize;
+        }
+
+        _ => {}
+    }
+
+    Ok(Insn { op, idx, target, next: reader.pos })
+}
+
+/// Number of values popped and pushed by an instruction
+fn stack_effect(insn: &Insn) -> Result<(usize, usize), String>
+{
+    use Op::*;
+
+    let effect = match insn.op {
+        panic | nop | breakpoint | jmp => (0, 0),
+
+        push_0 | push_1 | push_2 | push_i8 | push_u32 | push_u64 |
+        get_argc | get_arg | get_local => (0, 1),
+
+        pop | set_arg | set_local | jz | jnz | exit | ret => (1, 0),
+
+        dup => (1, 2),
+        swap => (2, 2),
+
+        // getn copies a value without removing anything
+        getn => (insn.idx + 1, insn.idx + 2),
+
+        not_u32 | not_u64 |
+        sx_i8_i32 | sx_i8_i64 | sx_i16_i32 | sx_i16_i64 | sx_i32_i64 |
+        trunc_u8 | trunc_u16 | trunc_u32 |
+        sin_f32 | cos_f32 | tan_f32 | asin_f32 | acos_f32 | atan_f32 | sqrt_f32 |
+        i32_to_f32 | f32_to_i32 |
+        load_u8 | load_u16 | load_u32 | load_u64 |
+        get_var_arg => (1, 1),
+
+        and_u32 | or_u32 | xor_u32 | lshift_u32 | rshift_u32 | rshift_i32 |
+        add_u32 | sub_u32 | mul_u32 | div_u32 | mod_u32 | div_i32 | mod_i32 |
+        eq_u32 | ne_u32 | lt_u32 | le_u32 | gt_u32 | ge_u32 |
+        lt_i32 | le_i32 | gt_i32 | ge_i32 |
+        and_u64 | or_u64 | xor_u64 | lshift_u64 | rshift_u64 | rshift_i64 |
+        add_u64 | sub_u64 | mul_u64 | div_u64 | mod_u64 | div_i64 | mod_i64 |
+        eq_u64 | ne_u64 | lt_u64 | le_u64 | gt_u64 | ge_u64 |
+        lt_i64 | le_i64 | gt_i64 | ge_i64 |
+        add_f32 | sub_f32 | mul_f32 | div_f32 |
+        eq_f32 | ne_f32 | lt_f32 | le_f32 | gt_f32 | ge_f32 => (2, 1),
+
+        store_u8 | store_u16 | store_u32 | store_u64 => (2, 0),
+
+        // The callee pops its arguments and pushes its return value
+        call | call_host => (insn.idx, 1),
+
+        // The callee returns to the caller in place of this function
+        tail_call => (insn.idx, 0),
+
+        syscall => {
+            match SYSCALL_DESCS.get(insn.idx) {
+                Some(Some(desc)) => (desc.argc, if desc.has_ret { 1 } else { 0 }),
+                _ => return Err(format!("unknown syscall {}", insn.idx)),
+            }
+        }
+
+        OP_EXT => return Err("invalid opcode".to_string()),
+    };
+
+    Ok(effect)
+}
+
+/// Find the address of every instruction, decoding the code from
+/// start to end. Code which can't be decoded is only an error if it
+/// is reachable, so decoding stops there.
+fn insn_starts(code: &[u8]) -> HashSet<usize>
+{
+    let mut starts = HashSet::new();
+    let mut pc = 0;
+
+    while pc < code.len() {
+        starts.insert(pc);
+        match decode_insn(code, pc) {
+            Ok(insn) => pc = insn.next,
+            Err(_) => break,
+        }
+    }
+
+    starts
+}
+
+/// Verify the stack effects of one function, and collect
+/// the functions it calls
+fn verify_fun(code: &[u8], starts: &HashSet<usize>, entry: usize, callees: &mut Vec<usize>) -> Result<(), (usize, String)>
+{
+    // Stack depth before each instruction visited so far
+    let mut depths: HashMap<usize, usize> = HashMap::new();
+    let mut worklist = vec![(entry, 0)];
+
+    while let Some((pc, depth)) = worklist.pop() {
+        if let Some(&prev_depth) = depths.get(&pc) {
+            if prev_depth != depth {
+                return Err((pc, format!(
+                    "inconsistent stack depth at join point, {} and {}",
+                    prev_depth,
+                    depth
+                )));
+            }
+            continue;
+        }
+        depths.insert(pc, depth);
+
+        if pc >= code.len() {
+            return Err((pc, "execution runs past the end of the code".to_string()));
+        }
+
+        let insn = decode_insn(code, pc).map_err(|msg| (pc, msg))?;
+
+        if let Some(target) = insn.target {
+            if !starts.contains(&target) {
+                return Err((pc, format!("branch target {} is inside an instruction", target)));
+            }
+        }
+        let (pops, pushes) = stack_effect(&insn).map_err(|msg| (pc, msg))?;
+
+        if depth < pops {
+            let msg = match insn.op {
+                Op::ret | Op::exit => format!("{:?} with no value on the stack", insn.op),
+                _ => format!("stack underflow in {:?}, depth {}", insn.op, depth),
+            };
+            return Err((pc, msg));
+        }
+
+        // Locals are indexed from the base pointer
+        let local_ok = match insn.op {
+            Op::get_local => insn.idx < depth,
+            Op::set_local => insn.idx < depth - 1,
+            _ => true,
+        };
+        if !local_ok {
+            return Err((pc, format!("invalid local index {} in {:?}, depth {}", insn.idx, insn.op, depth)));
+        }
+
+        let depth = depth - pops + pushes;
+
+        match insn.op {
+            Op::panic | Op::exit | Op::ret => {}
+
+            Op::jmp => worklist.push((insn.target.unwrap(), depth)),
+
+            Op::jz | Op::jnz => {
+                worklist.push((insn.target.unwrap(), depth));
+                worklist.push((insn.next, depth));
+            }
+
+            Op::call => {
+                callees.push(insn.target.unwrap());
+                worklist.push((insn.next, depth));
+            }
+
+            Op::tail_call => callees.push(insn.target.unwrap()),
+
+            _ => worklist.push((insn.next, depth)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify the stack effects of every function reachable from
+/// the program entry point
+pub fn verify(program: &Program) -> Result<(), VerifyError>
+{
+    let code = program.code.as_bytes();
+
+    // An empty program has nothing to run
+    if code.is_empty() {
+        return Ok(());
+    }
+
+    let starts = insn_starts(code);
+    let mut visited = HashSet::new();
+    let mut funs = vec![program.entry];
+
+    while let Some(fun) = funs.pop() {
+        if !visited.insert(fun) {
+            continue;
+        }
+
+        if let Err((pc, msg)) = verify_fun(code, &starts, fun, &mut funs) {
+            let fun_name = match program.symbol_at(fun) {
+                Some(name) => name.to_string(),
+                None => format!("at {}", fun),
+            };
+
+            return Err(VerifyError {
+                pc,
+                msg: format!("{} (function {}, address {})", msg, fun_name, pc)
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::asm::Assembler;
+
+    fn verify_src(src: &str) -> Result<(), VerifyError>
+    {
+        dbg!(src);
+        let program = Assembler::new().assemble_str(src).unwrap();
+        verify(&program)
+    }
+
+    fn verify_ok(src: &str)
+    {
+        verify_src(src).unwrap();
+    }
+
+    fn verify_fails(src: &str)
+    {
+        assert!(verify_src(src).is_err());
+    }
+
+    #[test]
+    fn test_valid()
+    {
+        verify_ok("");
+        verify_ok("push_i8 1; exit;");
+        verify_ok("push_i8 1; push_i8 2; add_u64; dup; pop; exit;");
+        verify_ok("push_i8 1; push_i8 2; getn 1; swap; pop; pop; exit;");
+        verify_ok("push_0; jz L0; push_i8 1; jmp L1; L0: push_i8 2; L1: exit;");
+        verify_ok("push_0; LOOP: push_1; add_u64; dup; push_i8 10; eq_u64; jz LOOP; exit;");
+
+        // Locals and calls
+        verify_ok("push_i8 5; call FN, 1; exit; FN: push_0; get_arg 0; set_local 0; get_local 0; ret;");
+        verify_ok("call FN, 0; exit; FN: push_0; push_1; push_2; ret;");
+
+        // Tail calls end the function, and the callee is checked too
+        verify_ok("push_i8 5; call A, 1; exit; A: push_0; get_arg 0; push_1; tail_call B, 2; B: get_arg 1; ret;");
+        verify_fails("push_i8 5; call A, 1; exit; A: tail_call B, 1; B: get_arg 0; ret;");
+        verify_fails("call A, 0; exit; A: push_0; tail_call B, 0; B: pop; push_0; ret;");
+
+        // Unreachable code is not checked
+        verify_ok("push_i8 1; exit; pop; pop; ret;");
+        verify_ok("panic;");
+    }
+
+    #[test]
+    fn test_underflow()
+    {
+        verify_fails("pop; push_i8 1; exit;");
+        verify_fails("push_i8 1; add_u64; exit;");
+        verify_fails("push_i8 1; getn 1; exit;");
+        verify_fails("push_i8 1; store_u64; push_0; exit;");
+
+        // Arguments are below the frame and can't be popped
+        verify_fails("push_i8 1; push_i8 2; call FN, 2; exit; FN: pop; push_0; ret;");
+        verify_fails("push_i8 1; call FN, 2; exit; FN: push_0; ret;");
+    }
+
+    #[test]
+    fn test_locals()
+    {
+        verify_fails("get_local 0; exit;");
+        verify_fails("push_0; get_local 1; exit;");
+        verify_fails("push_0; set_local 0; push_0; exit;");
+        verify_ok("push_0; push_0; set_local 0; exit;");
+    }
+
+    #[test]
+    fn test_join()
+    {
+        // One branch pushes an extra value
+        verify_fails("push_0; jz L0; push_i8 1; push_i8 1; jmp L1; L0: push_i8 2; L1: exit;");
+
+        // Loop which grows the stack on every iteration
+        verify_fails("push_0; LOOP: push_1; push_0; jz LOOP; exit;");
+    }
+
+    #[test]
+    fn test_return()
+    {
+        verify_fails("exit;");
+        verify_fails("ret;");
+        verify_fails("call FN, 0; exit; FN: ret;");
+        verify_fails("push_i8 1; call FN, 1; exit; FN: ret;");
+
+        // Falling off the end of the code
+        verify_fails("push_0;");
+        verify_fails("push_0; jz L0; push_0; exit; L0:");
+    }
+
+    #[test]
+    fn test_branch_targets()
+    {
+        use crate::vm::MemBlock;
+
+        // Jump back into the operand of push_u32
+        let mut program = Assembler::new().assemble_str("").unwrap();
+        let mut code = vec![Op::push_u32 as u8, 0, 0, 0, 0, Op::jmp as u8];
+        code.extend((-9 as i32).to_le_bytes());
+        program.code = MemBlock::from_bytes(code);
+
+        let error = verify(&program).unwrap_err();
+        assert_eq!(error.pc, 5);
+        assert!(error.to_string().contains("branch target 1 is inside an instruction"), "{}", error);
+    }
+
+    #[test]
+    fn test_error_pc()
+    {
+        let error = verify_src("push_i8 1; add_u64; exit;").unwrap_err();
+        assert_eq!(error.pc, 2);
+
+        let error = verify_src("call FN, 0; exit; FN: push_0; pop; pop; push_0; ret;").unwrap_err();
+        assert_eq!(error.pc, 9);
+    }
+
+    #[test]
+    fn test_syscalls()
+    {
+        verify_ok("push_i8 100; syscall print_i64; push_0; exit;");
+        verify_ok("syscall time_current_ms; exit;");
+        verify_fails("syscall print_i64; push_0; exit;");
+    }
+
+    #[test]
+    fn test_host_fns()
+    {
+        verify_ok("push_i8 1; push_i8 2; call_host foo, 2; exit;");
+        verify_ok("call_host foo, 0; exit;");
+        verify_fails("push_i8 1; call_host foo, 2; exit;");
+    }
+
+    #[test]
+    fn test_examples()
+    {
+        for file in std::fs::read_dir("./examples").unwrap() {
+            let file_name = file.unwrap().path().display().to_string();
+            if !file_name.ends_with(".asm") {
+                continue;
+            }
+
+            let program = Assembler::new().assemble_file(&file_name).unwrap();
+            if let Err(error) = verify(&program) {
+                panic!("{}: {}", file_name, error);
+            }
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use std::mem::{transmute, size_of};
 use std::collections::HashSet;
 use std::ffi::CStr;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, TryLockError};
 use crate::sys::*;
+use crate::debug::DebugInfo;
+use crate::debugger::Debugger;
 
 /// Instruction opcodes
 /// Note: commonly used upcodes should be in the [0, 127] range (one byte)
 ///       less frequently used opcodes can take multiple bytes if necessary.
 #[allow(non_camel_case_types)]
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 #[repr(u8)]
 pub enum Op
 {
@@ -79,6 +84,8 @@ pub enum Op
     rshift_i32,
 
     // 32-bit integer arithmetic
+    // Results wrap around on overflow. Division and remainder
+    // by zero trap, see VM::set_overflow_check for signed overflow
     add_u32,
     sub_u32,
     mul_u32,
@@ -230,6 +237,11 @@ pub enum Op
     // call <offset:i32> <num_args:u8> (arg0, arg1, ..., argN)
     call,
 
+    // Call a function in place of the current one, reusing its stack
+    // frame, so that the callee returns directly to the caller
+    // tail_call <offset:i32> <num_args:u8> (arg0, arg1, ..., argN)
+    tail_call,
+
     // Call a function pointer passed as argument
     // call <num_args:u8> (f_ptr, arg0, arg1, ..., argN)
     //call_fp,
@@ -239,6 +251,10 @@ pub enum Op
     // syscall <syscall_idx:u16> (arg0, arg1, ..., argN)
     syscall,
 
+    // Call a function registered by the program embedding the VM
+    // call_host <host_fn_idx:u16> <num_args:u8> (arg0, arg1, ..., argN)
+    call_host,
+
     // Return to caller function
     // ret (value)
     ret,
@@ -391,6 +407,18 @@ impl MemBlock
         }
     }
 
+    /// Create a memory block holding the given bytes
+    pub fn from_bytes(data: Vec<u8>) -> Self
+    {
+        Self { data }
+    }
+
+    /// Get the contents of the memory block
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.data
+    }
+
     /// Get the memory block size in bytes
     pub fn len(&self) -> usize
     {
@@ -470,6 +498,9 @@ impl MemBlock
 
 struct StackFrame
 {
+    // Address of the function executing in this frame
+    fun_addr: usize,
+
     // Previous base pointer at the time of call
     prev_bp: usize,
 
@@ -480,6 +511,7 @@ struct StackFrame
     argc: usize,
 }
 
+#[derive(Debug, PartialEq)]
 pub enum ExitReason
 {
     Return(Value),
@@ -487,6 +519,108 @@ pub enum ExitReason
     //Panic,
 }
 
+/// Outcome of a call with a limited instruction budget
+#[derive(Debug, PartialEq)]
+pub enum RunResult
+{
+    /// The function returned or the program exited
+    Done(ExitReason),
+
+    /// Execution panicked, with the panic message
+    Panic(String),
+
+    /// The budget ran out before the function finished
+    OutOfFuel,
+
+    /// An instruction trapped, see TrapKind
+    Trap(Trap),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrapKind
+{
+    /// Integer division or remainder by zero
+    DivByZero,
+
+    /// Signed division of the minimum value by -1, which only
+    /// traps when overflow checks are enabled
+    Overflow,
+
+    /// A call would nest deeper than the maximum call depth
+    StackOverflow { max_call_depth: usize },
+
+    /// Load or store in the guard region at address 0, which is
+    /// only reserved when a null guard is set
+    NullDereference { addr: usize, size: usize },
+
+    /// Load or store of size bytes which goes past the end of the heap
+    OutOfBounds { addr: usize, size: usize },
+
+    /// Load or store at an address which isn't a multiple of its size
+    Unaligned { addr: usize, size: usize },
+}
+
+impl fmt::Display for TrapKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapKind::DivByZero => write!(f, "division by zero"),
+            TrapKind::Overflow => write!(f, "integer overflow"),
+            TrapKind::StackOverflow { .. } => write!(f, "stack overflow"),
+            TrapKind::NullDereference { .. } => write!(f, "null pointer dereference"),
+            TrapKind::OutOfBounds { .. } => write!(f, "out of bounds memory access"),
+            TrapKind::Unaligned { .. } => write!(f, "unaligned memory access"),
+        }
+    }
+}
+
+/// Error which stops execution. Unlike panics, traps are caused
+/// by the program computing with bad values or accessing invalid
+/// memory, and can be handled by the embedder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap
+{
+    pub kind: TrapKind,
+
+    /// Instruction which trapped, and its address
+    pub op: Op,
+    pub pc: usize,
+
+    /// Source position of the instruction, with debug info
+    pub src_loc: Option<String>,
+
+    /// Function address and current address of each stack frame,
+    /// starting with the innermost frame, as with VM::call_stack
+    pub call_stack: Vec<(usize, usize)>,
+}
+
+impl fmt::Display for Trap
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            TrapKind::StackOverflow { max_call_depth } => write!(
+                f,
+                "{}: call depth exceeds {} frames, pc {}",
+                self.kind,
+                max_call_depth,
+                self.pc
+            ),
+            TrapKind::NullDereference { addr, size } |
+            TrapKind::OutOfBounds { addr, size } |
+            TrapKind::Unaligned { addr, size } => write!(
+                f,
+                "{}: {:?} of {} bytes at address {}, pc {}",
+                self.kind,
+                self.op,
+                size,
+                addr,
+                self.pc
+            ),
+            _ => write!(f, "{}: {:?}, pc {}", self.kind, self.op, self.pc),
+        }
+    }
+}
+
 impl Default for ExitReason
 {
     fn default() -> ExitReason {
@@ -494,6 +628,42 @@ impl Default for ExitReason
     }
 }
 
+/// Default maximum size of the heap in bytes
+pub const DEFAULT_MEM_SIZE: usize = 1 << 32;
+
+/// Size of the word ncc reserves at address 0 for null pointers
+pub const NULL_GUARD_SIZE: usize = 8;
+
+/// Default maximum number of stack frames
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Function provided by the program embedding the VM, which compiled
+/// code calls with call_host. It receives the arguments in order and
+/// returns a single value.
+pub type HostFn = Box<dyn FnMut(&mut VM, &[Value]) -> Value + Send>;
+
+struct HostFnEntry
+{
+    name: String,
+    argc: usize,
+
+    // Shared so that the function can be called while borrowing the VM
+    fun: Arc<Mutex<HostFn>>,
+}
+
+#[derive(Debug)]
+pub struct HostFnError
+{
+    msg: String,
+}
+
+impl fmt::Display for HostFnError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
 pub struct VM
 {
     // Host system state
@@ -502,6 +672,12 @@ pub struct VM
     // Heap memory space
     heap: MemBlock,
 
+    // Maximum size the heap can be resized to
+    mem_size: usize,
+
+    // Number of bytes at address 0 which can't be loaded or stored
+    null_guard: usize,
+
     // Code memory space
     code: MemBlock,
 
@@ -510,12 +686,44 @@ pub struct VM
 
     // List of stack frames (activation records)
     frames: Vec<StackFrame>,
+
+    // Maximum number of stack frames, past which calls overflow
+    max_call_depth: usize,
+
+    // Trap on signed division overflow instead of wrapping around
+    overflow_check: bool,
+
+    // Symbols and source positions, for error messages
+    debug_info: DebugInfo,
+
+    // Number of instructions left to execute in the current call
+    fuel: u64,
+
+    // Exit value requested by a host function, taken when
+    // the fuel it zeroed runs out
+    exit_request: Option<Value>,
+
+    // Host functions registered by the embedder
+    host_fns: Vec<HostFnEntry>,
+
+    // Host functions imported by the program, indexed by the operand
+    // of call_host, with the registered function bound to each name
+    host_imports: Vec<(String, Option<usize>)>,
 }
 
 impl VM
 {
-    pub fn new(code: MemBlock, heap: MemBlock, syscalls: HashSet<u16>) -> Self
+    pub fn new(
+        code: MemBlock,
+        heap: MemBlock,
+        mem_size: usize,
+        syscalls: HashSet<u16>,
+        host_fns: Vec<String>,
+        debug_info: DebugInfo
+    ) -> Self
     {
+        assert!(heap.len() <= mem_size);
+
         // Initialize the system state
         let sys_state = SysState::new();
 
@@ -524,16 +732,79 @@ impl VM
             //syscalls: syscall_fns,
             code,
             heap,
+            mem_size,
+            null_guard: 0,
             stack: Vec::default(),
             frames: Vec::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            overflow_check: false,
+            debug_info,
+            fuel: u64::MAX,
+            exit_request: None,
+            host_fns: Vec::default(),
+            host_imports: host_fns.into_iter().map(|name| (name, None)).collect(),
         }
     }
 
+    /// Register a host function which the program can call by name.
+    /// The name can't be that of a syscall or of another host function.
+    pub fn register_host_fn<F>(&mut self, name: &str, argc: usize, fun: F) -> Result<(), HostFnError>
+    where F: FnMut(&mut VM, &[Value]) -> Value + Send + 'static
+    {
+        use crate::sys::constants::SYSCALL_DESCS;
+
+        let error = |msg: String| Err(HostFnError { msg });
+
+        if SYSCALL_DESCS.iter().flatten().any(|desc| desc.name == name) {
+            return error(format!("host function \"{}\" has the same name as a syscall", name));
+        }
+
+        if self.host_fns.iter().any(|entry| entry.name == name) {
+            return error(format!("host function \"{}\" is already registered", name));
+        }
+
+        // The argument count of call_host is a single byte
+        if argc > u8::MAX as usize {
+            return error(format!("host function \"{}\" takes too many arguments", name));
+        }
+
+        let fn_idx = self.host_fns.len();
+        self.host_fns.push(HostFnEntry {
+            name: name.to_string(),
+            argc,
+            fun: Arc::new(Mutex::new(Box::new(fun))),
+        });
+
+        for (import_name, binding) in &mut self.host_imports {
+            if import_name == name {
+                *binding = Some(fn_idx);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Names of the host functions used by the program which
+    /// haven't been registered
+    pub fn missing_host_fns(&self) -> Vec<&str>
+    {
+        self.host_imports.iter()
+            .filter(|(_, binding)| binding.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
     pub fn stack_size(&self) -> usize
     {
         self.stack.len()
     }
 
+    /// Values on the stack, with the top of the stack last
+    pub fn stack(&self) -> &[Value]
+    {
+        &self.stack
+    }
+
     pub fn push<T>(&mut self, val: T) where Value: From<T>
     {
         self.stack.push(Value::from(val));
@@ -553,16 +824,158 @@ impl VM
         self.heap.len()
     }
 
-    /// Resize the heap to a new size in bytes
-    pub fn resize_heap(&mut self, num_bytes: usize)
+    /// Get the maximum size of the heap in bytes
+    pub fn mem_size(&self) -> usize
+    {
+        self.mem_size
+    }
+
+    /// Make loads and stores to the first num_bytes of the heap fail,
+    /// to catch null pointer dereferences in programs which don't
+    /// use that memory
+    pub fn set_null_guard(&mut self, num_bytes: usize)
+    {
+        self.null_guard = num_bytes;
+    }
+
+    /// Stop the program before its next instruction, as if it had
+    /// called exit with the given value. This is meant to be called
+    /// from host functions, e.g. to end a program interrupted while
+    /// it was sleeping.
+    pub fn request_exit(&mut self, val: Value)
+    {
+        self.exit_request = Some(val);
+        self.fuel = 0;
+    }
+
+    /// Set the maximum number of stack frames. Calls which would
+    /// nest deeper trap with TrapKind::StackOverflow.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize)
     {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Make signed division of the minimum value by -1 trap with
+    /// TrapKind::Overflow instead of wrapping around. Addition,
+    /// subtraction and multiplication always wrap, since the same
+    /// instructions are used for signed and unsigned integers.
+    pub fn set_overflow_check(&mut self, overflow_check: bool)
+    {
+        self.overflow_check = overflow_check;
+    }
+
+    /// Resize the heap to a new size in bytes. Returns false if
+    /// the new size is larger than the maximum heap size.
+    pub fn resize_heap(&mut self, num_bytes: usize) -> bool
+    {
+        if num_bytes > self.mem_size {
+            return false;
+        }
+
         self.heap.resize(num_bytes);
+        true
+    }
+
+    /// Copy the arguments of a program to the end of the heap, so that
+    /// they can be passed to main(argc, argv). The heap grows by:
+    ///
+    ///   argv      argc + 1 pointers of 8 bytes, aligned to 8 bytes,
+    ///             the last of which is null
+    ///   strings   the arguments, as null-terminated strings
+    ///
+    /// Returns argc and argv, to be passed as the arguments of the
+    /// entry point, or None if the heap can't grow enough.
+    pub fn write_args(&mut self, args: &[String]) -> Option<[Value; 2]>
+    {
+        let argv = self.heap_size().checked_add(7)? & !7;
+        let mut str_ptr = argv + (args.len() + 1) * 8;
+        let heap_size = args.iter().fold(str_ptr, |size, arg| size + arg.len() + 1);
+
+        if !self.resize_heap(heap_size) {
+            return None;
+        }
+
+        for (idx, arg) in args.iter().enumerate() {
+            let ptr_addr = argv + idx * 8;
+            self.heap.data[ptr_addr..ptr_addr + 8].copy_from_slice(&(str_ptr as u64).to_le_bytes());
+
+            self.heap.data[str_ptr..str_ptr + arg.len()].copy_from_slice(arg.as_bytes());
+            self.heap.data[str_ptr + arg.len()] = 0;
+            str_ptr += arg.len() + 1;
+        }
+
+        let null_addr = argv + args.len() * 8;
+        self.heap.data[null_addr..null_addr + 8].fill(0);
+
+        Some([Value::from(args.len()), Value::from(argv)])
+    }
+
+    /// Check the operands of a division or remainder instruction at
+    /// a given pc. Division by zero always traps, and overflow only
+    /// traps in checked mode, otherwise the result wraps around.
+    fn check_div(&self, div_by_zero: bool, overflow: bool, op: Op, pc: usize) -> Result<(), Trap>
+    {
+        if div_by_zero {
+            Err(self.trap(TrapKind::DivByZero, op, pc))
+        } else if overflow && self.overflow_check {
+            Err(self.trap(TrapKind::Overflow, op, pc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Make a trap for the instruction at a given pc. The call
+    /// stack is filled in once execution has stopped.
+    fn trap(&self, kind: TrapKind, op: Op, pc: usize) -> Trap
+    {
+        Trap {
+            kind,
+            op,
+            pc,
+            src_loc: self.debug_info.line_table.lookup(pc).map(|loc| loc.to_string()),
+            call_stack: Vec::default(),
+        }
+    }
+
+    /// Get a pointer for a load or store instruction at a given pc,
+    /// reporting null, out of bounds and unaligned accesses
+    fn get_mem_ptr<T>(&mut self, op: Op, addr: usize, pc: usize) -> Result<*mut T, Trap>
+    {
+        let num_bytes = size_of::<T>();
+
+        if addr < self.null_guard {
+            return Err(self.trap(TrapKind::NullDereference { addr, size: num_bytes }, op, pc));
+        }
+
+        match addr.checked_add(num_bytes) {
+            Some(end) if end <= self.heap.len() => {}
+            _ => return Err(self.trap(TrapKind::OutOfBounds { addr, size: num_bytes }, op, pc)),
+        }
+
+        if addr & (num_bytes - 1) != 0 {
+            return Err(self.trap(TrapKind::Unaligned { addr, size: num_bytes }, op, pc));
+        }
+
+        Ok(self.get_heap_ptr(addr))
+    }
+
+    /// Get the bytes of a region of the heap, or None if the region is
+    /// out of bounds. Host functions use this to access the memory
+    /// behind pointers passed by the program.
+    pub fn heap_bytes(&mut self, addr: usize, num_bytes: usize) -> Option<&mut [u8]>
+    {
+        if addr < self.null_guard && num_bytes > 0 {
+            return None;
+        }
+
+        let end = addr.checked_add(num_bytes)?;
+        self.heap.data.get_mut(addr..end)
     }
 
     /// Get a pointer to an address/offset in the heap
     pub fn get_heap_ptr<T>(&mut self, addr: usize) -> *mut T
     {
-        if addr + size_of::<T>() > self.heap.len() {
+        if addr.checked_add(size_of::<T>()).is_none_or(|end| end > self.heap.len()) {
             panic!(
                 "attempting to access data of type {} past end of heap",
                 std::any::type_name::<T>()
@@ -585,7 +998,8 @@ impl VM
     /// Get a mutable slice to access a memory region in the heap
     pub fn get_heap_slice<T>(&mut self, addr: usize, num_elems: usize) -> &mut [T]
     {
-        if addr + std::mem::size_of::<T>() * num_elems > self.heap.len() {
+        let end = std::mem::size_of::<T>().checked_mul(num_elems).and_then(|len| addr.checked_add(len));
+        if end.is_none_or(|end| end > self.heap.len()) {
             panic!("attempting to access memory slice past end of heap");
         }
 
@@ -605,6 +1019,17 @@ impl VM
         }
     }
 
+    /// Copy bytes from one place in the heap to another,
+    /// the source and destination may overlap
+    pub fn copy_heap(&mut self, dst_addr: usize, src_addr: usize, num_bytes: usize)
+    {
+        // Check that both ranges are inside the heap
+        self.get_heap_slice::<u8>(src_addr, num_bytes);
+        self.get_heap_slice::<u8>(dst_addr, num_bytes);
+
+        self.heap.data.copy_within(src_addr..src_addr + num_bytes, dst_addr);
+    }
+
     /// Copy an UTF-8 string at a given address in the heap
     pub fn get_heap_str(&mut self, str_ptr: usize) -> &str
     {
@@ -632,14 +1057,117 @@ impl VM
         rust_str
     }
 
-    /// Call a function at a given address
-    pub fn call(&mut self, callee_pc: u64, args: &[Value]) -> ExitReason
+    /// Number of instructions left in the budget of the last call
+    pub fn fuel(&self) -> u64
+    {
+        self.fuel
+    }
+
+    /// Call a function at a given address. Traps are returned, with
+    /// the call stack where they happened, and the VM can be called
+    /// again afterwards. Panics are propagated to the caller.
+    pub fn call(&mut self, callee_pc: u64, args: &[Value]) -> Result<ExitReason, Trap>
+    {
+        // Effectively unlimited, this would take centuries to run out
+        self.fuel = u64::MAX;
+
+        let mut cur_pc = callee_pc as usize;
+        let result = self.call_inner(callee_pc, args, &mut cur_pc, None);
+
+        match result {
+            Ok(Ok(Some(exit_reason))) => Ok(exit_reason),
+            Ok(Ok(None)) => unreachable!("instruction budget exhausted"),
+            Ok(Err(trap)) => {
+                // Discard the state of the interrupted call
+                self.stack.clear();
+                self.frames.clear();
+                Err(trap)
+            }
+            Err(payload) => {
+                // Print the call stack before reporting the panic
+                eprint!("{}", self.debug_info.format_call_stack(&self.call_stack(cur_pc)));
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Format a call stack, as recorded in traps, with the
+    /// function names and source positions from the debug info
+    pub fn format_call_stack(&self, call_stack: &[(usize, usize)]) -> String
+    {
+        self.debug_info.format_call_stack(call_stack)
+    }
+
+    /// Call a function at a given address, executing at most
+    /// max_insns instructions. Panics and traps are caught and
+    /// returned, so that the VM can be called again afterwards.
+    pub fn call_with_fuel(&mut self, callee_pc: u64, args: &[Value], max_insns: u64) -> RunResult
+    {
+        self.fuel = max_insns;
+        self.call_catching(callee_pc, args, None)
+    }
+
+    /// Call a function at a given address, calling the debugger before
+    /// each instruction and when execution panics or traps. Panics
+    /// and traps are caught and returned, as with call_with_fuel.
+    pub fn call_with_debugger(&mut self, callee_pc: u64, args: &[Value], debugger: &mut dyn Debugger) -> RunResult
+    {
+        self.fuel = u64::MAX;
+        self.call_catching(callee_pc, args, Some(debugger))
+    }
+
+    /// Call a function and turn panics into a result
+    fn call_catching(&mut self, callee_pc: u64, args: &[Value], mut debugger: Option<&mut dyn Debugger>) -> RunResult
+    {
+        // Reborrow the debugger so that it can still be notified of panics
+        let step_debugger = debugger.as_mut().map(|debugger| &mut **debugger as &mut dyn Debugger);
+
+        let mut cur_pc = callee_pc as usize;
+        let result = match self.call_inner(callee_pc, args, &mut cur_pc, step_debugger) {
+            Ok(Ok(Some(exit_reason))) => return RunResult::Done(exit_reason),
+            Ok(Ok(None)) => RunResult::OutOfFuel,
+            Ok(Err(trap)) => {
+                if let Some(debugger) = debugger {
+                    debugger.on_panic(self, &trap.to_string());
+                }
+
+                RunResult::Trap(trap)
+            }
+            Err(payload) => {
+                let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+                    msg.to_string()
+                } else if let Some(msg) = payload.downcast_ref::<String>() {
+                    msg.clone()
+                } else {
+                    "unknown panic".to_string()
+                };
+
+                if let Some(debugger) = debugger {
+                    debugger.on_panic(self, &msg);
+                }
+
+                RunResult::Panic(msg)
+            }
+        };
+
+        // Discard the state of the interrupted call
+        self.stack.clear();
+        self.frames.clear();
+
+        result
+    }
+
+    /// Call a function and catch panics, keeping track of the address
+    /// of the current instruction. Returns None if the instruction
+    /// budget runs out.
+    fn call_inner(&mut self, callee_pc: u64, args: &[Value], cur_pc: &mut usize, debugger: Option<&mut dyn Debugger>) -> std::thread::Result<Result<Option<ExitReason>, Trap>>
     {
         assert!(self.stack.len() == 0);
         assert!(self.frames.len() == 0);
 
         // Push a new stack frame
         self.frames.push(StackFrame {
+            fun_addr: callee_pc as usize,
             prev_bp: usize::MAX,
             ret_addr: usize::MAX,
             argc: args.len(),
@@ -650,13 +1178,66 @@ impl VM
             self.stack.push(*arg);
         }
 
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.eval(callee_pc as usize, cur_pc, debugger)
+        }));
+
+        // Record the call stack so that callers can report it
+        if let Ok(Err(mut trap)) = result {
+            trap.call_stack = self.call_stack(*cur_pc);
+            return Ok(Err(trap));
+        }
+
+        result
+    }
+
+    /// Get the function address and the current address of each
+    /// stack frame, starting with the innermost frame
+    pub fn call_stack(&self, cur_pc: usize) -> Vec<(usize, usize)>
+    {
+        let mut call_stack = Vec::new();
+
+        for (idx, frame) in self.frames.iter().enumerate().rev() {
+            // Outer frames are executing the call instruction
+            // which returns into the next frame
+            let pc = match self.frames.get(idx + 1) {
+                Some(next_frame) => next_frame.ret_addr - 6,
+                None => cur_pc,
+            };
+
+            call_stack.push((frame.fun_addr, pc));
+        }
+
+        call_stack
+    }
+
+    /// Execute instructions starting at a given address, keeping
+    /// track of the address of the current instruction. Returns
+    /// None if the instruction budget runs out.
+    fn eval(&mut self, callee_pc: usize, cur_pc: &mut usize, mut debugger: Option<&mut dyn Debugger>) -> Result<Option<ExitReason>, Trap>
+    {
         // The base pointer will point at the first local
         let mut bp = self.stack.len();
-        let mut pc = callee_pc as usize;
+        let mut pc = callee_pc;
 
         // For each instruction to execute
         loop
         {
+            *cur_pc = pc;
+
+            // Every instruction costs one unit of fuel, which also
+            // bounds loops and recursion
+            if self.fuel == 0 {
+                if let Some(val) = self.exit_request.take() {
+                    self.stack.clear();
+                    self.frames.clear();
+                    return Ok(Some(ExitReason::Exit(val)));
+                }
+
+                return Ok(None);
+            }
+            self.fuel -= 1;
+
             if pc >= self.code.len() {
                 panic!("pc outside bounds of code space")
             }
@@ -664,6 +1245,10 @@ impl VM
             let op = self.code.read_pc::<Op>(&mut pc);
             //dbg!(op);
 
+            if let Some(debugger) = &mut debugger {
+                debugger.on_step(self, *cur_pc, op);
+            }
+
             match op
             {
                 Op::panic => panic!("execution error, encountered panic opcode"),
@@ -693,6 +1278,11 @@ impl VM
                     self.push(b);
                 }
 
+                Op::get_argc => {
+                    let argc = self.frames[self.frames.len() - 1].argc;
+                    self.push(Value::from(argc));
+                }
+
                 Op::get_arg => {
                     let idx = self.code.read_pc::<u8>(&mut pc) as usize;
 
@@ -850,40 +1440,32 @@ impl VM
                     );
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::div_u32 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_u32() / v1.as_u32()
-                    );
+                    let v1 = self.pop().as_u32();
+                    let v0 = self.pop().as_u32();
+                    self.check_div(v1 == 0, false, op, *cur_pc)?;
+                    self.push(v0 / v1);
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::mod_u32 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_u32() % v1.as_u32()
-                    );
+                    let v1 = self.pop().as_u32();
+                    let v0 = self.pop().as_u32();
+                    self.check_div(v1 == 0, false, op, *cur_pc)?;
+                    self.push(v0 % v1);
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::div_i32 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_i32() / v1.as_i32()
-                    );
+                    let v1 = self.pop().as_i32();
+                    let v0 = self.pop().as_i32();
+                    self.check_div(v1 == 0, v0 == i32::MIN && v1 == -1, op, *cur_pc)?;
+                    self.push(v0.wrapping_div(v1));
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::mod_i32 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_i32() % v1.as_i32()
-                    );
+                    let v1 = self.pop().as_i32();
+                    let v0 = self.pop().as_i32();
+                    self.check_div(v1 == 0, v0 == i32::MIN && v1 == -1, op, *cur_pc)?;
+                    self.push(v0.wrapping_rem(v1));
                 }
 
                 Op::eq_u32 => {
@@ -1017,40 +1599,32 @@ impl VM
                     );
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::div_u64 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_u64() / v1.as_u64()
-                    );
+                    let v1 = self.pop().as_u64();
+                    let v0 = self.pop().as_u64();
+                    self.check_div(v1 == 0, false, op, *cur_pc)?;
+                    self.push(v0 / v1);
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::mod_u64 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_u64() % v1.as_u64()
-                    );
+                    let v1 = self.pop().as_u64();
+                    let v0 = self.pop().as_u64();
+                    self.check_div(v1 == 0, false, op, *cur_pc)?;
+                    self.push(v0 % v1);
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::div_i64 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_i64() / v1.as_i64()
-                    );
+                    let v1 = self.pop().as_i64();
+                    let v0 = self.pop().as_i64();
+                    self.check_div(v1 == 0, v0 == i64::MIN && v1 == -1, op, *cur_pc)?;
+                    self.push(v0.wrapping_div(v1));
                 }
 
-                // Division by zero will cause a panic (this is intentional)
                 Op::mod_i64 => {
-                    let v1 = self.pop();
-                    let v0 = self.pop();
-                    self.push(
-                        v0.as_i64() % v1.as_i64()
-                    );
+                    let v1 = self.pop().as_i64();
+                    let v0 = self.pop().as_i64();
+                    self.check_div(v1 == 0, v0 == i64::MIN && v1 == -1, op, *cur_pc)?;
+                    self.push(v0.wrapping_rem(v1));
                 }
 
                 Op::eq_u64 => {
@@ -1274,28 +1848,28 @@ impl VM
 
                 Op::load_u8 => {
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     let val: u8 = unsafe { *heap_ptr };
                     self.push(val);
                 }
 
                 Op::load_u16 => {
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     let val: u16 = unsafe { *heap_ptr };
                     self.push(val);
                 }
 
                 Op::load_u32 => {
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     let val: u32 = unsafe { *heap_ptr };
                     self.push(val);
                 }
 
                 Op::load_u64 => {
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     let val: u64 = unsafe { *heap_ptr };
                     self.push(val);
                 }
@@ -1303,28 +1877,28 @@ impl VM
                 Op::store_u8 => {
                     let val = self.pop().as_u8();
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     unsafe { *heap_ptr = val; }
                 }
 
                 Op::store_u16 => {
                     let val = self.pop().as_u16();
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     unsafe { *heap_ptr = val; }
                 }
 
                 Op::store_u32 => {
                     let val = self.pop().as_u32();
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     unsafe { *heap_ptr = val; }
                 }
 
                 Op::store_u64 => {
                     let val = self.pop().as_u64();
                     let addr = self.pop().as_usize();
-                    let heap_ptr = self.get_heap_ptr(addr);
+                    let heap_ptr = self.get_mem_ptr(op, addr, *cur_pc)?;
                     unsafe { *heap_ptr = val; }
                 }
 
@@ -1360,7 +1934,15 @@ impl VM
                     let num_args = self.code.read_pc::<u8>(&mut pc) as usize;
                     assert!(num_args <= self.stack.len() - bp);
 
+                    if self.frames.len() >= self.max_call_depth {
+                        let kind = TrapKind::StackOverflow { max_call_depth: self.max_call_depth };
+                        return Err(self.trap(kind, op, *cur_pc));
+                    }
+
+                    let fun_addr = ((pc as isize) + offset) as usize;
+
                     self.frames.push(StackFrame {
+                        fun_addr,
                         prev_bp: bp,
                         ret_addr: pc,
                         argc: num_args,
@@ -1368,7 +1950,30 @@ impl VM
 
                     // The base pointer will point at the first local
                     bp = self.stack.len();
-                    pc = ((pc as isize) + offset) as usize;
+                    pc = fun_addr;
+                }
+
+                // tail_call <offset:i32> <num_args:u8> (arg0, arg1, ..., argN)
+                Op::tail_call => {
+                    let offset = self.code.read_pc::<i32>(&mut pc) as isize;
+                    let num_args = self.code.read_pc::<u8>(&mut pc) as usize;
+                    assert!(num_args <= self.stack.len() - bp);
+
+                    let fun_addr = ((pc as isize) + offset) as usize;
+                    let top_frame = self.frames.last_mut().unwrap();
+
+                    // Replace the arguments and locals of the current
+                    // function by the arguments of the callee
+                    let args_start = bp - top_frame.argc;
+                    let new_args_start = self.stack.len() - num_args;
+                    self.stack.drain(args_start..new_args_start);
+
+                    // The frame keeps the return address of the caller
+                    top_frame.fun_addr = fun_addr;
+                    top_frame.argc = num_args;
+
+                    bp = self.stack.len();
+                    pc = fun_addr;
                 }
 
                 Op::syscall => {
@@ -1403,6 +2008,13 @@ impl VM
                             fun(self, a0, a1)
                         }
 
+                        SysCallFn::Fn2_1(fun) => {
+                            let a1 = self.pop();
+                            let a0 = self.pop();
+                            let v = fun(self, a0, a1);
+                            self.push(v);
+                        }
+
                         SysCallFn::Fn3_0(fun) => {
                             let a2 = self.pop();
                             let a1 = self.pop();
@@ -1410,6 +2022,14 @@ impl VM
                             fun(self, a0, a1, a2)
                         }
 
+                        SysCallFn::Fn3_1(fun) => {
+                            let a2 = self.pop();
+                            let a1 = self.pop();
+                            let a0 = self.pop();
+                            let v = fun(self, a0, a1, a2);
+                            self.push(v);
+                        }
+
                         SysCallFn::Fn4_0(fun) => {
                             let a3 = self.pop();
                             let a2 = self.pop();
@@ -1429,6 +2049,47 @@ impl VM
                     }
                 }
 
+                Op::call_host => {
+                    let import_idx = self.code.read_pc::<u16>(&mut pc) as usize;
+                    let num_args = self.code.read_pc::<u8>(&mut pc) as usize;
+
+                    let entry = match &self.host_imports[import_idx] {
+                        (_, Some(fn_idx)) => &self.host_fns[*fn_idx],
+                        (name, None) => panic!("host function \"{}\" is not registered", name),
+                    };
+
+                    if entry.argc != num_args {
+                        panic!(
+                            "host function \"{}\" takes {} arguments but {} were passed",
+                            entry.name,
+                            entry.argc,
+                            num_args
+                        );
+                    }
+
+                    if self.stack.len() < num_args {
+                        panic!("tried to pop when the stack is empty");
+                    }
+
+                    let host_fn = entry.fun.clone();
+                    let args = self.stack.split_off(self.stack.len() - num_args);
+
+                    // A panic in the host function poisons the lock, but
+                    // leaves the function itself usable
+                    let mut fun = match host_fn.try_lock() {
+                        Ok(fun) => fun,
+                        Err(TryLockError::Poisoned(error)) => error.into_inner(),
+                        Err(TryLockError::WouldBlock) => panic!(
+                            "host function \"{}\" called recursively",
+                            self.host_imports[import_idx].0
+                        ),
+                    };
+
+                    let v = fun(self, &args);
+                    drop(fun);
+                    self.push(v);
+                }
+
                 Op::exit => {
                     if self.stack.len() <= bp {
                         panic!("exit with no return value on stack");
@@ -1437,7 +2098,7 @@ impl VM
                     let val = self.pop();
                     self.stack.clear();
                     self.frames.clear();
-                    return ExitReason::Exit(val);
+                    return Ok(Some(ExitReason::Exit(val)));
                 }
 
                 Op::ret => {
@@ -1451,7 +2112,7 @@ impl VM
                     if self.frames.len() == 1 {
                         self.stack.clear();
                         self.frames.clear();
-                        return ExitReason::Return(ret_val);
+                        return Ok(Some(ExitReason::Return(ret_val)));
                     }
 
                     assert!(self.frames.len() > 0);
@@ -1475,7 +2136,7 @@ impl VM
 }
 
 #[cfg(test)]
-mod tests
+pub(crate) mod tests
 {
     use super::*;
     use crate::asm::*;
@@ -1485,7 +2146,7 @@ mod tests
         dbg!(src);
         let asm = Assembler::new();
         let mut vm = asm.parse_str(src).unwrap();
-        let result = vm.call(0, &[]);
+        let result = vm.call(0, &[]).unwrap();
         assert!(vm.stack.len() == 0 && vm.frames.len() == 0);
 
         match result
@@ -1571,6 +2232,10 @@ mod tests
         eval_i64("call FN, 0; exit; FN: push_i8 33; ret;", 33);
         eval_i64("push_i8 3; call FN, 1; exit; FN: get_arg 0; push_i8 1; add_u64; ret;", 4);
 
+        // Argument count
+        eval_i64("push_i8 3; push_i8 4; call FN, 2; exit; FN: get_argc; ret;", 2);
+        eval_i64("call FN, 0; exit; FN: get_argc; ret;", 0);
+
         // set_arg
         eval_i64("push_i8 3; call FN, 1; exit; FN: push 7; set_arg 0; get_arg 0; ret;", 7);
 
@@ -1580,6 +2245,15 @@ mod tests
         // Recursive decrement function
         eval_i64("push 10; call DEC, 1; exit; DEC: get_arg 0; dup; jz ZERO; push 1; sub_u64; call DEC, 1; ret; ZERO: ret;", 0);
 
+        // Recursive fibonacci function
+        let fib = concat!(
+            "push 10; call FIB, 1; exit;",
+            "FIB: get_arg 0; push 2; lt_i64; jz REC; get_arg 0; ret;",
+            "REC: get_arg 0; push 1; sub_u64; call FIB, 1;",
+            "get_arg 0; push 2; sub_u64; call FIB, 1; add_u64; ret;",
+        );
+        eval_i64(fib, 55);
+
         // Regression: stack corruption
         eval_i64("push 5; call foo, 0; pop; exit; foo: push 2; push 0; ret;", 5);
     }
@@ -1590,6 +2264,465 @@ mod tests
         eval_src(".data; LABEL: .zero 256; .code; push LABEL; push 255; push 0; syscall memset; push 0; exit;");
     }
 
+    #[test]
+    fn test_input()
+    {
+        let mut vm = Assembler::new().parse_str("syscall read_char; exit;").unwrap();
+        vm.sys_state.set_input(std::io::Cursor::new("a\n"));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(b'a')));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(b'\n')));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(-1 as i64)));
+
+        // Lines longer than the buffer are split across reads
+        let src = ".data; .u64 0; BUF: .zero 8; .code; push BUF; push 8; syscall read_line; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.sys_state.set_input(std::io::Cursor::new("hi\nlong line\n\nlast"));
+        for line in ["hi\n", "long li", "ne\n", "\n", "last", ""] {
+            assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(line.len())));
+            assert_eq!(vm.get_heap_str(8), line);
+        }
+
+        // Buffers too small for a byte and the null terminator fail
+        // without consuming any input
+        let src = ".data; .u64 0; BUF: .zero 8; .code; push BUF; get_arg 0; syscall read_line; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.sys_state.set_input(std::io::Cursor::new("a\n"));
+        for buf_size in [0u64, 1] {
+            assert_eq!(vm.call(0, &[Value::from(buf_size)]).unwrap(), ExitReason::Exit(Value::from(-1i64)));
+        }
+        assert_eq!(vm.call(0, &[Value::from(2u64)]).unwrap(), ExitReason::Exit(Value::from(1u64)));
+        assert_eq!(vm.get_heap_str(8), "a");
+
+        // The input is shared with read_i64
+        let src = "syscall read_i64; push 8; push 8; syscall read_line; add_u64; exit;";
+        let mut vm = Assembler::new().parse_str(&format!(".data; .zero 16; .code; {}", src)).unwrap();
+        vm.sys_state.set_input(std::io::Cursor::new("42\nabc\n"));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(46u64)));
+        assert_eq!(vm.get_heap_str(8), "abc\n");
+
+        // Buffers past the end of the heap fail without consuming any input
+        let src = ".data; .zero 16; .code; push 8; get_arg 0; syscall read_line; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.sys_state.set_input(std::io::Cursor::new("abc\n"));
+        for buf_size in [9u64, u64::MAX] {
+            assert_eq!(vm.call(0, &[Value::from(buf_size)]).unwrap(), ExitReason::Exit(Value::from(-1i64)));
+        }
+        assert_eq!(vm.call(0, &[Value::from(8u64)]).unwrap(), ExitReason::Exit(Value::from(4u64)));
+    }
+
+    #[test]
+    fn test_main_args()
+    {
+        let args = |strs: &[&str]| -> Vec<String> {
+            strs.iter().map(|s| s.to_string()).collect()
+        };
+
+        let mut vm = compile_ncc("tests/args/count.c");
+        let argv = vm.write_args(&args(&["prog", "a", "bc"])).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(3u64)));
+
+        // Without arguments, main gets zeros
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(0u64)));
+
+        let mut vm = compile_ncc("tests/args/first_char.c");
+        let argv = vm.write_args(&args(&["prog", "xyz"])).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(b'x')));
+        let argv = vm.write_args(&args(&["prog", ""])).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(0u64)));
+
+        // A main without parameters ignores the arguments
+        let mut vm = compile_ncc("tests/output/hello.c");
+        vm.sys_state.set_output(OutputBuffer::new());
+        let argv = vm.write_args(&args(&["prog"])).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(0u64)));
+    }
+
+    #[test]
+    fn test_exit_codes()
+    {
+        let exit = |val: u64| RunResult::Done(ExitReason::Exit(Value::from(val)));
+
+        let mut vm = compile_ncc("tests/exit/ret_val.c");
+        assert_eq!(vm.call_with_fuel(0, &[], 1000), exit(7));
+
+        let mut vm = compile_ncc("tests/exit/void_main.c");
+        assert_eq!(vm.call_with_fuel(0, &[], 1000), exit(0));
+
+        // exit() unwinds every frame, so the VM can run again
+        let mut vm = compile_ncc("tests/exit/deep_exit.c");
+        assert_eq!(vm.call_with_fuel(0, &[], 10_000), exit(3));
+        assert_eq!(vm.stack_size(), 0);
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(3u64)));
+    }
+
+    #[test]
+    fn test_write_args()
+    {
+        let mut vm = Assembler::new().parse_str(".data; .u8 1; .code; push 0; exit;").unwrap();
+        let [argc, argv] = vm.write_args(&["ab".to_string(), "c".to_string()]).unwrap();
+        assert_eq!(argc.as_u64(), 2);
+
+        // The pointers are aligned after the existing heap, followed by the strings
+        let argv = argv.as_usize();
+        assert_eq!(argv, 8);
+        let ptrs: Vec<u64> = (0..3).map(|idx| *vm.get_heap_slice::<u64>(argv + idx * 8, 1).first().unwrap()).collect();
+        assert_eq!(ptrs, vec![32, 35, 0]);
+        assert_eq!(vm.get_heap_str(32), "ab");
+        assert_eq!(vm.get_heap_str(35), "c");
+        assert_eq!(vm.heap_size(), 37);
+
+        // The heap can't grow past the memory size
+        let mut vm = Assembler::new().assemble_str("push 0; exit;").unwrap().into_vm_with_mem_size(64).unwrap();
+        assert!(vm.write_args(&["x".repeat(64)]).is_none());
+    }
+
+    #[test]
+    fn test_output()
+    {
+        let src = concat!(
+            ".data; .u64 0; STR: .stringz \"hé!\"; .code;",
+            "push -12; syscall print_i64; syscall print_endl;",
+            "push 0xBEEF; syscall print_u64_hex; push STR; syscall print_str;",
+            "push_f32 1.5; syscall print_f32; push 0; exit;",
+        );
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+        vm.call(0, &[]).unwrap();
+        assert_eq!(output.contents(), "-12\nbeefhé!1.5".as_bytes());
+
+        // Strings must be terminated within the heap
+        let mut vm = Assembler::new().parse_str(".data; .u64 0; .fill 8, 65; .code; get_arg 0; syscall print_str; push 0; exit;").unwrap();
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+        assert!(matches!(vm.call_with_fuel(0, &[Value::from(8u64)], 100), RunResult::Panic(_)));
+        assert!(matches!(vm.call_with_fuel(0, &[Value::from(1000u64)], 100), RunResult::Panic(_)));
+        assert!(output.contents().is_empty());
+    }
+
+    #[test]
+    fn test_output_compiled()
+    {
+        let mut vm = compile_ncc("tests/output/hello.c");
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(0u64)));
+        assert_eq!(
+            String::from_utf8(output.contents()).unwrap(),
+            "hello world\n-42 ff ok done 100%\n7 deadbeef\n"
+        );
+    }
+
+    #[test]
+    fn test_window_headless()
+    {
+        use crate::sys::window::{HeadlessWindow, WindowEvent};
+        use crate::sys::constants::{KEY_ESCAPE, WINDOW_EVENT_KEYDOWN, WINDOW_EVENT_MOUSEUP};
+        use crate::sys::time::{FakeClock, register_clock_fns};
+
+        let src = concat!(
+            ".data; .u64 0; EVENT: .zero 16; FRAME: .u32 0xFF0000FF; .u32 0xFF00FF00; TITLE: .stringz \"w\";",
+            ".code; push 2; push 1; push TITLE; push 0; syscall window_create; pop;",
+            "push 0; push FRAME; syscall window_draw_frame;",
+            "push 0; push EVENT; syscall window_poll_event; exit;",
+        );
+
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let window = HeadlessWindow::new();
+        vm.sys_state.window_state.set_backend(window.clone());
+        window.push_event(WindowEvent::KeyDown(KEY_ESCAPE));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(true)));
+        assert_eq!(window.title(), "w");
+        assert_eq!(window.pixel(0, 0), Some([0xFF, 0, 0, 0xFF]));
+        assert_eq!(window.pixel(1, 0), Some([0, 0xFF, 0, 0xFF]));
+        assert_eq!(window.pixel(2, 0), None);
+
+        // The event struct is four 32-bit fields: kind, key code or button, x and y
+        let event = vm.heap_bytes(8, 16).unwrap().to_vec();
+        assert_eq!(event[0..4], WINDOW_EVENT_KEYDOWN.to_le_bytes());
+        assert_eq!(event[4..8], (KEY_ESCAPE as u32).to_le_bytes());
+        assert_eq!(event[8..16], [0; 8]);
+
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let window = HeadlessWindow::new();
+        vm.sys_state.window_state.set_backend(window.clone());
+        window.push_event(WindowEvent::MouseUp { button: 2, x: 7, y: -3 });
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(true)));
+        let event = vm.heap_bytes(8, 16).unwrap().to_vec();
+        assert_eq!(event[0..4], WINDOW_EVENT_MOUSEUP.to_le_bytes());
+        assert_eq!(event[4..8], 2u32.to_le_bytes());
+        assert_eq!(event[8..12], 7i32.to_le_bytes());
+        assert_eq!(event[12..16], (-3i32).to_le_bytes());
+
+        // No more pending events
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.sys_state.window_state.set_backend(HeadlessWindow::new());
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(false)));
+
+        // Pointers to data past the end of the heap are rejected
+        let bad_frame = src.replace("push FRAME", "push 0xFFFF");
+        let mut vm = Assembler::new().parse_str(&bad_frame).unwrap();
+        vm.sys_state.window_state.set_backend(HeadlessWindow::new());
+        assert!(matches!(vm.call_with_fuel(0, &[], 100), RunResult::Panic(_)));
+
+        let bad_event = src.replace("push EVENT", "push_u64 0xFFFF_FFFF_FFFF_FFFF");
+        let mut vm = Assembler::new().parse_str(&bad_event).unwrap();
+        vm.sys_state.window_state.set_backend(HeadlessWindow::new());
+        assert!(matches!(vm.call_with_fuel(0, &[], 100), RunResult::Panic(_)));
+
+        // The gradient example draws until its window is closed
+        let mut vm = compile_ncc("examples/gradient.c");
+        let window = HeadlessWindow::new();
+        vm.sys_state.window_state.set_backend(window.clone());
+        register_clock_fns(&mut vm, FakeClock::new()).unwrap();
+        window.push_event(WindowEvent::Close);
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(0u64)));
+        assert_eq!(window.num_frames(), 1);
+        assert_eq!(window.pixel(0, 0), Some([128, 0, 0, 255]));
+        assert_eq!(window.pixel(255, 0), Some([128, 0, 255, 255]));
+        assert_eq!(window.pixel(10, 200), Some([128, 200, 10, 255]));
+        assert_eq!(window.pixel(255, 255), Some([128, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_window_events()
+    {
+        use crate::sys::window::{HeadlessWindow, WindowEvent};
+        use crate::sys::constants::KEY_A;
+
+        let mut vm = compile_ncc("tests/window/events.c");
+        let window = HeadlessWindow::new();
+        vm.sys_state.window_state.set_backend(window.clone());
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+
+        // Events are polled in the order they happen
+        window.push_event(WindowEvent::KeyDown(KEY_A));
+        window.push_event(WindowEvent::MouseMove { x: 10, y: -2 });
+        window.push_event(WindowEvent::MouseDown { button: 0, x: 10, y: 20 });
+        window.push_event(WindowEvent::MouseUp { button: 2, x: 11, y: 21 });
+        window.push_event(WindowEvent::KeyUp(KEY_A));
+        window.push_event(WindowEvent::Close);
+        window.push_event(WindowEvent::KeyDown(KEY_A));
+
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(0u64)));
+        assert_eq!(
+            String::from_utf8(output.contents()).unwrap(),
+            "keydown 65\nmousemove 10 -2\nmousedown 0 10 20\nmouseup 2 11 21\nkeyup 65\nclose\n"
+        );
+    }
+
+    #[test]
+    fn test_audio_headless()
+    {
+        use crate::sys::audio::{HeadlessAudio, AUDIO_CB_SAMPLES, fill_audio};
+
+        let mut vm = compile_ncc("tests/audio/square.c");
+        let audio = HeadlessAudio::new();
+        vm.sys_state.audio_state.set_backend(audio.clone());
+
+        // The program returns to the event loop after opening the output
+        assert!(matches!(vm.call(0, &[]).unwrap(), ExitReason::Return(_)));
+        assert_eq!(audio.format(), Some((44100, 1)));
+        assert!(audio.take_samples(10).is_empty());
+
+        // Samples are generated ahead of the audio device
+        assert_eq!(fill_audio(&mut vm), Ok(ExitReason::default()));
+        let samples = audio.take_samples(4 * AUDIO_CB_SAMPLES);
+        assert_eq!(samples.len(), 2 * AUDIO_CB_SAMPLES);
+
+        // A period of 440 Hz is 100.2 samples at 44100 Hz
+        assert!(samples[0..51].iter().all(|s| *s == 8000));
+        assert!(samples[51..101].iter().all(|s| *s == -8000));
+        assert_eq!(samples[101], 8000);
+        let num_periods = samples.windows(2).filter(|w| w[0] < 0 && w[1] > 0).count();
+        assert_eq!(num_periods, 2 * AUDIO_CB_SAMPLES * 440 / 44100);
+
+        // The next samples continue the wave
+        fill_audio(&mut vm).unwrap();
+        let samples = audio.take_samples(1);
+        let idx = 2 * AUDIO_CB_SAMPLES;
+        let expected = if (idx * 2 * 440 / 44100) % 2 == 0 { 8000 } else { -8000 };
+        assert_eq!(samples, vec![expected]);
+    }
+
+    #[test]
+    fn test_audio_bad_buffer()
+    {
+        use crate::sys::audio::{HeadlessAudio, fill_audio};
+
+        let mut vm = compile_ncc("tests/audio/bad_buffer.c");
+        let audio = HeadlessAudio::new();
+        vm.sys_state.audio_state.set_backend(audio.clone());
+        assert!(matches!(vm.call(0, &[]).unwrap(), ExitReason::Return(_)));
+
+        // The output is stopped instead of panicking,
+        // and the callback isn't called again
+        assert_eq!(fill_audio(&mut vm), Ok(ExitReason::default()));
+        assert_eq!(fill_audio(&mut vm), Ok(ExitReason::default()));
+        assert!(audio.take_samples(10).is_empty());
+    }
+
+    #[test]
+    fn test_files()
+    {
+        use crate::sys::fs::FsAccess;
+
+        // Write "hello" to a file with argument 1, or read it back with argument 0
+        let file_vm = |path: &str, access: FsAccess| {
+            let src = format!(concat!(
+                ".data; .u64 0; MSG: .stringz \"hello\"; BUF: .zero 16; PATH: .stringz \"{}\";",
+                ".code; get_arg 0; jz READ;",
+                "push PATH; push 1; syscall file_open; dup; push MSG; push 5; syscall file_write;",
+                "swap; syscall file_close; pop; exit;",
+                "READ: push PATH; push 0; syscall file_open; dup; push BUF; push 16; syscall file_read;",
+                "swap; syscall file_close; pop; exit;",
+            ), path);
+            let mut vm = Assembler::new().parse_str(&src).unwrap();
+            vm.sys_state.fs_state.set_access(access);
+            vm
+        };
+        let write = Value::from(1u64);
+        let read = Value::from(0u64);
+        let failed = ExitReason::Exit(Value::from(-1 as i64));
+
+        let root = std::env::temp_dir().join(format!("uvm_test_files_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let sandbox = FsAccess::Sandbox(root.clone());
+
+        let mut vm = file_vm("out.txt", sandbox.clone());
+        assert_eq!(vm.call(0, &[write]).unwrap(), ExitReason::Exit(Value::from(5u64)));
+        assert_eq!(vm.call(0, &[read]).unwrap(), ExitReason::Exit(Value::from(5u64)));
+        assert_eq!(vm.get_heap_str(14), "hello");
+        assert_eq!(std::fs::read_to_string(root.join("out.txt")).unwrap(), "hello");
+
+        // Paths leaving the sandbox are rejected
+        let outside = root.join("out.txt").display().to_string();
+        for path in ["../out.txt", "./sub/../../out.txt", &outside, "missing_dir/out.txt"] {
+            let mut vm = file_vm(path, sandbox.clone());
+            assert_eq!(vm.call(0, &[write]).unwrap(), failed);
+            assert_eq!(vm.call(0, &[read]).unwrap(), failed);
+        }
+
+        // Dangling links can't create files outside of the sandbox
+        #[cfg(unix)]
+        {
+            let target = root.with_extension("link_target");
+            std::os::unix::fs::symlink(&target, root.join("link.txt")).unwrap();
+            let mut vm = file_vm("link.txt", sandbox.clone());
+            assert_eq!(vm.call(0, &[write]).unwrap(), failed);
+            assert!(!target.exists());
+        }
+
+        let mut vm = file_vm(&outside, FsAccess::Full);
+        assert_eq!(vm.call(0, &[read]).unwrap(), ExitReason::Exit(Value::from(5u64)));
+        let mut vm = file_vm(&outside, FsAccess::Disabled);
+        assert_eq!(vm.call(0, &[read]).unwrap(), failed);
+
+        // Invalid file descriptors and buffers outside of the heap
+        let mut vm = file_vm("out.txt", sandbox.clone());
+        vm.call(0, &[write]).unwrap();
+        for src in ["push 3; syscall file_close; exit;", "push -1; push 0; push 1; syscall file_read; exit;"] {
+            let mut vm = Assembler::new().parse_str(src).unwrap();
+            assert_eq!(vm.call(0, &[]).unwrap(), failed);
+        }
+        let src = ".data; P: .stringz \"out.txt\"; .code; push P; push 0; syscall file_open; push 4; push 8; syscall file_read; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.sys_state.fs_state.set_access(sandbox);
+        assert_eq!(vm.call(0, &[]).unwrap(), failed);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tcp()
+    {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        let failed = ExitReason::Exit(Value::from(-1 as i64));
+        let free_port = || TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        // Compiled client talking to a server which replies once
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let server_thread = std::thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut msg = [0; 4];
+            stream.read_exact(&mut msg).unwrap();
+            assert_eq!(&msg, b"ping");
+            stream.write_all(b"pong").unwrap();
+        });
+
+        let args = ["client".to_string(), port.to_string()];
+        let mut vm = compile_ncc("tests/net/client.c");
+        vm.sys_state.net_state.set_enabled(true);
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+        let argv = vm.write_args(&args).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(0u64)));
+        assert_eq!(
+            String::from_utf8(output.contents()).unwrap(),
+            "sent: 4\nreceived: pong\nend: 0\nclosed: 0 -1\n"
+        );
+        server_thread.join().unwrap();
+
+        // Network access is disabled by default
+        let mut vm = compile_ncc("tests/net/client.c");
+        let output = OutputBuffer::new();
+        vm.sys_state.set_output(output.clone());
+        let argv = vm.write_args(&args).unwrap();
+        assert_eq!(vm.call(0, &argv).unwrap(), ExitReason::Exit(Value::from(1u64)));
+        assert_eq!(output.contents(), b"connect failed\n");
+        let mut vm = Assembler::new().parse_str("push 0; syscall tcp_listen; exit;").unwrap();
+        assert_eq!(vm.call(0, &[]).unwrap(), failed);
+
+        // Echo server, which then reads into a buffer outside of the heap
+        let port = free_port();
+        let src = format!(concat!(
+            ".data; .u64 0; BUF: .zero 8;",
+            ".code; push {}; syscall tcp_listen; syscall tcp_accept;",
+            "dup; push BUF; push 8; syscall tcp_read; pop;",
+            "dup; push BUF; push 4; syscall tcp_write; pop;",
+            "push 0xFFFFFF; push 4; syscall tcp_read; exit;",
+        ), port);
+        let mut vm = Assembler::new().parse_str(&src).unwrap();
+        vm.sys_state.net_state.set_enabled(true);
+        let vm_thread = std::thread::spawn(move || vm.call(0, &[]).unwrap());
+
+        // Retry until the VM is listening
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        stream.write_all(b"echo").unwrap();
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"echo");
+        assert_eq!(vm_thread.join().unwrap(), failed);
+
+        // Invalid socket descriptors
+        for src in ["push 0; syscall tcp_accept; exit;", "push 5; syscall tcp_close; exit;"] {
+            let mut vm = Assembler::new().parse_str(src).unwrap();
+            vm.sys_state.net_state.set_enabled(true);
+            assert_eq!(vm.call(0, &[]).unwrap(), failed);
+        }
+
+        // Ports out of range aren't truncated to another port
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = 0x10000 + server.local_addr().unwrap().port() as u64;
+        for src in [
+            format!(".data; H: .stringz \"127.0.0.1\"; .code; push H; push {}; syscall tcp_connect; exit;", port),
+            format!("push {}; syscall tcp_listen; exit;", 0x10000 + free_port() as u64),
+        ] {
+            let mut vm = Assembler::new().parse_str(&src).unwrap();
+            vm.sys_state.net_state.set_enabled(true);
+            assert_eq!(vm.call(0, &[]).unwrap(), failed);
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_div_zero()
@@ -1624,4 +2757,465 @@ mod tests
     {
         eval_src(".data; LABEL: .zero 1; .code; push LABEL; push 255; push 256; syscall memset; push 0; exit;");
     }
+
+    #[test]
+    #[should_panic]
+    fn test_memcpy_oob()
+    {
+        eval_src(".data; LABEL: .zero 1; .code; push LABEL; push 0; push 1048576; syscall memcpy; push 0; exit;");
+    }
+
+    #[test]
+    fn test_memcpy_overlap()
+    {
+        // Overlapping copies behave like memmove
+        let src = ".data; LABEL: .u32 0x04030201; .code; \
+                   push LABEL; push_1; add_u64; push LABEL; push 3; syscall memcpy; \
+                   push LABEL; load_u32; exit;";
+        eval_i64(src, 0x03020101);
+    }
+
+    #[test]
+    fn test_call_stack()
+    {
+        use crate::debug::DebugInfo;
+
+        let src = "call MAIN, 0; exit; MAIN: call A, 0; ret; A: push_0; call B, 1; ret; B: push_0; pop; panic;";
+        let program = Assembler::new().assemble_str(src).unwrap();
+        let addr = |name: &str| program.symbols.iter().find(|(n, _)| n == name).unwrap().1;
+        let (main, a, b) = (addr("MAIN"), addr("A"), addr("B"));
+        let symbols = program.symbols.clone();
+
+        let mut vm = program.into_vm();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| vm.call(0, &[]).unwrap()));
+        assert!(result.is_err());
+
+        // Each outer frame is at its call instruction
+        let call_stack = vm.call_stack(b + 2);
+        assert_eq!(call_stack, vec![(b, b + 2), (a, a + 1), (main, main), (0, 0)]);
+
+        let debug_info = DebugInfo { symbols, ..DebugInfo::default() };
+        let text = debug_info.format_call_stack(&call_stack);
+        assert_eq!(text, format!(
+            "call stack:\n    #0 B at address {}\n    #1 A at address {}\n    #2 MAIN at address {}\n    #3 <function at 0> at address 0\n",
+            b + 2, a + 1, main
+        ));
+    }
+
+    #[test]
+    fn test_tail_call()
+    {
+        // The callee gets its own arguments in place of the
+        // arguments and locals of the current function
+        eval_i64("push_i8 5; push_i8 6; call A, 2; exit; A: push_0; get_arg 0; get_arg 1; add_u64; tail_call B, 1; B: get_arg 0; get_argc; mul_u64; ret;", 11);
+        eval_i64("push_i8 5; call A, 1; exit; A: push_i8 2; get_arg 0; tail_call B, 2; B: get_arg 0; get_arg 1; sub_u64; ret;", -3);
+
+        // Tail recursion doesn't grow the stack
+        let countdown = "push_u32 100000; call COUNT, 1; exit; \
+                         COUNT: get_arg 0; jz DONE; get_arg 0; push_1; sub_u64; tail_call COUNT, 1; \
+                         DONE: push_i8 7; ret;";
+        let mut vm = Assembler::new().parse_str(countdown).unwrap();
+        vm.set_max_call_depth(2);
+        assert_eq!(vm.call_with_fuel(0, &[], 1_000_000), RunResult::Done(ExitReason::Exit(Value::from(7))));
+
+        // The function making the tail call is no longer on the call stack
+        let src = "call MAIN, 0; exit; MAIN: push_0; tail_call A, 0; A: panic;";
+        let program = Assembler::new().assemble_str(src).unwrap();
+        let a = program.symbols.iter().find(|(n, _)| n == "A").unwrap().1;
+        let mut vm = program.into_vm();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| vm.call(0, &[]).unwrap()));
+        assert!(result.is_err());
+        assert_eq!(vm.call_stack(a), vec![(a, a), (0, 0)]);
+    }
+
+    #[test]
+    fn test_fuel()
+    {
+        let fuel_src = |src: &str, max_insns: u64| {
+            let mut vm = Assembler::new().parse_str(src).unwrap();
+            let result = vm.call_with_fuel(0, &[], max_insns);
+            assert!(vm.stack.len() == 0 && vm.frames.len() == 0);
+            (result, vm.fuel())
+        };
+
+        // Terminating programs keep the fuel they didn't use
+        let (result, fuel) = fuel_src("push_i8 1; push_i8 2; add_u64; exit;", 100);
+        assert_eq!(result, RunResult::Done(ExitReason::Exit(Value::from(3))));
+        assert_eq!(fuel, 96);
+
+        // Exactly enough fuel
+        let (result, fuel) = fuel_src("push_i8 1; push_i8 2; add_u64; exit;", 4);
+        assert_eq!(result, RunResult::Done(ExitReason::Exit(Value::from(3))));
+        assert_eq!(fuel, 0);
+        assert_eq!(fuel_src("push_i8 1; push_i8 2; add_u64; exit;", 3).0, RunResult::OutOfFuel);
+
+        // Infinite loops and infinite recursion stop at the budget
+        let (result, fuel) = fuel_src("LOOP: jmp LOOP;", 1000);
+        assert_eq!(result, RunResult::OutOfFuel);
+        assert_eq!(fuel, 0);
+        let (result, _) = fuel_src("push_0; LOOP: push_1; jnz LOOP; exit;", 1000);
+        assert_eq!(result, RunResult::OutOfFuel);
+        // The recursion runs out of fuel before reaching the call depth limit
+        let (result, _) = fuel_src("call FN, 0; exit; FN: call FN, 0; ret;", 500);
+        assert_eq!(result, RunResult::OutOfFuel);
+
+        // Panics are returned
+        let (result, _) = fuel_src("push_0; push_0; panic;", 1000);
+        assert_eq!(result, RunResult::Panic("execution error, encountered panic opcode".to_string()));
+
+        // The VM can be called again after running out of fuel
+        let mut vm = Assembler::new().parse_str("push_0; jz LOOP; push_i8 5; exit; LOOP: jmp LOOP;").unwrap();
+        assert_eq!(vm.call_with_fuel(0, &[], 50), RunResult::OutOfFuel);
+        // push_i8 is at address 6, after push_0 and jz
+        assert_eq!(vm.call_with_fuel(6, &[], 50), RunResult::Done(ExitReason::Exit(Value::from(5))));
+        assert_eq!(vm.fuel(), 48);
+    }
+
+    #[test]
+    fn test_mem_bounds()
+    {
+        // Run a load or store in a heap of 16 bytes
+        let access = |op: &str, addr: u64| {
+            let src = if op.starts_with("load") {
+                format!(".data; .zero 16; .code; push_u64 {}; {}; exit;", addr, op)
+            } else {
+                format!(".data; .zero 16; .code; push_u64 {}; push 7; {}; push 0; exit;", addr, op)
+            };
+            let mut vm = Assembler::new().parse_str(&src).unwrap();
+            vm.call_with_fuel(0, &[], 100)
+        };
+
+        for (num_bytes, load, store) in [(1, "load_u8", "store_u8"), (2, "load_u16", "store_u16"), (4, "load_u32", "store_u32"), (8, "load_u64", "store_u64")] {
+            for op in [load, store] {
+                // Last valid bytes
+                let last = 16 - num_bytes;
+                assert!(matches!(access(op, last), RunResult::Done(_)), "{} {}", op, last);
+
+                // First invalid byte, and accesses straddling the end
+                for addr in [16, last + 1, u64::MAX - num_bytes + 1, u64::MAX] {
+                    let pc = if op.starts_with("load") { 9 } else { 11 };
+                    let kind = TrapKind::OutOfBounds { addr: addr as usize, size: num_bytes as usize };
+                    let trap = match access(op, addr) {
+                        RunResult::Trap(trap) => trap,
+                        result => panic!("{} {}: {:?}", op, addr, result),
+                    };
+                    assert_eq!((trap.kind, format!("{:?}", trap.op), trap.pc), (kind, op.to_string(), pc));
+                }
+            }
+        }
+
+        let trap = match access("load_u32", 2) {
+            RunResult::Trap(trap) => trap,
+            result => panic!("{:?}", result),
+        };
+        assert_eq!((trap.kind, trap.op, trap.pc), (TrapKind::Unaligned { addr: 2, size: 4 }, Op::load_u32, 9));
+        assert_eq!(trap.to_string(), "unaligned memory access: load_u32 of 4 bytes at address 2, pc 9");
+    }
+
+    #[test]
+    fn test_mem_size()
+    {
+        let src = ".data; .zero 16; .code; push 32; syscall vm_resize_heap; exit;";
+
+        // Data which doesn't fit in memory is rejected
+        assert!(Assembler::new().assemble_str(src).unwrap().into_vm_with_mem_size(8).is_err());
+
+        // The heap can't be resized past the memory size
+        let mut vm = Assembler::new().assemble_str(src).unwrap().into_vm_with_mem_size(16).unwrap();
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(false)));
+        assert_eq!(vm.heap_size(), 16);
+
+        let mut vm = Assembler::new().assemble_str(src).unwrap().into_vm_with_mem_size(32).unwrap();
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(true)));
+        assert_eq!(vm.heap_size(), 32);
+        assert_eq!(vm.mem_size(), 32);
+    }
+
+    #[test]
+    fn test_null_guard()
+    {
+        let access = |src: &str, null_guard: usize| {
+            let mut vm = Assembler::new().parse_str(src).unwrap();
+            vm.set_null_guard(null_guard);
+            vm.call_with_fuel(0, &[], 100)
+        };
+
+        let load_0 = ".data; .zero 16; .code; push_u64 0; load_u64; exit;";
+        let store_4 = ".data; .zero 16; .code; push_u64 4; push 1; store_u32; push 0; exit;";
+        let load_8 = ".data; .zero 16; .code; push_u64 8; load_u64; exit;";
+
+        // Address 0 is valid memory without a guard
+        assert!(matches!(access(load_0, 0), RunResult::Done(_)));
+
+        let trap = |kind, op, pc| RunResult::Trap(Trap { kind, op, pc, src_loc: None, call_stack: vec![(0, pc)] });
+        assert_eq!(
+            access(load_0, NULL_GUARD_SIZE),
+            trap(TrapKind::NullDereference { addr: 0, size: 8 }, Op::load_u64, 9)
+        );
+        assert_eq!(
+            access(store_4, NULL_GUARD_SIZE),
+            trap(TrapKind::NullDereference { addr: 4, size: 4 }, Op::store_u32, 10)
+        );
+        assert!(matches!(access(load_8, NULL_GUARD_SIZE), RunResult::Done(_)));
+    }
+
+    #[test]
+    fn test_traps()
+    {
+        let run = |src: &str, overflow_check: bool| {
+            let mut vm = Assembler::new().parse_str(src).unwrap();
+            vm.set_overflow_check(overflow_check);
+            vm.call_with_fuel(0, &[], 100)
+        };
+        let trap = |kind, op, pc| RunResult::Trap(Trap { kind, op, pc, src_loc: None, call_stack: vec![(0, pc)] });
+        let exit = |val: Value| RunResult::Done(ExitReason::Exit(val));
+
+        // Division and remainder by zero trap for every integer type
+        assert_eq!(run("push_u32 7; push_0; div_u32; exit;", false), trap(TrapKind::DivByZero, Op::div_u32, 6));
+        assert_eq!(run("push_u32 7; push_0; mod_i32; exit;", false), trap(TrapKind::DivByZero, Op::mod_i32, 6));
+        assert_eq!(run("push_i8 7; push_0; div_i64; exit;", false), trap(TrapKind::DivByZero, Op::div_i64, 3));
+        assert_eq!(run("push_i8 7; push_0; mod_u64; exit;", true), trap(TrapKind::DivByZero, Op::mod_u64, 3));
+
+        // Signed division overflow wraps around unless checked
+        let min_div_i32 = "push_u32 2147483648; push_i8 -1; div_i32; exit;";
+        let min_mod_i64 = "push_u64 9223372036854775808; push_i8 -1; mod_i64; exit;";
+        assert_eq!(run(min_div_i32, false), exit(Value::from(i32::MIN)));
+        assert_eq!(run(min_mod_i64, false), exit(Value::from(0i64)));
+        assert_eq!(run(min_div_i32, true), trap(TrapKind::Overflow, Op::div_i32, 7));
+        assert_eq!(run(min_mod_i64, true), trap(TrapKind::Overflow, Op::mod_i64, 11));
+
+        // Other arithmetic always wraps around
+        assert_eq!(run("push_u32 2147483647; push_1; add_u32; exit;", true), exit(Value::from(0x8000_0000u32)));
+        assert_eq!(run("push_0; push_1; sub_u64; exit;", true), exit(Value::from(u64::MAX)));
+
+        // The VM can run again after a trap
+        let mut vm = Assembler::new().parse_str("push_i8 7; push_0; div_u64; exit;").unwrap();
+        assert!(matches!(vm.call_with_fuel(0, &[], 100), RunResult::Trap(_)));
+        assert!(matches!(vm.call_with_fuel(0, &[], 100), RunResult::Trap(_)));
+
+        // Calls without a budget return traps instead of panicking
+        let trap = vm.call(0, &[]).unwrap_err();
+        assert_eq!((trap.kind, trap.op, trap.pc), (TrapKind::DivByZero, Op::div_u64, 3));
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+        assert!(vm.call(0, &[]).is_err());
+    }
+
+    #[test]
+    fn test_trap_src_loc()
+    {
+        // Traps report the source position with debug info
+        let mut vm = compile_ncc_with("tests/panics/mod_zero.c", &["-g"]);
+        match vm.call_with_fuel(0, &[], 10_000) {
+            RunResult::Trap(trap) => {
+                assert_eq!(trap.kind, TrapKind::DivByZero);
+                assert_eq!(trap.op, Op::mod_i32);
+                assert_eq!(trap.src_loc.as_deref(), Some("tests/panics/mod_zero.c:6:5"));
+            }
+            result => panic!("expected a trap, got {:?}", result),
+        }
+
+        let mut vm = compile_ncc_with("tests/panics/null_deref.c", &["-g"]);
+        vm.set_null_guard(NULL_GUARD_SIZE);
+        match vm.call_with_fuel(0, &[], 10_000) {
+            RunResult::Trap(trap) => {
+                assert_eq!(trap.kind, TrapKind::NullDereference { addr: 0, size: 4 });
+                assert_eq!(trap.src_loc.as_deref(), Some("tests/panics/null_deref.c:6:5"));
+            }
+            result => panic!("expected a trap, got {:?}", result),
+        }
+
+        let mut vm = compile_ncc_with("tests/panics/overflow.c", &["-g"]);
+        assert_eq!(vm.call_with_fuel(0, &[], 10_000), RunResult::Done(ExitReason::Exit(Value::from(0))));
+        vm.set_overflow_check(true);
+        match vm.call_with_fuel(0, &[], 10_000) {
+            RunResult::Trap(trap) => {
+                assert_eq!(trap.kind, TrapKind::Overflow);
+                assert_eq!(trap.src_loc.as_deref(), Some("tests/panics/overflow.c:6:5"));
+            }
+            result => panic!("expected a trap, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_tail_calls()
+    {
+        // 10 million tail calls with room for only a few frames
+        let mut vm = compile_ncc("tests/tail_calls/countdown.c");
+        vm.set_max_call_depth(4);
+        assert_eq!(vm.call_with_fuel(0, &[], u64::MAX), RunResult::Done(ExitReason::Exit(Value::from(0))));
+    }
+
+    /// Compile a C program with ncc and load it
+    pub(crate) fn compile_ncc(file_path: &str) -> VM
+    {
+        compile_ncc_with(file_path, &[])
+    }
+
+    /// Same as compile_ncc, passing extra options to ncc
+    pub(crate) fn compile_ncc_with(file_path: &str, ncc_args: &[&str]) -> VM
+    {
+        let file_stem = std::path::Path::new(file_path).file_stem().unwrap().to_str().unwrap();
+        let asm_path = std::env::temp_dir().join(format!("uvm_ncc_{}.asm", file_stem));
+        let status = std::process::Command::new("cargo")
+            .current_dir("../ncc")
+            .args(["run", "-q", "--"])
+            .args(ncc_args)
+            .arg("-o")
+            .arg(&asm_path)
+            .arg(file_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        Assembler::new().parse_file(asm_path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_host_fns()
+    {
+        let mut vm = Assembler::new().parse_str("push 3; push 4; call_host sum, 2; exit;").unwrap();
+        assert_eq!(vm.missing_host_fns(), vec!["sum"]);
+
+        // Arguments are passed in order
+        vm.register_host_fn("sum", 2, |_, args| Value::from(args[0].as_u64() * 10 + args[1].as_u64())).unwrap();
+        assert!(vm.missing_host_fns().is_empty());
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(34u64)));
+
+        // Names can't collide with syscalls or other host functions
+        assert!(vm.register_host_fn("sum", 2, |_, _| Value::from(0u64)).is_err());
+        assert!(vm.register_host_fn("print_i64", 1, |_, _| Value::from(0u64)).is_err());
+        assert!(vm.register_host_fn("big", 256, |_, _| Value::from(0u64)).is_err());
+
+        // Host functions keep their state between calls
+        let src = "call_host count, 0; pop; call_host count, 0; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        let mut count = 0u64;
+        vm.register_host_fn("count", 0, move |_, _| { count += 1; Value::from(count) }).unwrap();
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(2u64)));
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(4u64)));
+
+        // Unregistered functions and wrong argument counts panic
+        let mut vm = Assembler::new().parse_str("push 1; call_host foo, 1; exit;").unwrap();
+        assert_eq!(
+            vm.call_with_fuel(0, &[], 100),
+            RunResult::Panic("host function \"foo\" is not registered".to_string())
+        );
+        vm.register_host_fn("foo", 2, |_, _| Value::from(0u64)).unwrap();
+        assert_eq!(
+            vm.call_with_fuel(0, &[], 100),
+            RunResult::Panic("host function \"foo\" takes 2 arguments but 1 were passed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_host_fn_memory()
+    {
+        // The host function uppercases a string in place
+        let src = ".data; .u64 0; STR: .stringz \"abc\"; .code; push STR; push 3; call_host upcase, 2; pop; push STR; load_u8; exit;";
+        let mut vm = Assembler::new().parse_str(src).unwrap();
+        vm.register_host_fn("upcase", 2, |vm, args| {
+            match vm.heap_bytes(args[0].as_usize(), args[1].as_usize()) {
+                Some(bytes) => {
+                    bytes.make_ascii_uppercase();
+                    Value::from(true)
+                }
+                None => Value::from(false),
+            }
+        }).unwrap();
+
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(b'A')));
+        assert_eq!(vm.get_heap_str(8), "ABC");
+
+        // Accesses are checked against the heap size and the null guard
+        let heap_size = vm.heap_size();
+        assert!(vm.heap_bytes(heap_size, 0).is_some());
+        assert!(vm.heap_bytes(heap_size - 1, 2).is_none());
+        assert!(vm.heap_bytes(usize::MAX, 2).is_none());
+        vm.set_null_guard(NULL_GUARD_SIZE);
+        assert!(vm.heap_bytes(0, 8).is_none());
+        assert!(vm.heap_bytes(8, 4).is_some());
+    }
+
+    #[test]
+    fn test_host_fn_compiled()
+    {
+        // Compile a C program which calls host functions with ncc
+        let mut vm = compile_ncc("tests/host/buffers.c");
+        assert_eq!(vm.missing_host_fns(), vec!["host_fill", "host_sum"]);
+
+        vm.register_host_fn("host_fill", 3, |vm, args| {
+            let bytes = vm.heap_bytes(args[0].as_usize(), args[1].as_usize()).unwrap();
+            bytes.fill(args[2].as_u8());
+            Value::from(0u64)
+        }).unwrap();
+        vm.register_host_fn("host_sum", 2, |vm, args| {
+            let bytes = vm.heap_bytes(args[0].as_usize(), args[1].as_usize()).unwrap();
+            Value::from(bytes.iter().map(|b| *b as u64).sum::<u64>())
+        }).unwrap();
+
+        // 8 bytes of 3 read by the program, then 100 + 7 * 3 read by the host
+        assert_eq!(vm.call(0, &[]).unwrap(), ExitReason::Exit(Value::from(24_121u64)));
+    }
+
+    #[test]
+    fn test_call_depth()
+    {
+        // A and B call each other until the argument reaches zero
+        let src = |depth: u64| format!(
+            "push_u64 {}; call A, 1; exit; \
+             A: get_arg 0; dup; jz DONE; push 1; sub_u64; call B, 1; ret; DONE: ret; \
+             B: get_arg 0; call A, 1; ret;",
+            depth
+        );
+
+        let mut vm = Assembler::new().parse_str(&src(400)).unwrap();
+        assert_eq!(vm.call_with_fuel(0, &[], 100_000), RunResult::Done(ExitReason::Exit(Value::from(0))));
+
+        // Each decrement takes two frames
+        let mut vm = Assembler::new().parse_str(&src(600)).unwrap();
+        match vm.call_with_fuel(0, &[], 100_000) {
+            RunResult::Trap(trap) => {
+                assert_eq!(trap.kind, TrapKind::StackOverflow { max_call_depth: DEFAULT_MAX_CALL_DEPTH });
+                assert_eq!(trap.op, Op::call);
+                assert_eq!(trap.call_stack.len(), DEFAULT_MAX_CALL_DEPTH);
+                assert_eq!(
+                    trap.to_string(),
+                    format!("stack overflow: call depth exceeds {} frames, pc {}", DEFAULT_MAX_CALL_DEPTH, trap.pc)
+                );
+            }
+            result => panic!("expected a trap, got {:?}", result),
+        }
+
+        // The limit is configurable
+        vm.set_max_call_depth(1300);
+        assert_eq!(vm.call_with_fuel(0, &[], 100_000), RunResult::Done(ExitReason::Exit(Value::from(0))));
+        vm.set_max_call_depth(10);
+        assert!(matches!(vm.call_with_fuel(0, &[], 100_000), RunResult::Trap(_)));
+    }
+
+    #[test]
+    fn test_call_depth_ncc()
+    {
+        // The program recurses 100 calls deep, under main and the
+        // top-level frame of the call
+        let mut vm = compile_ncc("tests/recursion.c");
+        vm.set_max_call_depth(102);
+        assert_eq!(vm.call_with_fuel(0, &[], 100_000), RunResult::Done(ExitReason::Exit(Value::from(0))));
+
+        vm.set_max_call_depth(101);
+        match vm.call_with_fuel(0, &[], 100_000) {
+            RunResult::Trap(trap) => assert_eq!(trap.kind, TrapKind::StackOverflow { max_call_depth: 101 }),
+            result => panic!("expected a trap, got {:?}", result),
+        }
+
+        // Unbounded recursion always traps
+        let mut vm = compile_ncc("tests/panics/stack_overflow.c");
+        match vm.call_with_fuel(0, &[], 100_000) {
+            RunResult::Trap(trap) => {
+                assert_eq!(trap.kind, TrapKind::StackOverflow { max_call_depth: DEFAULT_MAX_CALL_DEPTH });
+                assert_eq!(trap.call_stack.len(), DEFAULT_MAX_CALL_DEPTH);
+            }
+            result => panic!("expected a trap, got {:?}", result),
+        }
+    }
 }